@@ -0,0 +1,30 @@
+DROP FUNCTION query_product_item_customize_items(PRODUCT_ITEM);
+DROP FUNCTION query_customize_selections(CUSTOMIZE);
+DROP FUNCTION query_product_customizes(PRODUCT);
+DROP FUNCTION query_shop_products(uuid_nn);
+DROP FUNCTION check_guest_session(uuid_nn);
+DROP FUNCTION is_guest_session_valid(uuid_nn);
+DROP FUNCTION get_session_user(uuid_nn);
+
+DROP TABLE orders;
+DROP TABLE cart;
+DROP TABLE shop_user;
+DROP TABLE shops;
+DROP TABLE guest_session;
+DROP TABLE user_session;
+DROP TABLE users;
+
+DROP TYPE PRODUCT_ITEM;
+DROP TYPE CUSTOMIZE_ITEM;
+DROP TYPE PRODUCT;
+DROP TYPE CUSTOMIZE;
+DROP TYPE SELECTION;
+
+DROP DOMAIN uuid_nn;
+DROP DOMAIN int_nn;
+DROP DOMAIN text_nz;
+DROP DOMAIN text_nn;
+DROP DOMAIN authority_nn;
+DROP TYPE authority;
+DROP DOMAIN permission_nn;
+DROP TYPE permission;
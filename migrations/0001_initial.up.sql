@@ -0,0 +1,169 @@
+CREATE EXTENSION IF NOT EXISTS hstore;
+CREATE EXTENSION IF NOT EXISTS pgcrypto;
+
+-- Types and domains shared with `sql::*`.
+
+CREATE TYPE permission AS ENUM ('none', 'read-only', 'all');
+CREATE DOMAIN permission_nn AS permission NOT NULL;
+
+CREATE TYPE authority AS ENUM ('member_authority', 'order_authority', 'product_authority');
+CREATE DOMAIN authority_nn AS authority NOT NULL;
+
+CREATE DOMAIN text_nn AS TEXT NOT NULL;
+CREATE DOMAIN text_nz AS TEXT NOT NULL CHECK (VALUE <> '');
+CREATE DOMAIN int_nn AS INTEGER NOT NULL;
+CREATE DOMAIN uuid_nn AS UUID NOT NULL;
+
+-- Catalog snapshots stored in hstore values.
+
+CREATE TYPE SELECTION AS (
+    name TEXT,
+    price INTEGER
+);
+
+CREATE TYPE CUSTOMIZE AS (
+    name TEXT,
+    description TEXT,
+    selections HSTORE,
+    latest_update TIMESTAMPTZ
+);
+
+CREATE TYPE PRODUCT AS (
+    name TEXT,
+    description TEXT,
+    price INTEGER,
+    series_id UUID,
+    has_picture BOOLEAN,
+    customizes HSTORE,
+    latest_update TIMESTAMPTZ
+);
+
+CREATE TYPE CUSTOMIZE_ITEM AS (
+    name TEXT,
+    selection TEXT,
+    selection_key UUID,
+    price INTEGER,
+    order_at TIMESTAMPTZ
+);
+
+CREATE TYPE PRODUCT_ITEM AS (
+    product_key UUID,
+    name TEXT,
+    price INTEGER,
+    count INTEGER,
+    remark TEXT,
+    customize_items HSTORE,
+    order_at TIMESTAMPTZ
+);
+
+-- Tables.
+
+CREATE TABLE users (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    username TEXT NOT NULL UNIQUE CHECK (username <> ''),
+    password TEXT NOT NULL,
+    nickname TEXT
+);
+
+CREATE TABLE user_session (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    expire_time TIMESTAMPTZ NOT NULL DEFAULT now() + INTERVAL '1 day'
+);
+
+CREATE TABLE guest_session (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    expire_time TIMESTAMPTZ NOT NULL DEFAULT now() + INTERVAL '1 day'
+);
+
+CREATE TABLE shops (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name TEXT NOT NULL CHECK (name <> ''),
+    name_upper TEXT GENERATED ALWAYS AS (upper(name)) STORED,
+    products HSTORE NOT NULL DEFAULT '',
+    latest_update TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE shop_user (
+    shop_id UUID NOT NULL REFERENCES shops (id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    member_authority permission NOT NULL DEFAULT 'none',
+    order_authority permission NOT NULL DEFAULT 'none',
+    product_authority permission NOT NULL DEFAULT 'none',
+    PRIMARY KEY (shop_id, user_id)
+);
+
+CREATE TABLE cart (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    shop_id UUID NOT NULL REFERENCES shops (id) ON DELETE CASCADE,
+    guest_session_id UUID NOT NULL REFERENCES guest_session (id) ON DELETE CASCADE,
+    items HSTORE NOT NULL DEFAULT '',
+    UNIQUE (shop_id, guest_session_id)
+);
+
+CREATE TABLE orders (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    shop_id UUID NOT NULL REFERENCES shops (id) ON DELETE CASCADE,
+    guest_session_id UUID NOT NULL,
+    order_number INTEGER NOT NULL,
+    items HSTORE NOT NULL DEFAULT '',
+    order_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (shop_id, order_number)
+);
+
+CREATE INDEX orders_guest_session_id ON orders (guest_session_id);
+
+-- Sessions. Expired sessions raise the SQLSTATEs mapped in `error.rs`.
+
+CREATE FUNCTION get_session_user(session_id uuid_nn) RETURNS TABLE (user_id UUID) AS $$
+DECLARE
+    session_user_id UUID;
+BEGIN
+    SELECT user_session.user_id INTO session_user_id
+    FROM user_session
+    WHERE user_session.id = session_id AND user_session.expire_time > now();
+
+    IF NOT FOUND THEN
+        RAISE EXCEPTION 'User session % has expired.', session_id USING ERRCODE = 'C2002';
+    END IF;
+
+    RETURN QUERY SELECT session_user_id;
+END;
+$$ LANGUAGE plpgsql STABLE;
+
+CREATE FUNCTION is_guest_session_valid(session_id uuid_nn) RETURNS BOOLEAN AS $$
+    SELECT EXISTS (
+        SELECT 1 FROM guest_session WHERE id = $1 AND expire_time > now()
+    );
+$$ LANGUAGE sql STABLE;
+
+CREATE FUNCTION check_guest_session(session_id uuid_nn) RETURNS VOID AS $$
+BEGIN
+    IF NOT is_guest_session_valid(session_id) THEN
+        RAISE EXCEPTION 'Guest session % has expired.', session_id USING ERRCODE = 'C3001';
+    END IF;
+END;
+$$ LANGUAGE plpgsql STABLE;
+
+-- Catalog queries.
+
+CREATE FUNCTION query_shop_products(shop_id uuid_nn) RETURNS TABLE (key UUID, product PRODUCT) AS $$
+    SELECT entry.key::UUID, entry.value::PRODUCT
+    FROM shops, each(shops.products) entry
+    WHERE shops.id = $1;
+$$ LANGUAGE sql STABLE;
+
+CREATE FUNCTION query_product_customizes(product PRODUCT) RETURNS TABLE (key UUID, customize CUSTOMIZE) AS $$
+    SELECT entry.key::UUID, entry.value::CUSTOMIZE
+    FROM each(($1).customizes) entry;
+$$ LANGUAGE sql IMMUTABLE;
+
+CREATE FUNCTION query_customize_selections(customize CUSTOMIZE) RETURNS TABLE (key UUID, selection SELECTION) AS $$
+    SELECT entry.key::UUID, entry.value::SELECTION
+    FROM each(($1).selections) entry;
+$$ LANGUAGE sql IMMUTABLE;
+
+CREATE FUNCTION query_product_item_customize_items(item PRODUCT_ITEM) RETURNS TABLE (key UUID, customize CUSTOMIZE_ITEM) AS $$
+    SELECT entry.key::UUID, entry.value::CUSTOMIZE_ITEM
+    FROM each(($1).customize_items) entry;
+$$ LANGUAGE sql IMMUTABLE;
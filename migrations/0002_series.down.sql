@@ -0,0 +1,5 @@
+DROP FUNCTION query_shop_series(uuid_nn);
+
+ALTER TABLE shops DROP COLUMN series;
+
+DROP TYPE SERIES;
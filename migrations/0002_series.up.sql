@@ -0,0 +1,13 @@
+CREATE TYPE SERIES AS (
+    name TEXT,
+    ordering INTEGER,
+    latest_update TIMESTAMPTZ
+);
+
+ALTER TABLE shops ADD COLUMN series HSTORE NOT NULL DEFAULT '';
+
+CREATE FUNCTION query_shop_series(shop_id uuid_nn) RETURNS TABLE (key UUID, series SERIES) AS $$
+    SELECT entry.key::UUID, entry.value::SERIES
+    FROM shops, each(shops.series) entry
+    WHERE shops.id = $1;
+$$ LANGUAGE sql STABLE;
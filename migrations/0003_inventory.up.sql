@@ -0,0 +1,9 @@
+-- Stock of a product or a selection, keyed by its product or selection key.
+-- Keys without a row are untracked and always available.
+CREATE TABLE inventory (
+    shop_id UUID NOT NULL REFERENCES shops (id) ON DELETE CASCADE,
+    key UUID NOT NULL,
+    stock INTEGER CHECK (stock >= 0),
+    sold_out BOOLEAN NOT NULL DEFAULT FALSE,
+    PRIMARY KEY (shop_id, key)
+);
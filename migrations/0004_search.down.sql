@@ -0,0 +1,6 @@
+DROP TRIGGER shops_search_documents ON shops;
+DROP FUNCTION index_shop_trigger();
+DROP FUNCTION index_shop(uuid_nn);
+DROP TABLE search_documents;
+DROP FUNCTION search_query(TEXT);
+DROP FUNCTION search_text(TEXT, BOOLEAN);
@@ -0,0 +1,67 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- Text prepared for the `simple` parser. CJK scripts are written without
+-- spaces between words, so their runs are split into overlapping bigrams,
+-- plus single characters unless the text is a query.
+CREATE FUNCTION search_text(input TEXT, is_query BOOLEAN DEFAULT FALSE) RETURNS TEXT AS $$
+    SELECT lower(regexp_replace(input, '[\u3040-\u30ff\u3400-\u4dbf\u4e00-\u9fff\uac00-\ud7af\uf900-\ufaff]+', ' ', 'g'))
+        || ' '
+        || coalesce((
+            SELECT string_agg(token, ' ')
+            FROM
+                regexp_matches(input, '[\u3040-\u30ff\u3400-\u4dbf\u4e00-\u9fff\uac00-\ud7af\uf900-\ufaff]+', 'g') AS run (chars),
+                LATERAL (
+                    SELECT substr(chars[1], i, 2) token
+                    FROM generate_series(1, greatest(length(chars[1]) - 1, 1)) i
+                    UNION ALL
+                    SELECT substr(chars[1], i, 1)
+                    FROM generate_series(1, length(chars[1])) i
+                    WHERE NOT is_query
+                ) tokens
+        ), '');
+$$ LANGUAGE sql IMMUTABLE;
+
+-- Every token of the query as a prefix, all of them required.
+CREATE FUNCTION search_query(input TEXT) RETURNS TSQUERY AS $$
+    SELECT to_tsquery('simple', string_agg(quote_literal(token) || ':*', ' & '))
+    FROM unnest(tsvector_to_array(to_tsvector('simple', search_text(input, TRUE)))) token;
+$$ LANGUAGE sql IMMUTABLE;
+
+-- Shops and their products as searchable documents, kept in sync with
+-- `shops` by the trigger below. `product_key` is NULL for the shop itself.
+CREATE TABLE search_documents (
+    shop_id UUID NOT NULL REFERENCES shops (id) ON DELETE CASCADE,
+    product_key UUID,
+    name TEXT NOT NULL,
+    description TEXT,
+    document TSVECTOR GENERATED ALWAYS AS (
+        setweight(to_tsvector('simple', search_text(name)), 'A')
+            || setweight(to_tsvector('simple', search_text(coalesce(description, ''))), 'B')
+    ) STORED
+);
+
+CREATE INDEX search_documents_shop_id ON search_documents (shop_id);
+CREATE INDEX search_documents_document ON search_documents USING GIN (document);
+CREATE INDEX search_documents_name ON search_documents USING GIN (lower(name) gin_trgm_ops);
+
+CREATE FUNCTION index_shop(shop_id uuid_nn) RETURNS VOID AS $$
+    DELETE FROM search_documents WHERE search_documents.shop_id = $1;
+
+    INSERT INTO search_documents (shop_id, product_key, name, description)
+    SELECT id, NULL, name, NULL FROM shops WHERE id = $1
+    UNION ALL
+    SELECT $1, key, (product).name, (product).description FROM query_shop_products($1);
+$$ LANGUAGE sql;
+
+CREATE FUNCTION index_shop_trigger() RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM index_shop(NEW.id);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER shops_search_documents
+AFTER INSERT OR UPDATE OF name, products ON shops
+FOR EACH ROW EXECUTE FUNCTION index_shop_trigger();
+
+SELECT index_shop(id) FROM shops;
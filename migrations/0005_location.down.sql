@@ -0,0 +1,7 @@
+DROP INDEX shops_location;
+
+ALTER TABLE shops
+    DROP CONSTRAINT shops_coordinates,
+    DROP COLUMN address,
+    DROP COLUMN latitude,
+    DROP COLUMN longitude;
@@ -0,0 +1,12 @@
+CREATE EXTENSION IF NOT EXISTS cube;
+CREATE EXTENSION IF NOT EXISTS earthdistance;
+
+ALTER TABLE shops
+    ADD COLUMN address TEXT,
+    ADD COLUMN latitude DOUBLE PRECISION CHECK (latitude BETWEEN -90 AND 90),
+    ADD COLUMN longitude DOUBLE PRECISION CHECK (longitude BETWEEN -180 AND 180),
+    ADD CONSTRAINT shops_coordinates CHECK ((latitude IS NULL) = (longitude IS NULL));
+
+-- Lets `earth_box` narrow nearby queries down before measuring distances.
+CREATE INDEX shops_location ON shops USING GIST (ll_to_earth(latitude, longitude))
+WHERE latitude IS NOT NULL;
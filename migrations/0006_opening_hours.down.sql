@@ -0,0 +1,6 @@
+DROP TABLE opening_exceptions;
+DROP TABLE opening_hours;
+
+ALTER TABLE shops
+    DROP COLUMN timezone,
+    DROP COLUMN ordering_paused_until;
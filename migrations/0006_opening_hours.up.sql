@@ -0,0 +1,26 @@
+-- Local times below are in the shop's `timezone`. Ordering is paused until
+-- `ordering_paused_until` when it is in the future.
+ALTER TABLE shops
+    ADD COLUMN timezone TEXT NOT NULL DEFAULT 'UTC',
+    ADD COLUMN ordering_paused_until TIMESTAMPTZ;
+
+-- Weekly hours, `weekday` 0 being Monday. Hours closing at or before they
+-- open run past midnight. Shops without any are open around the clock.
+CREATE TABLE opening_hours (
+    shop_id UUID NOT NULL REFERENCES shops (id) ON DELETE CASCADE,
+    weekday SMALLINT NOT NULL CHECK (weekday BETWEEN 0 AND 6),
+    opens TIME NOT NULL,
+    closes TIME NOT NULL,
+    PRIMARY KEY (shop_id, weekday, opens)
+);
+
+-- Hours replacing the weekly ones on a date; none means closed all day.
+CREATE TABLE opening_exceptions (
+    shop_id UUID NOT NULL REFERENCES shops (id) ON DELETE CASCADE,
+    date DATE NOT NULL,
+    opens TIME[] NOT NULL DEFAULT '{}',
+    closes TIME[] NOT NULL DEFAULT '{}',
+    note TEXT,
+    PRIMARY KEY (shop_id, date),
+    CHECK (cardinality(opens) = cardinality(closes))
+);
@@ -0,0 +1,11 @@
+-- Text of a product, customize or selection in other locales, keyed by its
+-- product, customize or selection key. The text stored with the product is
+-- shown when no translation applies.
+CREATE TABLE translations (
+    shop_id UUID NOT NULL REFERENCES shops (id) ON DELETE CASCADE,
+    key UUID NOT NULL,
+    field TEXT NOT NULL CHECK (field IN ('name', 'description')),
+    locale TEXT NOT NULL CHECK (locale ~ '^[A-Za-z]{2,8}(-[A-Za-z0-9]{1,8})*$'),
+    text TEXT NOT NULL CHECK (text <> ''),
+    PRIMARY KEY (shop_id, key, field, locale)
+);
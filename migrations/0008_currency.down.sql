@@ -0,0 +1,3 @@
+ALTER TABLE shops
+    DROP COLUMN currency,
+    DROP COLUMN minor_units;
@@ -0,0 +1,5 @@
+-- Prices are stored as integers in the shop's minor units, so an amount of
+-- 1250 is 12.50 in a currency with two minor units and 1250 in one with none.
+ALTER TABLE shops
+    ADD COLUMN currency TEXT NOT NULL DEFAULT 'TWD' CHECK (currency ~ '^[A-Z]{3}$'),
+    ADD COLUMN minor_units SMALLINT NOT NULL DEFAULT 0 CHECK (minor_units BETWEEN 0 AND 4);
@@ -0,0 +1,3 @@
+DROP TABLE order_promotions;
+ALTER TABLE cart DROP COLUMN coupon_code;
+DROP TABLE promotions;
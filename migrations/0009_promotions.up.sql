@@ -0,0 +1,44 @@
+-- Discounts a shop offers. Promotions without a code apply to every cart
+-- while active; those with one only to carts the code was applied to.
+CREATE TABLE promotions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    shop_id UUID NOT NULL REFERENCES shops (id) ON DELETE CASCADE,
+    name TEXT NOT NULL CHECK (name <> ''),
+    kind TEXT NOT NULL CHECK (kind IN ('percentage', 'fixed', 'buy_x_get_y')),
+    percent INTEGER CHECK (percent BETWEEN 1 AND 100),
+    amount INTEGER CHECK (amount > 0),
+    product_key UUID,
+    buy_count INTEGER CHECK (buy_count > 0),
+    get_count INTEGER CHECK (get_count > 0),
+    min_spend INTEGER NOT NULL DEFAULT 0 CHECK (min_spend >= 0),
+    starts_at TIMESTAMPTZ,
+    ends_at TIMESTAMPTZ,
+    code TEXT CHECK (code ~ '^[A-Z0-9_-]{3,32}$'),
+    usage_limit INTEGER CHECK (usage_limit > 0),
+    usage_count INTEGER NOT NULL DEFAULT 0 CHECK (usage_count <= usage_limit),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CONSTRAINT promotions_reward CHECK (
+        CASE kind
+            WHEN 'percentage' THEN percent IS NOT NULL
+            WHEN 'fixed' THEN amount IS NOT NULL
+            ELSE product_key IS NOT NULL AND buy_count IS NOT NULL AND get_count IS NOT NULL
+        END
+    ),
+    CONSTRAINT promotions_window CHECK (starts_at < ends_at)
+);
+
+CREATE UNIQUE INDEX promotions_code ON promotions (shop_id, code);
+
+ALTER TABLE cart ADD COLUMN coupon_code TEXT;
+
+-- Promotions as applied when the order was placed, so its total stays the
+-- same however the promotions change later.
+CREATE TABLE order_promotions (
+    order_id UUID NOT NULL REFERENCES orders (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    promotion_id UUID NOT NULL,
+    name TEXT NOT NULL,
+    code TEXT,
+    discount INTEGER NOT NULL CHECK (discount > 0),
+    PRIMARY KEY (order_id, position)
+);
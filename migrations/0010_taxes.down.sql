@@ -0,0 +1,10 @@
+DROP TABLE order_taxes;
+ALTER TABLE orders
+    DROP COLUMN tax_inclusive,
+    DROP COLUMN service_charge_rate,
+    DROP COLUMN service_charge;
+ALTER TABLE shops
+    DROP COLUMN tax_inclusive,
+    DROP COLUMN tax_rate,
+    DROP COLUMN series_tax_rates,
+    DROP COLUMN service_charge_rate;
@@ -0,0 +1,23 @@
+-- Rates are in basis points, so 500 is 5%. Series rates map series ids to
+-- rates for products of those series; others take the shop's rate. The
+-- defaults leave existing prices as final.
+ALTER TABLE shops
+    ADD COLUMN tax_inclusive BOOLEAN NOT NULL DEFAULT TRUE,
+    ADD COLUMN tax_rate INTEGER NOT NULL DEFAULT 0 CHECK (tax_rate BETWEEN 0 AND 10000),
+    ADD COLUMN series_tax_rates HSTORE NOT NULL DEFAULT '',
+    ADD COLUMN service_charge_rate INTEGER NOT NULL DEFAULT 0 CHECK (service_charge_rate BETWEEN 0 AND 10000);
+
+-- Charges as worked out when the order was placed, so its receipt stays the
+-- same however the shop's rates change later.
+ALTER TABLE orders
+    ADD COLUMN tax_inclusive BOOLEAN NOT NULL DEFAULT TRUE,
+    ADD COLUMN service_charge_rate INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN service_charge INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE order_taxes (
+    order_id UUID NOT NULL REFERENCES orders (id) ON DELETE CASCADE,
+    rate INTEGER NOT NULL CHECK (rate > 0),
+    taxable INTEGER NOT NULL,
+    amount INTEGER NOT NULL,
+    PRIMARY KEY (order_id, rate)
+);
@@ -0,0 +1,15 @@
+-- Changes members made to their shops, with the entity as JSON before and
+-- after. Entries outlive the users who made them.
+CREATE TABLE audit_log (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    shop_id UUID NOT NULL REFERENCES shops (id) ON DELETE CASCADE,
+    actor_id UUID NOT NULL,
+    entity TEXT NOT NULL CHECK (entity IN ('shop', 'product', 'series', 'promotion')),
+    entity_id UUID NOT NULL,
+    action TEXT NOT NULL CHECK (action IN ('create', 'update', 'delete')),
+    before JSONB,
+    after JSONB,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT clock_timestamp()
+);
+
+CREATE INDEX audit_log_shop_id ON audit_log (shop_id, created_at DESC);
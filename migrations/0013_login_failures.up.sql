@@ -0,0 +1,8 @@
+-- Failed logins in a row, per `account:{username}` and `ip:{address}`.
+-- Passwords in `users` are argon2id hashes from here on, and those stored
+-- before are rehashed as their users log in.
+CREATE TABLE login_failures (
+    key TEXT PRIMARY KEY,
+    failures INTEGER NOT NULL CHECK (failures > 0),
+    last_failed_at TIMESTAMPTZ NOT NULL
+);
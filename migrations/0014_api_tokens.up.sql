@@ -0,0 +1,18 @@
+-- Tokens users issue for scripts and integrations, sent as
+-- `Authorization: Bearer`. Only a SHA-256 hash of each token is kept. A token
+-- can do no more than its authority columns, nor than its user can, and one
+-- with a shop is limited to that shop.
+CREATE TABLE api_tokens (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    shop_id UUID REFERENCES shops (id) ON DELETE CASCADE,
+    name TEXT NOT NULL CHECK (name <> ''),
+    token_hash TEXT NOT NULL UNIQUE,
+    member_authority permission NOT NULL,
+    order_authority permission NOT NULL,
+    product_authority permission NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ
+);
+
+CREATE INDEX api_tokens_user_id ON api_tokens (user_id, created_at);
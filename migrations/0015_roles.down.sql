@@ -0,0 +1,27 @@
+-- Each member gets the most of each permission their role covers in full.
+ALTER TABLE shop_user
+    ADD COLUMN member_authority permission NOT NULL DEFAULT 'none',
+    ADD COLUMN order_authority permission NOT NULL DEFAULT 'none',
+    ADD COLUMN product_authority permission NOT NULL DEFAULT 'none';
+
+UPDATE shop_user SET
+    member_authority = CASE
+        WHEN capabilities @> ARRAY['view_members', 'manage_members', 'manage_shop', 'view_audit_log'] THEN 'all'
+        WHEN capabilities @> ARRAY['view_members'] THEN 'read-only'
+        ELSE 'none'
+    END::permission,
+    order_authority = CASE
+        WHEN capabilities @> ARRAY['view_orders', 'view_analytics', 'manage_ordering', 'refund_orders'] THEN 'all'
+        WHEN capabilities @> ARRAY['view_orders', 'view_analytics'] THEN 'read-only'
+        ELSE 'none'
+    END::permission,
+    product_authority = CASE
+        WHEN capabilities @> ARRAY['view_promotions', 'edit_catalog', 'edit_prices', 'manage_stock'] THEN 'all'
+        WHEN capabilities @> ARRAY['view_promotions'] THEN 'read-only'
+        ELSE 'none'
+    END::permission
+FROM roles
+WHERE roles.id = shop_user.role_id;
+
+ALTER TABLE shop_user DROP COLUMN role_id;
+DROP TABLE roles;
@@ -0,0 +1,72 @@
+-- Named roles per shop, each a set of capabilities, in place of the member,
+-- order and product permissions of `shop_user`. Every combination members
+-- had becomes a role of their shop with the same capabilities, named after
+-- the built-in template it matches.
+CREATE TABLE roles (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    shop_id UUID NOT NULL REFERENCES shops (id) ON DELETE CASCADE,
+    name TEXT NOT NULL CHECK (name <> ''),
+    capabilities TEXT[] NOT NULL CHECK (capabilities <@ ARRAY[
+        'view_members', 'manage_members', 'manage_shop', 'view_audit_log',
+        'view_orders', 'view_analytics', 'manage_ordering', 'refund_orders',
+        'view_promotions', 'edit_catalog', 'edit_prices', 'manage_stock'
+    ]),
+    UNIQUE (shop_id, name),
+    UNIQUE (shop_id, id)
+);
+
+-- What each permission allowed, as the server checked it.
+CREATE FUNCTION pg_temp.permission_capabilities(member permission, orders permission, product permission)
+RETURNS TEXT[] AS $$
+    SELECT
+        CASE member
+            WHEN 'all' THEN ARRAY['view_members', 'manage_members', 'manage_shop', 'view_audit_log']
+            WHEN 'read-only' THEN ARRAY['view_members']
+            ELSE ARRAY[]::TEXT[]
+        END
+        || CASE orders
+            WHEN 'all' THEN ARRAY['view_orders', 'view_analytics', 'manage_ordering', 'refund_orders']
+            WHEN 'read-only' THEN ARRAY['view_orders', 'view_analytics']
+            ELSE ARRAY[]::TEXT[]
+        END
+        || CASE product
+            WHEN 'all' THEN ARRAY['view_promotions', 'edit_catalog', 'edit_prices', 'manage_stock']
+            WHEN 'read-only' THEN ARRAY['view_promotions']
+            ELSE ARRAY[]::TEXT[]
+        END
+$$ LANGUAGE SQL IMMUTABLE;
+
+CREATE FUNCTION pg_temp.permission_role_name(member permission, orders permission, product permission)
+RETURNS TEXT AS $$
+    SELECT CASE format('%s/%s/%s', member, orders, product)
+        WHEN 'all/all/all' THEN 'Owner'
+        WHEN 'read-only/all/all' THEN 'Manager'
+        WHEN 'read-only/none/read-only' THEN 'Staff'
+        WHEN 'read-only/read-only/read-only' THEN 'Viewer'
+        ELSE format('Custom (members %s, orders %s, products %s)', member, orders, product)
+    END
+$$ LANGUAGE SQL IMMUTABLE;
+
+INSERT INTO roles (shop_id, name, capabilities)
+SELECT DISTINCT
+    shop_id,
+    pg_temp.permission_role_name(member_authority, order_authority, product_authority),
+    pg_temp.permission_capabilities(member_authority, order_authority, product_authority)
+FROM shop_user;
+
+ALTER TABLE shop_user ADD COLUMN role_id UUID;
+
+UPDATE shop_user SET role_id = roles.id
+FROM roles
+WHERE
+    roles.shop_id = shop_user.shop_id
+    AND roles.name = pg_temp.permission_role_name(member_authority, order_authority, product_authority);
+
+ALTER TABLE shop_user
+    ALTER COLUMN role_id SET NOT NULL,
+    ADD FOREIGN KEY (shop_id, role_id) REFERENCES roles (shop_id, id),
+    DROP COLUMN member_authority,
+    DROP COLUMN order_authority,
+    DROP COLUMN product_authority;
+
+CREATE INDEX shop_user_role_id ON shop_user (role_id);
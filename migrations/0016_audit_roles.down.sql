@@ -0,0 +1,5 @@
+DELETE FROM audit_log WHERE entity IN ('role', 'member');
+
+ALTER TABLE audit_log
+    DROP CONSTRAINT audit_log_entity_check,
+    ADD CONSTRAINT audit_log_entity_check CHECK (entity IN ('shop', 'product', 'series', 'promotion'));
@@ -0,0 +1,4 @@
+-- Changes to roles, and to which role members have, are audited too.
+ALTER TABLE audit_log
+    DROP CONSTRAINT audit_log_entity_check,
+    ADD CONSTRAINT audit_log_entity_check CHECK (entity IN ('shop', 'product', 'series', 'promotion', 'role', 'member'));
@@ -1,4 +1,6 @@
-use clap::{App, Arg, ArgMatches};
+use std::net::IpAddr;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use crate::auth::password::HashParams;
 
 pub fn parse_arguments<'a>() -> ArgMatches<'a> {
     App::new("pigskit-server")
@@ -13,11 +15,138 @@ pub fn parse_arguments<'a>() -> ArgMatches<'a> {
                 .help("Set the port that server will listen.")
                 .takes_value(true)
         )
+        .arg(
+            Arg::with_name("shutdown-timeout")
+                .long("shutdown-timeout")
+                .value_name("SECONDS")
+                .help("Set how long in-flight requests may take to finish on shutdown.")
+                .takes_value(true)
+        )
         .arg(
             Arg::with_name("dev")
                 .help("run server in development mode.")
                 .short("d"),
         )
+        .arg(
+            Arg::with_name("memory")
+                .long("memory")
+                .help("Serve from an in-memory store instead of the database."),
+        )
+        .arg(
+            Arg::with_name("shared-rate-limits")
+                .long("shared-rate-limits")
+                .help("Share rate limits with other servers through the database."),
+        )
+        .arg(
+            Arg::with_name("trusted-proxy")
+                .long("trusted-proxy")
+                .value_name("ADDRESS")
+                .help("Trust X-Forwarded-For from this proxy. May be given more than once.")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+        )
+        .arg(
+            Arg::with_name("db-pool-size")
+                .long("db-pool-size")
+                .value_name("COUNT")
+                .help("Set how many database connections the server may open.")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("argon2-memory")
+                .long("argon2-memory")
+                .value_name("KIB")
+                .help("Set how much memory hashing a password takes.")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("argon2-iterations")
+                .long("argon2-iterations")
+                .value_name("COUNT")
+                .help("Set how many passes hashing a password makes over its memory.")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("argon2-lanes")
+                .long("argon2-lanes")
+                .value_name("COUNT")
+                .help("Set how many lanes hashing a password uses.")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("picture-dir")
+                .long("picture-dir")
+                .value_name("DIR")
+                .help("Set the directory product pictures are stored in.")
+                .takes_value(true)
+        )
+        .subcommand(
+            SubCommand::with_name("schema")
+                .about("Print the GraphQL schema without connecting to the database.")
+                .arg(
+                    Arg::with_name("format")
+                        .short("f")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .help("Set the output format.")
+                        .possible_values(&["sdl", "json"])
+                        .default_value("sdl")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Write the schema to a file instead of stdout.")
+                        .takes_value(true)
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about("Manage the database schema.")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("up")
+                        .about("Apply pending migrations.")
+                        .arg(
+                            Arg::with_name("to")
+                                .long("to")
+                                .value_name("VERSION")
+                                .help("Stop after applying this version.")
+                                .takes_value(true)
+                        )
+                )
+                .subcommand(
+                    SubCommand::with_name("down")
+                        .about("Revert applied migrations.")
+                        .arg(
+                            Arg::with_name("steps")
+                                .long("steps")
+                                .value_name("COUNT")
+                                .help("Set how many migrations to revert.")
+                                .default_value("1")
+                                .takes_value(true)
+                        )
+                )
+                .subcommand(
+                    SubCommand::with_name("baseline")
+                        .about("Mark migrations as applied without running them, for a database created before migrations.")
+                        .arg(
+                            Arg::with_name("to")
+                                .long("to")
+                                .value_name("VERSION")
+                                .help("Mark migrations up to this version as applied.")
+                                .required(true)
+                                .takes_value(true)
+                        )
+                )
+                .subcommand(
+                    SubCommand::with_name("status")
+                        .about("List embedded migrations and whether they are applied.")
+                )
+        )
         .get_matches()
 }
 
@@ -31,4 +160,60 @@ pub fn args_port(args: &ArgMatches) -> Option<u16> {
     } else {
         None
     }
-}
\ No newline at end of file
+}
+
+pub fn args_shutdown_timeout(args: &ArgMatches) -> Option<u64> {
+    if let Some(timeout) = args.value_of("shutdown-timeout") {
+        if let Ok(timeout) = timeout.parse::<u64>() {
+            Some(timeout)
+        } else {
+            None
+        }
+    } else {
+        None
+    }
+}
+
+pub fn args_db_pool_size(args: &ArgMatches) -> Option<u32> {
+    args.value_of("db-pool-size")
+        .and_then(|size| size.parse::<u32>().ok())
+        .filter(|&size| size > 0)
+}
+
+// Addresses that don't parse are left out.
+pub fn args_trusted_proxies(args: &ArgMatches) -> Vec<IpAddr> {
+    args.values_of("trusted-proxy")
+        .map_or(Vec::new(), |values| values.filter_map(|value| value.parse::<IpAddr>().ok()).collect())
+}
+
+pub fn args_migrate_target(args: &ArgMatches) -> Option<i32> {
+    if let Some(version) = args.value_of("to") {
+        if let Ok(version) = version.parse::<i32>() {
+            Some(version)
+        } else {
+            None
+        }
+    } else {
+        None
+    }
+}
+
+pub fn args_migrate_steps(args: &ArgMatches) -> usize {
+    args.value_of("steps")
+        .and_then(|steps| steps.parse::<usize>().ok())
+        .unwrap_or(1)
+}
+// Costs not given keep their defaults.
+pub fn args_password_params(args: &ArgMatches) -> HashParams {
+    let value = |name: &str, default: u32| {
+        args.value_of(name)
+            .and_then(|value| value.parse::<u32>().ok())
+            .unwrap_or(default)
+    };
+    let default = HashParams::default();
+    HashParams {
+        memory_kib: value("argon2-memory", default.memory_kib),
+        iterations: value("argon2-iterations", default.iterations),
+        lanes: value("argon2-lanes", default.lanes),
+    }
+}
@@ -0,0 +1,66 @@
+use chrono::{DateTime, Duration, Utc};
+
+// Failures are forgotten this long after the last one.
+pub const FORGET_AFTER_HOURS: i64 = 24;
+
+// Failed logins in a row, against an account or from an address.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoginFailures {
+    pub count: i32,
+    pub last_failed_at: DateTime<Utc>,
+}
+
+// After `free_attempts` failures logins wait `base_seconds`, doubling with
+// every failure after that up to `max_seconds`.
+pub struct Lockout {
+    pub free_attempts: i32,
+    pub base_seconds: i64,
+    pub max_seconds: i64,
+}
+
+pub const ACCOUNT_LOCKOUT: Lockout = Lockout {
+    free_attempts: 5,
+    base_seconds: 30,
+    max_seconds: 60 * 60,
+};
+
+// Addresses are shared by offices and phones behind the same carrier, so
+// they get more room than an account.
+pub const ADDRESS_LOCKOUT: Lockout = Lockout {
+    free_attempts: 20,
+    base_seconds: 30,
+    max_seconds: 60 * 60,
+};
+
+impl Lockout {
+    pub fn locked_until(&self, failures: &LoginFailures) -> Option<DateTime<Utc>> {
+        if failures.count < self.free_attempts {
+            return None;
+        }
+        let doublings = (failures.count - self.free_attempts).min(32) as u32;
+        let seconds = self.base_seconds.saturating_mul(1 << doublings).min(self.max_seconds);
+        Some(failures.last_failed_at + Duration::seconds(seconds))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+    use super::{LoginFailures, ACCOUNT_LOCKOUT};
+
+    #[test]
+    fn test_locked_until() {
+        let now = Utc::now();
+        let locked_for = |count: i32| {
+            ACCOUNT_LOCKOUT.locked_until(&LoginFailures { count: count, last_failed_at: now })
+                .map(|until| until - now)
+        };
+
+        assert_eq!(locked_for(4), None);
+        assert_eq!(locked_for(5), Some(Duration::seconds(30)));
+        assert_eq!(locked_for(6), Some(Duration::seconds(60)));
+        assert_eq!(locked_for(8), Some(Duration::seconds(240)));
+        assert_eq!(locked_for(12), Some(Duration::hours(1)));
+        assert_eq!(locked_for(1000), Some(Duration::hours(1)));
+    }
+}
@@ -0,0 +1,180 @@
+use std::net::IpAddr;
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+use crate::{
+    state::State,
+    error::Error,
+};
+
+pub mod password;
+pub mod lockout;
+
+use password::Verified;
+use lockout::{Lockout, ACCOUNT_LOCKOUT, ADDRESS_LOCKOUT, FORGET_AFTER_HOURS};
+
+// How long a session from logging in lasts.
+pub const SESSION_DAYS: i64 = 30;
+
+pub struct Session {
+    pub id: Uuid,
+    pub expire_time: DateTime<Utc>,
+}
+
+// Logs a user in with a new user session. Every attempt counts against the
+// account and the address it came from until the password checks out, and
+// they are locked out for a while once they have too many failures.
+// Usernames nobody has only count against the address.
+pub fn login(state: &State, username: &str, password: &str, remote_addr: Option<IpAddr>, now: DateTime<Utc>) -> Result<Session, Error> {
+    let credentials = state.credentials().credentials(username)?;
+    let mut lockouts: Vec<(String, &Lockout)> = Vec::new();
+    if credentials.is_some() {
+        lockouts.push((format!("account:{}", username), &ACCOUNT_LOCKOUT));
+    }
+    if let Some(ip) = remote_addr {
+        lockouts.push((format!("ip:{}", ip), &ADDRESS_LOCKOUT));
+    }
+
+    let forget_before = now - Duration::hours(FORGET_AFTER_HOURS);
+    let mut taken = Vec::new();
+    let mut locked_until = None;
+    for (key, lockout) in &lockouts {
+        if state.credentials().take_login_attempt(key, lockout, now, forget_before)? {
+            taken.push(key);
+        } else {
+            let until = state.credentials().login_failures(key)?.and_then(|failures| lockout.locked_until(&failures));
+            locked_until = locked_until.max(Some(until.unwrap_or(now)));
+        }
+    }
+    if let Some(until) = locked_until {
+        for key in taken {
+            state.credentials().release_login_attempt(key)?;
+        }
+        return Err(Error::login_locked(until));
+    }
+
+    let params = state.password_params();
+    let verified = match credentials {
+        Some((user_id, stored)) => Some((user_id, params.verify(&stored, password)?)),
+        None => {
+            // As slow as a wrong password, so usernames can't be told apart.
+            params.hash(password)?;
+            None
+        }
+    };
+
+    match verified {
+        Some((user_id, Verified::Valid)) | Some((user_id, Verified::Outdated)) => {
+            if let Some((_, Verified::Outdated)) = verified {
+                state.credentials().set_password_hash(user_id, params.hash(password)?)?;
+            }
+            // Logging in clears the account's failures, but not those of an
+            // address others may share.
+            state.credentials().clear_login_failures(&lockouts[0].0)?;
+            for key in taken.into_iter().skip(1) {
+                state.credentials().release_login_attempt(key)?;
+            }
+
+            let expire_time = now + Duration::days(SESSION_DAYS);
+            Ok(Session {
+                id: state.sessions().create_user_session(user_id, expire_time)?,
+                expire_time: expire_time,
+            })
+        }
+        _ => Err(Error::invalid_credentials()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::IpAddr,
+        sync::Arc,
+    };
+    use chrono::{Duration, Utc};
+    use crate::{
+        repository::memory::MemoryRepository,
+        state::State,
+        error::Error,
+        tests::seed,
+    };
+    use super::{login, password::HashParams};
+
+    fn state() -> (Arc<MemoryRepository>, State) {
+        let repository = Arc::new(seed::memory());
+        let state = State::init_memory(repository.clone())
+            .with_password_params(HashParams { memory_kib: 64, iterations: 1, lanes: 1 });
+        (repository, state)
+    }
+
+    fn error_type<T>(result: Result<T, Error>) -> String {
+        result.err().map(|err| err.error_type().to_string()).unwrap_or_default()
+    }
+
+    #[test]
+    fn test_login() {
+        let (repository, state) = state();
+        let now = Utc::now();
+
+        // Plain text passwords from before hashing are rehashed on login.
+        let session = login(&state, "owner", "owner-password", None, now).unwrap();
+        assert_eq!(state.sessions().session_user(session.id).unwrap(), seed::OWNER);
+        assert_eq!(session.expire_time, now + Duration::days(30));
+        let stored = repository.read().users.iter().find(|user| user.id == seed::OWNER).unwrap().password.clone();
+        assert!(stored.starts_with("$argon2id$v=19$m=64,t=1,p=1$"));
+        assert!(login(&state, "owner", "owner-password", None, now).is_ok());
+        assert_eq!(repository.read().users.iter().find(|user| user.id == seed::OWNER).unwrap().password, stored);
+
+        // Stronger costs rehash again.
+        let stronger = state.clone().with_password_params(HashParams { memory_kib: 128, iterations: 1, lanes: 1 });
+        assert!(login(&stronger, "owner", "owner-password", None, now).is_ok());
+        assert!(repository.read().users.iter().any(|user| user.password.starts_with("$argon2id$v=19$m=128,")));
+
+        assert_eq!(error_type(login(&state, "owner", "wrong", None, now)), "InvalidCredentials");
+        assert_eq!(error_type(login(&state, "nobody", "owner-password", None, now)), "InvalidCredentials");
+    }
+
+    #[test]
+    fn test_account_lockout() {
+        let (_, state) = state();
+        let now = Utc::now();
+
+        for _ in 0..5 {
+            assert_eq!(error_type(login(&state, "staff", "wrong", None, now)), "InvalidCredentials");
+        }
+        // Even the right password waits out the lockout, which doubles with
+        // every failure after it.
+        assert_eq!(error_type(login(&state, "staff", "staff-password", None, now)), "LoginLocked");
+        let later = now + Duration::seconds(31);
+        assert_eq!(error_type(login(&state, "staff", "wrong", None, later)), "InvalidCredentials");
+        assert_eq!(error_type(login(&state, "staff", "staff-password", None, later + Duration::seconds(31))), "LoginLocked");
+        assert!(login(&state, "staff", "staff-password", None, later + Duration::seconds(61)).is_ok());
+
+        // Logging in clears the failures.
+        assert_eq!(error_type(login(&state, "staff", "wrong", None, later + Duration::seconds(62))), "InvalidCredentials");
+        assert!(login(&state, "staff", "staff-password", None, later + Duration::seconds(62)).is_ok());
+
+        // Other accounts are not affected.
+        assert!(login(&state, "owner", "owner-password", None, now).is_ok());
+    }
+
+    #[test]
+    fn test_address_lockout() {
+        let (repository, state) = state();
+        let now = Utc::now();
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+
+        for attempt in 0..20 {
+            let username = format!("user{}", attempt);
+            assert_eq!(error_type(login(&state, &username, "wrong", Some(ip), now)), "InvalidCredentials");
+        }
+        assert_eq!(error_type(login(&state, "owner", "owner-password", Some(ip), now)), "LoginLocked");
+        assert!(login(&state, "owner", "owner-password", Some("192.0.2.2".parse().unwrap()), now).is_ok());
+        assert!(login(&state, "owner", "owner-password", Some(ip), now + Duration::seconds(31)).is_ok());
+
+        // Made up usernames are only counted against the address, and
+        // logging in takes back its own attempt.
+        let failures = repository.read().login_failures.clone();
+        assert_eq!(failures.keys().collect::<Vec<_>>(), vec!["ip:192.0.2.1"]);
+        assert_eq!(failures["ip:192.0.2.1"].count, 20);
+    }
+}
@@ -0,0 +1,100 @@
+use argon2::{Config, ThreadMode, Variant, Version};
+use uuid::Uuid;
+use crate::error::Error;
+
+// Argon2id costs. Changing them outdates every stored hash, and each is
+// rehashed with the new ones on the user's next login.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HashParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub lanes: u32,
+}
+
+impl Default for HashParams {
+    fn default() -> Self {
+        HashParams {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            lanes: 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Verified {
+    Invalid,
+    Valid,
+    // Valid, but stored in plain text or hashed with other costs.
+    Outdated,
+}
+
+impl HashParams {
+    fn config(&self) -> Config<'static> {
+        Config {
+            variant: Variant::Argon2id,
+            version: Version::Version13,
+            mem_cost: self.memory_kib,
+            time_cost: self.iterations,
+            lanes: self.lanes,
+            thread_mode: ThreadMode::Sequential,
+            secret: &[],
+            ad: &[],
+            hash_length: 32,
+        }
+    }
+
+    // What every hash made with these costs starts with.
+    fn prefix(&self) -> String {
+        format!("$argon2id$v=19$m={},t={},p={}$", self.memory_kib, self.iterations, self.lanes)
+    }
+
+    // A PHC string with a fresh salt.
+    pub fn hash(&self, password: &str) -> Result<String, Error> {
+        Ok(argon2::hash_encoded(password.as_bytes(), Uuid::new_v4().as_bytes(), &self.config())?)
+    }
+
+    // Checks `password` against what is stored for the user. Passwords from
+    // before hashing are stored as they are.
+    pub fn verify(&self, stored: &str, password: &str) -> Result<Verified, Error> {
+        if stored.starts_with("$argon2") {
+            if !argon2::verify_encoded(stored, password.as_bytes())? {
+                Ok(Verified::Invalid)
+            } else if stored.starts_with(&self.prefix()) {
+                Ok(Verified::Valid)
+            } else {
+                Ok(Verified::Outdated)
+            }
+        } else if constant_time_eq(stored.as_bytes(), password.as_bytes()) {
+            Ok(Verified::Outdated)
+        } else {
+            Ok(Verified::Invalid)
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HashParams, Verified};
+
+    #[test]
+    fn test_verify() {
+        let params = HashParams { memory_kib: 64, iterations: 1, lanes: 1 };
+        let hash = params.hash("secret").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert_ne!(hash, params.hash("secret").unwrap());
+
+        assert_eq!(params.verify(&hash, "secret").unwrap(), Verified::Valid);
+        assert_eq!(params.verify(&hash, "Secret").unwrap(), Verified::Invalid);
+
+        // Hashes with other costs and plain text still work, once.
+        let stronger = HashParams { memory_kib: 128, ..params };
+        assert_eq!(stronger.verify(&hash, "secret").unwrap(), Verified::Outdated);
+        assert_eq!(params.verify("secret", "secret").unwrap(), Verified::Outdated);
+        assert_eq!(params.verify("secret", "secret!").unwrap(), Verified::Invalid);
+    }
+}
@@ -1,4 +1,5 @@
 use juniper::{graphql_value, IntoFieldError, FieldError};
+use chrono::{DateTime, Utc};
 use serde_json::json;
 
 #[derive(Debug)]
@@ -6,6 +7,9 @@ enum InnerError {
     Sql(postgres::error::Error),
     R2d2(r2d2::Error),
     SerdeJson(serde_json::error::Error),
+    Io(std::io::Error),
+    Image(image::ImageError),
+    Argon2(argon2::Error),
 }
 
 #[derive(Debug)]
@@ -46,6 +50,20 @@ impl Error {
         )
     }
 
+    pub fn invalid_credentials() -> Self {
+        Self::new(
+            "InvalidCredentials",
+            "Wrong username or password.",
+        )
+    }
+
+    pub fn login_locked(until: DateTime<Utc>) -> Self {
+        Self::new(
+            "LoginLocked",
+            &format!("Too many failed logins, try again after {}.", until.to_rfc3339()),
+        )
+    }
+
     pub fn no_valid_cookie(name: &str) -> Self {
         Self::new(
             "NoValidCookie",
@@ -53,6 +71,115 @@ impl Error {
         )
     }
 
+    pub fn invalid_token() -> Self {
+        Self::new(
+            "InvalidToken",
+            "Missing, revoked or expired API token.",
+        )
+    }
+
+    pub fn not_found(entity: &str) -> Self {
+        Self::new(
+            "NotFound",
+            &format!("{} not found.", entity),
+        )
+    }
+
+    pub fn unavailable(name: &str) -> Self {
+        Self::new(
+            "ItemUnavailable",
+            &format!(r#""{}" is sold out or not in stock."#, name),
+        )
+    }
+
+    pub fn shop_closed(next_open_at: Option<DateTime<Utc>>) -> Self {
+        Self::new(
+            "ShopClosed",
+            &match next_open_at {
+                Some(at) => format!("Shop is not taking orders until {}.", at.to_rfc3339()),
+                None => "Shop is not taking orders.".to_string(),
+            },
+        )
+    }
+
+    pub fn invalid_coupon(reason: &str) -> Self {
+        Self::new(
+            "InvalidCoupon",
+            reason,
+        )
+    }
+
+    pub fn rate_limited(retry_after: u64) -> Self {
+        Self::new(
+            "RateLimited",
+            &format!("Too many requests, try again in {} seconds.", retry_after),
+        )
+    }
+
+    pub fn unavailable(message: &str) -> Self {
+        Self::new(
+            "Unavailable",
+            message,
+        )
+    }
+
+    pub fn invalid_input(message: &str) -> Self {
+        Self::new(
+            "InvalidInput",
+            message,
+        )
+    }
+
+    pub fn outdated_schema(current: i32, latest: i32) -> Self {
+        Self::new(
+            "OutdatedSchema",
+            &format!("Database schema is at version {} but version {} is required.", current, latest),
+        )
+    }
+
+    pub fn unversioned_schema() -> Self {
+        Self::new(
+            "UnversionedSchema",
+            "Database has a schema but no record of migrations; run `migrate baseline --to 1` first.",
+        )
+    }
+
+    pub fn unknown_migration(version: i32) -> Self {
+        Self::new(
+            "UnknownMigration",
+            &format!("Migration {} is not embedded in this server.", version),
+        )
+    }
+
+    pub fn invalid_picture(reason: &str) -> Self {
+        Self::new(
+            "InvalidPicture",
+            reason,
+        )
+    }
+
+    pub fn picture_too_large(limit: usize) -> Self {
+        Self::new(
+            "PictureTooLarge",
+            &format!("Picture may be at most {} bytes.", limit),
+        )
+    }
+
+    pub fn missing_upload(name: &str) -> Self {
+        Self::new(
+            "MissingUpload",
+            &format!(r#"No file was uploaded as "{}"."#, name),
+        )
+    }
+
+    pub fn error_type(&self) -> &str {
+        &self.r#type
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
     pub fn is_inner(&self) -> bool {
         self.inner.is_some()
     }
@@ -104,6 +231,9 @@ macro_rules! impl_from_for_error {
 
 impl_from_for_error!(r2d2::Error, R2d2);
 impl_from_for_error!(serde_json::error::Error, SerdeJson);
+impl_from_for_error!(std::io::Error, Io);
+impl_from_for_error!(image::ImageError, Image);
+impl_from_for_error!(argon2::Error, Argon2);
 
 impl From<postgres::error::Error> for InnerError {
     fn from(err: postgres::error::Error) -> Self {
@@ -0,0 +1,308 @@
+use std::collections::BTreeMap;
+use uuid::Uuid;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use crate::{
+    graphql::{
+        context::Context,
+        hours::local,
+    },
+    error::Error,
+};
+
+// How many products and selections are ranked in each bucket.
+pub const TOP_SALES: usize = 5;
+
+// How many buckets one summary may span, a year by day.
+pub const MAX_BUCKETS: usize = 366;
+
+#[derive(juniper::GraphQLEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Granularity {
+    Hour,
+    Day,
+    Week,
+    Month,
+}
+
+impl Granularity {
+    // The field name accepted by Postgres `date_trunc`.
+    pub fn as_sql(&self) -> &'static str {
+        match self {
+            Granularity::Hour => "hour",
+            Granularity::Day => "day",
+            Granularity::Week => "week",
+            Granularity::Month => "month",
+        }
+    }
+
+    // Truncate a timestamp to the start of its bucket in `timezone`, as
+    // `date_trunc` does on the local time.
+    pub fn truncate(&self, at: DateTime<Utc>, timezone: Tz) -> DateTime<Utc> {
+        let local_at = at.with_timezone(&timezone).naive_local();
+        let date = local_at.date();
+        let start = match self {
+            // Counted back from the instant, as an hour can repeat when
+            // clocks go back.
+            Granularity::Hour => {
+                return Utc.timestamp_opt(at.timestamp() - (local_at.minute() * 60 + local_at.second()) as i64, 0).unwrap();
+            }
+            Granularity::Day => date,
+            Granularity::Week => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+            Granularity::Month => NaiveDate::from_ymd_opt(date.year(), date.month(), 1).unwrap(),
+        };
+        local(timezone, start, NaiveTime::from_hms_opt(0, 0, 0).unwrap())
+    }
+
+    // The start of the bucket after the one starting at `start`.
+    fn next(&self, start: DateTime<Utc>, timezone: Tz) -> DateTime<Utc> {
+        let date = start.with_timezone(&timezone).date_naive();
+        let next = match self {
+            Granularity::Hour => return start + Duration::hours(1),
+            Granularity::Day => date + Duration::days(1),
+            Granularity::Week => date + Duration::days(7),
+            Granularity::Month => {
+                let (year, month) = if date.month() == 12 { (date.year() + 1, 1) } else { (date.year(), date.month() + 1) };
+                NaiveDate::from_ymd_opt(year, month, 1).unwrap()
+            }
+        };
+        local(timezone, next, NaiveTime::from_hms_opt(0, 0, 0).unwrap())
+    }
+
+    // The start of every bucket `[from, to)` touches, failing past
+    // `MAX_BUCKETS`.
+    pub fn starts(&self, from: DateTime<Utc>, to: DateTime<Utc>, timezone: Tz) -> Result<Vec<DateTime<Utc>>, Error> {
+        if from >= to {
+            return Err(Error::invalid_input(r#""to" must be after "from"."#));
+        }
+        let mut starts = vec![self.truncate(from, timezone)];
+        loop {
+            let next = self.next(*starts.last().unwrap(), timezone);
+            if next >= to {
+                return Ok(starts);
+            }
+            if starts.len() == MAX_BUCKETS {
+                return Err(Error::invalid_input(&format!("A summary spans at most {} buckets.", MAX_BUCKETS)));
+            }
+            starts.push(next);
+        }
+    }
+}
+
+// A bucket for each of `starts`, zeroed where there were no sales.
+pub fn fill(starts: Vec<DateTime<Utc>>, buckets: Vec<SalesBucket>) -> Vec<SalesBucket> {
+    let mut buckets: BTreeMap<DateTime<Utc>, SalesBucket> = buckets.into_iter().map(|bucket| (bucket.start, bucket)).collect();
+    starts.into_iter()
+        .map(|start| buckets.remove(&start).unwrap_or_else(|| SalesBucket::new(start, 0, 0, 0)))
+        .collect()
+}
+
+pub struct SalesBucket {
+    start: DateTime<Utc>,
+    revenue: i32,
+    order_count: i32,
+    item_count: i32,
+    top_products: Vec<ProductSales>,
+    top_selections: Vec<SelectionSales>,
+}
+
+impl SalesBucket {
+    pub fn new(start: DateTime<Utc>, revenue: i32, order_count: i32, item_count: i32) -> Self {
+        SalesBucket {
+            start: start,
+            revenue: revenue,
+            order_count: order_count,
+            item_count: item_count,
+            top_products: Vec::new(),
+            top_selections: Vec::new(),
+        }
+    }
+
+    pub fn push_product(&mut self, product: ProductSales) {
+        self.top_products.push(product);
+    }
+
+    pub fn push_selection(&mut self, selection: SelectionSales) {
+        self.top_selections.push(selection);
+    }
+}
+
+#[juniper::graphql_object(Context = Context)]
+impl SalesBucket {
+    fn start(&self) -> DateTime<Utc> {
+        self.start
+    }
+
+    #[graphql(description = "Gross sales: what the items came to before promotions, service charge and tax.")]
+    fn revenue(&self) -> i32 {
+        self.revenue
+    }
+
+    fn order_count(&self) -> i32 {
+        self.order_count
+    }
+
+    fn item_count(&self) -> i32 {
+        self.item_count
+    }
+
+    #[graphql(description = "Gross sales per order.")]
+    fn average_ticket(&self) -> f64 {
+        if self.order_count == 0 {
+            0.0
+        } else {
+            self.revenue as f64 / self.order_count as f64
+        }
+    }
+
+    fn top_products(&self) -> &Vec<ProductSales> {
+        &self.top_products
+    }
+
+    fn top_selections(&self) -> &Vec<SelectionSales> {
+        &self.top_selections
+    }
+}
+
+pub struct ProductSales {
+    product_key: Uuid,
+    name: String,
+    count: i32,
+    revenue: i32,
+}
+
+impl ProductSales {
+    pub fn new(product_key: Uuid, name: String, count: i32, revenue: i32) -> Self {
+        ProductSales {
+            product_key: product_key,
+            name: name,
+            count: count,
+            revenue: revenue,
+        }
+    }
+}
+
+#[juniper::graphql_object(Context = Context)]
+impl ProductSales {
+    fn product_key(&self) -> Uuid {
+        self.product_key
+    }
+
+    fn name(&self) -> &String {
+        &self.name
+    }
+
+    fn count(&self) -> i32 {
+        self.count
+    }
+
+    #[graphql(description = "Gross sales of the product, before promotions, service charge and tax.")]
+    fn revenue(&self) -> i32 {
+        self.revenue
+    }
+}
+
+pub struct SelectionSales {
+    customize_key: Uuid,
+    selection_key: Uuid,
+    customize_name: String,
+    selection: Option<String>,
+    count: i32,
+}
+
+impl SelectionSales {
+    pub fn new(customize_key: Uuid, selection_key: Uuid, customize_name: String, selection: Option<String>, count: i32) -> Self {
+        SelectionSales {
+            customize_key: customize_key,
+            selection_key: selection_key,
+            customize_name: customize_name,
+            selection: selection,
+            count: count,
+        }
+    }
+}
+
+#[juniper::graphql_object(Context = Context)]
+impl SelectionSales {
+    fn customize_key(&self) -> Uuid {
+        self.customize_key
+    }
+
+    fn selection_key(&self) -> Uuid {
+        self.selection_key
+    }
+
+    fn customize_name(&self) -> &String {
+        &self.customize_name
+    }
+
+    fn selection(&self) -> &Option<String> {
+        &self.selection
+    }
+
+    fn count(&self) -> i32 {
+        self.count
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, TimeZone, Utc};
+    use chrono_tz::Tz;
+    use super::{Granularity, SalesBucket, MAX_BUCKETS, fill};
+
+    #[test]
+    fn test_truncate_in_timezone() {
+        let taipei = "Asia/Taipei".parse().unwrap();
+        // 2024-03-31T20:30 in Taipei, the last day of the month there.
+        let at = Utc.with_ymd_and_hms(2024, 3, 31, 12, 30, 0).unwrap();
+        assert_eq!(Granularity::Hour.truncate(at, taipei), Utc.with_ymd_and_hms(2024, 3, 31, 12, 0, 0).unwrap());
+        assert_eq!(Granularity::Day.truncate(at, taipei), Utc.with_ymd_and_hms(2024, 3, 30, 16, 0, 0).unwrap());
+        assert_eq!(Granularity::Week.truncate(at, taipei), Utc.with_ymd_and_hms(2024, 3, 24, 16, 0, 0).unwrap());
+        assert_eq!(Granularity::Month.truncate(at, taipei), Utc.with_ymd_and_hms(2024, 2, 29, 16, 0, 0).unwrap());
+
+        let kolkata = "Asia/Kolkata".parse().unwrap();
+        assert_eq!(Granularity::Hour.truncate(at, kolkata), Utc.with_ymd_and_hms(2024, 3, 31, 12, 30, 0).unwrap());
+    }
+
+    #[test]
+    fn test_starts() {
+        let taipei = "Asia/Taipei".parse().unwrap();
+        let from = Utc.with_ymd_and_hms(2024, 1, 30, 0, 0, 0).unwrap();
+        let to = Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap();
+        assert_eq!(
+            Granularity::Month.starts(from, to, taipei).unwrap(),
+            vec![
+                Utc.with_ymd_and_hms(2023, 12, 31, 16, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2024, 1, 31, 16, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2024, 2, 29, 16, 0, 0).unwrap(),
+            ],
+        );
+
+        // Days in London are 23 hours long when clocks go forward.
+        let london = "Europe/London".parse().unwrap();
+        let from = Utc.with_ymd_and_hms(2024, 3, 30, 12, 0, 0).unwrap();
+        let to = Utc.with_ymd_and_hms(2024, 4, 1, 0, 0, 0).unwrap();
+        assert_eq!(
+            Granularity::Day.starts(from, to, london).unwrap(),
+            vec![
+                Utc.with_ymd_and_hms(2024, 3, 30, 0, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2024, 3, 31, 0, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2024, 3, 31, 23, 0, 0).unwrap(),
+            ],
+        );
+
+        assert!(Granularity::Day.starts(to, from, london).is_err());
+        let to = from + Duration::days(MAX_BUCKETS as i64 - 1);
+        assert_eq!(Granularity::Day.starts(from, to, Tz::UTC).unwrap().len(), MAX_BUCKETS);
+        assert!(Granularity::Day.starts(from, to + Duration::days(1), Tz::UTC).is_err());
+    }
+
+    #[test]
+    fn test_fill() {
+        let day = |d| Utc.with_ymd_and_hms(2024, 1, d, 0, 0, 0).unwrap();
+        let buckets = fill(vec![day(1), day(2), day(3)], vec![SalesBucket::new(day(2), 100, 1, 2)]);
+        let summary: Vec<_> = buckets.iter().map(|bucket| (bucket.start, bucket.revenue, bucket.order_count)).collect();
+        assert_eq!(summary, vec![(day(1), 0, 0), (day(2), 100, 1), (day(3), 0, 0)]);
+    }
+}
@@ -0,0 +1,247 @@
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use crate::{
+    sql::Permission,
+    graphql::{
+        context::Context,
+        user::Authority,
+        role::Capabilities,
+    },
+    error::Error,
+};
+
+// Tokens start with this, so leaked ones are easy to search for.
+pub const TOKEN_PREFIX: &str = "pk_";
+pub const MAX_NAME_LENGTH: usize = 64;
+
+// A token a user issued to act as them. It can do no more than `authority`,
+// nor than the user's roles let them at the time, and one with a shop works
+// for that shop alone.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApiToken {
+    id: Uuid,
+    user_id: Uuid,
+    shop_id: Option<Uuid>,
+    name: String,
+    authority: Authority,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl ApiToken {
+    pub fn new(
+        id: Uuid,
+        user_id: Uuid,
+        shop_id: Option<Uuid>,
+        name: String,
+        authority: Authority,
+        created_at: DateTime<Utc>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Self {
+        ApiToken {
+            id: id,
+            user_id: user_id,
+            shop_id: shop_id,
+            name: name,
+            authority: authority,
+            created_at: created_at,
+            expires_at: expires_at,
+        }
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn user_id(&self) -> Uuid {
+        self.user_id
+    }
+
+    pub fn is_expired_at(&self, at: DateTime<Utc>) -> bool {
+        self.expires_at.map_or(false, |expires_at| expires_at <= at)
+    }
+
+    // What the token may do in a shop where its user's role has
+    // `capabilities`, `None` when the token is for another shop.
+    pub fn limit(&self, shop_id: Uuid, capabilities: &Capabilities) -> Option<Capabilities> {
+        if self.shop_id.map_or(true, |id| id == shop_id) {
+            Some(capabilities.intersection(&Capabilities::from_authority(&self.authority)))
+        } else {
+            None
+        }
+    }
+}
+
+#[juniper::graphql_object(Context = Context)]
+impl ApiToken {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn name(&self) -> &String {
+        &self.name
+    }
+
+    // Null for tokens that work in every shop of the user.
+    fn shop_id(&self) -> Option<Uuid> {
+        self.shop_id
+    }
+
+    fn authority(&self) -> &Authority {
+        &self.authority
+    }
+
+    fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+
+    fn expires_at(&self) -> Option<DateTime<Utc>> {
+        self.expires_at
+    }
+}
+
+// A token as it is created, the only time its secret is shown.
+pub struct CreatedApiToken {
+    token: String,
+    api_token: ApiToken,
+}
+
+impl CreatedApiToken {
+    pub fn new(token: String, api_token: ApiToken) -> Self {
+        CreatedApiToken {
+            token: token,
+            api_token: api_token,
+        }
+    }
+}
+
+#[juniper::graphql_object(Context = Context)]
+impl CreatedApiToken {
+    // Sent as `Authorization: Bearer <token>`.
+    fn token(&self) -> &String {
+        &self.token
+    }
+
+    fn api_token(&self) -> &ApiToken {
+        &self.api_token
+    }
+}
+
+#[derive(juniper::GraphQLInputObject)]
+pub struct ApiTokenInput {
+    pub name: String,
+    // Limits the token to one shop of the user.
+    pub shop_id: Option<Uuid>,
+    // Each defaults to none, so tokens only get what they are asked for, and
+    // can't be more than the user has for a token with a shop.
+    pub member_authority: Option<Permission>,
+    pub order_authority: Option<Permission>,
+    pub product_authority: Option<Permission>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl ApiTokenInput {
+    // The name and the token's authority, given the user's authority in its
+    // shop, or `None` for a token without one.
+    pub fn checked(&self, user_authority: Option<Authority>, now: DateTime<Utc>) -> Result<(String, Authority), Error> {
+        let name = self.name.trim().to_string();
+        if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+            return Err(Error::invalid_input(&format!("Token name must be 1 to {} characters.", MAX_NAME_LENGTH)));
+        }
+        if self.expires_at.map_or(false, |expires_at| expires_at <= now) {
+            return Err(Error::invalid_input("Token must expire in the future."));
+        }
+
+        let most = user_authority.unwrap_or(Authority::new(Permission::All, Permission::All, Permission::All));
+        let authority = Authority::new(
+            self.member_authority.unwrap_or(Permission::None),
+            self.order_authority.unwrap_or(Permission::None),
+            self.product_authority.unwrap_or(Permission::None),
+        );
+        if authority.capped(&most) != authority {
+            return Err(Error::unauthorized());
+        }
+        Ok((name, authority))
+    }
+}
+
+// A new secret: two random UUIDs, 244 random bits in all.
+pub fn generate() -> String {
+    let mut bytes = Vec::with_capacity(32);
+    bytes.extend_from_slice(Uuid::new_v4().as_bytes());
+    bytes.extend_from_slice(Uuid::new_v4().as_bytes());
+    format!("{}{}", TOKEN_PREFIX, base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD))
+}
+
+// What is stored in place of the secret. Secrets are random enough that a
+// fast, unsalted hash is safe, and lets tokens be looked up by it.
+pub fn hash(token: &str) -> String {
+    Sha256::digest(token.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+    use uuid::Uuid;
+    use crate::{
+        sql::Permission,
+        graphql::{
+            user::Authority,
+            role::{Capability, Capabilities},
+        },
+    };
+    use super::{ApiToken, ApiTokenInput, generate, hash, TOKEN_PREFIX};
+
+    #[test]
+    fn test_generate() {
+        let token = generate();
+        assert!(token.starts_with(TOKEN_PREFIX));
+        assert_ne!(token, generate());
+        assert_eq!(hash(&token).len(), 64);
+        assert_eq!(hash(&token), hash(&token));
+        assert_ne!(hash(&token), hash(&generate()));
+    }
+
+    #[test]
+    fn test_limit() {
+        let shop_id = Uuid::new_v4();
+        let read_only = Authority::new(Permission::ReadOnly, Permission::ReadOnly, Permission::ReadOnly);
+        let token = ApiToken::new(Uuid::new_v4(), Uuid::new_v4(), Some(shop_id), "ci".to_string(), read_only, Utc::now(), None);
+
+        let role = Capabilities::new(vec![Capability::ManageMembers, Capability::ViewMembers, Capability::ViewPromotions, Capability::ManageStock]);
+        assert_eq!(
+            token.limit(shop_id, &role),
+            Some(Capabilities::new(vec![Capability::ViewMembers, Capability::ViewPromotions])),
+        );
+        assert_eq!(token.limit(Uuid::new_v4(), &role), None);
+
+        assert!(!token.is_expired_at(Utc::now()));
+        let expiring = ApiToken { expires_at: Some(Utc::now()), ..token };
+        assert!(expiring.is_expired_at(Utc::now() + Duration::seconds(1)));
+    }
+
+    #[test]
+    fn test_checked() {
+        let input = |order_authority| ApiTokenInput {
+            name: " ci ".to_string(),
+            shop_id: None,
+            member_authority: None,
+            order_authority: order_authority,
+            product_authority: None,
+            expires_at: None,
+        };
+        let staff = Authority::new(Permission::ReadOnly, Permission::None, Permission::ReadOnly);
+
+        // Only what is asked for.
+        assert_eq!(
+            input(Some(Permission::ReadOnly)).checked(None, Utc::now()).unwrap(),
+            ("ci".to_string(), Authority::new(Permission::None, Permission::ReadOnly, Permission::None)),
+        );
+        assert_eq!(
+            input(None).checked(Some(staff), Utc::now()).unwrap().1,
+            Authority::new(Permission::None, Permission::None, Permission::None),
+        );
+        assert!(input(Some(Permission::ReadOnly)).checked(Some(staff), Utc::now()).is_err());
+    }
+}
@@ -0,0 +1,272 @@
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use crate::{
+    graphql::{
+        context::Context,
+        user::User,
+    },
+    repository::NewAuditEntry,
+    state::State,
+    error::Error,
+};
+
+pub const DEFAULT_AUDIT_LIMIT: i32 = 50;
+pub const MAX_AUDIT_LIMIT: i32 = 200;
+
+// What an audit entry is about. Changes to a shop's settings, such as its
+// hours, currency or taxes, are about the shop itself; giving a member
+// another role is about the member, by their user id.
+#[derive(juniper::GraphQLEnum, Clone, Copy, Debug, PartialEq)]
+pub enum AuditEntity {
+    Shop,
+    Product,
+    Series,
+    Promotion,
+    Role,
+    Member,
+}
+
+impl AuditEntity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditEntity::Shop => "shop",
+            AuditEntity::Product => "product",
+            AuditEntity::Series => "series",
+            AuditEntity::Promotion => "promotion",
+            AuditEntity::Role => "role",
+            AuditEntity::Member => "member",
+        }
+    }
+
+    // How errors about the entity name it.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AuditEntity::Shop => "Shop",
+            AuditEntity::Product => "Product",
+            AuditEntity::Series => "Series",
+            AuditEntity::Promotion => "Promotion",
+            AuditEntity::Role => "Role",
+            AuditEntity::Member => "Member",
+        }
+    }
+
+    pub fn parse(entity: &str) -> Option<Self> {
+        match entity {
+            "shop" => Some(AuditEntity::Shop),
+            "product" => Some(AuditEntity::Product),
+            "series" => Some(AuditEntity::Series),
+            "promotion" => Some(AuditEntity::Promotion),
+            "role" => Some(AuditEntity::Role),
+            "member" => Some(AuditEntity::Member),
+            _ => None,
+        }
+    }
+}
+
+#[derive(juniper::GraphQLEnum, Clone, Copy, Debug, PartialEq)]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Create => "create",
+            AuditAction::Update => "update",
+            AuditAction::Delete => "delete",
+        }
+    }
+
+    pub fn parse(action: &str) -> Option<Self> {
+        match action {
+            "create" => Some(AuditAction::Create),
+            "update" => Some(AuditAction::Update),
+            "delete" => Some(AuditAction::Delete),
+            _ => None,
+        }
+    }
+}
+
+// Makes a change to the shop and records it for the request's user, in one
+// transaction so the entry is there exactly when the change is. The entity is
+// snapshotted before and after the change; `change` returns its id, and the
+// action follows from which snapshots exist.
+pub fn audited<S, F>(
+    context: &Context,
+    shop_id: Uuid,
+    entity: AuditEntity,
+    entity_id: Option<Uuid>,
+    snapshot: S,
+    change: F,
+) -> Result<Uuid, Error>
+where
+    S: Fn(&State, Uuid, Uuid) -> Result<Option<Value>, Error>,
+    F: FnOnce(&State) -> Result<Uuid, Error>,
+{
+    let actor_id = context.user_id()?;
+    context.state().transaction(|state| {
+        let before = match entity_id {
+            Some(entity_id) => Some(snapshot(state, shop_id, entity_id)?.ok_or_else(|| Error::not_found(entity.name()))?),
+            None => None,
+        };
+        let entity_id = change(state)?;
+        let after = snapshot(state, shop_id, entity_id)?;
+        let action = match (&before, &after) {
+            (None, _) => AuditAction::Create,
+            (_, None) => AuditAction::Delete,
+            _ => AuditAction::Update,
+        };
+
+        let entry = NewAuditEntry {
+            actor_id: actor_id,
+            entity: entity,
+            entity_id: entity_id,
+            action: action,
+            before: before,
+            after: after,
+        };
+        state.audit_log().record(shop_id, entry)?;
+        Ok(entity_id)
+    })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditEntry {
+    id: Uuid,
+    shop_id: Uuid,
+    actor_id: Uuid,
+    entity: AuditEntity,
+    entity_id: Uuid,
+    action: AuditAction,
+    before: Option<Value>,
+    after: Option<Value>,
+    created_at: DateTime<Utc>,
+}
+
+impl AuditEntry {
+    pub fn new(id: Uuid, shop_id: Uuid, entry: NewAuditEntry, created_at: DateTime<Utc>) -> Self {
+        AuditEntry {
+            id: id,
+            shop_id: shop_id,
+            actor_id: entry.actor_id,
+            entity: entry.entity,
+            entity_id: entry.entity_id,
+            action: entry.action,
+            before: entry.before,
+            after: entry.after,
+            created_at: created_at,
+        }
+    }
+
+    pub fn shop_id(&self) -> Uuid {
+        self.shop_id
+    }
+
+    pub fn matches(&self, filter: &AuditLogFilter) -> bool {
+        filter.entity.map_or(true, |entity| self.entity == entity)
+            && filter.entity_id.map_or(true, |id| self.entity_id == id)
+            && filter.actor_id.map_or(true, |id| self.actor_id == id)
+            && filter.action.map_or(true, |action| self.action == action)
+            && filter.from.map_or(true, |from| self.created_at >= from)
+            && filter.to.map_or(true, |to| self.created_at < to)
+    }
+}
+
+#[juniper::graphql_object(Context = Context)]
+impl AuditEntry {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn actor_id(&self) -> Uuid {
+        self.actor_id
+    }
+
+    // Null once the user no longer exists.
+    fn actor(&self, context: &Context) -> Result<Option<User>, Error> {
+        match context.state().users().user(self.actor_id) {
+            Ok(user) => Ok(Some(user)),
+            Err(ref err) if err.error_type() == "NotFound" => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn entity(&self) -> AuditEntity {
+        self.entity
+    }
+
+    fn entity_id(&self) -> Uuid {
+        self.entity_id
+    }
+
+    fn action(&self) -> AuditAction {
+        self.action
+    }
+
+    // The entity as JSON before the change, null when it was created.
+    fn before(&self) -> Result<Option<String>, Error> {
+        Ok(self.before.as_ref().map(serde_json::to_string).transpose()?)
+    }
+
+    // The entity as JSON after the change, null when it was deleted.
+    fn after(&self) -> Result<Option<String>, Error> {
+        Ok(self.after.as_ref().map(serde_json::to_string).transpose()?)
+    }
+
+    fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+}
+
+// Entries match every filter given. `from` is inclusive and `to` exclusive.
+#[derive(juniper::GraphQLInputObject, Default)]
+pub struct AuditLogFilter {
+    pub entity: Option<AuditEntity>,
+    pub entity_id: Option<Uuid>,
+    pub actor_id: Option<Uuid>,
+    pub action: Option<AuditAction>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+pub struct AuditLogPage {
+    entries: Vec<AuditEntry>,
+    total_count: i32,
+}
+
+impl AuditLogPage {
+    pub fn new(entries: Vec<AuditEntry>, total_count: i32) -> Self {
+        AuditLogPage {
+            entries: entries,
+            total_count: total_count,
+        }
+    }
+}
+
+#[juniper::graphql_object(Context = Context)]
+impl AuditLogPage {
+    // Newest first.
+    fn entries(&self) -> &Vec<AuditEntry> {
+        &self.entries
+    }
+
+    // Entries matching the filter across all pages.
+    fn total_count(&self) -> i32 {
+        self.total_count
+    }
+}
+
+pub fn checked_page(offset: Option<i32>, limit: Option<i32>) -> Result<(i32, i32), Error> {
+    let offset = offset.unwrap_or(0);
+    if offset < 0 {
+        return Err(Error::invalid_input("Offset must not be negative."));
+    }
+    let limit = limit.unwrap_or(DEFAULT_AUDIT_LIMIT);
+    if limit < 1 || limit > MAX_AUDIT_LIMIT {
+        return Err(Error::invalid_input(&format!("Limit must be between 1 and {}.", MAX_AUDIT_LIMIT)));
+    }
+    Ok((offset, limit))
+}
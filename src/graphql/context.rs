@@ -1,13 +1,46 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+};
+use chrono::Utc;
 use uuid::Uuid;
 use crate::{
-    state::State,
+    state::{
+        State,
+        requests::RequestGuard,
+    },
+    graphql::{
+        api_token::{self, ApiToken},
+        role::{Capability, Capabilities},
+        locale::Locale,
+        money::Currency,
+    },
+    rate_limit::Client,
     error::Error
 };
 
+// A file sent along a multipart GraphQL request.
+pub struct Upload {
+    pub bytes: Vec<u8>,
+}
+
 pub struct Context {
     state: State,
     user_session_id: Option<Uuid>,
     guest_session_id: Option<Uuid>,
+    bearer_token: Option<String>,
+    // The API token `bearer_token` resolves to, once looked up.
+    api_token: Mutex<Option<ApiToken>>,
+    uploads: HashMap<String, Upload>,
+    locales: Vec<Locale>,
+    currencies: Mutex<HashMap<Uuid, Currency>>,
+    capabilities: Mutex<HashMap<Uuid, Capabilities>>,
+    remote_addr: Option<IpAddr>,
+    // Who operations are charged to, once the credentials are checked.
+    client: Mutex<Option<Client>>,
+    retry_after: Mutex<Option<u64>>,
+    _request: RequestGuard,
 }
 
 impl Context {
@@ -16,13 +49,48 @@ impl Context {
         user_session_id: Option<Uuid>,
         guest_session_id: Option<Uuid>,
     ) -> Self {
+        let request = state.requests().start();
         Context {
             state: state,
             user_session_id: user_session_id,
             guest_session_id: guest_session_id,
+            bearer_token: None,
+            api_token: Mutex::new(None),
+            uploads: HashMap::new(),
+            locales: Vec::new(),
+            currencies: Mutex::new(HashMap::new()),
+            capabilities: Mutex::new(HashMap::new()),
+            remote_addr: None,
+            client: Mutex::new(None),
+            retry_after: Mutex::new(None),
+            _request: request,
         }
     }
 
+    pub fn with_uploads(mut self, uploads: HashMap<String, Upload>) -> Self {
+        self.uploads = uploads;
+        self
+    }
+
+    // Preferred locales of the request, most preferred first.
+    pub fn with_locales(mut self, locales: Vec<Locale>) -> Self {
+        self.locales = locales;
+        self
+    }
+
+    // The secret from an `Authorization: Bearer` header, which stands in for
+    // the user session.
+    pub fn with_bearer_token(mut self, bearer_token: Option<String>) -> Self {
+        self.bearer_token = bearer_token;
+        self
+    }
+
+    // The address the request came from, past any trusted proxies.
+    pub fn with_remote_addr(mut self, remote_addr: Option<IpAddr>) -> Self {
+        self.remote_addr = remote_addr;
+        self
+    }
+
     pub fn state(&self) -> &State {
         &self.state
     }
@@ -42,6 +110,142 @@ impl Context {
             Err(Error::no_valid_cookie("GSSID"))
         }
     }
+
+    // Locales to show text in: `locale` when a field asks for one, otherwise
+    // the request's preferred locales.
+    pub fn locales(&self, locale: Option<String>) -> Result<Vec<Locale>, Error> {
+        match locale {
+            Some(tag) => Ok(vec![Locale::parse(&tag)?]),
+            None => Ok(self.locales.clone()),
+        }
+    }
+
+    // The currency a shop prices in, looked up once per request since every
+    // money field of the shop's products, carts and orders needs it.
+    pub fn currency(&self, shop_id: Uuid) -> Result<Currency, Error> {
+        if let Some(currency) = self.currencies.lock().unwrap().get(&shop_id) {
+            return Ok(currency.clone());
+        }
+        let currency = self.state.shops().currency(shop_id)?;
+        self.currencies.lock().unwrap().insert(shop_id, currency.clone());
+        Ok(currency)
+    }
+
+    // For when the shop's currency changes during the request.
+    pub fn forget_currency(&self, shop_id: Uuid) {
+        self.currencies.lock().unwrap().remove(&shop_id);
+    }
+
+    pub fn upload(&self, name: &str) -> Result<&Upload, Error> {
+        self.uploads.get(name).ok_or_else(|| Error::missing_upload(name))
+    }
+
+    // The API token the request was sent with, failing with `InvalidToken`
+    // when it is unknown or expired.
+    pub fn api_token(&self) -> Result<Option<ApiToken>, Error> {
+        let secret = match self.bearer_token.as_ref() {
+            Some(secret) => secret,
+            None => return Ok(None),
+        };
+        if let Some(token) = self.api_token.lock().unwrap().as_ref() {
+            return Ok(Some(token.clone()));
+        }
+
+        let token = self.state.api_tokens().find_api_token(&api_token::hash(secret))?
+            .filter(|token| !token.is_expired_at(Utc::now()))
+            .ok_or_else(Error::invalid_token)?;
+        *self.api_token.lock().unwrap() = Some(token.clone());
+        Ok(Some(token))
+    }
+
+    // The user signed in with the request's user session, or who issued its
+    // API token.
+    pub fn user_id(&self) -> Result<Uuid, Error> {
+        match self.api_token()? {
+            Some(token) => Ok(token.user_id()),
+            None => self.state.sessions().session_user(self.user_session_id()?),
+        }
+    }
+
+    // What the user's role lets the request do in a shop, `Unauthorized`
+    // unless they are a member. Looked up once per request, as each guarded
+    // field of the shop needs it and members can't change their own role.
+    pub fn shop_capabilities(&self, shop_id: Uuid) -> Result<Capabilities, Error> {
+        if let Some(capabilities) = self.capabilities.lock().unwrap().get(&shop_id) {
+            return Ok(capabilities.clone());
+        }
+        let role = self.state.shops().user_shops(self.user_id()?, Some(shop_id), None)?
+            .into_iter()
+            .next()
+            .map(|(_, role)| role)
+            .ok_or_else(Error::unauthorized)?;
+        let capabilities = self.limit_capabilities(shop_id, role.capabilities())?.ok_or_else(Error::unauthorized)?;
+        self.capabilities.lock().unwrap().insert(shop_id, capabilities.clone());
+        Ok(capabilities)
+    }
+
+    // `Unauthorized` unless the request may do `capability` in the shop.
+    pub fn require_capability(&self, shop_id: Uuid, capability: Capability) -> Result<(), Error> {
+        if self.shop_capabilities(shop_id)?.contains(capability) {
+            Ok(())
+        } else {
+            Err(Error::unauthorized())
+        }
+    }
+
+    // What the request may do in a shop where the user's role has
+    // `capabilities`: all of them with a user session, no more than the API
+    // token allows with one. `None` for shops other than the token's.
+    pub fn limit_capabilities(&self, shop_id: Uuid, capabilities: &Capabilities) -> Result<Option<Capabilities>, Error> {
+        match self.api_token()? {
+            Some(token) => Ok(token.limit(shop_id, capabilities)),
+            None => Ok(Some(capabilities.clone())),
+        }
+    }
+
+    // Charges the request to `operation`'s own rate limit, for fields costly
+    // enough to have one. Once over it, the response is a 429.
+    pub fn throttle(&self, operation: &str) -> Result<(), Error> {
+        match self.state.rate_limiter().check_operation(&self.client(), operation) {
+            Some(retry_after) => {
+                *self.retry_after.lock().unwrap() = Some(retry_after);
+                Err(Error::rate_limited(retry_after))
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Who the request is charged to: the API token or the user session when
+    // it is valid, then the guest session, then the address. Credentials
+    // that don't check out count as none, so made up ones can't dodge the
+    // address's budgets.
+    pub fn client(&self) -> Client {
+        if let Some(client) = self.client.lock().unwrap().as_ref() {
+            return client.clone();
+        }
+        let client = match (self.bearer_token.as_ref(), self.api_token()) {
+            (Some(secret), Ok(Some(_))) => Some(Client::Token(api_token::hash(secret))),
+            _ => None,
+        }
+        .or_else(|| {
+            self.user_session_id
+                .filter(|&id| self.state.sessions().session_user(id).is_ok())
+                .map(Client::User)
+        })
+        .or_else(|| {
+            self.guest_session_id
+                .filter(|&id| self.state.sessions().is_guest_session_valid(id).unwrap_or(false))
+                .map(Client::Guest)
+        })
+        .unwrap_or_else(|| self.remote_addr.map_or(Client::Unknown, Client::Address));
+        *self.client.lock().unwrap() = Some(client.clone());
+        client
+    }
+
+    // Seconds to wait before retrying, when a field was throttled.
+    pub fn retry_after(&self) -> Option<u64> {
+        *self.retry_after.lock().unwrap()
+    }
 }
 
 impl juniper::Context for Context {}
\ No newline at end of file
@@ -0,0 +1,176 @@
+use juniper::IntrospectionFormat;
+use serde_json::Value;
+use crate::{
+    graphql::{
+        context::Context,
+        schema,
+    },
+    error::Error,
+};
+
+const BUILTIN_SCALARS: [&str; 5] = ["String", "Int", "Float", "Boolean", "ID"];
+
+pub fn introspection(context: &Context) -> Result<Value, Error> {
+    let (result, errors) = juniper::introspect(&schema(), context, IntrospectionFormat::default())
+        .map_err(|err| Error::new("IntrospectionFailed", &format!("{:?}", err)))?;
+
+    if !errors.is_empty() {
+        return Err(Error::new("IntrospectionFailed", &format!("{:?}", errors)))
+    }
+
+    Ok(serde_json::to_value(&result)?)
+}
+
+pub fn introspection_json(context: &Context) -> Result<String, Error> {
+    Ok(serde_json::to_string_pretty(&introspection(context)?)?)
+}
+
+pub fn sdl(context: &Context) -> Result<String, Error> {
+    Ok(print_schema(&introspection(context)?["__schema"]))
+}
+
+fn print_schema(schema: &Value) -> String {
+    let mut blocks = Vec::new();
+
+    let mut roots = Vec::new();
+    for (operation, key) in &[("query", "queryType"), ("mutation", "mutationType"), ("subscription", "subscriptionType")] {
+        if let Some(name) = schema[key]["name"].as_str() {
+            roots.push(format!("  {}: {}", operation, name));
+        }
+    }
+    blocks.push(format!("schema {{\n{}\n}}", roots.join("\n")));
+
+    let mut types: Vec<&Value> = schema["types"].as_array()
+        .map(|types| types.iter().collect())
+        .unwrap_or_default();
+    types.sort_by_key(|t| t["name"].as_str().unwrap_or("").to_string());
+
+    for t in types {
+        let name = t["name"].as_str().unwrap_or("");
+        if name.starts_with("__") || BUILTIN_SCALARS.contains(&name) {
+            continue;
+        }
+
+        let body = match t["kind"].as_str().unwrap_or("") {
+            "SCALAR" => format!("scalar {}", name),
+            "OBJECT" => {
+                let interfaces = names(&t["interfaces"]);
+                let implements = if interfaces.is_empty() {
+                    String::new()
+                } else {
+                    format!(" implements {}", interfaces.join(" & "))
+                };
+                format!("type {}{} {{\n{}}}", name, implements, print_fields(&t["fields"]))
+            }
+            "INTERFACE" => format!("interface {} {{\n{}}}", name, print_fields(&t["fields"])),
+            "UNION" => format!("union {} = {}", name, names(&t["possibleTypes"]).join(" | ")),
+            "ENUM" => {
+                let mut values = String::new();
+                for value in t["enumValues"].as_array().into_iter().flatten() {
+                    values.push_str(&print_description(&value["description"], "  "));
+                    values.push_str(&format!("  {}{}\n", value["name"].as_str().unwrap_or(""), print_deprecated(value)));
+                }
+                format!("enum {} {{\n{}}}", name, values)
+            }
+            "INPUT_OBJECT" => {
+                let mut fields = String::new();
+                for field in t["inputFields"].as_array().into_iter().flatten() {
+                    fields.push_str(&print_description(&field["description"], "  "));
+                    fields.push_str(&format!("  {}\n", print_input_value(field)));
+                }
+                format!("input {} {{\n{}}}", name, fields)
+            }
+            _ => continue,
+        };
+
+        blocks.push(format!("{}{}", print_description(&t["description"], ""), body));
+    }
+
+    let mut sdl = blocks.join("\n\n");
+    sdl.push('\n');
+    sdl
+}
+
+fn names(types: &Value) -> Vec<String> {
+    types.as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|t| t["name"].as_str().map(|name| name.to_string()))
+        .collect()
+}
+
+fn print_fields(fields: &Value) -> String {
+    let mut printed = String::new();
+    for field in fields.as_array().into_iter().flatten() {
+        let args: Vec<String> = field["args"].as_array()
+            .into_iter()
+            .flatten()
+            .map(print_input_value)
+            .collect();
+        let args = if args.is_empty() {
+            String::new()
+        } else {
+            format!("({})", args.join(", "))
+        };
+
+        printed.push_str(&print_description(&field["description"], "  "));
+        printed.push_str(&format!(
+            "  {}{}: {}{}\n",
+            field["name"].as_str().unwrap_or(""),
+            args,
+            print_type(&field["type"]),
+            print_deprecated(field),
+        ));
+    }
+    printed
+}
+
+fn print_input_value(value: &Value) -> String {
+    let mut printed = format!("{}: {}", value["name"].as_str().unwrap_or(""), print_type(&value["type"]));
+    if let Some(default) = value["defaultValue"].as_str() {
+        printed.push_str(&format!(" = {}", default));
+    }
+    printed
+}
+
+fn print_type(t: &Value) -> String {
+    match t["kind"].as_str().unwrap_or("") {
+        "NON_NULL" => format!("{}!", print_type(&t["ofType"])),
+        "LIST" => format!("[{}]", print_type(&t["ofType"])),
+        _ => t["name"].as_str().unwrap_or("").to_string(),
+    }
+}
+
+fn print_deprecated(value: &Value) -> String {
+    if !value["isDeprecated"].as_bool().unwrap_or(false) {
+        return String::new()
+    }
+    match value["deprecationReason"].as_str() {
+        Some(reason) => format!(" @deprecated(reason: {})", Value::from(reason)),
+        None => " @deprecated".to_string(),
+    }
+}
+
+// As a block string, in which only `"""` needs escaping.
+fn print_description(description: &Value, indent: &str) -> String {
+    match description.as_str() {
+        Some(description) if !description.is_empty() => {
+            let description = description.replace("\"\"\"", "\\\"\"\"");
+            format!("{}\"\"\"\n{}{}\n{}\"\"\"\n", indent, indent, description.replace("\n", &format!("\n{}", indent)), indent)
+        }
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use super::print_description;
+
+    #[test]
+    fn test_print_description() {
+        assert_eq!(print_description(&json!(null), "  "), "");
+        assert_eq!(print_description(&json!("Two\nlines"), "  "), "  \"\"\"\n  Two\n  lines\n  \"\"\"\n");
+        assert_eq!(print_description(&json!(r#"Says """hi""""#), ""), "\"\"\"\nSays \\\"\"\"hi\\\"\"\"\"\n\"\"\"\n");
+    }
+}
@@ -0,0 +1,424 @@
+use uuid::Uuid;
+use crate::{
+    graphql::{
+        context::Context,
+        guest::valid_guest_session,
+        role::Capability::{self, *},
+    },
+    error::Error,
+};
+
+// What a request needs to resolve a field.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Policy {
+    // Anyone, signed in or not.
+    Public,
+    // Nothing more than the field that returned the object.
+    Inherited,
+    // A valid guest session, for its own carts and orders.
+    Guest,
+    // A signed-in user, by session or API token.
+    User,
+    // A user session. API tokens can't, so a leaked one can't issue others.
+    Session,
+    // A member whose role, and API token if any, allow this in the shop.
+    Member(Capability),
+}
+
+use Policy::*;
+
+// Objects only returned by fields with a policy, whose own fields need
+// nothing more.
+const INHERITED: &[&str] = &[
+    "ApiToken",
+    "AppliedPromotion",
+    "AuditEntry",
+    "AuditLogPage",
+    "Authority",
+    "BuiltInRole",
+    "Cart",
+    "Coordinates",
+    "CreatedApiToken",
+    "Customize",
+    "CustomizeItem",
+    "Money",
+    "NearbyShop",
+    "OpeningException",
+    "Order",
+    "Product",
+    "ProductItem",
+    "ProductSales",
+    "Promotion",
+    "Role",
+    "SalesBucket",
+    "SearchHit",
+    "Selection",
+    "SelectionSales",
+    "Series",
+    "SeriesTaxRate",
+    "TaxLine",
+    "TaxSettings",
+    "TimeRange",
+    "Translation",
+    "User",
+    "WeeklyHours",
+];
+
+// The policy of every field of the other objects, by schema coordinate.
+const FIELDS: &[(&str, Policy)] = &[
+    ("QueryRoot.user", Public),
+    ("QueryRoot.shop", Public),
+    ("QueryRoot.guest", Public),
+    // Each node is authorized as the field it is otherwise reached through.
+    ("QueryRoot.node", Public),
+    ("QueryRoot.nodes", Public),
+
+    ("MutationRoot.user", Public),
+    ("MutationRoot.shop", Public),
+    ("MutationRoot.guest", Public),
+
+    ("QueryUser.me", User),
+    ("QueryUser.search", Public),
+
+    ("MutationUser.createApiToken", Session),
+    ("MutationUser.revokeApiToken", Session),
+
+    ("CurrentUser.id", Inherited),
+    ("CurrentUser.username", Inherited),
+    ("CurrentUser.nickname", Inherited),
+    ("CurrentUser.shops", Inherited),
+    ("CurrentUser.apiTokens", Session),
+
+    ("UserShop.shop", Inherited),
+    ("UserShop.role", Inherited),
+    ("UserShop.capabilities", Inherited),
+    ("UserShop.memberAuthority", Inherited),
+    ("UserShop.orderAuthority", Inherited),
+    ("UserShop.productAuthority", Inherited),
+    ("UserShop.members", Member(ViewMembers)),
+    ("UserShop.roles", Member(ManageMembers)),
+    ("UserShop.orders", Member(ViewOrders)),
+    ("UserShop.salesSummary", Member(ViewAnalytics)),
+    ("UserShop.promotions", Member(ViewPromotions)),
+    ("UserShop.auditLog", Member(ViewAuditLog)),
+
+    ("Member.id", Inherited),
+    ("Member.username", Inherited),
+    ("Member.nickname", Inherited),
+    ("Member.role", Member(ManageMembers)),
+    ("Member.authority", Member(ManageMembers)),
+
+    ("QueryShop.search", Public),
+    ("QueryShop.find", Public),
+    ("QueryShop.nearby", Public),
+    ("QueryShop.roleTemplates", Public),
+
+    ("MutationShop.setOpeningHours", Member(ManageShop)),
+    ("MutationShop.setOpeningException", Member(ManageShop)),
+    ("MutationShop.deleteOpeningException", Member(ManageShop)),
+    ("MutationShop.pauseOrdering", Member(ManageOrdering)),
+    ("MutationShop.resumeOrdering", Member(ManageOrdering)),
+    ("MutationShop.setShopAddress", Member(ManageShop)),
+    ("MutationShop.setShopCoordinates", Member(ManageShop)),
+    ("MutationShop.setShopCurrency", Member(ManageShop)),
+    ("MutationShop.setShopTaxSettings", Member(ManageShop)),
+    ("MutationShop.uploadProductPicture", Member(EditCatalog)),
+    ("MutationShop.deleteProductPicture", Member(EditCatalog)),
+    ("MutationShop.createSeries", Member(EditCatalog)),
+    ("MutationShop.updateSeries", Member(EditCatalog)),
+    ("MutationShop.deleteSeries", Member(EditCatalog)),
+    ("MutationShop.createPromotion", Member(EditPrices)),
+    ("MutationShop.deletePromotion", Member(EditPrices)),
+    ("MutationShop.setProductSeries", Member(EditCatalog)),
+    ("MutationShop.setProductInventory", Member(ManageStock)),
+    ("MutationShop.setSelectionInventory", Member(ManageStock)),
+    ("MutationShop.setTranslation", Member(EditCatalog)),
+    ("MutationShop.deleteTranslation", Member(EditCatalog)),
+    ("MutationShop.createRole", Member(ManageMembers)),
+    ("MutationShop.updateRole", Member(ManageMembers)),
+    ("MutationShop.deleteRole", Member(ManageMembers)),
+    ("MutationShop.setMemberRole", Member(ManageMembers)),
+
+    // The menu, hours and location of every shop are public.
+    ("Shop.id", Public),
+    ("Shop.uuid", Public),
+    ("Shop.name", Public),
+    ("Shop.address", Public),
+    ("Shop.coordinates", Public),
+    ("Shop.currency", Public),
+    ("Shop.minorUnits", Public),
+    ("Shop.taxSettings", Public),
+    ("Shop.timezone", Public),
+    ("Shop.openingHours", Public),
+    ("Shop.openingExceptions", Public),
+    ("Shop.orderingPausedUntil", Public),
+    ("Shop.isOpen", Public),
+    ("Shop.nextOpenAt", Public),
+    ("Shop.latestUpdate", Public),
+    ("Shop.series", Public),
+    ("Shop.products", Public),
+    ("Shop.productsJson", Public),
+
+    ("QueryGuest.carts", Guest),
+    ("QueryGuest.orders", Guest),
+
+    ("MutationGuest.addCartItem", Guest),
+    ("MutationGuest.applyCoupon", Guest),
+    ("MutationGuest.removeCoupon", Guest),
+    ("MutationGuest.checkout", Guest),
+];
+
+// The policy of a field by its schema coordinate, as in `UserShop.members`.
+pub fn policy(coordinate: &str) -> Option<Policy> {
+    let object = coordinate.split('.').next().unwrap_or_default();
+    if INHERITED.contains(&object) {
+        return Some(Inherited);
+    }
+    FIELDS.iter()
+        .find(|(field, _)| *field == coordinate)
+        .map(|&(_, policy)| policy)
+}
+
+// Fails unless the request meets the policy of the field at `coordinate`.
+// `shop_id` is the shop the field is of, for `Member` policies. Resolvers
+// of `Public` and `Inherited` fields needn't call it, and fields without a
+// policy resolve for no one.
+pub fn check(context: &Context, coordinate: &str, shop_id: Option<Uuid>) -> Result<(), Error> {
+    match policy(coordinate) {
+        Some(Public) | Some(Inherited) => Ok(()),
+        Some(Guest) => valid_guest_session(context).map(|_| ()),
+        Some(User) => context.user_id().map(|_| ()),
+        Some(Session) => {
+            if context.api_token()?.is_some() {
+                return Err(Error::unauthorized());
+            }
+            context.user_id().map(|_| ())
+        },
+        Some(Member(capability)) => match shop_id {
+            Some(shop_id) => context.require_capability(shop_id, capability),
+            None => Err(Error::unauthorized()),
+        },
+        None => Err(Error::unauthorized()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        sync::Arc,
+    };
+    use chrono::Utc;
+    use serde_json::Value;
+    use uuid::Uuid;
+    use crate::{
+        sql::Permission,
+        graphql::{
+            api_token::{self, ApiToken},
+            role::{Capabilities, Capability, Role},
+            user::Authority,
+        },
+        repository::memory::{ApiTokenRow, MemoryRepository},
+        tests::{
+            harness::{execute, execute_with_bearer, error_type},
+            seed,
+        },
+    };
+    use super::{policy, Policy, FIELDS, INHERITED};
+
+    const TOKEN: &str = "pk_guard-test";
+
+    const SCHEMA: &str = "{
+        __schema {
+            types {
+                name
+                kind
+                fields { name args { name type { ...TypeRef } } type { ...TypeRef } }
+                inputFields { name type { ...TypeRef } }
+                enumValues { name }
+            }
+        }
+    }
+    fragment TypeRef on __Type { kind name ofType { kind name ofType { kind name ofType { kind name } } } }";
+
+    fn types() -> HashMap<String, Value> {
+        let response = execute(Arc::new(seed::memory()), SCHEMA, &[]);
+        response["data"]["__schema"]["types"].as_array().unwrap()
+            .iter()
+            .map(|object| (object["name"].as_str().unwrap().to_string(), object.clone()))
+            .collect()
+    }
+
+    // Every field of the schema's objects, by schema coordinate.
+    fn fields(types: &HashMap<String, Value>) -> Vec<(String, Value)> {
+        let mut fields = Vec::new();
+        for (name, object) in types {
+            if object["kind"] != "OBJECT" || name.starts_with("__") {
+                continue;
+            }
+            for field in object["fields"].as_array().unwrap() {
+                fields.push((format!("{}.{}", name, field["name"].as_str().unwrap()), field.clone()));
+            }
+        }
+        fields.sort_by(|a, b| a.0.cmp(&b.0));
+        fields
+    }
+
+    // Any valid value of a type, as the policy is checked before the value.
+    fn literal(types: &HashMap<String, Value>, type_ref: &Value, name: &str) -> String {
+        match (type_ref["kind"].as_str().unwrap(), type_ref["name"].as_str().unwrap_or_default()) {
+            ("NON_NULL", _) => literal(types, &type_ref["ofType"], name),
+            ("LIST", _) => "[]".to_string(),
+            ("ENUM", type_name) => types[type_name]["enumValues"][0]["name"].as_str().unwrap().to_string(),
+            ("INPUT_OBJECT", type_name) => {
+                let fields: Vec<String> = types[type_name]["inputFields"].as_array().unwrap()
+                    .iter()
+                    .filter(|field| field["type"]["kind"] == "NON_NULL")
+                    .map(|field| {
+                        let name = field["name"].as_str().unwrap();
+                        format!("{}: {}", name, literal(types, &field["type"], name))
+                    })
+                    .collect();
+                format!("{{ {} }}", fields.join(", "))
+            },
+            (_, "Uuid") if name == "shopId" => format!("\"{}\"", seed::DINER),
+            (_, "Uuid") => format!("\"{}\"", Uuid::nil()),
+            (_, "String") | (_, "ID") => "\"x\"".to_string(),
+            (_, "Int") => "1".to_string(),
+            (_, "Float") => "1.0".to_string(),
+            (_, "Boolean") => "false".to_string(),
+            (_, "NaiveDate") => "\"2020-01-01\"".to_string(),
+            (_, type_name) if type_name.starts_with("DateTime") => "\"2020-01-01T00:00:00Z\"".to_string(),
+            (_, type_name) => panic!("No literal for {}.", type_name),
+        }
+    }
+
+    // An operation resolving just the field at `coordinate`.
+    fn operation(types: &HashMap<String, Value>, coordinate: &str, field: &Value) -> String {
+        let args: Vec<String> = field["args"].as_array().unwrap()
+            .iter()
+            .filter(|arg| arg["type"]["kind"] == "NON_NULL")
+            .map(|arg| {
+                let name = arg["name"].as_str().unwrap();
+                format!("{}: {}", name, literal(types, &arg["type"], name))
+            })
+            .collect();
+        let mut returns = &field["type"];
+        while !returns["ofType"].is_null() {
+            returns = &returns["ofType"];
+        }
+        let mut selection = field["name"].as_str().unwrap().to_string();
+        if !args.is_empty() {
+            selection = format!("{}({})", selection, args.join(", "));
+        }
+        if returns["kind"] == "OBJECT" || returns["kind"] == "INTERFACE" {
+            selection = format!("{} {{ __typename }}", selection);
+        }
+
+        let shop = format!("\"{}\"", seed::DINER);
+        match coordinate.split('.').next().unwrap() {
+            "QueryRoot" => format!("{{ {} }}", selection),
+            "MutationRoot" => format!("mutation {{ {} }}", selection),
+            "QueryUser" => format!("{{ user {{ {} }} }}", selection),
+            "MutationUser" => format!("mutation {{ user {{ {} }} }}", selection),
+            "QueryShop" => format!("{{ shop {{ {} }} }}", selection),
+            "MutationShop" => format!("mutation {{ shop {{ {} }} }}", selection),
+            "QueryGuest" => format!("{{ guest {{ {} }} }}", selection),
+            "MutationGuest" => format!("mutation {{ guest {{ {} }} }}", selection),
+            "Shop" => format!("{{ shop {{ search(id: {}) {{ {} }} }} }}", shop, selection),
+            "CurrentUser" => format!("{{ user {{ me {{ {} }} }} }}", selection),
+            "UserShop" => format!("{{ user {{ me {{ shops(id: {}) {{ {} }} }} }} }}", shop, selection),
+            "Member" => format!("{{ user {{ me {{ shops(id: {}) {{ members {{ {} }} }} }} }} }}", shop, selection),
+            object => panic!("No operation reaches {}.", object),
+        }
+    }
+
+    // Staff of the diner with a role of just `capabilities`.
+    fn staff_with(capabilities: Capabilities) -> Arc<MemoryRepository> {
+        let repository = Arc::new(seed::memory());
+        {
+            let mut data = repository.write();
+            let role = Role::new(Uuid::new_v4(), seed::DINER, "Tested".to_string(), capabilities);
+            data.shop_users.iter_mut()
+                .find(|member| member.shop_id == seed::DINER && member.user_id == seed::STAFF)
+                .unwrap()
+                .role_id = role.id();
+            data.roles.push(role);
+        }
+        repository
+    }
+
+    // The owner's, with every permission in every shop.
+    fn with_token() -> Arc<MemoryRepository> {
+        let repository = Arc::new(seed::memory());
+        let all = Authority::new(Permission::All, Permission::All, Permission::All);
+        repository.write().api_tokens.push(ApiTokenRow {
+            token: ApiToken::new(Uuid::new_v4(), seed::OWNER, None, "Everything".to_string(), all, Utc::now(), None),
+            token_hash: api_token::hash(TOKEN),
+        });
+        repository
+    }
+
+    fn is_denied(response: &Value) -> bool {
+        error_type(response).map_or(false, |error| error == "Unauthorized" || error == "NoValidCookie")
+    }
+
+    #[test]
+    fn test_every_field_has_policy() {
+        let types = types();
+        let fields = fields(&types);
+
+        let without: Vec<&String> = fields.iter()
+            .map(|(coordinate, _)| coordinate)
+            .filter(|coordinate| policy(coordinate).is_none())
+            .collect();
+        assert!(without.is_empty(), "Fields without a policy: {:?}", without);
+
+        // Nor policies of fields no longer there.
+        for (coordinate, _) in FIELDS {
+            assert!(fields.iter().any(|(field, _)| field == coordinate), "No field {}.", coordinate);
+        }
+        for object in INHERITED {
+            assert!(types.contains_key(*object), "No object {}.", object);
+        }
+    }
+
+    #[test]
+    fn test_every_field_meets_policy() {
+        let types = types();
+        let everything = || Capabilities::new(Capability::ALL.iter().cloned());
+
+        for (coordinate, field) in fields(&types) {
+            let query = operation(&types, &coordinate, &field);
+            let (denied, allowed) = match policy(&coordinate).unwrap() {
+                Policy::Inherited => continue,
+                Policy::Public => {
+                    let response = execute(Arc::new(seed::memory()), &query, &[]);
+                    assert!(!is_denied(&response), "{} is public: {}", coordinate, response);
+                    continue;
+                },
+                Policy::Guest => (
+                    execute(Arc::new(seed::memory()), &query, &[]),
+                    execute(Arc::new(seed::memory()), &query, &[("GSSID", seed::GUEST_SESSION)]),
+                ),
+                Policy::User => (
+                    execute(Arc::new(seed::memory()), &query, &[]),
+                    execute_with_bearer(with_token(), &query, TOKEN),
+                ),
+                Policy::Session => (
+                    execute_with_bearer(with_token(), &query, TOKEN),
+                    execute(Arc::new(seed::memory()), &query, &[("USSID", seed::OWNER_SESSION)]),
+                ),
+                Policy::Member(capability) => {
+                    let without = everything().to_vec().into_iter().filter(|&other| other != capability);
+                    (
+                        execute(staff_with(Capabilities::new(without)), &query, &[("USSID", seed::STAFF_SESSION)]),
+                        execute(staff_with(everything()), &query, &[("USSID", seed::STAFF_SESSION)]),
+                    )
+                },
+            };
+            assert!(is_denied(&denied), "{} resolved without its policy: {}", coordinate, denied);
+            assert!(!is_denied(&allowed), "{} denied despite its policy: {}", coordinate, allowed);
+        }
+    }
+}
@@ -1,14 +1,15 @@
 use uuid::Uuid;
+use chrono::Utc;
 use crate::{
-    sql::UuidNN,
     graphql::{
         context::Context,
+        shop::{SelectionChoice, find_product},
         order::{
-            query_carts,
-            query_orders,
             Cart,
             Order,
-        }
+        },
+        promotion,
+        guard,
     },
     error::Error,
 };
@@ -18,34 +19,387 @@ pub struct QueryGuest;
 #[juniper::graphql_object(Context = Context)]
 impl QueryGuest {
     fn carts(context: &Context, shop_id: Option<Uuid>) -> Result<Vec<Cart>, Error> {
-        let mut conn = context.state().db_connection()?;
+        guard::check(context, "QueryGuest.carts", None)?;
         let guest_session_id = context.guest_session_id()?;
 
-        let (ok,) = query_one!(
-            conn,
-            "SELECT is_guest_session_valid($1) AS ok;",
-            &[&UuidNN(guest_session_id)],
-            (ok: bool),
-        )?;
+        context.state().carts().carts(shop_id, Some(guest_session_id))
+    }
 
-        if !ok { return Err(Error::session_expired("GSSID")) }
+    fn orders(context: &Context, shop_id: Uuid) -> Result<Option<Vec<Order>>, Error> {
+        guard::check(context, "QueryGuest.orders", None)?;
+        let guest_session_id = context.guest_session_id()?;
 
-        query_carts(conn, shop_id, Some(guest_session_id))
+        Ok(Some(context.state().orders().orders(Some(shop_id), Some(guest_session_id))?))
     }
+}
 
-    fn orders(context: &Context, shop_id: Uuid) -> Result<Option<Vec<Order>>, Error> {
+pub struct MutationGuest;
+
+#[juniper::graphql_object(Context = Context)]
+impl MutationGuest {
+    fn add_cart_item(
+        context: &Context,
+        shop_id: Uuid,
+        product_key: Uuid,
+        count: i32,
+        remark: Option<String>,
+        selections: Option<Vec<SelectionChoice>>,
+    ) -> Result<Cart, Error> {
+        guard::check(context, "MutationGuest.addCartItem", None)?;
+        let guest_session_id = context.guest_session_id()?;
+
+        let product = find_product(context, shop_id, product_key)?;
+        require_open(context, shop_id)?;
+        let item = product.cart_item(count, remark, &selections.unwrap_or_default())?;
+        context.state().carts().add_item(shop_id, guest_session_id, item)?;
+        find_cart(context, shop_id, guest_session_id)
+    }
+
+    // Fails with `InvalidCoupon` when the code can't be redeemed now. Whether
+    // it applies to the items shows in the cart's `couponError`.
+    fn apply_coupon(context: &Context, shop_id: Uuid, code: String) -> Result<Cart, Error> {
+        guard::check(context, "MutationGuest.applyCoupon", None)?;
         let guest_session_id = context.guest_session_id()?;
-        let mut conn = context.state().db_connection()?;
 
-        let (ok,) = query_one!(
-            conn,
-            "SELECT is_guest_session_valid($1) AS ok;",
-            &[&UuidNN(guest_session_id)],
-            (ok: bool),
-        )?;
+        let promotions = context.state().promotions().promotions(shop_id, None)?;
+        let code = promotion::coupon(&promotions, &code, Utc::now())?.code().map(str::to_string);
+        context.state().carts().set_coupon_code(shop_id, guest_session_id, code)?;
+        find_cart(context, shop_id, guest_session_id)
+    }
+
+    fn remove_coupon(context: &Context, shop_id: Uuid) -> Result<Cart, Error> {
+        guard::check(context, "MutationGuest.removeCoupon", None)?;
+        let guest_session_id = context.guest_session_id()?;
+
+        context.state().carts().set_coupon_code(shop_id, guest_session_id, None)?;
+        find_cart(context, shop_id, guest_session_id)
+    }
+
+    fn checkout(context: &Context, shop_id: Uuid) -> Result<Order, Error> {
+        guard::check(context, "MutationGuest.checkout", None)?;
+        let guest_session_id = context.guest_session_id()?;
+        require_open(context, shop_id)?;
+
+        let id = context.state().orders().place_order(shop_id, guest_session_id)?;
+        context.state().orders().orders(Some(shop_id), Some(guest_session_id))?
+            .into_iter()
+            .find(|order| order.id() == id)
+            .ok_or_else(|| Error::not_found("Order"))
+    }
+}
+
+fn find_cart(context: &Context, shop_id: Uuid, guest_session_id: Uuid) -> Result<Cart, Error> {
+    context.state().carts().carts(Some(shop_id), Some(guest_session_id))?
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::not_found("Cart"))
+}
+
+fn require_open(context: &Context, shop_id: Uuid) -> Result<(), Error> {
+    let schedule = context.state().shops().schedule(shop_id)?;
+    let now = Utc::now();
+    if schedule.is_open_at(now) {
+        Ok(())
+    } else {
+        Err(Error::shop_closed(schedule.next_open_at(now)))
+    }
+}
+
+pub fn valid_guest_session(context: &Context) -> Result<Uuid, Error> {
+    let guest_session_id = context.guest_session_id()?;
+
+    let ok = context.state().sessions().is_guest_session_valid(guest_session_id)?;
+    if !ok { return Err(Error::session_expired("GSSID")) }
+
+    Ok(guest_session_id)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+    use serde_json::json;
+    use uuid::Uuid;
+    use crate::tests::{
+        harness::{Api, Memory, TestServer, error_type},
+        seed,
+    };
+
+    fn add_cart_item(product: Uuid, count: i32, selection: Option<Uuid>) -> String {
+        let selections = selection
+            .map(|key| format!(", selections: [{{ customizeKey: \"{}\", selectionKey: \"{}\" }}]", seed::SIZE, key))
+            .unwrap_or_default();
+        format!(
+            "mutation {{ guest {{ addCartItem(shopId: \"{}\", productKey: \"{}\", count: {}{}) {{ items {{ name count }} }} }} }}",
+            seed::DINER,
+            product,
+            count,
+            selections,
+        )
+    }
+
+    #[test]
+    fn test_inventory() {
+        inventory(&mut Memory::seeded());
+    }
+
+    #[test]
+    #[ignore]
+    fn test_inventory_postgres() {
+        inventory(&mut TestServer::start());
+    }
+
+    fn inventory(api: &mut dyn Api) {
+        let guest = [("GSSID", seed::GUEST_SESSION)];
+        let products = "{ shop { search(name: \"diner\") { products { name stock available customizes { selections { name soldOut available } } } } } }";
+
+        let response = api.execute(products, &[]);
+        let products = response["data"]["shop"]["search"][0]["products"].as_array().unwrap();
+        assert!(products.contains(&json!({ "name": "Black Tea", "stock": 3, "available": true, "customizes": [] })));
+        let pork_rice = products.iter().find(|product| product["name"] == "Braised Pork Rice").unwrap();
+        let selections = pork_rice["customizes"][0]["selections"].as_array().unwrap();
+        assert!(selections.contains(&json!({ "name": "Small", "soldOut": true, "available": false })));
+
+        let response = api.execute(&add_cart_item(seed::PORK_RICE, 1, Some(seed::SMALL)), &guest);
+        assert_eq!(error_type(&response), Some("ItemUnavailable"));
+
+        let response = api.execute(&add_cart_item(seed::PORK_RICE, 1, Some(seed::LARGE)), &guest);
+        assert_eq!(response["data"]["guest"]["addCartItem"]["items"].as_array().unwrap().len(), 2);
+
+        let checkout = format!("mutation {{ guest {{ checkout(shopId: \"{}\") {{ orderNumber total }} }} }}", seed::DINER);
+        let response = api.execute(&checkout, &guest);
+        assert_eq!(response["data"]["guest"]["checkout"], json!({ "orderNumber": 2, "total": 160 }));
+
+        // Two of the three Black Teas are gone with the order.
+        let response = api.execute(&add_cart_item(seed::BLACK_TEA, 2, None), &guest);
+        assert_eq!(error_type(&response), Some("ItemUnavailable"));
+
+        let response = api.execute(&checkout, &guest);
+        assert_eq!(error_type(&response), Some("NotFound"));
+
+        let response = api.execute(&checkout, &[("GSSID", seed::EXPIRED_GUEST_SESSION)]);
+        assert_eq!(error_type(&response), Some("SessionExpired"));
+
+        let sold_out = format!(
+            "mutation {{ shop {{ setProductInventory(shopId: \"{}\", productKey: \"{}\", stock: null, soldOut: true) {{ stock soldOut available }} }} }}",
+            seed::DINER,
+            seed::BLACK_TEA,
+        );
+        let response = api.execute(&sold_out, &[("USSID", seed::OWNER_SESSION)]);
+        assert_eq!(
+            response["data"]["shop"]["setProductInventory"],
+            json!({ "stock": null, "soldOut": true, "available": false }),
+        );
+        let response = api.execute(&add_cart_item(seed::BLACK_TEA, 1, None), &guest);
+        assert_eq!(error_type(&response), Some("ItemUnavailable"));
+    }
+
+    #[test]
+    fn test_opening_hours() {
+        opening_hours(&mut Memory::seeded());
+    }
+
+    #[test]
+    #[ignore]
+    fn test_opening_hours_postgres() {
+        opening_hours(&mut TestServer::start());
+    }
+
+    fn opening_hours(api: &mut dyn Api) {
+        let owner = [("USSID", seed::OWNER_SESSION)];
+        let guest = [("GSSID", seed::GUEST_SESSION)];
+        let open = format!("{{ shop {{ search(id: \"{}\") {{ isOpen nextOpenAt orderingPausedUntil }} }} }}", seed::DINER);
+
+        let response = api.graphql(
+            "mutation Hours($shopId: Uuid!) {
+                shop {
+                    setOpeningHours(shopId: $shopId, timezone: \"Asia/Taipei\", hours: [
+                        { day: MONDAY, opens: \"11:00\", closes: \"14:00\" },
+                        { day: MONDAY, opens: \"17:00\", closes: \"02:00\" },
+                    ]) {
+                        timezone
+                        openingHours { day opens closes }
+                    }
+                    setOpeningException(shopId: $shopId, date: \"2030-01-01\", hours: [], note: \"New Year\") {
+                        openingExceptions(from: \"2030-01-01\") { date closed hours { opens } note }
+                    }
+                }
+            }",
+            json!({ "shopId": seed::DINER }),
+            &owner,
+        );
+        assert_eq!(
+            response["data"]["shop"],
+            json!({
+                "setOpeningHours": {
+                    "timezone": "Asia/Taipei",
+                    "openingHours": [
+                        { "day": "MONDAY", "opens": "11:00", "closes": "14:00" },
+                        { "day": "MONDAY", "opens": "17:00", "closes": "02:00" },
+                    ],
+                },
+                "setOpeningException": {
+                    "openingExceptions": [{ "date": "2030-01-01", "closed": true, "hours": [], "note": "New Year" }],
+                },
+            }),
+        );
+
+        let hours = |timezone: &str| format!(
+            "mutation {{ shop {{ setOpeningHours(shopId: \"{}\", timezone: \"{}\", hours: []) {{ name }} }} }}",
+            seed::DINER,
+            timezone,
+        );
+        assert_eq!(error_type(&api.execute(&hours("Mars/Olympus"), &owner)), Some("InvalidInput"));
+
+        // Back to around the clock, but paused.
+        assert!(api.execute(&hours("UTC"), &owner).get("errors").is_none());
+        let pause = format!("mutation {{ shop {{ pauseOrdering(shopId: \"{}\", minutes: 30) {{ isOpen }} }} }}", seed::DINER);
+        let response = api.execute(&pause, &[("USSID", seed::STAFF_SESSION)]);
+        assert_eq!(error_type(&response), Some("Unauthorized"));
+        let response = api.execute(&pause, &owner);
+        assert_eq!(response["data"]["shop"]["pauseOrdering"], json!({ "isOpen": false }));
+
+        let response = api.execute(&open, &[]);
+        let shop = &response["data"]["shop"]["search"][0];
+        assert!(shop["nextOpenAt"].is_string());
+        assert_eq!(shop["nextOpenAt"], shop["orderingPausedUntil"]);
+
+        let response = api.execute(&add_cart_item(seed::BLACK_TEA, 1, None), &guest);
+        assert_eq!(error_type(&response), Some("ShopClosed"));
+
+        let resume = format!("mutation {{ shop {{ resumeOrdering(shopId: \"{}\") {{ isOpen }} }} }}", seed::DINER);
+        let response = api.execute(&resume, &owner);
+        assert_eq!(response["data"]["shop"]["resumeOrdering"], json!({ "isOpen": true }));
+
+        // Closing today and tomorrow keeps the test stable around midnight.
+        let today = Utc::now().date_naive();
+        for date in [today, today.succ_opt().unwrap()].iter() {
+            let close = format!(
+                "mutation {{ shop {{ setOpeningException(shopId: \"{}\", date: \"{}\", hours: [], note: \"Inventory day\") {{ name }} }} }}",
+                seed::DINER,
+                date,
+            );
+            let response = api.execute(&close, &owner);
+            assert!(response.get("errors").is_none());
+        }
+
+        let checkout = format!("mutation {{ guest {{ checkout(shopId: \"{}\") {{ orderNumber }} }} }}", seed::DINER);
+        let response = api.execute(&checkout, &guest);
+        assert_eq!(error_type(&response), Some("ShopClosed"));
+
+        let response = api.execute(&open, &[]);
+        assert_eq!(response["data"]["shop"]["search"][0]["isOpen"], false);
+    }
+
+    #[test]
+    fn test_coupons() {
+        coupons(&mut Memory::seeded());
+    }
+
+    #[test]
+    #[ignore]
+    fn test_coupons_postgres() {
+        coupons(&mut TestServer::start());
+    }
+
+    fn coupons(api: &mut dyn Api) {
+        let owner = [("USSID", seed::OWNER_SESSION)];
+        let guest = [("GSSID", seed::GUEST_SESSION)];
+        let create = |promotion: &str| format!(
+            "mutation {{ shop {{ createPromotion(shopId: \"{}\", promotion: {{ {} }}) {{ code }} }} }}",
+            seed::DINER,
+            promotion,
+        );
+        let apply = |code: &str| format!(
+            "mutation {{ guest {{ applyCoupon(shopId: \"{}\", code: \"{}\") {{
+                couponCode couponError subtotal discount total promotions {{ code discount }}
+            }} }} }}",
+            seed::DINER,
+            code,
+        );
+
+        let tea = format!(
+            "name: \"Tea for two\", kind: BUY_X_GET_Y, productKey: \"{}\", buyCount: 1, getCount: 1, code: \"tea\", usageLimit: 1",
+            seed::BLACK_TEA,
+        );
+        let response = api.execute(&create(&tea), &[("USSID", seed::STAFF_SESSION)]);
+        assert_eq!(error_type(&response), Some("Unauthorized"));
+        let response = api.execute(&create(&tea), &owner);
+        assert_eq!(response["data"]["shop"]["createPromotion"], json!({ "code": "TEA" }));
+        let response = api.execute(&create(&tea), &owner);
+        assert_eq!(error_type(&response), Some("InvalidInput"));
+        // Every cart spending 100 or more gets this one.
+        let response = api.execute(&create("name: \"Big spender\", kind: PERCENTAGE, percent: 10, minSpend: 100"), &owner);
+        assert!(response.get("errors").is_none());
+        let response = api.execute(&create("name: \"Fifty off\", kind: FIXED, amount: 50, minSpend: 200, code: \"FIFTY\""), &owner);
+        assert!(response.get("errors").is_none());
+
+        let response = api.execute(&apply("nope"), &guest);
+        assert_eq!(error_type(&response), Some("InvalidCoupon"));
+
+        // The cart holds two Black Teas at 30.
+        let response = api.execute(&apply("fifty"), &guest);
+        let cart = &response["data"]["guest"]["applyCoupon"];
+        assert_eq!(cart["couponCode"], json!("FIFTY"));
+        assert!(cart["couponError"].is_string());
+        assert_eq!((cart["discount"].clone(), cart["total"].clone()), (json!(0), json!(60)));
+        let checkout = format!(
+            "mutation {{ guest {{ checkout(shopId: \"{}\") {{ subtotal discount total promotions {{ name code discount }} }} }} }}",
+            seed::DINER,
+        );
+        let response = api.execute(&checkout, &guest);
+        assert_eq!(error_type(&response), Some("InvalidCoupon"));
+
+        let response = api.execute(&apply(" Tea "), &guest);
+        assert_eq!(
+            response["data"]["guest"]["applyCoupon"],
+            json!({
+                "couponCode": "TEA",
+                "couponError": null,
+                "subtotal": 60,
+                "discount": 30,
+                "total": 30,
+                "promotions": [{ "code": "TEA", "discount": 30 }],
+            }),
+        );
+        let response = api.execute(&checkout, &guest);
+        let order = json!({
+            "subtotal": 60,
+            "discount": 30,
+            "total": 30,
+            "promotions": [{ "name": "Tea for two", "code": "TEA", "discount": 30 }],
+        });
+        assert_eq!(response["data"]["guest"]["checkout"], order);
+
+        // The only use is gone.
+        let response = api.execute(&add_cart_item(seed::PORK_RICE, 1, Some(seed::LARGE)), &guest);
+        assert!(response.get("errors").is_none());
+        let response = api.execute(&apply("TEA"), &guest);
+        assert_eq!(error_type(&response), Some("InvalidCoupon"));
+
+        let promotions = format!("{{ user {{ me {{ shops(id: \"{}\") {{ promotions {{ id name usageCount }} }} }} }} }}", seed::DINER);
+        let response = api.execute(&promotions, &owner);
+        let promotions = response["data"]["user"]["me"]["shops"][0]["promotions"].clone();
+        assert_eq!(promotions[0]["usageCount"], json!(1));
+        assert_eq!(promotions.as_array().unwrap().len(), 3);
 
-        if !ok { return Err(Error::session_expired("GSSID")) }
+        // Orders keep their promotions once those are deleted.
+        let delete = format!(
+            "mutation {{ shop {{ deletePromotion(shopId: \"{}\", promotionId: {}) }} }}",
+            seed::DINER,
+            promotions[0]["id"],
+        );
+        let response = api.execute(&delete, &owner);
+        assert!(response.get("errors").is_none());
+        let orders = format!(
+            "{{ guest {{ orders(shopId: \"{}\") {{ subtotal discount total promotions {{ name code discount }} }} }} }}",
+            seed::DINER,
+        );
+        let response = api.execute(&orders, &guest);
+        assert_eq!(response["data"]["guest"]["orders"][1], order);
 
-        query_orders(conn, Some(shop_id), Some(guest_session_id))
+        // The Big spender promotion applies on its own.
+        let cart = format!("{{ guest {{ carts(shopId: \"{}\") {{ total promotions {{ discount }} }} }} }}", seed::DINER);
+        let response = api.execute(&cart, &guest);
+        assert_eq!(response["data"]["guest"]["carts"][0], json!({ "total": 90, "promotions": [{ "discount": 10 }] }));
     }
-}
\ No newline at end of file
+}
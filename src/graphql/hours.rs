@@ -0,0 +1,352 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use crate::error::Error;
+
+// How far ahead `next_open_at` looks for an opening.
+const LOOKAHEAD_DAYS: i64 = 14;
+
+pub const MAX_PAUSE_MINUTES: i32 = 7 * 24 * 60;
+
+#[derive(juniper::GraphQLEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Day {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Day {
+    const ALL: [Day; 7] = [
+        Day::Monday,
+        Day::Tuesday,
+        Day::Wednesday,
+        Day::Thursday,
+        Day::Friday,
+        Day::Saturday,
+        Day::Sunday,
+    ];
+
+    // Days are stored as 0 for Monday through 6 for Sunday.
+    pub fn index(&self) -> i16 {
+        Day::ALL.iter().position(|day| day == self).unwrap() as i16
+    }
+
+    pub fn from_index(index: i16) -> Option<Self> {
+        Day::ALL.get(index as usize).cloned()
+    }
+
+    pub fn of(date: NaiveDate) -> Self {
+        Day::ALL[date.weekday().num_days_from_monday() as usize]
+    }
+}
+
+pub fn parse_timezone(name: &str) -> Result<Tz, Error> {
+    name.parse().map_err(|_| Error::invalid_input(&format!(r#"Unknown time zone "{}"."#, name)))
+}
+
+// Times are written as "HH:MM".
+pub fn parse_time(time: &str) -> Result<NaiveTime, Error> {
+    NaiveTime::parse_from_str(time, "%H:%M")
+        .map_err(|_| Error::invalid_input(&format!(r#"Invalid time "{}", expected "HH:MM"."#, time)))
+}
+
+// Opening hours within a day. An interval closing at or before it opens runs
+// past midnight, so 00:00 to 00:00 is the whole day.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Interval {
+    pub opens: NaiveTime,
+    pub closes: NaiveTime,
+}
+
+impl Interval {
+    pub fn new(opens: NaiveTime, closes: NaiveTime) -> Self {
+        Interval {
+            opens: opens,
+            closes: closes,
+        }
+    }
+
+    pub fn parse(opens: &str, closes: &str) -> Result<Self, Error> {
+        Ok(Interval::new(parse_time(opens)?, parse_time(closes)?))
+    }
+
+    fn all_day() -> Self {
+        let midnight = NaiveTime::from_hms_opt(0, 0, 0).unwrap();
+        Interval::new(midnight, midnight)
+    }
+
+    // The instants the interval spans on `date` in `timezone`.
+    fn on(&self, date: NaiveDate, timezone: Tz) -> (DateTime<Utc>, DateTime<Utc>) {
+        let close_date = if self.closes <= self.opens { date + Duration::days(1) } else { date };
+        (local(timezone, date, self.opens), local(timezone, close_date, self.closes))
+    }
+}
+
+// A local time as an instant. Times skipped by a daylight saving change are
+// moved past the gap.
+pub fn local(timezone: Tz, date: NaiveDate, time: NaiveTime) -> DateTime<Utc> {
+    let naive = date.and_time(time);
+    timezone.from_local_datetime(&naive).earliest()
+        .or_else(|| timezone.from_local_datetime(&(naive + Duration::hours(1))).earliest())
+        .map(|at| at.with_timezone(&Utc))
+        .unwrap_or_else(|| Utc.from_utc_datetime(&naive))
+}
+
+#[derive(juniper::GraphQLObject, Clone, Debug, PartialEq)]
+pub struct TimeRange {
+    pub opens: String,
+    pub closes: String,
+}
+
+impl From<Interval> for TimeRange {
+    fn from(interval: Interval) -> Self {
+        TimeRange {
+            opens: interval.opens.format("%H:%M").to_string(),
+            closes: interval.closes.format("%H:%M").to_string(),
+        }
+    }
+}
+
+#[derive(juniper::GraphQLInputObject)]
+pub struct TimeRangeInput {
+    pub opens: String,
+    pub closes: String,
+}
+
+#[derive(juniper::GraphQLInputObject)]
+pub struct OpeningHoursInput {
+    pub day: Day,
+    pub opens: String,
+    pub closes: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WeeklyHours {
+    pub day: Day,
+    pub interval: Interval,
+}
+
+impl WeeklyHours {
+    pub fn new(day: Day, interval: Interval) -> Self {
+        WeeklyHours {
+            day: day,
+            interval: interval,
+        }
+    }
+}
+
+#[juniper::graphql_object]
+impl WeeklyHours {
+    fn day(&self) -> Day {
+        self.day
+    }
+
+    fn opens(&self) -> String {
+        TimeRange::from(self.interval).opens
+    }
+
+    fn closes(&self) -> String {
+        TimeRange::from(self.interval).closes
+    }
+}
+
+// Hours replacing the weekly ones on a date, such as a holiday. Without any
+// intervals the shop is closed all day.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpeningException {
+    pub date: NaiveDate,
+    pub intervals: Vec<Interval>,
+    pub note: Option<String>,
+}
+
+impl OpeningException {
+    pub fn new(date: NaiveDate, intervals: Vec<Interval>, note: Option<String>) -> Self {
+        OpeningException {
+            date: date,
+            intervals: intervals,
+            note: note,
+        }
+    }
+}
+
+#[juniper::graphql_object]
+impl OpeningException {
+    fn date(&self) -> NaiveDate {
+        self.date
+    }
+
+    fn hours(&self) -> Vec<TimeRange> {
+        self.intervals.iter().cloned().map(TimeRange::from).collect()
+    }
+
+    fn closed(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    fn note(&self) -> &Option<String> {
+        &self.note
+    }
+}
+
+// When a shop takes orders. A shop without weekly hours is open around the
+// clock, apart from its date exceptions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schedule {
+    pub timezone: Tz,
+    pub weekly: Vec<WeeklyHours>,
+    pub exceptions: Vec<OpeningException>,
+    pub paused_until: Option<DateTime<Utc>>,
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Schedule {
+            timezone: Tz::UTC,
+            weekly: Vec::new(),
+            exceptions: Vec::new(),
+            paused_until: None,
+        }
+    }
+}
+
+impl Schedule {
+    fn intervals_on(&self, date: NaiveDate) -> Vec<Interval> {
+        if let Some(exception) = self.exceptions.iter().find(|exception| exception.date == date) {
+            exception.intervals.clone()
+        } else if self.weekly.is_empty() {
+            vec![Interval::all_day()]
+        } else {
+            self.weekly.iter()
+                .filter(|hours| hours.day == Day::of(date))
+                .map(|hours| hours.interval)
+                .collect()
+        }
+    }
+
+    fn local_date(&self, at: DateTime<Utc>) -> NaiveDate {
+        at.with_timezone(&self.timezone).date_naive()
+    }
+
+    // Whether `at` falls in opening hours, regardless of any pause.
+    fn within_hours(&self, at: DateTime<Utc>) -> bool {
+        let date = self.local_date(at);
+        // Intervals of the day before may run past midnight.
+        [date - Duration::days(1), date].iter().any(|&date| {
+            self.intervals_on(date).iter().any(|interval| {
+                let (opens, closes) = interval.on(date, self.timezone);
+                opens <= at && at < closes
+            })
+        })
+    }
+
+    pub fn paused_at(&self, at: DateTime<Utc>) -> bool {
+        self.paused_until.map_or(false, |until| at < until)
+    }
+
+    pub fn is_open_at(&self, at: DateTime<Utc>) -> bool {
+        !self.paused_at(at) && self.within_hours(at)
+    }
+
+    // When orders are next accepted after `at`. `None` while open, or when
+    // the shop does not open within the lookahead.
+    pub fn next_open_at(&self, at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if self.is_open_at(at) {
+            return None;
+        }
+
+        let from = self.paused_until.filter(|&until| until > at).unwrap_or(at);
+        if self.within_hours(from) {
+            return Some(from);
+        }
+
+        let date = self.local_date(from);
+        (-1..=LOOKAHEAD_DAYS)
+            .map(|offset| date + Duration::days(offset))
+            .flat_map(|date| {
+                self.intervals_on(date).into_iter()
+                    .map(move |interval| interval.on(date, self.timezone).0)
+                    .collect::<Vec<_>>()
+            })
+            .filter(|&opens| opens > from)
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text).unwrap().with_timezone(&Utc)
+    }
+
+    // Taipei is UTC+8 all year. 2024-01-01 is a Monday.
+    fn schedule() -> Schedule {
+        Schedule {
+            timezone: parse_timezone("Asia/Taipei").unwrap(),
+            weekly: vec![
+                WeeklyHours::new(Day::Monday, Interval::parse("11:00", "14:00").unwrap()),
+                WeeklyHours::new(Day::Monday, Interval::parse("17:00", "02:00").unwrap()),
+                WeeklyHours::new(Day::Wednesday, Interval::parse("11:00", "14:00").unwrap()),
+            ],
+            exceptions: vec![
+                OpeningException::new(NaiveDate::from_ymd_opt(2024, 1, 8).unwrap(), vec![], Some("Holiday".to_string())),
+            ],
+            paused_until: None,
+        }
+    }
+
+    #[test]
+    fn test_day() {
+        assert_eq!(Day::of(NaiveDate::from_ymd_opt(2024, 1, 7).unwrap()), Day::Sunday);
+        assert_eq!(Day::Sunday.index(), 6);
+        assert_eq!(Day::from_index(0), Some(Day::Monday));
+        assert_eq!(Day::from_index(7), None);
+    }
+
+    #[test]
+    fn test_is_open_at() {
+        let schedule = schedule();
+        assert!(schedule.is_open_at(at("2024-01-01T12:00:00+08:00")));
+        assert!(!schedule.is_open_at(at("2024-01-01T14:00:00+08:00")));
+        // Monday evening hours run into Tuesday.
+        assert!(schedule.is_open_at(at("2024-01-02T01:30:00+08:00")));
+        assert!(!schedule.is_open_at(at("2024-01-02T02:00:00+08:00")));
+        assert!(!schedule.is_open_at(at("2024-01-02T12:00:00+08:00")));
+        // Closed for the holiday.
+        assert!(!schedule.is_open_at(at("2024-01-08T12:00:00+08:00")));
+
+        assert!(Schedule::default().is_open_at(at("2024-01-02T03:00:00Z")));
+    }
+
+    #[test]
+    fn test_next_open_at() {
+        let schedule = schedule();
+        assert_eq!(schedule.next_open_at(at("2024-01-01T12:00:00+08:00")), None);
+        assert_eq!(schedule.next_open_at(at("2024-01-01T15:00:00+08:00")), Some(at("2024-01-01T17:00:00+08:00")));
+        assert_eq!(schedule.next_open_at(at("2024-01-02T12:00:00+08:00")), Some(at("2024-01-03T11:00:00+08:00")));
+        // The holiday is skipped.
+        assert_eq!(schedule.next_open_at(at("2024-01-04T12:00:00+08:00")), Some(at("2024-01-10T11:00:00+08:00")));
+    }
+
+    #[test]
+    fn test_pause() {
+        let schedule = Schedule {
+            paused_until: Some(at("2024-01-01T12:30:00+08:00")),
+            ..schedule()
+        };
+        assert!(!schedule.is_open_at(at("2024-01-01T12:00:00+08:00")));
+        assert_eq!(schedule.next_open_at(at("2024-01-01T12:00:00+08:00")), Some(at("2024-01-01T12:30:00+08:00")));
+        assert!(schedule.is_open_at(at("2024-01-01T12:30:00+08:00")));
+
+        // Pausing past closing waits for the next opening.
+        let schedule = Schedule {
+            paused_until: Some(at("2024-01-01T15:00:00+08:00")),
+            ..schedule
+        };
+        assert_eq!(schedule.next_open_at(at("2024-01-01T12:00:00+08:00")), Some(at("2024-01-01T17:00:00+08:00")));
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+use crate::error::Error;
+
+// Longest tag accepted, enough for language, script, region and a variant.
+const MAX_TAG_LENGTH: usize = 35;
+
+#[derive(juniper::GraphQLEnum, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TranslatedField {
+    Name,
+    Description,
+}
+
+impl TranslatedField {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TranslatedField::Name => "name",
+            TranslatedField::Description => "description",
+        }
+    }
+
+    pub fn parse(field: &str) -> Option<Self> {
+        match field {
+            "name" => Some(TranslatedField::Name),
+            "description" => Some(TranslatedField::Description),
+            _ => None,
+        }
+    }
+}
+
+// A language tag such as "zh-Hant-TW", normalized to the usual casing so
+// "ZH_tw" and "zh-TW" are the same locale.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Locale(String);
+
+impl Locale {
+    pub fn parse(tag: &str) -> Result<Self, Error> {
+        let invalid = || Error::invalid_input(&format!(r#"Invalid locale "{}"."#, tag));
+        if tag.is_empty() || tag.len() > MAX_TAG_LENGTH {
+            return Err(invalid());
+        }
+
+        let mut subtags = Vec::new();
+        for (i, subtag) in tag.split(|c| c == '-' || c == '_').enumerate() {
+            let valid = match i {
+                0 => (2..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphabetic()),
+                _ => (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric()),
+            };
+            if !valid {
+                return Err(invalid());
+            }
+
+            subtags.push(match subtag.len() {
+                // Scripts are title case and regions upper case.
+                4 if i > 0 && subtag.chars().all(|c| c.is_ascii_alphabetic()) => {
+                    subtag[..1].to_ascii_uppercase() + &subtag[1..].to_ascii_lowercase()
+                }
+                2 | 3 if i > 0 => subtag.to_ascii_uppercase(),
+                _ => subtag.to_ascii_lowercase(),
+            });
+        }
+        Ok(Locale(subtags.join("-")))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn language(&self) -> &str {
+        self.0.split('-').next().unwrap()
+    }
+
+    // The tag followed by its less specific forms, such as "zh-Hant-TW",
+    // "zh-Hant" and "zh".
+    fn fallbacks(&self) -> Vec<Locale> {
+        let subtags: Vec<&str> = self.0.split('-').collect();
+        (1..=subtags.len()).rev().map(|n| Locale(subtags[..n].join("-"))).collect()
+    }
+}
+
+// Locales of an `Accept-Language` header, most preferred first. Wildcards
+// and malformed entries are skipped.
+pub fn accept_language(header: &str) -> Vec<Locale> {
+    let mut weighted: Vec<(f64, Locale)> = Vec::new();
+    for entry in header.split(',') {
+        let mut parts = entry.split(';').map(str::trim);
+        let tag = parts.next().unwrap_or("");
+        let quality = parts
+            .find_map(|param| {
+                if param.starts_with("q=") {
+                    Some(param[2..].parse::<f64>().ok())
+                } else {
+                    None
+                }
+            })
+            .unwrap_or(Some(1.0));
+
+        match (Locale::parse(tag), quality) {
+            (Ok(locale), Some(quality)) if quality > 0.0 => {
+                if !weighted.iter().any(|(_, other)| *other == locale) {
+                    weighted.push((quality, locale));
+                }
+            }
+            _ => (),
+        }
+    }
+    // A stable sort keeps the header's order among equal weights.
+    weighted.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+    weighted.into_iter().map(|(_, locale)| locale).collect()
+}
+
+#[derive(juniper::GraphQLObject, Clone, Debug, PartialEq)]
+pub struct Translation {
+    pub field: TranslatedField,
+    pub locale: String,
+    pub text: String,
+}
+
+// Translated text of a product, customize or selection.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Translations(BTreeMap<(TranslatedField, Locale), String>);
+
+impl Translations {
+    pub fn insert(&mut self, field: TranslatedField, locale: Locale, text: String) {
+        self.0.insert((field, locale), text);
+    }
+
+    fn get(&self, field: TranslatedField, locale: &Locale) -> Option<&str> {
+        self.0.get(&(field, locale.clone())).map(String::as_str)
+    }
+
+    // Text of `field` for the most preferred of `locales` that has some.
+    // Each locale is first looked up as is and then less specific, so
+    // "zh-Hant-TW" finds "zh"; failing that for every locale, a translation
+    // sharing just the language is used, so "zh" finds "zh-TW". `None`
+    // means the original text applies.
+    pub fn resolve(&self, field: TranslatedField, locales: &[Locale]) -> Option<&str> {
+        locales.iter()
+            .flat_map(Locale::fallbacks)
+            .find_map(|locale| self.get(field, &locale))
+            .or_else(|| {
+                locales.iter().find_map(|locale| {
+                    self.0.iter()
+                        .find(|((f, other), _)| *f == field && other.language() == locale.language())
+                        .map(|(_, text)| text.as_str())
+                })
+            })
+    }
+
+    pub fn to_list(&self) -> Vec<Translation> {
+        self.0.iter()
+            .map(|((field, locale), text)| Translation {
+                field: *field,
+                locale: locale.as_str().to_string(),
+                text: text.clone(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn locales(tags: &[&str]) -> Vec<Locale> {
+        tags.iter().map(|tag| Locale::parse(tag).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_parse_locale() {
+        assert_eq!(Locale::parse("ZH_tw").unwrap().as_str(), "zh-TW");
+        assert_eq!(Locale::parse("zh-hant-tw").unwrap().as_str(), "zh-Hant-TW");
+        assert_eq!(Locale::parse("es-419").unwrap().as_str(), "es-419");
+        assert!(Locale::parse("").is_err());
+        assert!(Locale::parse("*").is_err());
+        assert!(Locale::parse("e").is_err());
+        assert!(Locale::parse("zh--TW").is_err());
+    }
+
+    #[test]
+    fn test_accept_language() {
+        assert_eq!(accept_language("en;q=0.5, zh-TW, zh;q=0.8, *;q=0.1"), locales(&["zh-TW", "zh", "en"]));
+        assert_eq!(accept_language("fr;q=0, de;q=x, ja"), locales(&["ja"]));
+        assert_eq!(accept_language(""), locales(&[]));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut translations = Translations::default();
+        translations.insert(TranslatedField::Name, Locale::parse("zh-TW").unwrap(), "滷肉飯".to_string());
+        translations.insert(TranslatedField::Name, Locale::parse("ja").unwrap(), "ルーローハン".to_string());
+        let name = |tags: &[&str]| translations.resolve(TranslatedField::Name, &locales(tags));
+
+        assert_eq!(name(&["zh-TW"]), Some("滷肉飯"));
+        assert_eq!(name(&["ja-JP"]), Some("ルーローハン"));
+        // Exact or less specific matches win over a shared language.
+        assert_eq!(name(&["zh-CN", "ja"]), Some("ルーローハン"));
+        assert_eq!(name(&["zh-CN", "en"]), Some("滷肉飯"));
+        assert_eq!(name(&["en"]), None);
+        assert_eq!(translations.resolve(TranslatedField::Description, &locales(&["zh-TW"])), None);
+    }
+}
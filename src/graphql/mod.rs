@@ -1,15 +1,27 @@
-use juniper::{
-    RootNode,
-    EmptyMutation,
-};
+use juniper::{RootNode, ID};
 
 mod context;
-mod user;
-mod order;
-mod shop;
+pub mod user;
+pub mod order;
+pub mod shop;
+pub mod analytics;
+pub mod hours;
+pub mod search;
+pub mod locale;
+pub mod node;
+pub mod money;
+pub mod promotion;
+pub mod tax;
+pub mod audit;
+pub mod api_token;
+pub mod role;
+pub mod guard;
 mod guest;
+pub mod export;
 
-pub use context::Context;
+pub use context::{Context, Upload};
+
+use crate::error::Error;
 
 pub struct QueryRoot;
 
@@ -26,10 +38,36 @@ impl QueryRoot {
     fn guest() -> guest::QueryGuest {
         guest::QueryGuest
     }
+
+    fn node(context: &Context, id: ID) -> Result<Option<node::Node>, Error> {
+        node::find_node(context, &id)
+    }
+
+    // In the order of `ids`, null where the object no longer exists.
+    fn nodes(context: &Context, ids: Vec<ID>) -> Result<Vec<Option<node::Node>>, Error> {
+        node::find_nodes(context, &ids)
+    }
 }
 
-type Schema = RootNode<'static, QueryRoot, EmptyMutation<Context>>;
+pub struct MutationRoot;
+
+#[juniper::graphql_object(Context = Context)]
+impl MutationRoot {
+    fn user() -> user::MutationUser {
+        user::MutationUser
+    }
+
+    fn shop() -> shop::MutationShop {
+        shop::MutationShop
+    }
+
+    fn guest() -> guest::MutationGuest {
+        guest::MutationGuest
+    }
+}
+
+pub type Schema = RootNode<'static, QueryRoot, MutationRoot>;
 
 pub fn schema() -> Schema {
-    Schema::new(QueryRoot, EmptyMutation::new())
-}
\ No newline at end of file
+    Schema::new(QueryRoot, MutationRoot)
+}
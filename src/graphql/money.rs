@@ -0,0 +1,198 @@
+use crate::error::Error;
+
+// Most minor units a shop may price in.
+pub const MAX_MINOR_UNITS: i16 = 4;
+
+// Symbols and usual minor units of common currencies. Others are shown by
+// their code and default to two minor units.
+const KNOWN_CURRENCIES: [(&str, &str, i16); 8] = [
+    ("TWD", "NT$", 0),
+    ("USD", "$", 2),
+    ("EUR", "€", 2),
+    ("JPY", "¥", 0),
+    ("GBP", "£", 2),
+    ("HKD", "HK$", 2),
+    ("CNY", "CN¥", 2),
+    ("KRW", "₩", 0),
+];
+
+// The currency a shop prices in. Amounts are integers in its minor units.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Currency {
+    code: String,
+    minor_units: i16,
+}
+
+impl Currency {
+    pub fn new(code: String, minor_units: i16) -> Self {
+        Currency {
+            code: code,
+            minor_units: minor_units,
+        }
+    }
+
+    // `code` is an ISO 4217 code in any case. Without `minor_units` those
+    // usual for the currency are used.
+    pub fn checked(code: &str, minor_units: Option<i32>) -> Result<Self, Error> {
+        let code = code.trim().to_ascii_uppercase();
+        if code.len() != 3 || !code.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(Error::invalid_input(&format!(r#"Invalid currency "{}", expected an ISO 4217 code."#, code)));
+        }
+
+        let minor_units = match minor_units {
+            Some(minor_units) if minor_units < 0 || minor_units > MAX_MINOR_UNITS as i32 => {
+                return Err(Error::invalid_input(&format!("Minor units must be between 0 and {}.", MAX_MINOR_UNITS)));
+            }
+            Some(minor_units) => minor_units as i16,
+            None => KNOWN_CURRENCIES.iter()
+                .find(|(known, _, _)| *known == code)
+                .map_or(2, |&(_, _, minor_units)| minor_units),
+        };
+        Ok(Currency::new(code, minor_units))
+    }
+
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    pub fn minor_units(&self) -> i16 {
+        self.minor_units
+    }
+
+    fn symbol(&self) -> Option<&'static str> {
+        KNOWN_CURRENCIES.iter()
+            .find(|(code, _, _)| *code == self.code)
+            .map(|&(_, symbol, _)| symbol)
+    }
+
+    // `amount` in major units, such as "-1234.50".
+    pub fn decimal(&self, amount: i32) -> String {
+        let (whole, fraction) = self.split(amount);
+        let sign = if amount < 0 { "-" } else { "" };
+        match fraction {
+            Some(fraction) => format!("{}{}.{}", sign, whole, fraction),
+            None => format!("{}{}", sign, whole),
+        }
+    }
+
+    // `amount` for display, such as "-NT$1,234" or "CHF 12.50".
+    pub fn format(&self, amount: i32) -> String {
+        let (whole, fraction) = self.split(amount);
+        let mut digits = String::new();
+        for (i, digit) in whole.chars().enumerate() {
+            if i > 0 && (whole.len() - i) % 3 == 0 {
+                digits.push(',');
+            }
+            digits.push(digit);
+        }
+        if let Some(fraction) = fraction {
+            digits.push('.');
+            digits.push_str(&fraction);
+        }
+
+        let sign = if amount < 0 { "-" } else { "" };
+        match self.symbol() {
+            Some(symbol) => format!("{}{}{}", sign, symbol, digits),
+            None => format!("{}{} {}", sign, self.code, digits),
+        }
+    }
+
+    // Digits of the whole and fractional parts of `amount`, without its sign.
+    fn split(&self, amount: i32) -> (String, Option<String>) {
+        let amount = (amount as i64).abs();
+        if self.minor_units == 0 {
+            return (amount.to_string(), None);
+        }
+        let scale = 10i64.pow(self.minor_units as u32);
+        (
+            (amount / scale).to_string(),
+            Some(format!("{:0width$}", amount % scale, width = self.minor_units as usize)),
+        )
+    }
+}
+
+// New shops price in New Taiwan dollars without minor units, as every shop
+// did before currencies were configurable.
+impl Default for Currency {
+    fn default() -> Self {
+        Currency::new("TWD".to_string(), 0)
+    }
+}
+
+pub struct Money {
+    amount: i32,
+    currency: Currency,
+}
+
+impl Money {
+    pub fn new(amount: i32, currency: Currency) -> Self {
+        Money {
+            amount: amount,
+            currency: currency,
+        }
+    }
+}
+
+#[juniper::graphql_object]
+impl Money {
+    // In minor units, the same integer as the plain price fields.
+    fn amount(&self) -> i32 {
+        self.amount
+    }
+
+    // ISO 4217 code.
+    fn currency(&self) -> &str {
+        self.currency.code()
+    }
+
+    fn minor_units(&self) -> i32 {
+        self.currency.minor_units() as i32
+    }
+
+    // In major units as a plain decimal, such as "12.50".
+    fn decimal(&self) -> String {
+        self.currency.decimal(self.amount)
+    }
+
+    // For display, such as "NT$1,280" or "$12.50".
+    fn formatted(&self) -> String {
+        self.currency.format(self.amount)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Currency;
+
+    #[test]
+    fn test_checked() {
+        assert_eq!(Currency::checked("usd", None).unwrap(), Currency::new("USD".to_string(), 2));
+        assert_eq!(Currency::checked("TWD", None).unwrap(), Currency::new("TWD".to_string(), 0));
+        assert_eq!(Currency::checked("CHF", None).unwrap(), Currency::new("CHF".to_string(), 2));
+        assert_eq!(Currency::checked("TWD", Some(2)).unwrap(), Currency::new("TWD".to_string(), 2));
+        assert!(Currency::checked("NT$", None).is_err());
+        assert!(Currency::checked("US", None).is_err());
+        assert!(Currency::checked("USD", Some(5)).is_err());
+        assert!(Currency::checked("USD", Some(-1)).is_err());
+    }
+
+    #[test]
+    fn test_format() {
+        let twd = Currency::default();
+        assert_eq!(twd.format(1280), "NT$1,280");
+        assert_eq!(twd.format(1234567), "NT$1,234,567");
+        assert_eq!(twd.format(-80), "-NT$80");
+        assert_eq!(twd.decimal(1280), "1280");
+
+        let usd = Currency::checked("USD", None).unwrap();
+        assert_eq!(usd.format(1250), "$12.50");
+        assert_eq!(usd.format(5), "$0.05");
+        assert_eq!(usd.format(-123456), "-$1,234.56");
+        assert_eq!(usd.decimal(-5), "-0.05");
+
+        let chf = Currency::checked("CHF", None).unwrap();
+        assert_eq!(chf.format(100000), "CHF 1,000.00");
+        assert_eq!(Currency::checked("KWD", Some(3)).unwrap().decimal(1500), "1.500");
+        assert_eq!(usd.format(i32::MIN), "-$21,474,836.48");
+    }
+}
@@ -0,0 +1,240 @@
+use juniper::ID;
+use uuid::Uuid;
+use crate::{
+    graphql::{
+        context::Context,
+        guest::valid_guest_session,
+        role::Capability,
+        shop::{Shop, Product, find_shop, find_product},
+        order::{Order, Cart},
+        user::User,
+    },
+    error::Error,
+};
+
+pub const MAX_NODES: usize = 100;
+
+// What a global ID refers to. Shop scoped types carry their shop so they
+// can be looked up and authorized without scanning every shop.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GlobalId {
+    Shop(Uuid),
+    Product(Uuid, Uuid),
+    Order(Uuid, Uuid),
+    Cart(Uuid, Uuid),
+    User(Uuid),
+}
+
+impl GlobalId {
+    // Encoded as "Product:{shop_id}:{product_key}" and so on, then base64
+    // so clients treat it as opaque.
+    pub fn encode(&self) -> ID {
+        let text = match self {
+            GlobalId::Shop(id) => format!("Shop:{}", id),
+            GlobalId::Product(shop_id, key) => format!("Product:{}:{}", shop_id, key),
+            GlobalId::Order(shop_id, id) => format!("Order:{}:{}", shop_id, id),
+            GlobalId::Cart(shop_id, id) => format!("Cart:{}:{}", shop_id, id),
+            GlobalId::User(id) => format!("User:{}", id),
+        };
+        ID::from(base64::encode_config(&text, base64::URL_SAFE_NO_PAD))
+    }
+
+    pub fn decode(id: &str) -> Result<Self, Error> {
+        let invalid = || Error::invalid_input(&format!(r#"Invalid node id "{}"."#, id));
+        let text = base64::decode_config(id, base64::URL_SAFE_NO_PAD)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or_else(invalid)?;
+
+        let parts: Vec<&str> = text.split(':').collect();
+        let uuids = parts[1..].iter()
+            .map(|part| Uuid::parse_str(part))
+            .collect::<Result<Vec<Uuid>, _>>()
+            .map_err(|_| invalid())?;
+        match (parts[0], uuids.as_slice()) {
+            ("Shop", &[id]) => Ok(GlobalId::Shop(id)),
+            ("Product", &[shop_id, key]) => Ok(GlobalId::Product(shop_id, key)),
+            ("Order", &[shop_id, id]) => Ok(GlobalId::Order(shop_id, id)),
+            ("Cart", &[shop_id, id]) => Ok(GlobalId::Cart(shop_id, id)),
+            ("User", &[id]) => Ok(GlobalId::User(id)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+pub enum Node {
+    Shop(Shop),
+    Product(Product),
+    Order(Order),
+    Cart(Cart),
+    User(User),
+}
+
+impl Node {
+    fn global_id(&self) -> GlobalId {
+        match self {
+            Node::Shop(shop) => shop.global_id(),
+            Node::Product(product) => product.global_id(),
+            Node::Order(order) => order.global_id(),
+            Node::Cart(cart) => cart.global_id(),
+            Node::User(user) => user.global_id(),
+        }
+    }
+}
+
+juniper::graphql_interface!(Node: Context |&self| {
+    description: "An object that can be refetched by its `id` through `node`. \
+        Its UUID, as taken by other arguments, is `uuid`, or `key` on products."
+
+    field id() -> ID {
+        self.global_id().encode()
+    }
+
+    instance_resolvers: |_| {
+        &Shop => match *self { Node::Shop(ref shop) => Some(shop), _ => None },
+        &Product => match *self { Node::Product(ref product) => Some(product), _ => None },
+        &Order => match *self { Node::Order(ref order) => Some(order), _ => None },
+        &Cart => match *self { Node::Cart(ref cart) => Some(cart), _ => None },
+        &User => match *self { Node::User(ref user) => Some(user), _ => None },
+    }
+});
+
+// Guests see their own orders, and members who view orders every
+// order of the shop, as through `guest.orders` and `UserShop.orders`.
+fn find_order(context: &Context, shop_id: Uuid, id: Uuid) -> Result<Option<Order>, Error> {
+    let is_staff = context.shop_capabilities(shop_id).map(|capabilities| capabilities.contains(Capability::ViewOrders));
+    let guest_session_id = match is_staff {
+        Ok(true) => None,
+        Ok(false) if context.guest_session_id().is_err() => return Err(Error::unauthorized()),
+        Err(err) if context.guest_session_id().is_err() => return Err(err),
+        _ => Some(valid_guest_session(context)?),
+    };
+
+    Ok(
+        context.state().orders().orders(Some(shop_id), guest_session_id)?
+            .into_iter()
+            .find(|order| order.id() == id)
+    )
+}
+
+// Carts are only visible to the guest they belong to, as through `guest.carts`.
+fn find_cart(context: &Context, shop_id: Uuid, id: Uuid) -> Result<Option<Cart>, Error> {
+    let guest_session_id = valid_guest_session(context)?;
+
+    Ok(
+        context.state().carts().carts(Some(shop_id), Some(guest_session_id))?
+            .into_iter()
+            .find(|cart| cart.id() == id)
+    )
+}
+
+// `None` when the object no longer exists. Fails like the resolvers owning
+// the object when the request may not see it.
+pub fn find_node(context: &Context, id: &str) -> Result<Option<Node>, Error> {
+    let found = match GlobalId::decode(id)? {
+        GlobalId::Shop(id) => find_shop(context, id).map(Node::Shop),
+        GlobalId::Product(shop_id, key) => find_product(context, shop_id, key).map(Node::Product),
+        GlobalId::User(id) => context.state().users().user(id).map(Node::User),
+        GlobalId::Order(shop_id, id) => return Ok(find_order(context, shop_id, id)?.map(Node::Order)),
+        GlobalId::Cart(shop_id, id) => return Ok(find_cart(context, shop_id, id)?.map(Node::Cart)),
+    };
+
+    match found {
+        Ok(node) => Ok(Some(node)),
+        Err(ref err) if err.error_type() == "NotFound" => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn find_nodes(context: &Context, ids: &[ID]) -> Result<Vec<Option<Node>>, Error> {
+    if ids.len() > MAX_NODES {
+        return Err(Error::invalid_input(&format!("At most {} nodes may be fetched at once.", MAX_NODES)));
+    }
+    ids.iter().map(|id| find_node(context, id)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use uuid::Uuid;
+    use serde_json::json;
+    use crate::tests::{
+        harness::{Api, Memory, TestServer, error_type},
+        seed,
+    };
+    use super::GlobalId;
+
+    #[test]
+    fn test_global_id() {
+        let id = GlobalId::Product(seed::DINER, seed::PORK_RICE);
+        assert_eq!(GlobalId::decode(&id.encode()).unwrap(), id);
+        assert!(!id.encode().contains(&seed::PORK_RICE.to_string()));
+
+        let forged = base64::encode_config("Product:x", base64::URL_SAFE_NO_PAD);
+        assert!(GlobalId::decode(&forged).is_err());
+        let forged = base64::encode_config(format!("Shop:{}:{}", Uuid::nil(), Uuid::nil()), base64::URL_SAFE_NO_PAD);
+        assert!(GlobalId::decode(&forged).is_err());
+        assert!(GlobalId::decode("not base64!").is_err());
+    }
+
+    #[test]
+    fn test_node() {
+        node(&mut Memory::seeded());
+    }
+
+    #[test]
+    #[ignore]
+    fn test_node_postgres() {
+        node(&mut TestServer::start());
+    }
+
+    fn node(api: &mut dyn Api) {
+        let query = "query Node($id: ID!) {
+            node(id: $id) { __typename id ... on Product { name } ... on Order { orderNumber total } ... on Cart { total } }
+        }";
+        let variables = |id: GlobalId| json!({ "id": id.encode().to_string() });
+
+        let product = GlobalId::Product(seed::DINER, seed::PORK_RICE);
+        let response = api.graphql(query, variables(product), &[]);
+        assert_eq!(
+            response["data"]["node"],
+            json!({ "__typename": "Product", "id": product.encode().to_string(), "name": "Braised Pork Rice" }),
+        );
+
+        let response = api.graphql(query, variables(GlobalId::Shop(Uuid::nil())), &[]);
+        assert_eq!(response["data"]["node"], json!(null));
+        let response = api.graphql(query, json!({ "id": "bm90IGFuIGlk" }), &[]);
+        assert_eq!(error_type(&response), Some("InvalidInput"));
+
+        // Orders follow `UserShop.orders` and `guest.orders`.
+        let order = variables(GlobalId::Order(seed::DINER, seed::ORDER));
+        assert_eq!(error_type(&api.graphql(query, order.clone(), &[])), Some("NoValidCookie"));
+        assert_eq!(error_type(&api.graphql(query, order.clone(), &[("USSID", seed::STAFF_SESSION)])), Some("Unauthorized"));
+        let response = api.graphql(query, order.clone(), &[("USSID", seed::OWNER_SESSION)]);
+        assert_eq!(
+            response["data"]["node"],
+            json!({ "__typename": "Order", "id": order["id"], "orderNumber": 1, "total": 100 }),
+        );
+        let response = api.graphql(query, order, &[("GSSID", seed::GUEST_SESSION)]);
+        assert_eq!(response["data"]["node"]["orderNumber"], json!(1));
+
+        // Carts only to their guest.
+        let cart = variables(GlobalId::Cart(seed::DINER, seed::CART));
+        assert_eq!(error_type(&api.graphql(query, cart.clone(), &[("USSID", seed::OWNER_SESSION)])), Some("NoValidCookie"));
+        let response = api.graphql(query, cart, &[("GSSID", seed::GUEST_SESSION)]);
+        assert_eq!(response["data"]["node"]["total"], json!(60));
+
+        // The IDs objects report are the ones they are found by.
+        let response = api.execute("{ shop { search(name: \"diner\") { id products { id name } } } }", &[]);
+        let shop = &response["data"]["shop"]["search"][0];
+        let product = &shop["products"][0];
+        let response = api.graphql(
+            "query Nodes($ids: [ID!]!) { nodes(ids: $ids) { __typename ... on Product { name } } }",
+            json!({ "ids": [shop["id"], product["id"], GlobalId::User(seed::STAFF).encode().to_string()] }),
+            &[],
+        );
+        assert_eq!(
+            response["data"]["nodes"],
+            json!([{ "__typename": "Shop" }, { "__typename": "Product", "name": product["name"] }, { "__typename": "User" }]),
+        );
+    }
+}
@@ -1,278 +1,150 @@
+use std::{convert::TryFrom, sync::Mutex};
+use juniper::ID;
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
 use crate::{
-    sql::{
-        clause::Clause,
+    graphql::{
+        context::Context,
+        node::{GlobalId, Node},
+        money::Money,
+        promotion::{self, AppliedPromotion, Line},
+        tax::{Charges, TaxLine},
     },
-    graphql::context::Context,
-    state::db::Connection,
     error::Error,
     utils::dict::Dict,
 };
 
-pub fn query_orders(mut db_connection: Connection, shop_id: Option<Uuid>, guest_session_id: Option<Uuid>) -> Result<Option<Vec<Order>>, Error> {
-    let mut clause = Clause::new();
-    if let Some(shop_id) = shop_id.as_ref() {
-        clause.and(Clause::equal("shop_id", format!("'{}'", shop_id)));
-    }
-    if let Some(guest_session_id) = guest_session_id.as_ref() {
-        clause.and(Clause::equal("guest_session_id", format!("'{}'", guest_session_id)));
-    }
-
-    let rows = query!(
-        db_connection,
-        format!(
-            "WITH
-                query_orders AS (
-                    SELECT * FROM orders {}
-                ),
-                query_items AS (
-                    SELECT id order_id, (each(items)).* FROM query_orders
-                ),
-                query_customizes AS (
-                    SELECT key item_key, (query_product_item_customize_items(value::PRODUCT_ITEM)).* FROM query_items
-                )
-            SELECT
-                id order_id,
-                guest_session_id,
-                shop_id,
-                order_number,
-                order_at,
-                item_key,
-                (item).product_key,
-                (item).name,
-                (item).price,
-                (item).count,
-                (item).remark,
-                (item).order_at item_order_at,
-                customize_key,
-                (customize).name customize_name,
-                (customize).selection,
-                (customize).selection_key,
-                (customize).price selection_price,
-                (customize).order_at customize_order_at
-            FROM
-                query_orders
-            LEFT JOIN
-                (
-                    SELECT
-                        order_id,
-                        query_items.key::UUID item_key,
-                        query_items.value::PRODUCT_ITEM item,
-                        query_customizes.key::UUID customize_key,
-                        customize
-                    FROM
-                        query_items
-                    LEFT JOIN
-                        query_customizes
-                    ON
-                        query_items.key = query_customizes.item_key
-                ) item_join_cus
-            ON
-                query_orders.id = item_join_cus.order_id
-            ",
-            clause,
-        ).as_str(),
-        &[],
-    )?;
-
-    let mut orders = Dict::new();
-    for row in rows.iter() {
-        let order_id = row.get::<&str, Uuid>("order_id");
-        let order = if let Some(order) = orders.ref_mut_value(order_id) {
-            order
-        } else {
-            let order = Order::new(
-                order_id,
-                row.get("guest_session_id"),
-                row.get("shop_id"),
-                row.get("order_number"),
-                row.get("order_at"),
-            );
-            orders.insert_uncheck(order_id, order)
-        };
-
-        if let Ok(item_key) = row.try_get::<&str, Uuid>("item_key") {
-            let item = if let Some(item) = order.ref_mut_item(item_key) {
-                item
-            } else {
-                let item = ProductItem::new(
-                    item_key,
-                    row.get("product_key"),
-                    row.get("name"),
-                    row.get("price"),
-                    row.get("count"),
-                    row.get("remark"),
-                    row.get("item_order_at"),
-                );
-                order.insert_item_uncheck(item_key, item)
-            };
-
-            if let Ok(customize_key) = row.try_get::<&str, Uuid>("customize_key") {
-                if let None = item.ref_mut_customize(customize_key) {
-                    let customize = CustomizeItem::new(
-                        customize_key,
-                        row.get("customize_name"),
-                        row.get("selection"),
-                        row.get("selection_key"),
-                        row.get("selection_price"),
-                        row.get("customize_order_at"),
-                    );
-                    item.insert_customize_uncheck(customize_key, customize);
-                }
-            }
-        }
-    }
+// Price of one unit of an item: its product price plus every selected option.
+// `SALES_ITEMS` in the Postgres repository works the same out in SQL for
+// `salesSummary`, so keep the two in step.
+pub fn unit_price<I: IntoIterator<Item = Option<i32>>>(price: i32, selection_prices: I) -> Result<i32, Error> {
+    sum(Some(Ok(price)).into_iter().chain(selection_prices.into_iter().map(|price| Ok(price.unwrap_or(0)))))
+}
 
-    Ok(Some(orders.values()))
+// Amounts fail rather than wrap around when too large to keep.
+pub fn subtotal(unit_price: i32, count: i32) -> Result<i32, Error> {
+    unit_price.checked_mul(count).ok_or_else(too_large)
 }
 
-pub fn query_carts(mut db_connection: Connection, shop_id: Option<Uuid>, guest_session_id: Option<Uuid>) -> Result<Vec<Cart>, Error> {
-    let mut clause = Clause::new();
-    if let Some(shop_id) = shop_id.as_ref() {
-        clause.and(Clause::equal("shop_id", format!("'{}'", shop_id)));
-    }
-    if let Some(guest_session_id) = guest_session_id.as_ref() {
-        clause.and(Clause::equal("guest_session_id", format!("'{}'", guest_session_id)));
-    }
-
-    let rows = query!(
-        db_connection,
-        format!(
-            "WITH
-                query_carts AS (
-                    SELECT * FROM cart {}
-                ),
-                query_items AS (
-                    SELECT id cart_id, (each(items)).* FROM query_carts
-                ),
-                query_customizes AS (
-                    SELECT key item_key, (query_product_item_customize_items(value::PRODUCT_ITEM)).* FROM query_items
-                )
-            SELECT
-                id cart_id,
-                guest_session_id,
-                shop_id,
-                item_key,
-                (item).product_key,
-                (item).name,
-                (item).price,
-                (item).count,
-                (item).remark,
-                (item).order_at item_order_at,
-                customize_key,
-                (customize).name customize_name,
-                (customize).selection,
-                (customize).selection_key,
-                (customize).price selection_price,
-                (customize).order_at customize_order_at
-            FROM
-                query_carts
-            LEFT JOIN
-                (
-                    SELECT
-                        cart_id,
-                        query_items.key::UUID item_key,
-                        query_items.value::PRODUCT_ITEM item,
-                        query_customizes.key::UUID customize_key,
-                        customize
-                    FROM
-                        query_items
-                    LEFT JOIN
-                        query_customizes
-                    ON
-                        query_items.key = query_customizes.item_key
-                ) item_join_cus
-            ON
-                query_carts.id = item_join_cus.cart_id
-            ",
-            clause,
-        ).as_str(),
-        &[],
-    )?;
-
-    let mut carts = Dict::new();
-    for row in rows.iter() {
-        let cart_id = row.get::<&str, Uuid>("cart_id");
-        let cart = if let Some(cart) = carts.ref_mut_value(cart_id) {
-            cart
-        } else {
-            let cart = Cart::new(
-                cart_id,
-                row.get("shop_id"),
-                row.get("guest_session_id"),
-            );
-            carts.insert_uncheck(cart_id, cart)
-        };
+fn sum<I: IntoIterator<Item = Result<i32, Error>>>(amounts: I) -> Result<i32, Error> {
+    amounts.into_iter().try_fold(0i32, |total, amount| total.checked_add(amount?).ok_or_else(too_large))
+}
 
-        if let Ok(item_key) = row.try_get::<&str, Uuid>("item_key") {
-            let item = if let Some(item) = cart.ref_mut_item(item_key) {
-                item
-            } else {
-                let item = ProductItem::new(
-                    item_key,
-                    row.get("product_key"),
-                    row.get("name"),
-                    row.get("price"),
-                    row.get("count"),
-                    row.get("remark"),
-                    row.get("item_order_at"),
-                );
-                cart.insert_item_uncheck(item_key, item)
-            };
-
-            if let Ok(customize_key) = row.try_get::<&str, Uuid>("customize_key") {
-                if let None = item.ref_mut_customize(customize_key) {
-                    let customize = CustomizeItem::new(
-                        customize_key,
-                        row.get("customize_name"),
-                        row.get("selection"),
-                        row.get("selection_key"),
-                        row.get("selection_price"),
-                        row.get("customize_order_at"),
-                    );
-                    item.insert_customize_uncheck(customize_key, customize);
-                }
-            }
-        }
-    }
+// What the subtotal comes to after `discount`, with `surcharge` added.
+fn total(subtotal: i32, discount: i32, surcharge: i32) -> Result<i32, Error> {
+    subtotal.checked_sub(discount)
+        .and_then(|total| total.checked_add(surcharge))
+        .ok_or_else(too_large)
+}
 
-    Ok(carts.values())
+fn too_large() -> Error {
+    Error::invalid_input("Amount is too large.")
 }
 
 pub struct Cart {
     id: Uuid,
     shop_id: Uuid,
     guest_session_id: Uuid,
+    coupon_code: Option<String>,
     items: Dict<Uuid, ProductItem>,
+    // Worked out by the first money field asked for.
+    breakdown: Mutex<Option<Breakdown>>,
+}
+
+// What a cart would come to if placed now.
+#[derive(Clone)]
+struct Breakdown {
+    promotions: Vec<AppliedPromotion>,
+    // Why the coupon doesn't apply if it doesn't. Checkout fails in that case.
+    coupon_error: Option<String>,
+    discount: i32,
+    charges: Charges,
 }
 
 impl Cart {
-    fn new(
+    pub fn new(
         id: Uuid,
         shop_id: Uuid,
         guest_session_id: Uuid,
+        coupon_code: Option<String>,
     ) -> Self {
         Cart {
             id: id,
             shop_id: shop_id,
             guest_session_id: guest_session_id,
+            coupon_code: coupon_code,
             items: Dict::new(),
+            breakdown: Mutex::new(None),
         }
     }
 
-    fn ref_mut_item(&mut self, key: Uuid) -> Option<&mut ProductItem> {
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn global_id(&self) -> GlobalId {
+        GlobalId::Cart(self.shop_id, self.id)
+    }
+
+    pub fn ref_mut_item(&mut self, key: Uuid) -> Option<&mut ProductItem> {
         self.items.ref_mut_value(key)
     }
 
-    fn insert_item_uncheck(&mut self, key: Uuid, item: ProductItem) -> &mut ProductItem {
+    pub fn insert_item_uncheck(&mut self, key: Uuid, item: ProductItem) -> &mut ProductItem {
         self.items.insert_uncheck(key, item)
     }
+
+    pub fn subtotal(&self) -> Result<i32, Error> {
+        sum(self.items.ref_values().iter().map(ProductItem::subtotal))
+    }
+
+    pub fn lines(&self) -> Result<Vec<Line>, Error> {
+        self.items.ref_values().iter().map(Line::try_from).collect()
+    }
+
+    // Promotions, service charge and taxes at the shop's current rates,
+    // looked up once however many of the cart's fields need them.
+    fn breakdown(&self, context: &Context) -> Result<Breakdown, Error> {
+        if let Some(breakdown) = self.breakdown.lock().unwrap().as_ref() {
+            return Ok(breakdown.clone());
+        }
+
+        let promotions = context.state().promotions().promotions(self.shop_id, None)?;
+        let lines = self.lines()?;
+        let now = Utc::now();
+        let code = self.coupon_code.as_ref().map(String::as_str);
+        let (promotions, coupon_error) = match promotion::redeem(&promotions, code, &lines, now) {
+            Ok(applied) => (applied, None),
+            Err(err) => (promotion::apply(&promotions, None, &lines, now), Some(err.message().to_string())),
+        };
+        let discount = sum(promotions.iter().map(|promotion| Ok(promotion.discount())))?;
+
+        let settings = context.state().shops().tax_settings(self.shop_id)?;
+        let product_keys: Vec<Uuid> = lines.iter().map(|line| line.product_key).collect();
+        let series = context.state().catalog().product_series(self.shop_id, &product_keys)?;
+        let breakdown = Breakdown {
+            charges: settings.charges(self.shop_id, &lines, &series, discount),
+            promotions: promotions,
+            coupon_error: coupon_error,
+            discount: discount,
+        };
+        *self.breakdown.lock().unwrap() = Some(breakdown.clone());
+        Ok(breakdown)
+    }
+
+    fn total(&self, context: &Context) -> Result<i32, Error> {
+        let breakdown = self.breakdown(context)?;
+        total(self.subtotal()?, breakdown.discount, breakdown.charges.surcharge())
+    }
 }
 
-#[juniper::graphql_object(Context = Context)]
+#[juniper::graphql_object(Context = Context, interfaces = [Node])]
 impl Cart {
-    fn id(&self) -> Uuid {
+    fn id(&self) -> ID {
+        self.global_id().encode()
+    }
+
+    fn uuid(&self) -> Uuid {
         self.id
     }
 
@@ -287,6 +159,75 @@ impl Cart {
     fn items(&self) -> &Vec<ProductItem> {
         self.items.ref_values()
     }
+
+    fn coupon_code(&self) -> &Option<String> {
+        &self.coupon_code
+    }
+
+    // Why the coupon won't be applied, such as the minimum spend not being met.
+    fn coupon_error(&self, context: &Context) -> Result<Option<String>, Error> {
+        Ok(self.breakdown(context)?.coupon_error)
+    }
+
+    fn promotions(&self, context: &Context) -> Result<Vec<AppliedPromotion>, Error> {
+        Ok(self.breakdown(context)?.promotions)
+    }
+
+    // Before any discount.
+    fn subtotal(&self) -> Result<i32, Error> {
+        self.subtotal()
+    }
+
+    fn subtotal_money(&self, context: &Context) -> Result<Money, Error> {
+        Ok(Money::new(self.subtotal()?, context.currency(self.shop_id)?))
+    }
+
+    fn discount(&self, context: &Context) -> Result<i32, Error> {
+        Ok(self.breakdown(context)?.discount)
+    }
+
+    fn discount_money(&self, context: &Context) -> Result<Money, Error> {
+        Ok(Money::new(self.breakdown(context)?.discount, context.currency(self.shop_id)?))
+    }
+
+    // Whether prices include tax, so `tax` is part of them rather than added.
+    fn tax_inclusive(&self, context: &Context) -> Result<bool, Error> {
+        Ok(self.breakdown(context)?.charges.inclusive())
+    }
+
+    // In basis points, of the discounted subtotal.
+    fn service_charge_rate(&self, context: &Context) -> Result<i32, Error> {
+        Ok(self.breakdown(context)?.charges.service_charge_rate())
+    }
+
+    fn service_charge(&self, context: &Context) -> Result<i32, Error> {
+        Ok(self.breakdown(context)?.charges.service_charge())
+    }
+
+    fn service_charge_money(&self, context: &Context) -> Result<Money, Error> {
+        Ok(Money::new(self.breakdown(context)?.charges.service_charge(), context.currency(self.shop_id)?))
+    }
+
+    fn taxes(&self, context: &Context) -> Result<Vec<TaxLine>, Error> {
+        Ok(self.breakdown(context)?.charges.taxes().clone())
+    }
+
+    fn tax(&self, context: &Context) -> Result<i32, Error> {
+        Ok(self.breakdown(context)?.charges.tax())
+    }
+
+    fn tax_money(&self, context: &Context) -> Result<Money, Error> {
+        Ok(Money::new(self.breakdown(context)?.charges.tax(), context.currency(self.shop_id)?))
+    }
+
+    // After discounts, with the service charge and any tax not included.
+    fn total(&self, context: &Context) -> Result<i32, Error> {
+        self.total(context)
+    }
+
+    fn total_money(&self, context: &Context) -> Result<Money, Error> {
+        Ok(Money::new(self.total(context)?, context.currency(self.shop_id)?))
+    }
 }
 
 pub struct Order {
@@ -296,10 +237,12 @@ pub struct Order {
     order_number: i32,
     order_at: DateTime<Utc>,
     items: Dict<Uuid, ProductItem>,
+    promotions: Vec<AppliedPromotion>,
+    charges: Charges,
 }
 
 impl Order {
-    fn new(
+    pub fn new(
         id: Uuid,
         guest_session_id: Uuid,
         shop_id: Uuid,
@@ -313,21 +256,89 @@ impl Order {
             order_number: order_number,
             order_at: order_at,
             items: Dict::new(),
+            promotions: Vec::new(),
+            charges: Charges::none(shop_id),
         }
     }
 
-    fn ref_mut_item(&mut self, key: Uuid) -> Option<&mut ProductItem> {
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn shop_id(&self) -> Uuid {
+        self.shop_id
+    }
+
+    pub fn global_id(&self) -> GlobalId {
+        GlobalId::Order(self.shop_id, self.id)
+    }
+
+    pub fn order_number(&self) -> i32 {
+        self.order_number
+    }
+
+    pub fn order_at(&self) -> DateTime<Utc> {
+        self.order_at
+    }
+
+    pub fn items(&self) -> &Vec<ProductItem> {
+        self.items.ref_values()
+    }
+
+    pub fn ref_mut_item(&mut self, key: Uuid) -> Option<&mut ProductItem> {
         self.items.ref_mut_value(key)
     }
 
-    fn insert_item_uncheck(&mut self, key: Uuid, item: ProductItem) -> &mut ProductItem {
+    pub fn insert_item_uncheck(&mut self, key: Uuid, item: ProductItem) -> &mut ProductItem {
         self.items.insert_uncheck(key, item)
     }
+
+    // Promotions as they applied when the order was placed.
+    pub fn promotions(&self) -> &Vec<AppliedPromotion> {
+        &self.promotions
+    }
+
+    pub fn add_promotion(&mut self, promotion: AppliedPromotion) {
+        self.promotions.push(promotion);
+    }
+
+    pub fn subtotal(&self) -> Result<i32, Error> {
+        sum(self.items.ref_values().iter().map(ProductItem::subtotal))
+    }
+
+    pub fn discount(&self) -> Result<i32, Error> {
+        sum(self.promotions.iter().map(|promotion| Ok(promotion.discount())))
+    }
+
+    // Service charge and taxes as they were when the order was placed.
+    pub fn charges(&self) -> &Charges {
+        &self.charges
+    }
+
+    pub fn set_charges(&mut self, charges: Charges) {
+        self.charges = charges;
+    }
+
+    pub fn add_tax(&mut self, tax: TaxLine) {
+        self.charges.add_tax(tax);
+    }
+
+    pub fn total(&self) -> Result<i32, Error> {
+        total(self.subtotal()?, self.discount()?, self.charges.surcharge())
+    }
+
+    pub fn item_count(&self) -> Result<i32, Error> {
+        sum(self.items.ref_values().iter().map(|item| Ok(item.count)))
+    }
 }
 
-#[juniper::graphql_object(Context = Context)]
+#[juniper::graphql_object(Context = Context, interfaces = [Node])]
 impl Order {
-    fn id(&self) -> Uuid {
+    fn id(&self) -> ID {
+        self.global_id().encode()
+    }
+
+    fn uuid(&self) -> Uuid {
         self.id
     }
 
@@ -350,9 +361,71 @@ impl Order {
     fn order_at(&self) -> DateTime<Utc> {
         self.order_at
     }
+
+    fn promotions(&self) -> &Vec<AppliedPromotion> {
+        &self.promotions
+    }
+
+    // Before any discount.
+    fn subtotal(&self) -> Result<i32, Error> {
+        self.subtotal()
+    }
+
+    fn subtotal_money(&self, context: &Context) -> Result<Money, Error> {
+        Ok(Money::new(self.subtotal()?, context.currency(self.shop_id)?))
+    }
+
+    fn discount(&self) -> Result<i32, Error> {
+        self.discount()
+    }
+
+    fn discount_money(&self, context: &Context) -> Result<Money, Error> {
+        Ok(Money::new(self.discount()?, context.currency(self.shop_id)?))
+    }
+
+    fn tax_inclusive(&self) -> bool {
+        self.charges.inclusive()
+    }
+
+    fn service_charge_rate(&self) -> i32 {
+        self.charges.service_charge_rate()
+    }
+
+    fn service_charge(&self) -> i32 {
+        self.charges.service_charge()
+    }
+
+    fn service_charge_money(&self, context: &Context) -> Result<Money, Error> {
+        Ok(Money::new(self.charges.service_charge(), context.currency(self.shop_id)?))
+    }
+
+    fn taxes(&self) -> &Vec<TaxLine> {
+        self.charges.taxes()
+    }
+
+    fn tax(&self) -> i32 {
+        self.charges.tax()
+    }
+
+    fn tax_money(&self, context: &Context) -> Result<Money, Error> {
+        Ok(Money::new(self.charges.tax(), context.currency(self.shop_id)?))
+    }
+
+    fn total(&self) -> Result<i32, Error> {
+        self.total()
+    }
+
+    fn total_money(&self, context: &Context) -> Result<Money, Error> {
+        Ok(Money::new(self.total()?, context.currency(self.shop_id)?))
+    }
+
+    fn item_count(&self) -> Result<i32, Error> {
+        self.item_count()
+    }
 }
 
-struct ProductItem {
+pub struct ProductItem {
+    shop_id: Uuid,
     key: Uuid,
     product_key: Uuid,
     name: String,
@@ -364,7 +437,8 @@ struct ProductItem {
 }
 
 impl ProductItem {
-    fn new(
+    pub fn new(
+        shop_id: Uuid,
         key: Uuid,
         product_key: Uuid,
         name: String,
@@ -374,6 +448,7 @@ impl ProductItem {
         order_at: DateTime<Utc>,
     ) -> Self {
         ProductItem {
+            shop_id: shop_id,
             key: key,
             product_key: product_key,
             name: name,
@@ -385,13 +460,53 @@ impl ProductItem {
         }
     }
 
-    fn ref_mut_customize(&mut self, key: Uuid) -> Option<&mut CustomizeItem> {
+    pub fn key(&self) -> Uuid {
+        self.key
+    }
+
+    pub fn product_key(&self) -> Uuid {
+        self.product_key
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn price(&self) -> i32 {
+        self.price
+    }
+
+    pub fn count(&self) -> i32 {
+        self.count
+    }
+
+    pub fn remark(&self) -> &Option<String> {
+        &self.remark
+    }
+
+    pub fn order_at(&self) -> DateTime<Utc> {
+        self.order_at
+    }
+
+    pub fn customizes(&self) -> &Vec<CustomizeItem> {
+        self.customizes.ref_values()
+    }
+
+    pub fn ref_mut_customize(&mut self, key: Uuid) -> Option<&mut CustomizeItem> {
         self.customizes.ref_mut_value(key)
     }
 
-    fn insert_customize_uncheck(&mut self, key: Uuid, cus: CustomizeItem) -> &mut CustomizeItem {
+    pub fn insert_customize_uncheck(&mut self, key: Uuid, cus: CustomizeItem) -> &mut CustomizeItem {
         self.customizes.insert_uncheck(key, cus)
     }
+
+    pub fn unit_price(&self) -> Result<i32, Error> {
+        unit_price(self.price, self.customizes.ref_values().iter().map(|cus| cus.selection_price))
+    }
+
+    pub fn subtotal(&self) -> Result<i32, Error> {
+        subtotal(self.unit_price()?, self.count)
+    }
 }
 
 #[juniper::graphql_object(Context = Context)]
@@ -412,6 +527,10 @@ impl ProductItem {
         self.price
     }
 
+    fn price_money(&self, context: &Context) -> Result<Money, Error> {
+        Ok(Money::new(self.price, context.currency(self.shop_id)?))
+    }
+
     fn count(&self) -> i32 {
         self.count
     }
@@ -427,9 +546,26 @@ impl ProductItem {
     fn customizes(&self) -> &Vec<CustomizeItem> {
         self.customizes.ref_values()
     }
+
+    fn unit_price(&self) -> Result<i32, Error> {
+        self.unit_price()
+    }
+
+    fn unit_price_money(&self, context: &Context) -> Result<Money, Error> {
+        Ok(Money::new(self.unit_price()?, context.currency(self.shop_id)?))
+    }
+
+    fn subtotal(&self) -> Result<i32, Error> {
+        self.subtotal()
+    }
+
+    fn subtotal_money(&self, context: &Context) -> Result<Money, Error> {
+        Ok(Money::new(self.subtotal()?, context.currency(self.shop_id)?))
+    }
 }
 
-struct CustomizeItem {
+pub struct CustomizeItem {
+    shop_id: Uuid,
     customize_key: Uuid,
     name: String,
     selection: Option<String>,
@@ -439,7 +575,8 @@ struct CustomizeItem {
 }
 
 impl CustomizeItem {
-    fn new(
+    pub fn new(
+        shop_id: Uuid,
         customize_key: Uuid,
         name: String,
         selection: Option<String>,
@@ -448,6 +585,7 @@ impl CustomizeItem {
         order_at: DateTime<Utc>,
     ) -> Self {
         CustomizeItem {
+            shop_id: shop_id,
             customize_key: customize_key,
             name: name,
             selection: selection,
@@ -456,6 +594,26 @@ impl CustomizeItem {
             order_at: order_at,
         }
     }
+
+    pub fn customize_key(&self) -> Uuid {
+        self.customize_key
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn selection(&self) -> &Option<String> {
+        &self.selection
+    }
+
+    pub fn selection_price(&self) -> Option<i32> {
+        self.selection_price
+    }
+
+    pub fn order_at(&self) -> DateTime<Utc> {
+        self.order_at
+    }
 }
 
 #[juniper::graphql_object(Context = Context)]
@@ -480,7 +638,91 @@ impl CustomizeItem {
         self.selection_price
     }
 
+    fn selection_price_money(&self, context: &Context) -> Result<Option<Money>, Error> {
+        self.selection_price
+            .map(|price| Ok(Money::new(price, context.currency(self.shop_id)?)))
+            .transpose()
+    }
+
     fn order_at(&self) -> DateTime<Utc> {
         self.order_at
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use uuid::Uuid;
+    use chrono::Utc;
+    use super::{
+        unit_price,
+        subtotal,
+        Order,
+        ProductItem,
+        CustomizeItem,
+        AppliedPromotion,
+        Charges,
+        TaxLine,
+    };
+
+    fn item(price: i32, count: i32, selection_prices: &[Option<i32>]) -> ProductItem {
+        let mut item = ProductItem::new(Uuid::nil(), Uuid::new_v4(), Uuid::new_v4(), "Item".to_string(), price, count, None, Utc::now());
+        for &selection_price in selection_prices {
+            let key = Uuid::new_v4();
+            let selection_key = selection_price.map(|_| Uuid::new_v4());
+            item.insert_customize_uncheck(
+                key,
+                CustomizeItem::new(Uuid::nil(), key, "Customize".to_string(), None, selection_key, selection_price, Utc::now()),
+            );
+        }
+        item
+    }
+
+    #[test]
+    fn test_unit_price() {
+        assert_eq!(unit_price(80, vec![]).unwrap(), 80);
+        assert_eq!(unit_price(80, vec![Some(20), Some(5)]).unwrap(), 105);
+        // Customizes without a selection cost nothing.
+        assert_eq!(unit_price(80, vec![None, Some(10)]).unwrap(), 90);
+        assert!(unit_price(i32::max_value(), vec![Some(1)]).is_err());
+        assert_eq!(subtotal(105, 3).unwrap(), 315);
+        assert!(subtotal(i32::max_value() / 2, 3).is_err());
+    }
+
+    #[test]
+    fn test_item_and_order_totals() {
+        let rice = item(80, 2, &[Some(20), None]);
+        assert_eq!(rice.unit_price().unwrap(), 100);
+        assert_eq!(rice.subtotal().unwrap(), 200);
+
+        let mut order = Order::new(Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4(), 1, Utc::now());
+        assert_eq!(order.total().unwrap(), 0);
+        order.insert_item_uncheck(Uuid::new_v4(), rice);
+        order.insert_item_uncheck(Uuid::new_v4(), item(30, 3, &[]));
+        assert_eq!(order.total().unwrap(), 290);
+        assert_eq!(order.item_count().unwrap(), 5);
+
+        order.add_promotion(AppliedPromotion::new(Uuid::nil(), Uuid::new_v4(), "Ten off".to_string(), None, 10));
+        assert_eq!((order.subtotal().unwrap(), order.discount().unwrap(), order.total().unwrap()), (290, 10, 280));
+
+        let mut charges = Charges::new(Uuid::nil(), false, 1000, 28);
+        charges.add_tax(TaxLine::new(Uuid::nil(), 500, 308, 15));
+        order.set_charges(charges);
+        assert_eq!(order.total().unwrap(), 323);
+
+        order.insert_item_uncheck(Uuid::new_v4(), item(i32::max_value(), 1, &[]));
+        assert!(order.total().is_err());
+    }
+
+    #[test]
+    fn test_total_overflow() {
+        // Every item fits, but not what the charges add to them.
+        let mut order = Order::new(Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4(), 1, Utc::now());
+        order.insert_item_uncheck(Uuid::new_v4(), item(i32::max_value() - 10, 1, &[]));
+        assert_eq!(order.total().unwrap(), i32::max_value() - 10);
+        order.set_charges(Charges::new(Uuid::nil(), false, 1000, 20));
+        assert!(order.total().is_err());
+
+        order.insert_item_uncheck(Uuid::new_v4(), item(1, i32::max_value(), &[]));
+        assert!(order.item_count().is_err());
+    }
+}
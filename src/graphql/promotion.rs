@@ -0,0 +1,613 @@
+use std::convert::TryFrom;
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use crate::{
+    graphql::{
+        context::Context,
+        money::Money,
+        order::ProductItem,
+    },
+    error::Error,
+};
+
+pub const MIN_CODE_LENGTH: usize = 3;
+pub const MAX_CODE_LENGTH: usize = 32;
+
+#[derive(juniper::GraphQLEnum, Clone, Copy, Debug, PartialEq)]
+pub enum PromotionKind {
+    Percentage,
+    Fixed,
+    BuyXGetY,
+}
+
+impl PromotionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PromotionKind::Percentage => "percentage",
+            PromotionKind::Fixed => "fixed",
+            PromotionKind::BuyXGetY => "buy_x_get_y",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reward {
+    // Percent off the subtotal.
+    Percentage(i32),
+    // Amount off the subtotal, in minor units.
+    Fixed(i32),
+    // For every `buy` units of the product the next `get` are free, the
+    // cheapest units being the free ones.
+    BuyGet { product_key: Uuid, buy: i32, get: i32 },
+}
+
+impl Reward {
+    pub fn kind(&self) -> PromotionKind {
+        match self {
+            Reward::Percentage(_) => PromotionKind::Percentage,
+            Reward::Fixed(_) => PromotionKind::Fixed,
+            Reward::BuyGet { .. } => PromotionKind::BuyXGetY,
+        }
+    }
+
+    // The columns `promotions` stores the reward in, other than `kind`.
+    pub fn parse(kind: &str, percent: Option<i32>, amount: Option<i32>, product_key: Option<Uuid>, buy: Option<i32>, get: Option<i32>) -> Option<Self> {
+        match (kind, percent, amount, product_key, buy, get) {
+            ("percentage", Some(percent), _, _, _, _) => Some(Reward::Percentage(percent)),
+            ("fixed", _, Some(amount), _, _, _) => Some(Reward::Fixed(amount)),
+            ("buy_x_get_y", _, _, Some(product_key), Some(buy), Some(get)) => {
+                Some(Reward::BuyGet { product_key: product_key, buy: buy, get: get })
+            }
+            _ => None,
+        }
+    }
+}
+
+// One item of a cart as far as discounts go.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Line {
+    pub product_key: Uuid,
+    pub unit_price: i32,
+    pub count: i32,
+}
+
+impl TryFrom<&ProductItem> for Line {
+    type Error = Error;
+
+    fn try_from(item: &ProductItem) -> Result<Self, Error> {
+        Ok(Line {
+            product_key: item.product_key(),
+            unit_price: item.unit_price()?,
+            count: item.count(),
+        })
+    }
+}
+
+// Capped rather than wrapping around; carts that large fail to total anyway.
+fn subtotal(lines: &[Line]) -> i32 {
+    let subtotal: i64 = lines.iter().map(|line| line.unit_price as i64 * line.count as i64).sum();
+    subtotal.min(i32::max_value() as i64) as i32
+}
+
+// What a promotion is set up with, before it has an id or has been used.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PromotionTerms {
+    pub name: String,
+    pub reward: Reward,
+    pub min_spend: i32,
+    pub starts_at: Option<DateTime<Utc>>,
+    pub ends_at: Option<DateTime<Utc>>,
+    pub code: Option<String>,
+    pub usage_limit: Option<i32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Promotion {
+    id: Uuid,
+    shop_id: Uuid,
+    terms: PromotionTerms,
+    usage_count: i32,
+}
+
+impl Promotion {
+    pub fn new(id: Uuid, shop_id: Uuid, terms: PromotionTerms, usage_count: i32) -> Self {
+        Promotion {
+            id: id,
+            shop_id: shop_id,
+            terms: terms,
+            usage_count: usage_count,
+        }
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn shop_id(&self) -> Uuid {
+        self.shop_id
+    }
+
+    pub fn terms(&self) -> &PromotionTerms {
+        &self.terms
+    }
+
+    pub fn code(&self) -> Option<&str> {
+        self.terms.code.as_ref().map(String::as_str)
+    }
+
+    pub fn usage_count(&self) -> i32 {
+        self.usage_count
+    }
+
+    // For repositories recording an order placed with the promotion.
+    pub fn count_use(&mut self) {
+        self.usage_count += 1;
+    }
+
+    pub fn has_uses_left(&self) -> bool {
+        self.terms.usage_limit.map_or(true, |limit| self.usage_count < limit)
+    }
+
+    fn has_started_at(&self, at: DateTime<Utc>) -> bool {
+        self.terms.starts_at.map_or(true, |starts_at| starts_at <= at)
+    }
+
+    fn has_ended_at(&self, at: DateTime<Utc>) -> bool {
+        self.terms.ends_at.map_or(false, |ends_at| ends_at <= at)
+    }
+
+    pub fn is_available_at(&self, at: DateTime<Utc>) -> bool {
+        self.has_started_at(at) && !self.has_ended_at(at) && self.has_uses_left()
+    }
+
+    // Nothing when the lines come short of the minimum spend.
+    pub fn discount(&self, lines: &[Line]) -> i32 {
+        let subtotal = subtotal(lines);
+        if subtotal < self.terms.min_spend {
+            return 0;
+        }
+
+        match self.terms.reward {
+            Reward::Percentage(percent) => (subtotal as i64 * percent as i64 / 100) as i32,
+            Reward::Fixed(amount) => amount,
+            Reward::BuyGet { product_key, buy, get } => {
+                let mut prices: Vec<(i32, i32)> = lines.iter()
+                    .filter(|line| line.product_key == product_key)
+                    .map(|line| (line.unit_price, line.count))
+                    .collect();
+                prices.sort();
+
+                let units: i32 = prices.iter().map(|&(_, count)| count).sum();
+                let mut free = units / (buy + get) * get;
+                let mut discount = 0;
+                for (unit_price, count) in prices {
+                    let taken = count.min(free);
+                    discount += unit_price * taken;
+                    free -= taken;
+                }
+                discount
+            }
+        }
+    }
+}
+
+#[juniper::graphql_object(Context = Context)]
+impl Promotion {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn name(&self) -> &String {
+        &self.terms.name
+    }
+
+    fn kind(&self) -> PromotionKind {
+        self.terms.reward.kind()
+    }
+
+    fn percent(&self) -> Option<i32> {
+        match self.terms.reward {
+            Reward::Percentage(percent) => Some(percent),
+            _ => None,
+        }
+    }
+
+    fn amount(&self) -> Option<i32> {
+        match self.terms.reward {
+            Reward::Fixed(amount) => Some(amount),
+            _ => None,
+        }
+    }
+
+    fn product_key(&self) -> Option<Uuid> {
+        match self.terms.reward {
+            Reward::BuyGet { product_key, .. } => Some(product_key),
+            _ => None,
+        }
+    }
+
+    fn buy_count(&self) -> Option<i32> {
+        match self.terms.reward {
+            Reward::BuyGet { buy, .. } => Some(buy),
+            _ => None,
+        }
+    }
+
+    fn get_count(&self) -> Option<i32> {
+        match self.terms.reward {
+            Reward::BuyGet { get, .. } => Some(get),
+            _ => None,
+        }
+    }
+
+    fn min_spend(&self) -> i32 {
+        self.terms.min_spend
+    }
+
+    fn min_spend_money(&self, context: &Context) -> Result<Money, Error> {
+        Ok(Money::new(self.terms.min_spend, context.currency(self.shop_id)?))
+    }
+
+    fn starts_at(&self) -> Option<DateTime<Utc>> {
+        self.terms.starts_at
+    }
+
+    fn ends_at(&self) -> Option<DateTime<Utc>> {
+        self.terms.ends_at
+    }
+
+    // Null for promotions every cart gets.
+    fn code(&self) -> Option<&str> {
+        self.code()
+    }
+
+    fn usage_limit(&self) -> Option<i32> {
+        self.terms.usage_limit
+    }
+
+    // Orders placed with the promotion.
+    fn usage_count(&self) -> i32 {
+        self.usage_count
+    }
+
+    fn available(&self) -> bool {
+        self.is_available_at(Utc::now())
+    }
+}
+
+#[derive(juniper::GraphQLInputObject)]
+pub struct PromotionInput {
+    pub name: String,
+    pub kind: PromotionKind,
+    // Percent off, for `PERCENTAGE`.
+    pub percent: Option<i32>,
+    // Amount off in minor units, for `FIXED`.
+    pub amount: Option<i32>,
+    // For `BUY_X_GET_Y`, every `buyCount` units of the product get the next
+    // `getCount` free.
+    pub product_key: Option<Uuid>,
+    pub buy_count: Option<i32>,
+    pub get_count: Option<i32>,
+    pub min_spend: Option<i32>,
+    pub starts_at: Option<DateTime<Utc>>,
+    pub ends_at: Option<DateTime<Utc>>,
+    // Without a code the promotion applies to every cart.
+    pub code: Option<String>,
+    pub usage_limit: Option<i32>,
+}
+
+impl PromotionInput {
+    pub fn checked(self) -> Result<PromotionTerms, Error> {
+        let name = self.name.trim().to_string();
+        if name.is_empty() {
+            return Err(Error::invalid_input("Promotion name must not be empty."));
+        }
+
+        let positive = |value: Option<i32>, field: &str| match value {
+            Some(value) if value > 0 => Ok(value),
+            _ => Err(Error::invalid_input(&format!("{} must be a positive number for this kind of promotion.", field))),
+        };
+        let reward = match self.kind {
+            PromotionKind::Percentage => match self.percent {
+                Some(percent) if percent >= 1 && percent <= 100 => Reward::Percentage(percent),
+                _ => return Err(Error::invalid_input("Percent must be between 1 and 100.")),
+            },
+            PromotionKind::Fixed => Reward::Fixed(positive(self.amount, "Amount")?),
+            PromotionKind::BuyXGetY => Reward::BuyGet {
+                product_key: self.product_key.ok_or_else(|| Error::invalid_input("Buy X get Y promotions need a product."))?,
+                buy: positive(self.buy_count, "Buy count")?,
+                get: positive(self.get_count, "Get count")?,
+            },
+        };
+
+        let min_spend = self.min_spend.unwrap_or(0);
+        if min_spend < 0 {
+            return Err(Error::invalid_input("Minimum spend must not be negative."));
+        }
+        if let (Some(starts_at), Some(ends_at)) = (self.starts_at, self.ends_at) {
+            if starts_at >= ends_at {
+                return Err(Error::invalid_input("Promotion must start before it ends."));
+            }
+        }
+        if let Some(limit) = self.usage_limit {
+            if limit < 1 {
+                return Err(Error::invalid_input("Usage limit must be at least 1."));
+            }
+        }
+
+        Ok(PromotionTerms {
+            name: name,
+            reward: reward,
+            min_spend: min_spend,
+            starts_at: self.starts_at,
+            ends_at: self.ends_at,
+            code: self.code.map(|code| checked_code(&code)).transpose()?,
+            usage_limit: self.usage_limit,
+        })
+    }
+}
+
+// Codes are matched ignoring case and surrounding space.
+pub fn normalize_code(code: &str) -> String {
+    code.trim().to_ascii_uppercase()
+}
+
+fn checked_code(code: &str) -> Result<String, Error> {
+    let code = normalize_code(code);
+    let valid = code.len() >= MIN_CODE_LENGTH && code.len() <= MAX_CODE_LENGTH
+        && code.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if valid {
+        Ok(code)
+    } else {
+        Err(Error::invalid_input(&format!(
+            "Code must be {} to {} letters, digits, underscores or hyphens.",
+            MIN_CODE_LENGTH,
+            MAX_CODE_LENGTH,
+        )))
+    }
+}
+
+// The promotion `code` redeems, failing with `InvalidCoupon` when there is
+// none or it can't be used at `at`.
+pub fn coupon<'a>(promotions: &'a [Promotion], code: &str, at: DateTime<Utc>) -> Result<&'a Promotion, Error> {
+    let code = normalize_code(code);
+    let promotion = promotions.iter()
+        .find(|promotion| promotion.code() == Some(code.as_str()))
+        .ok_or_else(|| Error::invalid_coupon(&format!(r#"Coupon "{}" does not exist."#, code)))?;
+
+    if !promotion.has_started_at(at) {
+        Err(Error::invalid_coupon(&format!(r#"Coupon "{}" is not valid yet."#, code)))
+    } else if promotion.has_ended_at(at) {
+        Err(Error::invalid_coupon(&format!(r#"Coupon "{}" has expired."#, code)))
+    } else if !promotion.has_uses_left() {
+        Err(Error::invalid_coupon(&format!(r#"Coupon "{}" has been used up."#, code)))
+    } else {
+        Ok(promotion)
+    }
+}
+
+// Fails with `InvalidCoupon` when the coupon would take nothing off `lines`.
+pub fn check_coupon(coupon: &Promotion, lines: &[Line]) -> Result<(), Error> {
+    let code = coupon.code().unwrap_or_default();
+    if subtotal(lines) < coupon.terms.min_spend {
+        Err(Error::invalid_coupon(&format!(r#"Coupon "{}" needs a spend of at least {}."#, code, coupon.terms.min_spend)))
+    } else if coupon.discount(lines) == 0 {
+        Err(Error::invalid_coupon(&format!(r#"Coupon "{}" does not apply to anything in the cart."#, code)))
+    } else {
+        Ok(())
+    }
+}
+
+// Discounts of a cart with the coupon `code`, if any. Fails with
+// `InvalidCoupon` when the coupon can't be used on `lines` at `at`.
+pub fn redeem(promotions: &[Promotion], code: Option<&str>, lines: &[Line], at: DateTime<Utc>) -> Result<Vec<AppliedPromotion>, Error> {
+    let coupon = match code {
+        Some(code) => {
+            let coupon = coupon(promotions, code, at)?;
+            check_coupon(coupon, lines)?;
+            Some(coupon)
+        }
+        None => None,
+    };
+    Ok(apply(promotions, coupon, lines, at))
+}
+
+// Discounts `lines` get from every promotion without a code available at
+// `at`, in the order given, and then from `coupon`. Each is worked out on
+// the undiscounted subtotal, and the last ones are cut short rather than
+// taking the total below zero. Promotions taking nothing off are left out.
+pub fn apply(promotions: &[Promotion], coupon: Option<&Promotion>, lines: &[Line], at: DateTime<Utc>) -> Vec<AppliedPromotion> {
+    let mut remaining = subtotal(lines);
+    promotions.iter()
+        .filter(|promotion| promotion.code().is_none() && promotion.is_available_at(at))
+        .chain(coupon)
+        .filter_map(|promotion| {
+            let discount = promotion.discount(lines).min(remaining);
+            if discount <= 0 {
+                return None;
+            }
+            remaining -= discount;
+            Some(AppliedPromotion::new(
+                promotion.shop_id,
+                promotion.id,
+                promotion.terms.name.clone(),
+                promotion.terms.code.clone(),
+                discount,
+            ))
+        })
+        .collect()
+}
+
+// A promotion as it applied to a cart or order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AppliedPromotion {
+    shop_id: Uuid,
+    promotion_id: Uuid,
+    name: String,
+    code: Option<String>,
+    discount: i32,
+}
+
+impl AppliedPromotion {
+    pub fn new(shop_id: Uuid, promotion_id: Uuid, name: String, code: Option<String>, discount: i32) -> Self {
+        AppliedPromotion {
+            shop_id: shop_id,
+            promotion_id: promotion_id,
+            name: name,
+            code: code,
+            discount: discount,
+        }
+    }
+
+    pub fn promotion_id(&self) -> Uuid {
+        self.promotion_id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn code(&self) -> Option<&str> {
+        self.code.as_ref().map(String::as_str)
+    }
+
+    pub fn discount(&self) -> i32 {
+        self.discount
+    }
+}
+
+#[juniper::graphql_object(Context = Context)]
+impl AppliedPromotion {
+    // The promotion may have been deleted since.
+    fn promotion_id(&self) -> Uuid {
+        self.promotion_id
+    }
+
+    fn name(&self) -> &String {
+        &self.name
+    }
+
+    fn code(&self) -> Option<&str> {
+        self.code()
+    }
+
+    fn discount(&self) -> i32 {
+        self.discount
+    }
+
+    fn discount_money(&self, context: &Context) -> Result<Money, Error> {
+        Ok(Money::new(self.discount, context.currency(self.shop_id)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use uuid::Uuid;
+    use chrono::{Duration, Utc};
+    use super::*;
+
+    fn promotion(reward: Reward, min_spend: i32, code: Option<&str>) -> Promotion {
+        Promotion::new(
+            Uuid::new_v4(),
+            Uuid::nil(),
+            PromotionTerms {
+                name: "Promotion".to_string(),
+                reward: reward,
+                min_spend: min_spend,
+                starts_at: None,
+                ends_at: None,
+                code: code.map(str::to_string),
+                usage_limit: None,
+            },
+            0,
+        )
+    }
+
+    fn line(product_key: Uuid, unit_price: i32, count: i32) -> Line {
+        Line { product_key: product_key, unit_price: unit_price, count: count }
+    }
+
+    #[test]
+    fn test_discount() {
+        let tea = Uuid::new_v4();
+        let lines = [line(tea, 30, 3), line(tea, 40, 2), line(Uuid::new_v4(), 100, 1)];
+
+        assert_eq!(promotion(Reward::Percentage(15), 0, None).discount(&lines), 40);
+        assert_eq!(promotion(Reward::Fixed(50), 0, None).discount(&lines), 50);
+        assert_eq!(promotion(Reward::Fixed(50), 291, None).discount(&lines), 0);
+        // Five teas make one set of buy two get one, so the cheapest is free.
+        let buy_get = |buy, get| promotion(Reward::BuyGet { product_key: tea, buy: buy, get: get }, 0, None);
+        assert_eq!(buy_get(2, 1).discount(&lines), 30);
+        assert_eq!(buy_get(1, 1).discount(&lines), 60);
+        assert_eq!(buy_get(5, 1).discount(&lines), 0);
+    }
+
+    #[test]
+    fn test_apply() {
+        let now = Utc::now();
+        let lines = [line(Uuid::new_v4(), 100, 2)];
+        let mut expired = promotion(Reward::Fixed(10), 0, None);
+        expired.terms.ends_at = Some(now - Duration::hours(1));
+        let promotions = vec![
+            promotion(Reward::Percentage(10), 0, None),
+            expired,
+            promotion(Reward::Fixed(5), 500, None),
+            promotion(Reward::Fixed(190), 0, Some("BIG")),
+        ];
+
+        let applied = apply(&promotions, None, &lines, now);
+        assert_eq!(applied.iter().map(AppliedPromotion::discount).collect::<Vec<_>>(), vec![20]);
+
+        // Coupons only count when redeemed, and never take the total below zero.
+        let big = coupon(&promotions, " big ", now).unwrap();
+        let applied = apply(&promotions, Some(big), &lines, now);
+        assert_eq!(applied.iter().map(AppliedPromotion::discount).collect::<Vec<_>>(), vec![20, 180]);
+        assert_eq!(applied[1].code(), Some("BIG"));
+    }
+
+    #[test]
+    fn test_coupon() {
+        let now = Utc::now();
+        let mut used_up = promotion(Reward::Fixed(10), 0, Some("ONCE"));
+        used_up.terms.usage_limit = Some(1);
+        used_up.usage_count = 1;
+        let mut later = promotion(Reward::Fixed(10), 0, Some("LATER"));
+        later.terms.starts_at = Some(now + Duration::days(1));
+        let promotions = vec![used_up, later, promotion(Reward::Fixed(10), 100, Some("MIN100"))];
+        let error = |code: &str| coupon(&promotions, code, now).err().map(|err| err.error_type().to_string());
+
+        assert_eq!(error("NOPE"), Some("InvalidCoupon".to_string()));
+        assert_eq!(error("once"), Some("InvalidCoupon".to_string()));
+        assert_eq!(error("LATER"), Some("InvalidCoupon".to_string()));
+        assert_eq!(error("min100"), None);
+
+        let min100 = coupon(&promotions, "MIN100", now).unwrap();
+        assert!(check_coupon(min100, &[line(Uuid::new_v4(), 50, 1)]).is_err());
+        assert!(check_coupon(min100, &[line(Uuid::new_v4(), 50, 2)]).is_ok());
+    }
+
+    #[test]
+    fn test_checked_input() {
+        let input = |kind, percent: Option<i32>, code: Option<&str>| PromotionInput {
+            name: " Happy hour ".to_string(),
+            kind: kind,
+            percent: percent,
+            amount: None,
+            product_key: None,
+            buy_count: None,
+            get_count: None,
+            min_spend: None,
+            starts_at: None,
+            ends_at: None,
+            code: code.map(str::to_string),
+            usage_limit: None,
+        };
+
+        let terms = input(PromotionKind::Percentage, Some(20), Some("happy-20")).checked().unwrap();
+        assert_eq!((terms.name.as_str(), terms.reward, terms.code), ("Happy hour", Reward::Percentage(20), Some("HAPPY-20".to_string())));
+        assert!(input(PromotionKind::Percentage, Some(101), None).checked().is_err());
+        assert!(input(PromotionKind::Fixed, Some(20), None).checked().is_err());
+        assert!(input(PromotionKind::BuyXGetY, None, None).checked().is_err());
+        assert!(input(PromotionKind::Percentage, Some(20), Some("no spaces")).checked().is_err());
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::BTreeSet;
+use uuid::Uuid;
+use crate::{
+    sql::Permission,
+    graphql::{
+        context::Context,
+        user::Authority,
+    },
+    error::Error,
+};
+
+pub const MAX_NAME_LENGTH: usize = 64;
+
+// Something a member may do in a shop. A role is a set of them.
+#[derive(juniper::GraphQLEnum, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Capability {
+    ViewMembers,
+    // Seeing members' roles, and managing roles.
+    ManageMembers,
+    // Opening hours, address, location, currency and taxes.
+    ManageShop,
+    ViewAuditLog,
+    ViewOrders,
+    ViewAnalytics,
+    // Pausing and resuming ordering.
+    ManageOrdering,
+    // Orders can't be refunded yet. Roles with full order authority have it,
+    // so they keep that authority once they can.
+    RefundOrders,
+    // Including their codes.
+    ViewPromotions,
+    // Series, pictures and translations of products.
+    EditCatalog,
+    // What customers pay, which promotions change.
+    EditPrices,
+    ManageStock,
+}
+
+use Capability::*;
+
+impl Capability {
+    pub const ALL: [Capability; 12] = [
+        ViewMembers, ManageMembers, ManageShop, ViewAuditLog,
+        ViewOrders, ViewAnalytics, ManageOrdering, RefundOrders,
+        ViewPromotions, EditCatalog, EditPrices, ManageStock,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ViewMembers => "view_members",
+            ManageMembers => "manage_members",
+            ManageShop => "manage_shop",
+            ViewAuditLog => "view_audit_log",
+            ViewOrders => "view_orders",
+            ViewAnalytics => "view_analytics",
+            ManageOrdering => "manage_ordering",
+            RefundOrders => "refund_orders",
+            ViewPromotions => "view_promotions",
+            EditCatalog => "edit_catalog",
+            EditPrices => "edit_prices",
+            ManageStock => "manage_stock",
+        }
+    }
+
+    pub fn parse(capability: &str) -> Option<Self> {
+        Capability::ALL.iter().cloned().find(|known| known.as_str() == capability)
+    }
+}
+
+// What read-only and full member, order and product authority allowed
+// before roles. Full authority has the read-only capabilities as well.
+const AREAS: [(&[Capability], &[Capability]); 3] = [
+    (&[ViewMembers], &[ManageMembers, ManageShop, ViewAuditLog]),
+    (&[ViewOrders, ViewAnalytics], &[ManageOrdering, RefundOrders]),
+    (&[ViewPromotions], &[EditCatalog, EditPrices, ManageStock]),
+];
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Capabilities(BTreeSet<Capability>);
+
+impl Capabilities {
+    pub fn new<I: IntoIterator<Item = Capability>>(capabilities: I) -> Self {
+        Capabilities(capabilities.into_iter().collect())
+    }
+
+    // As stored, skipping any this version doesn't know.
+    pub fn parse(capabilities: &[String]) -> Self {
+        Capabilities::new(capabilities.iter().filter_map(|capability| Capability::parse(capability)))
+    }
+
+    pub fn to_strings(&self) -> Vec<String> {
+        self.0.iter().map(|capability| capability.as_str().to_string()).collect()
+    }
+
+    pub fn to_vec(&self) -> Vec<Capability> {
+        self.0.iter().cloned().collect()
+    }
+
+    pub fn contains(&self, capability: Capability) -> bool {
+        self.0.contains(&capability)
+    }
+
+    pub fn is_subset(&self, other: &Capabilities) -> bool {
+        self.0.is_subset(&other.0)
+    }
+
+    pub fn intersection(&self, other: &Capabilities) -> Self {
+        Capabilities(self.0.intersection(&other.0).cloned().collect())
+    }
+
+    pub fn from_authority(authority: &Authority) -> Self {
+        let permissions = [authority.member(), authority.order(), authority.product()];
+        let mut capabilities = BTreeSet::new();
+        for (permission, (read_only, all)) in permissions.iter().zip(AREAS.iter()) {
+            if *permission != Permission::None {
+                capabilities.extend(read_only.iter().cloned());
+            }
+            if *permission == Permission::All {
+                capabilities.extend(all.iter().cloned());
+            }
+        }
+        Capabilities(capabilities)
+    }
+
+    // The most of each permission the capabilities cover in full.
+    pub fn to_authority(&self) -> Authority {
+        let has_all = |capabilities: &[Capability]| capabilities.iter().all(|capability| self.contains(*capability));
+        let permission = |(read_only, all): &(&[Capability], &[Capability])| {
+            if has_all(read_only) && has_all(all) {
+                Permission::All
+            } else if has_all(read_only) {
+                Permission::ReadOnly
+            } else {
+                Permission::None
+            }
+        };
+        Authority::new(permission(&AREAS[0]), permission(&AREAS[1]), permission(&AREAS[2]))
+    }
+}
+
+// Built-in roles shops can start from, matching the permissions members
+// commonly had before roles.
+#[derive(juniper::GraphQLEnum, Clone, Copy, Debug, PartialEq)]
+pub enum RoleTemplate {
+    Owner,
+    Manager,
+    Staff,
+    Viewer,
+}
+
+impl RoleTemplate {
+    pub const ALL: [RoleTemplate; 4] = [RoleTemplate::Owner, RoleTemplate::Manager, RoleTemplate::Staff, RoleTemplate::Viewer];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RoleTemplate::Owner => "Owner",
+            RoleTemplate::Manager => "Manager",
+            RoleTemplate::Staff => "Staff",
+            RoleTemplate::Viewer => "Viewer",
+        }
+    }
+
+    pub fn authority(&self) -> Authority {
+        match self {
+            RoleTemplate::Owner => Authority::new(Permission::All, Permission::All, Permission::All),
+            RoleTemplate::Manager => Authority::new(Permission::ReadOnly, Permission::All, Permission::All),
+            RoleTemplate::Staff => Authority::new(Permission::ReadOnly, Permission::None, Permission::ReadOnly),
+            RoleTemplate::Viewer => Authority::new(Permission::ReadOnly, Permission::ReadOnly, Permission::ReadOnly),
+        }
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities::from_authority(&self.authority())
+    }
+}
+
+pub struct BuiltInRole(pub RoleTemplate);
+
+#[juniper::graphql_object(Context = Context)]
+impl BuiltInRole {
+    fn template(&self) -> RoleTemplate {
+        self.0
+    }
+
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn capabilities(&self) -> Vec<Capability> {
+        self.0.capabilities().to_vec()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Role {
+    id: Uuid,
+    shop_id: Uuid,
+    name: String,
+    capabilities: Capabilities,
+}
+
+impl Role {
+    pub fn new(id: Uuid, shop_id: Uuid, name: String, capabilities: Capabilities) -> Self {
+        Role {
+            id: id,
+            shop_id: shop_id,
+            name: name,
+            capabilities: capabilities,
+        }
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn shop_id(&self) -> Uuid {
+        self.shop_id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+}
+
+#[juniper::graphql_object(Context = Context)]
+impl Role {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn name(&self) -> &String {
+        &self.name
+    }
+
+    fn capabilities(&self) -> Vec<Capability> {
+        self.capabilities.to_vec()
+    }
+}
+
+#[derive(juniper::GraphQLInputObject)]
+pub struct RoleInput {
+    // Defaults to the template's name.
+    pub name: Option<String>,
+    pub template: Option<RoleTemplate>,
+    // In place of the template's.
+    pub capabilities: Option<Vec<Capability>>,
+}
+
+impl RoleInput {
+    pub fn checked(self) -> Result<(String, Capabilities), Error> {
+        let name = match (self.name, self.template) {
+            (Some(name), _) => checked_name(&name)?,
+            (None, Some(template)) => template.name().to_string(),
+            (None, None) => return Err(Error::invalid_input("Roles need a name or a template.")),
+        };
+        let capabilities = match (self.capabilities, self.template) {
+            (Some(capabilities), _) => Capabilities::new(capabilities),
+            (None, Some(template)) => template.capabilities(),
+            (None, None) => return Err(Error::invalid_input("Roles need capabilities or a template.")),
+        };
+        Ok((name, capabilities))
+    }
+}
+
+pub fn checked_name(name: &str) -> Result<String, Error> {
+    let name = name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(Error::invalid_input(&format!("Role name must be 1 to {} characters.", MAX_NAME_LENGTH)));
+    }
+    Ok(name)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        sql::Permission,
+        graphql::user::Authority,
+    };
+    use super::{Capabilities, Capability, RoleTemplate};
+
+    #[test]
+    fn test_authority() {
+        // Every combination of permissions maps to capabilities and back.
+        let permissions = [Permission::None, Permission::ReadOnly, Permission::All];
+        for &member in &permissions {
+            for &order in &permissions {
+                for &product in &permissions {
+                    let authority = Authority::new(member, order, product);
+                    assert_eq!(Capabilities::from_authority(&authority).to_authority(), authority);
+                }
+            }
+        }
+
+        let staff = RoleTemplate::Staff.capabilities();
+        assert_eq!(staff.to_vec(), vec![Capability::ViewMembers, Capability::ViewPromotions]);
+        let partial = Capabilities::new(vec![Capability::ViewOrders, Capability::ViewAnalytics, Capability::ManageOrdering]);
+        assert_eq!(partial.to_authority(), Authority::new(Permission::None, Permission::ReadOnly, Permission::None));
+        assert_eq!(Capabilities::new(Capability::ALL.iter().cloned()), RoleTemplate::Owner.capabilities());
+    }
+
+    #[test]
+    fn test_stored() {
+        let owner = RoleTemplate::Owner.capabilities();
+        assert_eq!(Capabilities::parse(&owner.to_strings()), owner);
+        assert_eq!(
+            Capabilities::parse(&["view_orders".to_string(), "fly".to_string()]),
+            Capabilities::new(vec![Capability::ViewOrders]),
+        );
+    }
+}
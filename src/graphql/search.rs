@@ -0,0 +1,299 @@
+use std::collections::HashSet;
+use uuid::Uuid;
+use crate::{
+    graphql::{
+        context::Context,
+        shop::{Shop, Product, find_shop, find_product},
+    },
+    error::Error,
+};
+
+pub const DEFAULT_SEARCH_LIMIT: i32 = 20;
+pub const MAX_SEARCH_LIMIT: i32 = 100;
+
+// Same as the default `pg_trgm.word_similarity_threshold` behind `<%`.
+pub const SIMILARITY_THRESHOLD: f64 = 0.6;
+
+// Characters kept around the first match in a snippet.
+const SNIPPET_BEFORE: usize = 30;
+const SNIPPET_LENGTH: usize = 120;
+
+// Scripts written without spaces between words, matching `search_text` in
+// the search migration.
+fn is_cjk(c: char) -> bool {
+    match c {
+        '\u{3040}'..='\u{30ff}'
+        | '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}'
+        | '\u{ac00}'..='\u{d7af}'
+        | '\u{f900}'..='\u{faff}' => true,
+        _ => false,
+    }
+}
+
+fn lowercase(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+// Lowercase words, with CJK runs split into overlapping bigrams plus, unless
+// tokenizing a query, single characters.
+pub fn tokens(text: &str, is_query: bool) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut run: Vec<char> = Vec::new();
+
+    for c in text.chars().chain(Some(' ')) {
+        if is_cjk(c) {
+            if !word.is_empty() { tokens.push(word.split_off(0)) }
+            run.push(c);
+            continue;
+        }
+
+        if !run.is_empty() {
+            if run.len() == 1 {
+                tokens.push(run[0].to_string());
+            } else {
+                tokens.extend(run.windows(2).map(|pair| pair.iter().collect::<String>()));
+                if !is_query { tokens.extend(run.iter().map(|c| c.to_string())) }
+            }
+            run.clear();
+        }
+        if c.is_alphanumeric() {
+            word.push(lowercase(c));
+        } else if !word.is_empty() {
+            tokens.push(word.split_off(0));
+        }
+    }
+    tokens
+}
+
+fn trigrams(word: &str) -> HashSet<(char, char, char)> {
+    let padded: Vec<char> = "  ".chars().chain(word.chars()).chain(Some(' ')).collect();
+    padded.windows(3).map(|w| (w[0], w[1], w[2])).collect()
+}
+
+// Share of the query's trigrams found in the closest word of `text`, an
+// approximation of `pg_trgm`'s `word_similarity`.
+pub fn word_similarity(query: &str, text: &str) -> f64 {
+    let query = trigrams(&query.to_lowercase());
+    tokens(text, true).iter()
+        .map(|word| query.intersection(&trigrams(word)).count() as f64 / query.len() as f64)
+        .fold(0.0, f64::max)
+}
+
+// Relevance of a document to a query, or `None` when it does not match.
+// Mirrors the Postgres ranking closely enough for the in-memory repository:
+// every query token must prefix a token of the name or description, or the
+// query must be similar enough to the name.
+pub fn score(query: &str, name: &str, description: Option<&str>) -> Option<f64> {
+    let query_tokens = tokens(query, true);
+    let name_tokens = tokens(name, false);
+    let description_tokens = tokens(description.unwrap_or(""), false);
+
+    let weights: Vec<f64> = query_tokens.iter()
+        .map(|token| {
+            if name_tokens.iter().any(|t| t.starts_with(token.as_str())) {
+                1.0
+            } else if description_tokens.iter().any(|t| t.starts_with(token.as_str())) {
+                0.4
+            } else {
+                0.0
+            }
+        })
+        .collect();
+    let text_score = if !weights.is_empty() && weights.iter().all(|&w| w > 0.0) {
+        Some(weights.iter().sum::<f64>() / weights.len() as f64)
+    } else {
+        None
+    };
+
+    let similarity = word_similarity(query, name);
+    match text_score {
+        Some(text_score) => Some(text_score + similarity),
+        None if similarity >= SIMILARITY_THRESHOLD => Some(similarity),
+        None => None,
+    }
+}
+
+fn escape(c: char, out: &mut String) {
+    match c {
+        '&' => out.push_str("&amp;"),
+        '<' => out.push_str("&lt;"),
+        '>' => out.push_str("&gt;"),
+        '"' => out.push_str("&quot;"),
+        c => out.push(c),
+    }
+}
+
+// Which characters of `text` are part of a match. Words only match at their
+// start, like the prefix search does; CJK terms match anywhere.
+fn marks(chars: &[char], terms: &[String]) -> Vec<bool> {
+    let lower: Vec<char> = chars.iter().cloned().map(lowercase).collect();
+    let mut marked = vec![false; chars.len()];
+
+    for term in terms {
+        let term: Vec<char> = term.chars().collect();
+        if term.is_empty() || term.len() > lower.len() { continue }
+
+        for start in 0..=lower.len() - term.len() {
+            let at_word_start = start == 0 || !lower[start - 1].is_alphanumeric() || is_cjk(term[0]);
+            if at_word_start && lower[start..start + term.len()] == term[..] {
+                marked[start..start + term.len()].iter_mut().for_each(|m| *m = true);
+            }
+        }
+    }
+    marked
+}
+
+// HTML-escaped `chars[from..to]` with matches wrapped in `<mark>`.
+fn render(chars: &[char], marked: &[bool], from: usize, to: usize) -> String {
+    let mut out = String::new();
+    let mut open = false;
+    for i in from..to {
+        if marked[i] != open {
+            out.push_str(if marked[i] { "<mark>" } else { "</mark>" });
+            open = marked[i];
+        }
+        escape(chars[i], &mut out);
+    }
+    if open { out.push_str("</mark>") }
+    out
+}
+
+pub fn highlight(text: &str, terms: &[String]) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let marked = marks(&chars, terms);
+    render(&chars, &marked, 0, chars.len())
+}
+
+// A highlighted excerpt around the first match, if `text` has one.
+pub fn snippet(text: &str, terms: &[String]) -> Option<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let marked = marks(&chars, terms);
+    let first = marked.iter().position(|&m| m)?;
+
+    let from = first.saturating_sub(SNIPPET_BEFORE);
+    let to = (from + SNIPPET_LENGTH).min(chars.len());
+    let mut snippet = String::new();
+    if from > 0 { snippet.push('…') }
+    snippet.push_str(&render(&chars, &marked, from, to));
+    if to < chars.len() { snippet.push('…') }
+    Some(snippet)
+}
+
+// A shop, or a product of it, matching a search.
+pub struct SearchHit {
+    shop_id: Uuid,
+    product_key: Option<Uuid>,
+    name: String,
+    description: Option<String>,
+    score: f64,
+    terms: Vec<String>,
+}
+
+impl SearchHit {
+    pub fn new(shop_id: Uuid, product_key: Option<Uuid>, name: String, description: Option<String>, score: f64) -> Self {
+        SearchHit {
+            shop_id: shop_id,
+            product_key: product_key,
+            name: name,
+            description: description,
+            score: score,
+            terms: Vec::new(),
+        }
+    }
+
+    pub fn score(&self) -> f64 {
+        self.score
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Highlight what `query` matched.
+    pub fn highlighting(mut self, query: &str) -> Self {
+        self.terms = tokens(query, true);
+        self
+    }
+}
+
+#[juniper::graphql_object(Context = Context)]
+impl SearchHit {
+    fn score(&self) -> f64 {
+        self.score
+    }
+
+    fn shop(&self, context: &Context) -> Result<Shop, Error> {
+        find_shop(context, self.shop_id)
+    }
+
+    // Null when the shop itself matched.
+    fn product(&self, context: &Context) -> Result<Option<Product>, Error> {
+        self.product_key
+            .map(|product_key| find_product(context, self.shop_id, product_key))
+            .transpose()
+    }
+
+    // The shop or product name as HTML, with matches wrapped in `<mark>`.
+    fn highlighted_name(&self) -> String {
+        highlight(&self.name, &self.terms)
+    }
+
+    // An excerpt of the product description around the first match, as HTML.
+    fn snippet(&self) -> Option<String> {
+        self.description.as_ref().and_then(|description| snippet(description, &self.terms))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tokens() {
+        assert_eq!(tokens("Braised Pork-Rice!", false), vec!["braised", "pork", "rice"]);
+        assert_eq!(tokens("滷肉飯 Set", true), vec!["滷肉", "肉飯", "set"]);
+        assert_eq!(tokens("滷肉飯", false), vec!["滷肉", "肉飯", "滷", "肉", "飯"]);
+        assert_eq!(tokens("茶", true), vec!["茶"]);
+    }
+
+    #[test]
+    fn test_score() {
+        let pork_rice = score("pork", "Braised Pork Rice", Some("Slow cooked pork belly on rice.")).unwrap();
+        let tea = score("pork", "Black Tea", None);
+        assert!(pork_rice > 1.0);
+        assert!(tea.is_none());
+
+        // Typos fall back to trigram similarity on the name.
+        assert!(score("dinr", "Pigskit Diner", None).is_some());
+        assert!(score("xyz", "Pigskit Diner", None).is_none());
+
+        // Descriptions weigh less than names.
+        assert!(score("belly", "Braised Pork Rice", Some("Pork belly")).unwrap() < score("pork", "Braised Pork Rice", None).unwrap());
+
+        assert!(score("肉飯", "滷肉飯", None).is_some());
+        assert!(score("肉", "滷肉飯", None).is_some());
+        assert!(score("飯滷", "滷肉飯", None).is_none());
+    }
+
+    #[test]
+    fn test_highlight() {
+        let terms = tokens("pork ric", true);
+        assert_eq!(highlight("Braised Pork Rice", &terms), "Braised <mark>Pork</mark> <mark>Ric</mark>e");
+        assert_eq!(highlight("Spork & Rice", &terms), "Spork &amp; <mark>Ric</mark>e");
+        assert_eq!(highlight("特製滷肉飯", &tokens("滷肉飯", true)), "特製<mark>滷肉飯</mark>");
+    }
+
+    #[test]
+    fn test_snippet() {
+        let terms = tokens("belly", true);
+        let description = format!("{} pork belly {}", "a".repeat(50), "b".repeat(200));
+        let snippet = snippet(&description, &terms).unwrap();
+        assert!(snippet.starts_with('…'));
+        assert!(snippet.ends_with('…'));
+        assert!(snippet.contains("pork <mark>belly</mark>"));
+
+        assert_eq!(super::snippet("Short one.", &terms), None);
+    }
+}
@@ -1,12 +1,34 @@
+use juniper::ID;
 use uuid::Uuid;
-use chrono::{DateTime, Utc};
-use serde_json::{json, Map};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde_json::{json, Map, Value};
 use crate::{
-    sql::{
-        UuidNN,
-        clause::Clause,
+    graphql::{
+        context::Context,
+        hours::{
+            Interval,
+            OpeningException,
+            OpeningHoursInput,
+            TimeRange,
+            TimeRangeInput,
+            WeeklyHours,
+            MAX_PAUSE_MINUTES,
+            parse_timezone,
+        },
+        search::{SearchHit, DEFAULT_SEARCH_LIMIT, MAX_SEARCH_LIMIT},
+        locale::{Locale, TranslatedField, Translation, Translations},
+        node::{GlobalId, Node},
+        money::{Currency, Money},
+        order,
+        promotion::{Promotion, PromotionInput, Reward},
+        tax::{TaxSettings, TaxSettingsInput},
+        audit::{self, AuditEntity},
+        role::{self, BuiltInRole, Capabilities, Capability, Role, RoleInput, RoleTemplate},
+        guard,
     },
-    graphql::context::Context,
+    repository::{NewItem, NewCustomizeItem},
+    state::State,
+    picture,
     error::Error,
     utils::dict::Dict,
 };
@@ -16,56 +38,571 @@ pub struct QueryShop;
 #[juniper::graphql_object(Context = Context)]
 impl QueryShop {
     fn search(context: &Context, id: Option<Uuid>, name: Option<String>) -> Result<Vec<Shop>, Error> {
-        let mut clauses = String::new();
+        context.throttle("shop.search")?;
+        context.state().shops().search(id, name)
+    }
 
-        if let Some(id) = id.as_ref() {
-            if clauses == "" {
-                clauses.push_str(" WHERE");
-            } else {
-                clauses.push_str(" AND");
-            }
-            clauses.push_str(format!(" id = '{}'", id).as_str());
+    // Ranked full-text and fuzzy search over shop names and product names
+    // and descriptions, optionally within one shop.
+    fn find(context: &Context, query: String, shop_id: Option<Uuid>, limit: Option<i32>) -> Result<Vec<SearchHit>, Error> {
+        context.throttle("shop.find")?;
+        let query = query.trim().to_string();
+        if query.is_empty() {
+            return Err(Error::invalid_input("Search query must not be empty."));
+        }
+        let limit = limit.unwrap_or(DEFAULT_SEARCH_LIMIT);
+        if limit < 1 || limit > MAX_SEARCH_LIMIT {
+            return Err(Error::invalid_input(&format!("Limit must be between 1 and {}.", MAX_SEARCH_LIMIT)));
         }
 
-        if let Some(name) = name.as_ref() {
-            if clauses == "" {
-                clauses.push_str(" WHERE");
-            } else {
-                clauses.push_str(" AND");
-            }
-            clauses.push_str(format!(" name_upper LIKE upper('%{}%')", name).as_str());
+        Ok(
+            context.state().catalog().search(query.clone(), shop_id, limit)?
+                .into_iter()
+                .map(|hit| hit.highlighting(&query))
+                .collect()
+        )
+    }
+
+    // Shops within `radius_meters` of a point, nearest first.
+    fn nearby(context: &Context, lat: f64, lng: f64, radius_meters: f64, limit: Option<i32>) -> Result<Vec<NearbyShop>, Error> {
+        context.throttle("shop.nearby")?;
+        let center = Coordinates::checked(lat, lng)?;
+        if !(radius_meters > 0.0 && radius_meters <= MAX_NEARBY_RADIUS) {
+            return Err(Error::invalid_input(&format!("Radius must be greater than 0 and at most {} meters.", MAX_NEARBY_RADIUS)));
+        }
+        let limit = limit.unwrap_or(DEFAULT_NEARBY_LIMIT);
+        if limit < 1 || limit > MAX_NEARBY_LIMIT {
+            return Err(Error::invalid_input(&format!("Limit must be between 1 and {}.", MAX_NEARBY_LIMIT)));
         }
 
-        let mut conn = context.state().db_connection()?;
-        let rows = query!(
-            conn,
-            format!("SELECT id, name, latest_update FROM shops{}", clauses).as_str(),
-            &[],
-        )?;
         Ok(
-            rows.iter().map(|row| {
-                Shop::new(
-                    row.get("id"),
-                    row.get("name"),
-                    row.get("latest_update"),
-                )
-            })
-            .collect()
+            context.state().shops().nearby(center, radius_meters, limit)?
+                .into_iter()
+                .map(|(shop, distance)| NearbyShop { shop: shop, distance: distance })
+                .collect()
         )
     }
+
+    // What shops can start their roles from.
+    fn role_templates() -> Vec<BuiltInRole> {
+        RoleTemplate::ALL.iter().cloned().map(BuiltInRole).collect()
+    }
+}
+
+pub struct MutationShop;
+
+#[juniper::graphql_object(Context = Context)]
+impl MutationShop {
+    // Replaces all weekly hours. Without any the shop is open around the
+    // clock, apart from its exceptions.
+    fn set_opening_hours(context: &Context, shop_id: Uuid, timezone: String, hours: Vec<OpeningHoursInput>) -> Result<Shop, Error> {
+        guard::check(context, "MutationShop.setOpeningHours", Some(shop_id))?;
+        let timezone = parse_timezone(&timezone)?;
+        let mut weekly: Vec<WeeklyHours> = Vec::new();
+        for input in hours {
+            let entry = WeeklyHours::new(input.day, Interval::parse(&input.opens, &input.closes)?);
+            if weekly.iter().any(|other| other.day == entry.day && other.interval.opens == entry.interval.opens) {
+                return Err(Error::invalid_input("Hours of a day must open at different times."));
+            }
+            weekly.push(entry);
+        }
+
+        update_shop(context, shop_id, |state| state.shops().set_opening_hours(shop_id, timezone, weekly))
+    }
+
+    // Without any `hours` the shop is closed all day.
+    fn set_opening_exception(context: &Context, shop_id: Uuid, date: NaiveDate, hours: Vec<TimeRangeInput>, note: Option<String>) -> Result<Shop, Error> {
+        guard::check(context, "MutationShop.setOpeningException", Some(shop_id))?;
+        let intervals = hours.iter()
+            .map(|range| Interval::parse(&range.opens, &range.closes))
+            .collect::<Result<Vec<Interval>, Error>>()?;
+
+        update_shop(context, shop_id, |state| state.shops().set_opening_exception(shop_id, OpeningException::new(date, intervals, note)))
+    }
+
+    fn delete_opening_exception(context: &Context, shop_id: Uuid, date: NaiveDate) -> Result<Shop, Error> {
+        guard::check(context, "MutationShop.deleteOpeningException", Some(shop_id))?;
+        update_shop(context, shop_id, |state| state.shops().delete_opening_exception(shop_id, date))
+    }
+
+    // Stop taking orders for `minutes`, for example when the kitchen is
+    // overwhelmed.
+    fn pause_ordering(context: &Context, shop_id: Uuid, minutes: i32) -> Result<Shop, Error> {
+        guard::check(context, "MutationShop.pauseOrdering", Some(shop_id))?;
+        if minutes < 1 || minutes > MAX_PAUSE_MINUTES {
+            return Err(Error::invalid_input(&format!("Minutes must be between 1 and {}.", MAX_PAUSE_MINUTES)));
+        }
+
+        update_shop(context, shop_id, |state| state.shops().set_paused_until(shop_id, Some(Utc::now() + Duration::minutes(minutes as i64))))
+    }
+
+    fn resume_ordering(context: &Context, shop_id: Uuid) -> Result<Shop, Error> {
+        guard::check(context, "MutationShop.resumeOrdering", Some(shop_id))?;
+        update_shop(context, shop_id, |state| state.shops().set_paused_until(shop_id, None))
+    }
+
+    // A null or blank `address` clears it.
+    fn set_shop_address(context: &Context, shop_id: Uuid, address: Option<String>) -> Result<Shop, Error> {
+        guard::check(context, "MutationShop.setShopAddress", Some(shop_id))?;
+        let address = address.map(|address| address.trim().to_string()).filter(|address| !address.is_empty());
+
+        update_shop(context, shop_id, |state| state.shops().set_address(shop_id, address))
+    }
+
+    // Both or neither of `latitude` and `longitude`; neither clears them.
+    fn set_shop_coordinates(context: &Context, shop_id: Uuid, latitude: Option<f64>, longitude: Option<f64>) -> Result<Shop, Error> {
+        guard::check(context, "MutationShop.setShopCoordinates", Some(shop_id))?;
+        let coordinates = match (latitude, longitude) {
+            (Some(latitude), Some(longitude)) => Some(Coordinates::checked(latitude, longitude)?),
+            (None, None) => None,
+            _ => return Err(Error::invalid_input("Latitude and longitude must be set together.")),
+        };
+
+        update_shop(context, shop_id, |state| state.shops().set_coordinates(shop_id, coordinates))
+    }
+
+    // Without `minor_units` those usual for the currency are used. Prices
+    // are not converted, so set the currency before pricing products.
+    fn set_shop_currency(context: &Context, shop_id: Uuid, currency: String, minor_units: Option<i32>) -> Result<Shop, Error> {
+        guard::check(context, "MutationShop.setShopCurrency", Some(shop_id))?;
+        let currency = Currency::checked(&currency, minor_units)?;
+
+        let shop = update_shop(context, shop_id, |state| state.shops().set_currency(shop_id, currency))?;
+        context.forget_currency(shop_id);
+        Ok(shop)
+    }
+
+    // Replaces all of the shop's rates. Orders already placed keep theirs.
+    fn set_shop_tax_settings(context: &Context, shop_id: Uuid, settings: TaxSettingsInput) -> Result<Shop, Error> {
+        guard::check(context, "MutationShop.setShopTaxSettings", Some(shop_id))?;
+        let settings = settings.checked()?;
+        for &series_id in settings.series_rates.keys() {
+            find_series(context, shop_id, series_id)?;
+        }
+
+        update_shop(context, shop_id, |state| state.shops().set_tax_settings(shop_id, settings))
+    }
+
+    // `file` names the multipart field the picture was uploaded as.
+    fn upload_product_picture(context: &Context, shop_id: Uuid, product_key: Uuid, file: String) -> Result<Product, Error> {
+        guard::check(context, "MutationShop.uploadProductPicture", Some(shop_id))?;
+        find_product(context, shop_id, product_key)?;
+
+        let pictures = picture::process(&context.upload(&file)?.bytes)?;
+        context.state().pictures().put(shop_id, product_key, &pictures)?;
+        update_product(context, shop_id, product_key, |state| state.catalog().set_has_picture(shop_id, product_key, true))
+    }
+
+    fn delete_product_picture(context: &Context, shop_id: Uuid, product_key: Uuid) -> Result<Product, Error> {
+        guard::check(context, "MutationShop.deleteProductPicture", Some(shop_id))?;
+
+        let product = update_product(context, shop_id, product_key, |state| state.catalog().set_has_picture(shop_id, product_key, false))?;
+        context.state().pictures().delete(shop_id, product_key)?;
+        Ok(product)
+    }
+
+    fn create_series(context: &Context, shop_id: Uuid, name: String, ordering: Option<i32>) -> Result<Series, Error> {
+        guard::check(context, "MutationShop.createSeries", Some(shop_id))?;
+        let name = series_name(name)?;
+
+        let id = audit::audited(context, shop_id, AuditEntity::Series, None, series_snapshot, |state| {
+            state.catalog().create_series(shop_id, name, ordering)
+        })?;
+        find_series(context, shop_id, id)
+    }
+
+    fn update_series(context: &Context, shop_id: Uuid, series_id: Uuid, name: Option<String>, ordering: Option<i32>) -> Result<Series, Error> {
+        guard::check(context, "MutationShop.updateSeries", Some(shop_id))?;
+        let name = name.map(series_name).transpose()?;
+
+        audit::audited(context, shop_id, AuditEntity::Series, Some(series_id), series_snapshot, |state| {
+            state.catalog().update_series(shop_id, series_id, name, ordering).map(|_| series_id)
+        })?;
+        find_series(context, shop_id, series_id)
+    }
+
+    // Products of the series are left without one.
+    fn delete_series(context: &Context, shop_id: Uuid, series_id: Uuid) -> Result<Uuid, Error> {
+        guard::check(context, "MutationShop.deleteSeries", Some(shop_id))?;
+
+        audit::audited(context, shop_id, AuditEntity::Series, Some(series_id), series_snapshot, |state| {
+            state.catalog().delete_series(shop_id, series_id).map(|_| series_id)
+        })
+    }
+
+    fn create_promotion(context: &Context, shop_id: Uuid, promotion: PromotionInput) -> Result<Promotion, Error> {
+        guard::check(context, "MutationShop.createPromotion", Some(shop_id))?;
+        let terms = promotion.checked()?;
+        if let Reward::BuyGet { product_key, .. } = terms.reward {
+            find_product(context, shop_id, product_key)?;
+        }
+
+        let id = audit::audited(context, shop_id, AuditEntity::Promotion, None, promotion_snapshot, |state| {
+            state.promotions().create_promotion(shop_id, terms)
+        })?;
+        find_promotion(context, shop_id, id)
+    }
+
+    // Orders placed with the promotion keep it.
+    fn delete_promotion(context: &Context, shop_id: Uuid, promotion_id: Uuid) -> Result<Uuid, Error> {
+        guard::check(context, "MutationShop.deletePromotion", Some(shop_id))?;
+
+        audit::audited(context, shop_id, AuditEntity::Promotion, Some(promotion_id), promotion_snapshot, |state| {
+            state.promotions().delete_promotion(shop_id, promotion_id).map(|_| promotion_id)
+        })
+    }
+
+    fn set_product_series(context: &Context, shop_id: Uuid, product_key: Uuid, series_id: Option<Uuid>) -> Result<Product, Error> {
+        guard::check(context, "MutationShop.setProductSeries", Some(shop_id))?;
+        if let Some(series_id) = series_id {
+            find_series(context, shop_id, series_id)?;
+        }
+
+        update_product(context, shop_id, product_key, |state| state.catalog().set_product_series(shop_id, product_key, series_id))
+    }
+
+    // A null `stock` stops tracking stock for the product.
+    fn set_product_inventory(context: &Context, shop_id: Uuid, product_key: Uuid, stock: Option<i32>, sold_out: bool) -> Result<Product, Error> {
+        guard::check(context, "MutationShop.setProductInventory", Some(shop_id))?;
+        let inventory = Inventory::checked(stock, sold_out)?;
+
+        update_product(context, shop_id, product_key, |state| state.catalog().set_inventory(shop_id, product_key, inventory))
+    }
+
+    fn set_selection_inventory(context: &Context, shop_id: Uuid, product_key: Uuid, selection_key: Uuid, stock: Option<i32>, sold_out: bool) -> Result<Product, Error> {
+        guard::check(context, "MutationShop.setSelectionInventory", Some(shop_id))?;
+        let product = find_product(context, shop_id, product_key)?;
+        product.customizes.ref_values().iter()
+            .flat_map(|customize| customize.selections.ref_values().iter())
+            .find(|selection| selection.key == selection_key)
+            .ok_or_else(|| Error::not_found("Selection"))?;
+        let inventory = Inventory::checked(stock, sold_out)?;
+
+        update_product(context, shop_id, product_key, |state| state.catalog().set_inventory(shop_id, selection_key, inventory))
+    }
+
+    // `key` is the product's own key, or that of one of its customizes or
+    // selections. Replaces any translation of the field in the same locale.
+    fn set_translation(context: &Context, shop_id: Uuid, product_key: Uuid, key: Uuid, field: TranslatedField, locale: String, text: String) -> Result<Product, Error> {
+        guard::check(context, "MutationShop.setTranslation", Some(shop_id))?;
+        find_product(context, shop_id, product_key)?.check_translatable(key, field)?;
+        let locale = Locale::parse(&locale)?;
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(Error::invalid_input("Translation must not be empty."));
+        }
+
+        update_product(context, shop_id, product_key, |state| state.catalog().set_translation(shop_id, key, field, locale, text.to_string()))
+    }
+
+    fn delete_translation(context: &Context, shop_id: Uuid, product_key: Uuid, key: Uuid, field: TranslatedField, locale: String) -> Result<Product, Error> {
+        guard::check(context, "MutationShop.deleteTranslation", Some(shop_id))?;
+        find_product(context, shop_id, product_key)?.check_translatable(key, field)?;
+        let locale = Locale::parse(&locale)?;
+
+        update_product(context, shop_id, product_key, |state| state.catalog().delete_translation(shop_id, key, field, locale))
+    }
+
+    // Members can only give roles what they can do themselves.
+    fn create_role(context: &Context, shop_id: Uuid, role: RoleInput) -> Result<Role, Error> {
+        guard::check(context, "MutationShop.createRole", Some(shop_id))?;
+        let manager = context.shop_capabilities(shop_id)?;
+        let (name, capabilities) = role.checked()?;
+        if !capabilities.is_subset(&manager) {
+            return Err(Error::unauthorized());
+        }
+
+        let id = audit::audited(context, shop_id, AuditEntity::Role, None, role_snapshot, |state| {
+            state.roles().create_role(shop_id, name, &capabilities)
+        })?;
+        find_role(context, shop_id, id)
+    }
+
+    fn update_role(context: &Context, shop_id: Uuid, role_id: Uuid, name: Option<String>, capabilities: Option<Vec<Capability>>) -> Result<Role, Error> {
+        guard::check(context, "MutationShop.updateRole", Some(shop_id))?;
+        let manager = context.shop_capabilities(shop_id)?;
+        managed_role(context, shop_id, role_id, &manager)?;
+        let name = name.map(|name| role::checked_name(&name)).transpose()?;
+        let capabilities = capabilities.map(Capabilities::new);
+        if capabilities.as_ref().map_or(false, |capabilities| !capabilities.is_subset(&manager)) {
+            return Err(Error::unauthorized());
+        }
+
+        audit::audited(context, shop_id, AuditEntity::Role, Some(role_id), role_snapshot, |state| {
+            state.roles().update_role(shop_id, role_id, name, capabilities.as_ref()).map(|_| role_id)
+        })?;
+        find_role(context, shop_id, role_id)
+    }
+
+    // Only roles no member has.
+    fn delete_role(context: &Context, shop_id: Uuid, role_id: Uuid) -> Result<Uuid, Error> {
+        guard::check(context, "MutationShop.deleteRole", Some(shop_id))?;
+        let manager = context.shop_capabilities(shop_id)?;
+        managed_role(context, shop_id, role_id, &manager)?;
+
+        audit::audited(context, shop_id, AuditEntity::Role, Some(role_id), role_snapshot, |state| {
+            state.roles().delete_role(shop_id, role_id).map(|_| role_id)
+        })
+    }
+
+    // Members can't change their own role, so shops keep whoever manages
+    // them, nor that of a member who can do more than they can.
+    fn set_member_role(context: &Context, shop_id: Uuid, user_id: Uuid, role_id: Uuid) -> Result<Role, Error> {
+        guard::check(context, "MutationShop.setMemberRole", Some(shop_id))?;
+        let manager = context.shop_capabilities(shop_id)?;
+        if user_id == context.user_id()? {
+            return Err(Error::unauthorized());
+        }
+        let current = context.state().shops().members(shop_id)?
+            .into_iter()
+            .find(|(user, _)| user.id() == user_id)
+            .map(|(_, role)| role)
+            .ok_or_else(|| Error::not_found("Member"))?;
+        let role = find_role(context, shop_id, role_id)?;
+        if !current.capabilities().is_subset(&manager) || !role.capabilities().is_subset(&manager) {
+            return Err(Error::unauthorized());
+        }
+
+        audit::audited(context, shop_id, AuditEntity::Member, Some(user_id), member_snapshot, |state| {
+            state.roles().set_member_role(shop_id, user_id, role_id).map(|_| user_id)
+        })?;
+        Ok(role)
+    }
+}
+
+fn series_name(name: String) -> Result<String, Error> {
+    let name = name.trim();
+    if name.is_empty() {
+        Err(Error::invalid_input("Series name must not be empty."))
+    } else {
+        Ok(name.to_string())
+    }
+}
+
+// Changes the shop's settings and records it in the audit log.
+fn update_shop<F>(context: &Context, shop_id: Uuid, change: F) -> Result<Shop, Error>
+where
+    F: FnOnce(&State) -> Result<(), Error>,
+{
+    audit::audited(context, shop_id, AuditEntity::Shop, Some(shop_id), shop_snapshot, |state| {
+        change(state).map(|_| shop_id)
+    })?;
+    find_shop(context, shop_id)
+}
+
+// What the audit log keeps of a shop's settings.
+fn shop_snapshot(state: &State, _: Uuid, shop_id: Uuid) -> Result<Option<Value>, Error> {
+    let shop = match state.shops().search(Some(shop_id), None)?.into_iter().next() {
+        Some(shop) => shop,
+        None => return Ok(None),
+    };
+    let schedule = state.shops().schedule(shop_id)?;
+    let currency = state.shops().currency(shop_id)?;
+    let taxes = state.shops().tax_settings(shop_id)?;
+
+    let hours: Vec<Value> = schedule.weekly.iter()
+        .map(|hours| {
+            let range = TimeRange::from(hours.interval);
+            json!({ "day": format!("{:?}", hours.day), "opens": range.opens, "closes": range.closes })
+        })
+        .collect();
+    let exceptions: Vec<Value> = schedule.exceptions.iter()
+        .map(|exception| json!({
+            "date": exception.date.to_string(),
+            "hours": exception.intervals.iter().cloned().map(TimeRange::from).map(|range| json!([range.opens, range.closes])).collect::<Vec<Value>>(),
+            "note": exception.note,
+        }))
+        .collect();
+    let series_tax_rates: Map<String, Value> = taxes.series_rates.iter()
+        .map(|(series_id, rate)| (series_id.to_string(), json!(rate)))
+        .collect();
+
+    Ok(Some(json!({
+        "address": shop.address,
+        "coordinates": shop.coordinates.map(|coordinates| json!([coordinates.latitude, coordinates.longitude])),
+        "timezone": schedule.timezone.name(),
+        "opening_hours": hours,
+        "opening_exceptions": exceptions,
+        "paused_until": schedule.paused_until.map(|at| at.to_rfc3339()),
+        "currency": currency.code(),
+        "minor_units": currency.minor_units(),
+        "tax_inclusive": taxes.inclusive,
+        "tax_rate": taxes.rate,
+        "series_tax_rates": series_tax_rates,
+        "service_charge_rate": taxes.service_charge_rate,
+    })))
+}
+
+// Changes a product and records it in the audit log.
+fn update_product<F>(context: &Context, shop_id: Uuid, product_key: Uuid, change: F) -> Result<Product, Error>
+where
+    F: FnOnce(&State) -> Result<(), Error>,
+{
+    audit::audited(context, shop_id, AuditEntity::Product, Some(product_key), product_snapshot, |state| {
+        change(state).map(|_| product_key)
+    })?;
+    find_product(context, shop_id, product_key)
+}
+
+// What the audit log keeps of a product: the parts members change, that is
+// its picture, series, inventory and translations.
+fn product_snapshot(state: &State, shop_id: Uuid, product_key: Uuid) -> Result<Option<Value>, Error> {
+    let product = match state.catalog().products(shop_id, Some(product_key), None, None)?.into_iter().next() {
+        Some(product) => product,
+        None => return Ok(None),
+    };
+    let translations = |key: Uuid, translations: &Translations| -> Vec<Value> {
+        translations.to_list().into_iter()
+            .map(|translation| json!({
+                "key": key,
+                "field": translation.field.as_str(),
+                "locale": translation.locale,
+                "text": translation.text,
+            }))
+            .collect()
+    };
+
+    let mut inventory = Map::new();
+    let mut translated = translations(product.key, &product.translations);
+    for customize in product.customizes.ref_values() {
+        translated.extend(translations(customize.key, &customize.translations));
+        for selection in customize.selections.ref_values() {
+            inventory.insert(
+                selection.key.to_string(),
+                json!({ "stock": selection.inventory.stock, "sold_out": selection.inventory.sold_out }),
+            );
+            translated.extend(translations(selection.key, &selection.translations));
+        }
+    }
+
+    Ok(Some(json!({
+        "series_id": product.series_id,
+        "has_picture": product.has_picture,
+        "stock": product.inventory.stock,
+        "sold_out": product.inventory.sold_out,
+        "selections": inventory,
+        "translations": translated,
+    })))
+}
+
+fn series_snapshot(state: &State, shop_id: Uuid, series_id: Uuid) -> Result<Option<Value>, Error> {
+    Ok(
+        state.catalog().series(shop_id, Some(series_id))?
+            .first()
+            .map(|series| json!({ "name": series.name, "ordering": series.ordering }))
+    )
+}
+
+fn promotion_snapshot(state: &State, shop_id: Uuid, promotion_id: Uuid) -> Result<Option<Value>, Error> {
+    let promotion = match state.promotions().promotions(shop_id, Some(promotion_id))?.into_iter().next() {
+        Some(promotion) => promotion,
+        None => return Ok(None),
+    };
+    let terms = promotion.terms();
+    let (percent, amount, product_key, buy_count, get_count) = match terms.reward {
+        Reward::Percentage(percent) => (Some(percent), None, None, None, None),
+        Reward::Fixed(amount) => (None, Some(amount), None, None, None),
+        Reward::BuyGet { product_key, buy, get } => (None, None, Some(product_key), Some(buy), Some(get)),
+    };
+    Ok(Some(json!({
+        "name": terms.name,
+        "kind": terms.reward.kind().as_str(),
+        "percent": percent,
+        "amount": amount,
+        "product_key": product_key,
+        "buy_count": buy_count,
+        "get_count": get_count,
+        "min_spend": terms.min_spend,
+        "starts_at": terms.starts_at.map(|at| at.to_rfc3339()),
+        "ends_at": terms.ends_at.map(|at| at.to_rfc3339()),
+        "code": terms.code,
+        "usage_limit": terms.usage_limit,
+        "usage_count": promotion.usage_count(),
+    })))
+}
+
+// What the audit log keeps of a role: its name and capabilities.
+fn role_snapshot(state: &State, shop_id: Uuid, role_id: Uuid) -> Result<Option<Value>, Error> {
+    Ok(
+        state.roles().roles(shop_id, Some(role_id))?
+            .first()
+            .map(|role| json!({ "name": role.name(), "capabilities": role.capabilities().to_strings() }))
+    )
+}
+
+// What the audit log keeps of a member: their role.
+fn member_snapshot(state: &State, shop_id: Uuid, user_id: Uuid) -> Result<Option<Value>, Error> {
+    Ok(
+        state.shops().members(shop_id)?
+            .into_iter()
+            .find(|(user, _)| user.id() == user_id)
+            .map(|(_, role)| json!({ "role_id": role.id(), "role": role.name() }))
+    )
+}
+
+// A role `manager` may change: not their own, and with nothing they can't do.
+fn managed_role(context: &Context, shop_id: Uuid, role_id: Uuid, manager: &Capabilities) -> Result<Role, Error> {
+    let role = find_role(context, shop_id, role_id)?;
+    let own_role = context.state().shops().user_shops(context.user_id()?, Some(shop_id), None)?
+        .into_iter()
+        .next()
+        .map(|(_, role)| role.id());
+    if own_role == Some(role_id) || !role.capabilities().is_subset(manager) {
+        return Err(Error::unauthorized());
+    }
+    Ok(role)
+}
+
+fn find_role(context: &Context, shop_id: Uuid, role_id: Uuid) -> Result<Role, Error> {
+    context.state().roles().roles(shop_id, Some(role_id))?
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::not_found("Role"))
+}
+
+pub fn find_shop(context: &Context, shop_id: Uuid) -> Result<Shop, Error> {
+    context.state().shops().search(Some(shop_id), None)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::not_found("Shop"))
+}
+
+pub fn find_product(context: &Context, shop_id: Uuid, product_key: Uuid) -> Result<Product, Error> {
+    context.state().catalog().products(shop_id, Some(product_key), None, None)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::not_found("Product"))
+}
+
+fn find_series(context: &Context, shop_id: Uuid, series_id: Uuid) -> Result<Series, Error> {
+    context.state().catalog().series(shop_id, Some(series_id))?
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::not_found("Series"))
+}
+
+fn find_promotion(context: &Context, shop_id: Uuid, id: Uuid) -> Result<Promotion, Error> {
+    context.state().promotions().promotions(shop_id, Some(id))?
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::not_found("Promotion"))
 }
 
 pub struct Shop {
     id: Uuid,
     name: String,
+    address: Option<String>,
+    coordinates: Option<Coordinates>,
     latest_update: DateTime<Utc>,
 }
 
 impl Shop {
-    pub fn new(id: Uuid, name: String, latest_update: DateTime<Utc>) -> Self {
+    pub fn new(id: Uuid, name: String, address: Option<String>, coordinates: Option<Coordinates>, latest_update: DateTime<Utc>) -> Self {
         Shop {
             id: id,
             name: name,
+            address: address,
+            coordinates: coordinates,
             latest_update: latest_update,
         }
     }
@@ -73,11 +610,23 @@ impl Shop {
     pub fn id(&self) -> Uuid {
         self.id
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn global_id(&self) -> GlobalId {
+        GlobalId::Shop(self.id)
+    }
 }
 
-#[juniper::graphql_object(Context = Context)]
+#[juniper::graphql_object(Context = Context, interfaces = [Node])]
 impl Shop {
-    fn id(&self) -> Uuid {
+    fn id(&self) -> ID {
+        self.global_id().encode()
+    }
+
+    fn uuid(&self) -> Uuid {
         self.id
     }
 
@@ -85,309 +634,451 @@ impl Shop {
         &self.name
     }
 
+    fn address(&self) -> &Option<String> {
+        &self.address
+    }
+
+    fn coordinates(&self) -> &Option<Coordinates> {
+        &self.coordinates
+    }
+
+    // ISO 4217 code of the currency every price of the shop is in.
+    fn currency(&self, context: &Context) -> Result<String, Error> {
+        Ok(context.currency(self.id)?.code().to_string())
+    }
+
+    // Digits after the decimal point, so prices are integers in minor units.
+    fn minor_units(&self, context: &Context) -> Result<i32, Error> {
+        Ok(context.currency(self.id)?.minor_units() as i32)
+    }
+
+    fn tax_settings(&self, context: &Context) -> Result<TaxSettings, Error> {
+        context.state().shops().tax_settings(self.id)
+    }
+
+    fn timezone(&self, context: &Context) -> Result<String, Error> {
+        Ok(context.state().shops().schedule(self.id)?.timezone.name().to_string())
+    }
+
+    fn opening_hours(&self, context: &Context) -> Result<Vec<WeeklyHours>, Error> {
+        Ok(context.state().shops().schedule(self.id)?.weekly)
+    }
+
+    // Exceptions on or after `from`, or all of them.
+    fn opening_exceptions(&self, context: &Context, from: Option<NaiveDate>) -> Result<Vec<OpeningException>, Error> {
+        Ok(
+            context.state().shops().schedule(self.id)?.exceptions
+                .into_iter()
+                .filter(|exception| from.map_or(true, |from| exception.date >= from))
+                .collect()
+        )
+    }
+
+    fn ordering_paused_until(&self, context: &Context) -> Result<Option<DateTime<Utc>>, Error> {
+        Ok(context.state().shops().schedule(self.id)?.paused_until.filter(|&until| until > Utc::now()))
+    }
+
+    // Whether the shop takes orders right now.
+    fn is_open(&self, context: &Context) -> Result<bool, Error> {
+        Ok(context.state().shops().schedule(self.id)?.is_open_at(Utc::now()))
+    }
+
+    // Null while open, or when the shop does not open in the next two weeks.
+    fn next_open_at(&self, context: &Context) -> Result<Option<DateTime<Utc>>, Error> {
+        Ok(context.state().shops().schedule(self.id)?.next_open_at(Utc::now()))
+    }
+
     fn latest_update(&self) -> DateTime<Utc> {
         self.latest_update
     }
 
-    fn products(&self, context: &Context, key: Option<Uuid>, name: Option<String>) -> Result<Vec<Product>, Error> {
-        let mut conn = context.state().db_connection()?;
-
-        let mut clause = Clause::new();
-        if let Some(key) = key.as_ref() {
-            clause.and(Clause::equal("key", format!("'{}'", key)));
-        }
-        if let Some(name) = name.as_ref() {
-            clause.and(Clause::like("upper((product).name)", format!("upper('%{}%')", name)));
-        }
-
-        let rows = query!(
-            conn,
-            format!(
-                "WITH
-                    products AS (
-                        SELECT
-                            key,
-                            product
-                        FROM
-                            query_shop_products($1){}
-                    ),
-                    customizes AS (
-                        SELECT
-                            key prod_key,
-                            (query_product_customizes(product)).*
-                        FROM
-                            products
-                    ),
-                    selections AS (
-                        SELECT
-                            key cus_key,
-                            (query_customize_selections(customize)).*
-                        FROM
-                            customizes
-                    )
-                SELECT
-                    products.key prod_key,
-                    (product).name prod_name,
-                    (product).description prod_description,
-                    (product).price prod_price,
-                    (product).series_id prod_series_id,
-                    (product).has_picture prod_has_picture,
-                    (product).latest_update prod_latest_update,
-                    cus_join_sel.cus_key,
-                    (cus_join_sel.customize).name cus_name,
-                    (cus_join_sel.customize).description cus_description,
-                    (cus_join_sel.customize).latest_update cus_latest_update,
-                    cus_join_sel.sel_key,
-                    (cus_join_sel.selection).name sel_name,
-                    (cus_join_sel.selection).price sel_price
-                FROM
-                    products
-                LEFT JOIN
-                    (
-                        SELECT
-                            prod_key,
-                            customizes.key cus_key,
-                            customize,
-                            selections.key sel_key,
-                            selection
-                        FROM
-                            customizes
-                        LEFT JOIN
-                            selections
-                        ON
-                            customizes.key = selections.cus_key
-                    ) cus_join_sel
-                ON
-                    products.key = cus_join_sel.prod_key",
-                clause,
-            ).as_str(),
-            &[&UuidNN(self.id)],
-        )?;
-
-        let mut products = Dict::new();
-        for row in rows.iter() {
-            let prod_key = row.get::<&str, Uuid>("prod_key");
-            let product = if let Some(product) = products.ref_mut_value(prod_key) {
-                product
-            } else {
-                let product = Product::new(
-                    prod_key,
-                    row.get("prod_name"),
-                    row.get("prod_description"),
-                    row.get("prod_price"),
-                    row.get("prod_has_picture"),
-                    row.get("prod_latest_update"),
-                );
-                products.insert_uncheck(prod_key, product)
-            };
-
-            if let Ok(cus_key) = row.try_get::<&str, Uuid>("cus_key") {
-                let customize = if let Some(customize) = product.ref_mut_customize(cus_key) {
-                    customize
-                } else {
-                    let customize = Customize::new(
-                        cus_key,
-                        row.get("cus_name"),
-                        row.get("cus_description"),
-                        row.get("cus_latest_update"),
-                    );
-                    product.insert_customize_uncheck(cus_key, customize)
-                };
-
-                if let Ok(sel_key) = row.try_get::<&str, Uuid>("sel_key") {
-                    if let None = customize.ref_mut_selection(sel_key) {
-                        let selection = Selection::new(
-                            sel_key,
-                            row.get("sel_name"),
-                            row.get("sel_price"),
-                        );
-                        customize.insert_selection_uncheck(sel_key, selection);
-                    }
-                }
-            }
-        }
+    fn series(&self, context: &Context) -> Result<Vec<Series>, Error> {
+        context.state().catalog().series(self.id, None)
+    }
 
-        Ok(products.values())
-    }
-    
-    fn products_json(&self, context: &Context, key: Option<Uuid>, name: Option<String>) -> Result<String, Error> {
-        let mut conn = context.state().db_connection()?;
-
-        let mut clause = Clause::new();
-        if let Some(key) = key.as_ref() {
-            clause.and(Clause::equal("key", format!("'{}'", key)));
-        }
-        if let Some(name) = name.as_ref() {
-            clause.and(Clause::like("upper((product).name)", format!("upper('%{}%')", name)));
-        }
-
-        let rows = query!(
-            conn,
-            format!(
-                "WITH
-                    products AS (
-                        SELECT
-                            key,
-                            product
-                        FROM
-                            query_shop_products($1){}
-                    ),
-                    customizes AS (
-                        SELECT
-                            key prod_key,
-                            (query_product_customizes(product)).*
-                        FROM
-                            products
-                    ),
-                    selections AS (
-                        SELECT
-                            key cus_key,
-                            (query_customize_selections(customize)).*
-                        FROM
-                            customizes
-                    )
-                SELECT
-                    products.key prod_key,
-                    (product).name prod_name,
-                    (product).description prod_description,
-                    (product).price prod_price,
-                    (product).series_id prod_series_id,
-                    (product).has_picture prod_has_picture,
-                    (product).latest_update prod_latest_update,
-                    cus_join_sel.cus_key,
-                    (cus_join_sel.customize).name cus_name,
-                    (cus_join_sel.customize).description cus_description,
-                    (cus_join_sel.customize).latest_update cus_latest_update,
-                    cus_join_sel.sel_key,
-                    (cus_join_sel.selection).name sel_name,
-                    (cus_join_sel.selection).price sel_price
-                FROM
-                    products
-                LEFT JOIN
-                    (
-                        SELECT
-                            prod_key,
-                            customizes.key cus_key,
-                            customize,
-                            selections.key sel_key,
-                            selection
-                        FROM
-                            customizes
-                        LEFT JOIN
-                            selections
-                        ON
-                            customizes.key = selections.cus_key
-                    ) cus_join_sel
-                ON
-                    products.key = cus_join_sel.prod_key",
-                clause,
-            ).as_str(),
-            &[&UuidNN(self.id)],
-        )?;
-
-        let mut products = Map::new();
-        for row in rows.iter() {
-            let prod_key = row.get::<&str, Uuid>("prod_key");
-            if !products.contains_key(&prod_key.to_string()) {
-                products.insert(
-                    prod_key.to_string(),
-                    json!({
-                        "name": row.get::<&str, String>("prod_name"),
-                        "description": row.get::<&str, Option<String>>("prod_description"),
-                        "price": row.get::<&str, i32>("prod_price"),
-                        "series_id": row.get::<&str, Option<Uuid>>("prod_series_id"),
-                        "has_picture": row.get::<&str, bool>("prod_has_picture"),
-                        "latest_update": row.get::<&str, DateTime<Utc>>("prod_latest_update").to_string(),
-                        "customizes": Map::new()
-                    })
-                );
-            }
+    fn products(&self, context: &Context, key: Option<Uuid>, name: Option<String>, series_id: Option<Uuid>) -> Result<Vec<Product>, Error> {
+        context.state().catalog().products(self.id, key, name, series_id)
+    }
 
-            if let Ok(cus_key) = row.try_get::<&str, Uuid>("cus_key") {
-                let customizes = products.get_mut(&prod_key.to_string()).unwrap().get_mut("customizes").unwrap().as_object_mut().unwrap();
-                if !customizes.contains_key(&cus_key.to_string()) {
-                    customizes.insert(
-                        cus_key.to_string(),
-                        json!({
-                            "name": row.get::<&str, String>("cus_name"),
-                            "description": row.get::<&str, Option<String>>("cus_description"),
-                            "latest_update": row.get::<&str, DateTime<Utc>>("cus_latest_update").to_string(),
-                            "selections": Map::new()
-                        })
-                    );
-                }
+    fn products_json(&self, context: &Context, key: Option<Uuid>, name: Option<String>, series_id: Option<Uuid>) -> Result<String, Error> {
+        let products = context.state().catalog().products(self.id, key, name, series_id)?;
 
-                if let Ok(sel_key) = row.try_get::<&str, Uuid>("sel_key") {
-                    let selections = customizes.get_mut(&cus_key.to_string()).unwrap().get_mut("selections").unwrap().as_object_mut().unwrap();
+        let mut products_json = Map::new();
+        for product in products.iter() {
+            let mut customizes = Map::new();
+            for customize in product.customizes.ref_values() {
+                let mut selections = Map::new();
+                for selection in customize.selections.ref_values() {
                     selections.insert(
-                        sel_key.to_string(),
+                        selection.key.to_string(),
                         json!({
-                            "name": row.get::<&str, String>("sel_name"),
-                            "price": row.get::<&str, i32>("sel_price")
+                            "name": selection.name,
+                            "price": selection.price
                         })
                     );
                 }
+
+                customizes.insert(
+                    customize.key.to_string(),
+                    json!({
+                        "name": customize.name,
+                        "description": customize.description,
+                        "latest_update": customize.latest_update.to_string(),
+                        "selections": selections
+                    })
+                );
             }
+
+            products_json.insert(
+                product.key.to_string(),
+                json!({
+                    "name": product.name,
+                    "description": product.description,
+                    "price": product.price,
+                    "series_id": product.series_id,
+                    "has_picture": product.has_picture,
+                    "latest_update": product.latest_update.to_string(),
+                    "customizes": customizes
+                })
+            );
+        }
+
+        Ok(serde_json::to_string(&products_json)?)
+    }
+}
+
+// Radius of the sphere Postgres `earthdistance` measures on, so both
+// repositories agree on distances.
+pub const EARTH_RADIUS: f64 = 6_378_168.0;
+
+pub const MAX_NEARBY_RADIUS: f64 = 50_000.0;
+pub const DEFAULT_NEARBY_LIMIT: i32 = 20;
+pub const MAX_NEARBY_LIMIT: i32 = 100;
+
+#[derive(juniper::GraphQLObject, Clone, Copy, Debug, PartialEq)]
+pub struct Coordinates {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Coordinates {
+    pub fn new(latitude: f64, longitude: f64) -> Self {
+        Coordinates {
+            latitude: latitude,
+            longitude: longitude,
+        }
+    }
+
+    fn checked(latitude: f64, longitude: f64) -> Result<Self, Error> {
+        if !(-90.0..=90.0).contains(&latitude) {
+            return Err(Error::invalid_input("Latitude must be between -90 and 90."));
+        }
+        if !(-180.0..=180.0).contains(&longitude) {
+            return Err(Error::invalid_input("Longitude must be between -180 and 180."));
+        }
+        Ok(Coordinates::new(latitude, longitude))
+    }
+
+    // Great-circle distance in meters.
+    pub fn distance(&self, other: &Coordinates) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let d_lat = lat2 - lat1;
+        let d_lng = (other.longitude - self.longitude).to_radians();
+
+        let h = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lng / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS * h.sqrt().asin()
+    }
+}
+
+pub struct NearbyShop {
+    shop: Shop,
+    distance: f64,
+}
+
+#[juniper::graphql_object(Context = Context)]
+impl NearbyShop {
+    fn shop(&self) -> &Shop {
+        &self.shop
+    }
+
+    fn distance_meters(&self) -> f64 {
+        self.distance
+    }
+}
+
+// Stock of a product or selection. Without a `stock` it is not tracked.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Inventory {
+    pub stock: Option<i32>,
+    pub sold_out: bool,
+}
+
+impl Inventory {
+    pub fn new(stock: Option<i32>, sold_out: bool) -> Self {
+        Inventory {
+            stock: stock,
+            sold_out: sold_out,
+        }
+    }
+
+    fn checked(stock: Option<i32>, sold_out: bool) -> Result<Self, Error> {
+        if stock.map_or(false, |stock| stock < 0) {
+            return Err(Error::invalid_input("Stock must not be negative."));
+        }
+        Ok(Inventory::new(stock, sold_out))
+    }
+
+    // Whether `count` more units may be ordered.
+    pub fn allows(&self, count: i32) -> bool {
+        !self.sold_out && self.stock.map_or(true, |stock| stock >= count)
+    }
+}
+
+#[derive(juniper::GraphQLInputObject)]
+pub struct SelectionChoice {
+    pub customize_key: Uuid,
+    pub selection_key: Uuid,
+}
+
+pub struct Series {
+    id: Uuid,
+    name: String,
+    ordering: i32,
+    latest_update: DateTime<Utc>,
+}
+
+impl Series {
+    pub fn new(id: Uuid, name: String, ordering: i32, latest_update: DateTime<Utc>) -> Self {
+        Series {
+            id: id,
+            name: name,
+            ordering: ordering,
+            latest_update: latest_update,
         }
+    }
+}
+
+#[juniper::graphql_object(Context = Context)]
+impl Series {
+    fn id(&self) -> Uuid {
+        self.id
+    }
 
-        Ok(serde_json::to_string(&products)?)
+    fn name(&self) -> &String {
+        &self.name
+    }
+
+    fn ordering(&self) -> i32 {
+        self.ordering
+    }
+
+    fn latest_update(&self) -> DateTime<Utc> {
+        self.latest_update
     }
 }
 
-struct Product {
+pub struct Product {
+    shop_id: Uuid,
     key: Uuid,
     name: String,
     description: Option<String>,
     price: i32,
+    series_id: Option<Uuid>,
     has_picture: bool,
+    inventory: Inventory,
     latest_update: DateTime<Utc>,
+    translations: Translations,
     customizes: Dict<Uuid, Customize>,
 }
 
 impl Product {
-    fn new(key: Uuid, name: String, description: Option<String>, price: i32, has_picture: bool, latest_update: DateTime<Utc>) -> Self {
+    pub fn new(shop_id: Uuid, key: Uuid, name: String, description: Option<String>, price: i32, series_id: Option<Uuid>, has_picture: bool, inventory: Inventory, latest_update: DateTime<Utc>) -> Self {
         Product {
+            shop_id: shop_id,
             key: key,
             name: name,
             description: description,
             price: price,
+            series_id: series_id,
             has_picture: has_picture,
+            inventory: inventory,
             latest_update: latest_update,
+            translations: Translations::default(),
             customizes: Dict::new(),
         }
     }
-    
-    fn ref_mut_customize(&mut self, key: Uuid) -> Option<&mut Customize> {
+
+    pub fn key(&self) -> Uuid {
+        self.key
+    }
+
+    pub fn series_id(&self) -> Option<Uuid> {
+        self.series_id
+    }
+
+    pub fn global_id(&self) -> GlobalId {
+        GlobalId::Product(self.shop_id, self.key)
+    }
+
+    pub fn ref_mut_customize(&mut self, key: Uuid) -> Option<&mut Customize> {
         self.customizes.ref_mut_value(key)
     }
 
-    fn insert_customize_uncheck(&mut self, key: Uuid, cus: Customize) -> &mut Customize {
+    pub fn insert_customize_uncheck(&mut self, key: Uuid, cus: Customize) -> &mut Customize {
         self.customizes.insert_uncheck(key, cus)
     }
+
+    // The translations of the product itself, or of the customize or
+    // selection with `key`.
+    fn translations_of(&mut self, key: Uuid) -> Option<&mut Translations> {
+        if key == self.key {
+            return Some(&mut self.translations);
+        }
+        self.customizes.ref_mut_values().iter_mut().find_map(|customize| {
+            if customize.key == key {
+                Some(&mut customize.translations)
+            } else {
+                customize.selections.ref_mut_values().iter_mut()
+                    .find(|selection| selection.key == key)
+                    .map(|selection| &mut selection.translations)
+            }
+        })
+    }
+
+    // Fails with `NotFound` when neither the product nor any of its
+    // customizes or selections has `key`. Only products have a description.
+    pub fn check_translatable(&self, key: Uuid, field: TranslatedField) -> Result<(), Error> {
+        let found = key == self.key || self.customizes.ref_values().iter().any(|customize| {
+            customize.key == key || customize.selections.ref_values().iter().any(|selection| selection.key == key)
+        });
+        if !found {
+            Err(Error::not_found("Product, customize or selection"))
+        } else if field == TranslatedField::Description && key != self.key {
+            Err(Error::invalid_input("Only products have a translatable description."))
+        } else {
+            Ok(())
+        }
+    }
+
+    // Keys matching nothing in the product are ignored.
+    pub fn translate(&mut self, key: Uuid, field: TranslatedField, locale: Locale, text: String) {
+        if let Some(translations) = self.translations_of(key) {
+            translations.insert(field, locale, text);
+        }
+    }
+
+    // Price `count` of the product with the chosen selections for a cart,
+    // failing with `ItemUnavailable` when any of them cannot be ordered.
+    pub fn cart_item(&self, count: i32, remark: Option<String>, choices: &[SelectionChoice]) -> Result<NewItem, Error> {
+        if count < 1 {
+            return Err(Error::invalid_input("Count must be at least 1."));
+        }
+        if !self.inventory.allows(count) {
+            return Err(Error::unavailable(&self.name));
+        }
+
+        let mut customizes: Vec<NewCustomizeItem> = Vec::new();
+        for choice in choices {
+            if customizes.iter().any(|cus| cus.customize_key == choice.customize_key) {
+                return Err(Error::invalid_input("Each customize may only be chosen once."));
+            }
+            let customize = self.customizes.ref_values().iter()
+                .find(|customize| customize.key == choice.customize_key)
+                .ok_or_else(|| Error::not_found("Customize"))?;
+            let selection = customize.selections.ref_values().iter()
+                .find(|selection| selection.key == choice.selection_key)
+                .ok_or_else(|| Error::not_found("Selection"))?;
+            if !selection.inventory.allows(count) {
+                return Err(Error::unavailable(&selection.name));
+            }
+
+            customizes.push(NewCustomizeItem {
+                customize_key: customize.key,
+                name: customize.name.clone(),
+                selection: Some(selection.name.clone()),
+                selection_key: Some(selection.key),
+                selection_price: Some(selection.price),
+            });
+        }
+        order::subtotal(order::unit_price(self.price, customizes.iter().map(|cus| cus.selection_price))?, count)?;
+
+        Ok(NewItem {
+            product_key: self.key,
+            name: self.name.clone(),
+            price: self.price,
+            count: count,
+            remark: remark,
+            customizes: customizes,
+        })
+    }
 }
 
-#[juniper::graphql_object(Context = Context)]
+#[juniper::graphql_object(Context = Context, interfaces = [Node])]
 impl Product {
+    fn id(&self) -> ID {
+        self.global_id().encode()
+    }
+
     fn key(&self) -> Uuid {
         self.key
     }
 
-    fn name(&self) -> &String {
-        &self.name
+    // In `locale` if given, or else the most preferred of `Accept-Language`
+    // with a translation, falling back to the shop's own text.
+    fn name(&self, context: &Context, locale: Option<String>) -> Result<String, Error> {
+        let locales = context.locales(locale)?;
+        Ok(self.translations.resolve(TranslatedField::Name, &locales).unwrap_or(&self.name).to_string())
     }
 
-    fn description(&self) -> &Option<String> {
-        &self.description
+    fn description(&self, context: &Context, locale: Option<String>) -> Result<Option<String>, Error> {
+        let locales = context.locales(locale)?;
+        Ok(
+            self.translations.resolve(TranslatedField::Description, &locales)
+                .map(str::to_string)
+                .or_else(|| self.description.clone())
+        )
+    }
+
+    fn translations(&self) -> Vec<Translation> {
+        self.translations.to_list()
     }
 
     fn price(&self) -> i32 {
         self.price
     }
 
+    fn price_money(&self, context: &Context) -> Result<Money, Error> {
+        Ok(Money::new(self.price, context.currency(self.shop_id)?))
+    }
+
+    fn series_id(&self) -> Option<Uuid> {
+        self.series_id
+    }
+
+    fn series(&self, context: &Context) -> Result<Option<Series>, Error> {
+        if let Some(series_id) = self.series_id {
+            Ok(context.state().catalog().series(self.shop_id, Some(series_id))?.into_iter().next())
+        } else {
+            Ok(None)
+        }
+    }
+
     fn has_picture(&self) -> bool {
         self.has_picture
     }
 
+    fn stock(&self) -> Option<i32> {
+        self.inventory.stock
+    }
+
+    fn sold_out(&self) -> bool {
+        self.inventory.sold_out
+    }
+
+    fn available(&self) -> bool {
+        self.inventory.allows(1)
+    }
+
     fn latest_update(&self) -> DateTime<Utc> {
         self.latest_update
     }
@@ -397,30 +1088,32 @@ impl Product {
     }
 }
 
-struct Customize {
+pub struct Customize {
     key: Uuid,
     name: String,
     description: Option<String>,
     latest_update: DateTime<Utc>,
+    translations: Translations,
     selections: Dict<Uuid, Selection>,
 }
 
 impl Customize {
-    fn new(key: Uuid, name: String, description: Option<String>, latest_update: DateTime<Utc>) -> Self {
+    pub fn new(key: Uuid, name: String, description: Option<String>, latest_update: DateTime<Utc>) -> Self {
         Customize {
             key: key,
             name: name,
             description: description,
             latest_update: latest_update,
+            translations: Translations::default(),
             selections: Dict::new(),
         }
     }
 
-    fn ref_mut_selection(&mut self, key: Uuid) -> Option<&mut Selection> {
+    pub fn ref_mut_selection(&mut self, key: Uuid) -> Option<&mut Selection> {
         self.selections.ref_mut_value(key)
     }
 
-    fn insert_selection_uncheck(&mut self, key: Uuid, sel: Selection) -> &mut Selection {
+    pub fn insert_selection_uncheck(&mut self, key: Uuid, sel: Selection) -> &mut Selection {
         self.selections.insert_uncheck(key, sel)
     }
 }
@@ -431,8 +1124,13 @@ impl Customize {
         self.key
     }
 
-    fn name(&self) -> &String {
-        &self.name
+    fn name(&self, context: &Context, locale: Option<String>) -> Result<String, Error> {
+        let locales = context.locales(locale)?;
+        Ok(self.translations.resolve(TranslatedField::Name, &locales).unwrap_or(&self.name).to_string())
+    }
+
+    fn translations(&self) -> Vec<Translation> {
+        self.translations.to_list()
     }
 
     fn description(&self) -> &Option<String> {
@@ -448,33 +1146,779 @@ impl Customize {
     }
 }
 
-struct Selection {
+pub struct Selection {
+    shop_id: Uuid,
     key: Uuid,
     name: String,
     price: i32,
+    inventory: Inventory,
+    translations: Translations,
 }
 
 impl Selection {
-    fn new(key: Uuid, name: String, price: i32) -> Self {
+    pub fn new(shop_id: Uuid, key: Uuid, name: String, price: i32, inventory: Inventory) -> Self {
         Selection {
+            shop_id: shop_id,
             key: key,
             name: name,
             price: price,
+            inventory: inventory,
+            translations: Translations::default(),
         }
     }
 }
 
-#[juniper::graphql_object]
+#[juniper::graphql_object(Context = Context)]
 impl Selection {
     fn key(&self) -> &Uuid {
         &self.key
     }
 
-    fn name(&self) -> &String {
-        &self.name
+    fn name(&self, context: &Context, locale: Option<String>) -> Result<String, Error> {
+        let locales = context.locales(locale)?;
+        Ok(self.translations.resolve(TranslatedField::Name, &locales).unwrap_or(&self.name).to_string())
+    }
+
+    fn translations(&self) -> Vec<Translation> {
+        self.translations.to_list()
     }
 
     fn price(&self) -> &i32 {
         &self.price
     }
-}
\ No newline at end of file
+
+    fn price_money(&self, context: &Context) -> Result<Money, Error> {
+        Ok(Money::new(self.price, context.currency(self.shop_id)?))
+    }
+
+    fn stock(&self) -> &Option<i32> {
+        &self.inventory.stock
+    }
+
+    fn sold_out(&self) -> &bool {
+        &self.inventory.sold_out
+    }
+
+    fn available(&self) -> bool {
+        self.inventory.allows(1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        sync::Arc,
+    };
+    use image::{DynamicImage, ImageOutputFormat};
+    use serde_json::json;
+    use crate::{
+        graphql::{
+            Upload,
+            audit::{self, AuditEntity},
+            context::Context,
+            shop::Coordinates,
+        },
+        state::State,
+        error::Error,
+        tests::{
+            harness::{Api, Memory, TestServer, execute, execute_with_uploads, error_type},
+            seed,
+        },
+    };
+
+    fn uploads(bytes: Vec<u8>) -> HashMap<String, Upload> {
+        let mut uploads = HashMap::new();
+        uploads.insert("0".to_string(), Upload { bytes: bytes });
+        uploads
+    }
+
+    #[test]
+    fn test_product_picture() {
+        let repository = Arc::new(seed::memory());
+        let mut png = Vec::new();
+        DynamicImage::new_rgb8(800, 600).write_to(&mut png, ImageOutputFormat::Png).unwrap();
+        let upload = format!(
+            "mutation {{ shop {{ uploadProductPicture(shopId: \"{}\", productKey: \"{}\", file: \"0\") {{ hasPicture }} }} }}",
+            seed::DINER,
+            seed::PORK_RICE,
+        );
+
+        let response = execute_with_uploads(repository.clone(), &upload, &[("USSID", seed::STAFF_SESSION)], uploads(png.clone()));
+        assert_eq!(error_type(&response), Some("Unauthorized"));
+
+        let response = execute_with_uploads(repository.clone(), &upload, &[("USSID", seed::OWNER_SESSION)], uploads(b"not a picture".to_vec()));
+        assert_eq!(error_type(&response), Some("InvalidPicture"));
+
+        let response = execute(repository.clone(), &upload, &[("USSID", seed::OWNER_SESSION)]);
+        assert_eq!(error_type(&response), Some("MissingUpload"));
+
+        let response = execute_with_uploads(repository.clone(), &upload, &[("USSID", seed::OWNER_SESSION)], uploads(png));
+        assert_eq!(response["data"]["shop"]["uploadProductPicture"], json!({ "hasPicture": true }));
+
+        let delete = format!(
+            "mutation {{ shop {{ deleteProductPicture(shopId: \"{}\", productKey: \"{}\") {{ hasPicture }} }} }}",
+            seed::DINER,
+            seed::PORK_RICE,
+        );
+        let response = execute(repository, &delete, &[("USSID", seed::OWNER_SESSION)]);
+        assert_eq!(response["data"]["shop"]["deleteProductPicture"], json!({ "hasPicture": false }));
+    }
+
+    #[test]
+    fn test_series() {
+        series(&mut Memory::seeded());
+    }
+
+    #[test]
+    #[ignore]
+    fn test_series_postgres() {
+        series(&mut TestServer::start());
+    }
+
+    fn series(api: &mut dyn Api) {
+        let query = format!(
+            "{{ shop {{ search(id: \"{}\") {{ series {{ name }} products(seriesId: \"{}\") {{ name series {{ name }} }} }} }} }}",
+            seed::DINER,
+            seed::DRINKS,
+        );
+        let response = api.execute(&query, &[]);
+        assert_eq!(
+            response["data"]["shop"]["search"][0],
+            json!({
+                "series": [{ "name": "Mains" }, { "name": "Drinks" }],
+                "products": [{ "name": "Black Tea", "series": { "name": "Drinks" } }],
+            }),
+        );
+
+        let create = format!(
+            "mutation {{ shop {{ createSeries(shopId: \"{}\", name: \" Desserts \") {{ id name ordering }} }} }}",
+            seed::DINER,
+        );
+        let response = api.execute(&create, &[("USSID", seed::STAFF_SESSION)]);
+        assert_eq!(error_type(&response), Some("Unauthorized"));
+        let response = api.execute(&create, &[("USSID", seed::OWNER_SESSION)]);
+        let desserts = &response["data"]["shop"]["createSeries"];
+        assert_eq!((&desserts["name"], &desserts["ordering"]), (&json!("Desserts"), &json!(2)));
+        let desserts = desserts["id"].as_str().unwrap().to_string();
+
+        let update = format!(
+            "mutation {{ shop {{ updateSeries(shopId: \"{}\", seriesId: \"{}\", ordering: 5) {{ name ordering }} }} }}",
+            seed::DINER,
+            seed::DRINKS,
+        );
+        let response = api.execute(&update, &[("USSID", seed::OWNER_SESSION)]);
+        assert_eq!(response["data"]["shop"]["updateSeries"], json!({ "name": "Drinks", "ordering": 5 }));
+
+        let delete = format!(
+            "mutation {{ shop {{ deleteSeries(shopId: \"{}\", seriesId: \"{}\") }} }}",
+            seed::DINER,
+            seed::DRINKS,
+        );
+        let response = api.execute(&delete, &[("USSID", seed::OWNER_SESSION)]);
+        assert!(response.get("errors").is_none());
+
+        let response = api.execute(&query, &[]);
+        assert_eq!(
+            response["data"]["shop"]["search"][0],
+            json!({
+                "series": [{ "name": "Mains" }, { "name": "Desserts" }],
+                "products": [],
+            }),
+        );
+
+        // Its products are left without a series until moved to another.
+        let black_tea = format!("{{ shop {{ search(id: \"{}\") {{ products(key: \"{}\") {{ seriesId }} }} }} }}", seed::DINER, seed::BLACK_TEA);
+        let response = api.execute(&black_tea, &[]);
+        assert_eq!(response["data"]["shop"]["search"][0]["products"][0]["seriesId"], json!(null));
+        let assign = |series_id: &str| format!(
+            "mutation {{ shop {{ setProductSeries(shopId: \"{}\", productKey: \"{}\", seriesId: \"{}\") {{ series {{ name }} }} }} }}",
+            seed::DINER,
+            seed::BLACK_TEA,
+            series_id,
+        );
+        let response = api.execute(&assign(&seed::DRINKS.to_string()), &[("USSID", seed::OWNER_SESSION)]);
+        assert_eq!(error_type(&response), Some("NotFound"));
+        let response = api.execute(&assign(&desserts), &[("USSID", seed::OWNER_SESSION)]);
+        assert_eq!(response["data"]["shop"]["setProductSeries"], json!({ "series": { "name": "Desserts" } }));
+    }
+
+    #[test]
+    fn test_find() {
+        find(&mut Memory::seeded());
+    }
+
+    #[test]
+    #[ignore]
+    fn test_find_postgres() {
+        find(&mut TestServer::start());
+    }
+
+    fn find(api: &mut dyn Api) {
+        let find = |query: &str| format!(
+            "{{ shop {{ find(query: \"{}\") {{ shop {{ uuid }} product {{ key }} highlightedName snippet }} }} }}",
+            query,
+        );
+
+        let response = api.execute(&find("PORK"), &[]);
+        assert_eq!(
+            response["data"]["shop"]["find"],
+            json!([{
+                "shop": { "uuid": seed::DINER.to_string() },
+                "product": { "key": seed::PORK_RICE.to_string() },
+                "highlightedName": "Braised <mark>Pork</mark> Rice",
+                "snippet": "Slow cooked <mark>pork</mark> belly on rice.",
+            }]),
+        );
+
+        // A typo still finds the shop through trigram similarity.
+        let response = api.execute(&find("dinr"), &[]);
+        assert_eq!(
+            response["data"]["shop"]["find"],
+            json!([{
+                "shop": { "uuid": seed::DINER.to_string() },
+                "product": null,
+                "highlightedName": "Pigskit Diner",
+                "snippet": null,
+            }]),
+        );
+
+        let response = api.execute(&find(" "), &[]);
+        assert_eq!(error_type(&response), Some("InvalidInput"));
+    }
+
+    #[test]
+    fn test_coordinates_distance() {
+        let station = Coordinates::new(25.0478, 121.5170);
+        let distance = station.distance(&Coordinates::new(25.0330, 121.5654));
+        assert!((distance - 5152.011).abs() < 0.01);
+        assert_eq!(station.distance(&station), 0.0);
+    }
+
+    #[test]
+    fn test_nearby() {
+        nearby(&mut Memory::seeded());
+    }
+
+    #[test]
+    #[ignore]
+    fn test_nearby_postgres() {
+        nearby(&mut TestServer::start());
+    }
+
+    fn nearby(api: &mut dyn Api) {
+        let nearby = |radius: f64| format!(
+            "{{ shop {{ nearby(lat: 25.04, lng: 121.52, radiusMeters: {}) {{ shop {{ name address }} distanceMeters }} }} }}",
+            radius,
+        );
+
+        let response = api.execute(&nearby(1000.0), &[]);
+        let shops = response["data"]["shop"]["nearby"].as_array().unwrap();
+        assert_eq!(shops.len(), 1);
+        assert_eq!(shops[0]["shop"]["name"], "Pigskit Diner");
+        assert!((shops[0]["distanceMeters"].as_f64().unwrap() - 919.501).abs() < 0.01);
+
+        let response = api.execute(&nearby(10000.0), &[]);
+        let names: Vec<&str> = response["data"]["shop"]["nearby"].as_array().unwrap().iter()
+            .map(|shop| shop["shop"]["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["Pigskit Diner", "Bacon Bar"]);
+
+        let response = api.execute(&nearby(0.0), &[]);
+        assert_eq!(error_type(&response), Some("InvalidInput"));
+
+        let set = format!(
+            "mutation {{ shop {{ setShopCoordinates(shopId: \"{}\", latitude: 25.0401, longitude: 121.5201) {{ coordinates {{ latitude longitude }} }} }} }}",
+            seed::BACON_BAR,
+        );
+        let response = api.execute(&set, &[("USSID", seed::STAFF_SESSION)]);
+        assert_eq!(error_type(&response), Some("Unauthorized"));
+        let response = api.execute(&set, &[("USSID", seed::OWNER_SESSION)]);
+        assert_eq!(
+            response["data"]["shop"]["setShopCoordinates"],
+            json!({ "coordinates": { "latitude": 25.0401, "longitude": 121.5201 } }),
+        );
+
+        let response = api.execute(&nearby(1000.0), &[]);
+        assert_eq!(response["data"]["shop"]["nearby"][0]["shop"]["name"], "Bacon Bar");
+
+        // Addresses are trimmed, and shops without coordinates aren't nearby.
+        let locate = format!(
+            "mutation {{ shop {{
+                setShopAddress(shopId: \"{}\", address: \"  5 Zhongxiao W. Rd., Taipei \") {{ address }}
+                setShopCoordinates(shopId: \"{}\") {{ coordinates {{ latitude }} }}
+            }} }}",
+            seed::DINER,
+            seed::DINER,
+        );
+        let response = api.execute(&locate, &[("USSID", seed::OWNER_SESSION)]);
+        assert_eq!(
+            response["data"]["shop"],
+            json!({
+                "setShopAddress": { "address": "5 Zhongxiao W. Rd., Taipei" },
+                "setShopCoordinates": { "coordinates": null },
+            }),
+        );
+        let response = api.execute(&nearby(10000.0), &[]);
+        let shops = response["data"]["shop"]["nearby"].as_array().unwrap();
+        assert_eq!(shops.len(), 1);
+        assert_eq!(shops[0]["shop"], json!({ "name": "Bacon Bar", "address": null }));
+    }
+
+    #[test]
+    fn test_translations() {
+        translations(&mut Memory::seeded());
+    }
+
+    #[test]
+    #[ignore]
+    fn test_translations_postgres() {
+        translations(&mut TestServer::start());
+    }
+
+    fn translations(api: &mut dyn Api) {
+        let query = |locale: &str| format!(
+            "{{ shop {{ search(id: \"{}\") {{ products(key: \"{}\") {{
+                name(locale: \"{}\") description(locale: \"{}\")
+                customizes {{ name(locale: \"{}\") selections {{ name(locale: \"{}\") }} }}
+            }} }} }} }}",
+            seed::DINER,
+            seed::PORK_RICE,
+            locale, locale, locale, locale,
+        );
+        let product = |api: &mut dyn Api, locale: &str| {
+            api.execute(&query(locale), &[])["data"]["shop"]["search"][0]["products"][0].clone()
+        };
+
+        assert_eq!(
+            product(api, "zh-TW"),
+            json!({
+                "name": "滷肉飯",
+                "description": "慢燉五花肉配白飯。",
+                "customizes": [{ "name": "份量", "selections": [{ "name": "大" }, { "name": "Small" }] }],
+            }),
+        );
+        // Other locales of the language are used before the original text.
+        assert_eq!(product(api, "zh-Hant")["name"], json!("滷肉飯"));
+        assert_eq!(product(api, "en-US")["name"], json!("Braised Pork Rice"));
+        assert_eq!(error_type(&api.execute(&query("*"), &[])), Some("InvalidInput"));
+
+        let set = |key, field: &str, text: &str| format!(
+            "mutation {{ shop {{ setTranslation(shopId: \"{}\", productKey: \"{}\", key: \"{}\", field: {}, locale: \"zh_tw\", text: \"{}\") {{ key }} }} }}",
+            seed::DINER,
+            seed::PORK_RICE,
+            key,
+            field,
+            text,
+        );
+        let response = api.execute(&set(seed::SMALL, "NAME", "小"), &[("USSID", seed::STAFF_SESSION)]);
+        assert_eq!(error_type(&response), Some("Unauthorized"));
+        let response = api.execute(&set(seed::SIZE, "DESCRIPTION", "大小"), &[("USSID", seed::OWNER_SESSION)]);
+        assert_eq!(error_type(&response), Some("InvalidInput"));
+        let response = api.execute(&set(seed::BLACK_TEA, "NAME", "紅茶"), &[("USSID", seed::OWNER_SESSION)]);
+        assert_eq!(error_type(&response), Some("NotFound"));
+        let response = api.execute(&set(seed::SMALL, "NAME", " "), &[("USSID", seed::OWNER_SESSION)]);
+        assert_eq!(error_type(&response), Some("InvalidInput"));
+        let response = api.execute(&set(seed::SMALL, "NAME", "小"), &[("USSID", seed::OWNER_SESSION)]);
+        assert!(response.get("errors").is_none());
+        assert_eq!(product(api, "zh-TW")["customizes"][0]["selections"][1], json!({ "name": "小" }));
+
+        let delete = format!(
+            "mutation {{ shop {{ deleteTranslation(shopId: \"{}\", productKey: \"{}\", key: \"{}\", field: NAME, locale: \"zh-TW\") {{
+                translations {{ field locale text }}
+            }} }} }}",
+            seed::DINER,
+            seed::PORK_RICE,
+            seed::PORK_RICE,
+        );
+        let response = api.execute(&delete, &[("USSID", seed::OWNER_SESSION)]);
+        assert_eq!(
+            response["data"]["shop"]["deleteTranslation"]["translations"],
+            json!([{ "field": "DESCRIPTION", "locale": "zh-TW", "text": "慢燉五花肉配白飯。" }]),
+        );
+        assert_eq!(product(api, "zh-TW")["name"], json!("Braised Pork Rice"));
+        let response = api.execute(&delete, &[("USSID", seed::OWNER_SESSION)]);
+        assert_eq!(error_type(&response), Some("NotFound"));
+    }
+
+    #[test]
+    fn test_currency() {
+        currency(&mut Memory::seeded());
+    }
+
+    #[test]
+    #[ignore]
+    fn test_currency_postgres() {
+        currency(&mut TestServer::start());
+    }
+
+    fn currency(api: &mut dyn Api) {
+        let catalog = format!(
+            "{{ shop {{ search(id: \"{}\") {{ currency minorUnits products(key: \"{}\") {{
+                price priceMoney {{ amount currency formatted }}
+                customizes {{ selections {{ priceMoney {{ formatted }} }} }}
+            }} }} }} }}",
+            seed::DINER,
+            seed::PORK_RICE,
+        );
+        let shop = |api: &mut dyn Api| api.execute(&catalog, &[])["data"]["shop"]["search"][0].clone();
+        let guest = format!(
+            "{{ guest {{
+                carts {{ total totalMoney {{ formatted }} items {{ subtotalMoney {{ decimal }} }} }}
+                orders(shopId: \"{}\") {{ totalMoney {{ formatted }} items {{
+                    priceMoney {{ formatted }} unitPriceMoney {{ formatted }}
+                    customizes {{ selectionPriceMoney {{ formatted }} }}
+                }} }}
+            }} }}",
+            seed::DINER,
+        );
+
+        let response = shop(api);
+        assert_eq!((response["currency"].clone(), response["minorUnits"].clone()), (json!("TWD"), json!(0)));
+        assert_eq!(
+            response["products"][0]["priceMoney"],
+            json!({ "amount": 80, "currency": "TWD", "formatted": "NT$80" }),
+        );
+        assert_eq!(
+            response["products"][0]["customizes"][0]["selections"],
+            json!([{ "priceMoney": { "formatted": "NT$20" } }, { "priceMoney": { "formatted": "NT$0" } }]),
+        );
+
+        let set = |currency: &str| format!(
+            "mutation {{ shop {{ setShopCurrency(shopId: \"{}\", currency: \"{}\") {{ currency minorUnits }} }} }}",
+            seed::DINER,
+            currency,
+        );
+        let response = api.execute(&set("usd"), &[("USSID", seed::STAFF_SESSION)]);
+        assert_eq!(error_type(&response), Some("Unauthorized"));
+        let response = api.execute(&set("US$"), &[("USSID", seed::OWNER_SESSION)]);
+        assert_eq!(error_type(&response), Some("InvalidInput"));
+        let response = api.execute(&set("usd"), &[("USSID", seed::OWNER_SESSION)]);
+        assert_eq!(response["data"]["shop"]["setShopCurrency"], json!({ "currency": "USD", "minorUnits": 2 }));
+
+        // Stored amounts are kept and read in the new minor units.
+        let response = shop(api);
+        assert_eq!(response["products"][0]["price"], json!(80));
+        assert_eq!(response["products"][0]["priceMoney"]["formatted"], json!("$0.80"));
+
+        let response = api.execute(&guest, &[("GSSID", seed::GUEST_SESSION)]);
+        assert_eq!(
+            response["data"]["guest"],
+            json!({
+                "carts": [{ "total": 60, "totalMoney": { "formatted": "$0.60" }, "items": [{ "subtotalMoney": { "decimal": "0.60" } }] }],
+                "orders": [{
+                    "totalMoney": { "formatted": "$1.00" },
+                    "items": [{
+                        "priceMoney": { "formatted": "$0.80" },
+                        "unitPriceMoney": { "formatted": "$1.00" },
+                        "customizes": [{ "selectionPriceMoney": { "formatted": "$0.20" } }],
+                    }],
+                }],
+            }),
+        );
+    }
+
+    #[test]
+    fn test_tax_settings() {
+        tax_settings(&mut Memory::seeded());
+    }
+
+    #[test]
+    #[ignore]
+    fn test_tax_settings_postgres() {
+        tax_settings(&mut TestServer::start());
+    }
+
+    fn tax_settings(api: &mut dyn Api) {
+        let owner = [("USSID", seed::OWNER_SESSION)];
+        let guest = [("GSSID", seed::GUEST_SESSION)];
+        let set = |settings: &str| format!(
+            "mutation {{ shop {{ setShopTaxSettings(shopId: \"{}\", settings: {{ {} }}) {{
+                taxSettings {{ inclusive rate seriesRates {{ seriesId rate }} serviceChargeRate }}
+            }} }} }}",
+            seed::DINER,
+            settings,
+        );
+        let charges = "total tax serviceCharge taxes { rate taxable amount }";
+
+        let exclusive = format!(
+            "inclusive: false, rate: 500, seriesRates: [{{ seriesId: \"{}\", rate: 1000 }}], serviceChargeRate: 1000",
+            seed::DRINKS,
+        );
+        let response = api.execute(&set(&exclusive), &[("USSID", seed::STAFF_SESSION)]);
+        assert_eq!(error_type(&response), Some("Unauthorized"));
+        let unknown = format!("inclusive: false, rate: 500, seriesRates: [{{ seriesId: \"{}\", rate: 1000 }}]", seed::PORK_RICE);
+        let response = api.execute(&set(&unknown), &owner);
+        assert_eq!(error_type(&response), Some("NotFound"));
+        let response = api.execute(&set("inclusive: true, rate: 10001"), &owner);
+        assert_eq!(error_type(&response), Some("InvalidInput"));
+        let response = api.execute(&set(&exclusive), &owner);
+        assert_eq!(
+            response["data"]["shop"]["setShopTaxSettings"]["taxSettings"],
+            json!({
+                "inclusive": false,
+                "rate": 500,
+                "seriesRates": [{ "seriesId": seed::DRINKS.to_string(), "rate": 1000 }],
+                "serviceChargeRate": 1000,
+            }),
+        );
+
+        // Two Black Teas at 30 are drinks, and the service charge of 6 is
+        // taxed at the shop's rate.
+        let cart = format!("{{ guest {{ carts(shopId: \"{}\") {{ {} }} }} }}", seed::DINER, charges);
+        let response = api.execute(&cart, &guest);
+        let placed = json!({
+            "total": 72,
+            "tax": 6,
+            "serviceCharge": 6,
+            "taxes": [{ "rate": 500, "taxable": 6, "amount": 0 }, { "rate": 1000, "taxable": 60, "amount": 6 }],
+        });
+        assert_eq!(response["data"]["guest"]["carts"][0], placed);
+        let checkout = format!("mutation {{ guest {{ checkout(shopId: \"{}\") {{ {} }} }} }}", seed::DINER, charges);
+        let response = api.execute(&checkout, &guest);
+        assert_eq!(response["data"]["guest"]["checkout"], placed);
+
+        // Orders keep the charges they were placed with.
+        let response = api.execute(&set("inclusive: true, rate: 500"), &owner);
+        assert!(response.get("errors").is_none());
+        let orders = format!("{{ guest {{ orders(shopId: \"{}\") {{ taxInclusive {} }} }} }}", seed::DINER, charges);
+        let response = api.execute(&orders, &guest);
+        let orders = response["data"]["guest"]["orders"].as_array().unwrap();
+        assert_eq!(orders[0], json!({ "taxInclusive": true, "total": 100, "tax": 0, "serviceCharge": 0, "taxes": [] }));
+        assert_eq!(orders[1]["total"], json!(72));
+
+        // Deleting a series drops its rate.
+        let response = api.execute(&set(&exclusive), &owner);
+        assert!(response.get("errors").is_none());
+        let delete = format!("mutation {{ shop {{ deleteSeries(shopId: \"{}\", seriesId: \"{}\") }} }}", seed::DINER, seed::DRINKS);
+        let response = api.execute(&delete, &owner);
+        assert!(response.get("errors").is_none());
+        let settings = format!("{{ shop {{ search(id: \"{}\") {{ taxSettings {{ seriesRates {{ rate }} }} }} }} }}", seed::DINER);
+        let response = api.execute(&settings, &[]);
+        assert_eq!(response["data"]["shop"]["search"][0]["taxSettings"]["seriesRates"], json!([]));
+    }
+
+    #[test]
+    fn test_audit_log() {
+        audit_log(&mut Memory::seeded());
+    }
+
+    #[test]
+    #[ignore]
+    fn test_audit_log_postgres() {
+        audit_log(&mut TestServer::start());
+    }
+
+    fn audit_log(api: &mut dyn Api) {
+        let owner = [("USSID", seed::OWNER_SESSION)];
+        let log = |args: &str| format!(
+            "{{ user {{ me {{ shops {{ shop {{ uuid }} auditLog({}) {{
+                totalCount entries {{ actorId actor {{ username }} entity entityId action before after }}
+            }} }} }} }} }}",
+            args,
+        );
+        let parse = |entry: &serde_json::Value, field: &str| -> serde_json::Value {
+            serde_json::from_str(entry[field].as_str().unwrap()).unwrap()
+        };
+        let diner_log = |response: &serde_json::Value| {
+            response["data"]["user"]["me"]["shops"].as_array().unwrap().iter()
+                .find(|shop| shop["shop"]["uuid"] == json!(seed::DINER.to_string()))
+                .map(|shop| shop["auditLog"].clone())
+                .unwrap()
+        };
+
+        let address = format!("mutation {{ shop {{ setShopAddress(shopId: \"{}\", address: \"1 Main St\") {{ address }} }} }}", seed::DINER);
+        let response = api.execute(&address, &owner);
+        assert!(response.get("errors").is_none());
+        let inventory = format!(
+            "mutation {{ shop {{ setProductInventory(shopId: \"{}\", productKey: \"{}\", stock: 5, soldOut: false) {{ stock }} }} }}",
+            seed::DINER,
+            seed::PORK_RICE,
+        );
+        let response = api.execute(&inventory, &owner);
+        assert!(response.get("errors").is_none());
+        let create = format!("mutation {{ shop {{ createSeries(shopId: \"{}\", name: \"Sides\") {{ id }} }} }}", seed::DINER);
+        let response = api.execute(&create, &owner);
+        let sides = response["data"]["shop"]["createSeries"]["id"].clone();
+
+        // Newest first, with the entity as it was and as it became.
+        let response = api.execute(&log(""), &owner);
+        let entries = diner_log(&response);
+        assert_eq!(entries["totalCount"], json!(3));
+        let entries = entries["entries"].as_array().unwrap();
+        assert_eq!(
+            (&entries[0]["entity"], &entries[0]["entityId"], &entries[0]["action"], &entries[0]["before"]),
+            (&json!("SERIES"), &sides, &json!("CREATE"), &json!(null)),
+        );
+        assert_eq!(entries[0]["actorId"], json!(seed::OWNER.to_string()));
+        assert_eq!(entries[0]["actor"], json!({ "username": "owner" }));
+        assert_eq!(parse(&entries[0], "after")["name"], json!("Sides"));
+        assert_eq!(entries[1]["entityId"], json!(seed::PORK_RICE.to_string()));
+        assert_eq!((parse(&entries[1], "before")["stock"].clone(), parse(&entries[1], "after")["stock"].clone()), (json!(null), json!(5)));
+        assert_eq!(entries[2]["entity"], json!("SHOP"));
+        assert_eq!(parse(&entries[2], "after")["address"], json!("1 Main St"));
+
+        // Filters and pages.
+        let response = api.execute(&log("filter: { entity: SHOP, action: UPDATE }"), &owner);
+        let entries = diner_log(&response);
+        assert_eq!(entries["totalCount"], json!(1));
+        assert_eq!(entries["entries"][0]["entityId"], json!(seed::DINER.to_string()));
+        let response = api.execute(&log("offset: 1, limit: 1"), &owner);
+        let entries = diner_log(&response);
+        assert_eq!(entries["totalCount"], json!(3));
+        assert_eq!(entries["entries"].as_array().unwrap().len(), 1);
+        assert_eq!(entries["entries"][0]["entity"], json!("PRODUCT"));
+        let response = api.execute(&log("limit: 0"), &owner);
+        assert_eq!(error_type(&response), Some("InvalidInput"));
+
+        // Only members with full member authority read the log.
+        let response = api.execute(&log(""), &[("USSID", seed::STAFF_SESSION)]);
+        assert_eq!(error_type(&response), Some("Unauthorized"));
+    }
+
+    #[test]
+    fn test_audited_failure() {
+        let repository = Arc::new(seed::memory());
+        let context = Context::new(State::init_memory(repository.clone()), Some(seed::OWNER_SESSION), None);
+
+        // A change that fails part way is undone, and leaves no entry.
+        let result = audit::audited(&context, seed::DINER, AuditEntity::Shop, Some(seed::DINER), super::shop_snapshot, |state| {
+            state.shops().set_address(seed::DINER, Some("1 Main St".to_string()))?;
+            Err(Error::invalid_input("Failed."))
+        });
+        assert!(result.is_err());
+
+        let owner = [("USSID", seed::OWNER_SESSION)];
+        let address = format!("{{ shop {{ search(id: \"{}\") {{ address }} }} }}", seed::DINER);
+        let response = execute(repository.clone(), &address, &owner);
+        assert_eq!(response["data"]["shop"]["search"][0]["address"], json!("3 Beiping W. Rd., Taipei"));
+        let log = "{ user { me { shops { auditLog { totalCount } } } } }";
+        let response = execute(repository, log, &owner);
+        for shop in response["data"]["user"]["me"]["shops"].as_array().unwrap() {
+            assert_eq!(shop["auditLog"]["totalCount"], json!(0));
+        }
+    }
+
+    #[test]
+    fn test_roles() {
+        roles(&mut Memory::seeded());
+    }
+
+    #[test]
+    #[ignore]
+    fn test_roles_postgres() {
+        roles(&mut TestServer::start());
+    }
+
+    fn roles(api: &mut dyn Api) {
+        let owner = [("USSID", seed::OWNER_SESSION)];
+        let staff = [("USSID", seed::STAFF_SESSION)];
+
+        let response = api.execute("{ shop { roleTemplates { template name } } }", &[]);
+        assert_eq!(
+            response["data"]["shop"]["roleTemplates"],
+            json!([
+                { "template": "OWNER", "name": "Owner" },
+                { "template": "MANAGER", "name": "Manager" },
+                { "template": "STAFF", "name": "Staff" },
+                { "template": "VIEWER", "name": "Viewer" },
+            ]),
+        );
+
+        let create = |role: &str| format!("mutation {{ shop {{ createRole(shopId: \"{}\", role: {{ {} }}) {{ id name capabilities }} }} }}", seed::DINER, role);
+        let response = api.execute(&create("template: MANAGER"), &owner);
+        assert_eq!(response["data"]["shop"]["createRole"]["name"], json!("Manager"));
+        assert_eq!(
+            response["data"]["shop"]["createRole"]["capabilities"],
+            json!(["VIEW_MEMBERS", "VIEW_ORDERS", "VIEW_ANALYTICS", "MANAGE_ORDERING", "REFUND_ORDERS", "VIEW_PROMOTIONS", "EDIT_CATALOG", "EDIT_PRICES", "MANAGE_STOCK"]),
+        );
+        assert_eq!(error_type(&api.execute(&create("template: MANAGER"), &owner)), Some("InvalidInput"));
+        assert_eq!(error_type(&api.execute(&create("name: \"Runner\""), &owner)), Some("InvalidInput"));
+        assert_eq!(error_type(&api.execute(&create("template: VIEWER"), &staff)), Some("Unauthorized"));
+
+        let response = api.execute(&create("name: \"Stock keeper\", capabilities: [VIEW_MEMBERS, MANAGE_STOCK]"), &owner);
+        let stock_keeper = response["data"]["shop"]["createRole"]["id"].as_str().unwrap().to_string();
+
+        // A custom role grants what it lists and nothing more.
+        let set_role = |user_id, role_id: &str| format!(
+            "mutation {{ shop {{ setMemberRole(shopId: \"{}\", userId: \"{}\", roleId: \"{}\") {{ name }} }} }}",
+            seed::DINER,
+            user_id,
+            role_id,
+        );
+        let response = api.execute(&set_role(seed::STAFF, &stock_keeper), &owner);
+        assert_eq!(response["data"]["shop"]["setMemberRole"], json!({ "name": "Stock keeper" }));
+        let set_inventory = format!(
+            "mutation {{ shop {{ setProductInventory(shopId: \"{}\", productKey: \"{}\", stock: 3, soldOut: false) {{ key }} }} }}",
+            seed::DINER,
+            seed::PORK_RICE,
+        );
+        assert!(api.execute(&set_inventory, &staff).get("errors").is_none());
+        let set_address = format!("mutation {{ shop {{ setShopAddress(shopId: \"{}\", address: \"1 Main St\") {{ id }} }} }}", seed::DINER);
+        assert_eq!(error_type(&api.execute(&set_address, &staff)), Some("Unauthorized"));
+        let shops = "{ user { me { shops { capabilities productAuthority } } } }";
+        let response = api.execute(shops, &staff);
+        assert_eq!(
+            response["data"]["user"]["me"]["shops"],
+            json!([{ "capabilities": ["VIEW_MEMBERS", "MANAGE_STOCK"], "productAuthority": "NONE" }]),
+        );
+
+        // Members can't change their own role, nor delete one in use.
+        let own_role = seed::DINER_OWNER.to_string();
+        assert_eq!(error_type(&api.execute(&set_role(seed::OWNER, &stock_keeper), &owner)), Some("Unauthorized"));
+        let update = |role_id: &str| format!(
+            "mutation {{ shop {{ updateRole(shopId: \"{}\", roleId: \"{}\", capabilities: [VIEW_MEMBERS]) {{ capabilities }} }} }}",
+            seed::DINER,
+            role_id,
+        );
+        assert_eq!(error_type(&api.execute(&update(&own_role), &owner)), Some("Unauthorized"));
+        let delete = |role_id: &str| format!("mutation {{ shop {{ deleteRole(shopId: \"{}\", roleId: \"{}\") }} }}", seed::DINER, role_id);
+        assert_eq!(error_type(&api.execute(&delete(&own_role), &owner)), Some("Unauthorized"));
+        assert_eq!(error_type(&api.execute(&delete(&stock_keeper), &owner)), Some("InvalidInput"));
+
+        let response = api.execute(&update(&stock_keeper), &owner);
+        assert_eq!(response["data"]["shop"]["updateRole"], json!({ "capabilities": ["VIEW_MEMBERS"] }));
+        assert_eq!(error_type(&api.execute(&set_inventory, &staff)), Some("Unauthorized"));
+        api.execute(&set_role(seed::STAFF, &seed::DINER_STAFF.to_string()), &owner);
+        assert!(api.execute(&delete(&stock_keeper), &owner).get("errors").is_none());
+
+        let response = api.execute(&format!("{{ user {{ me {{ shops(id: \"{}\") {{ roles {{ name }} }} }} }} }}", seed::DINER), &owner);
+        assert_eq!(
+            response["data"]["user"]["me"]["shops"][0]["roles"],
+            json!([{ "name": "Manager" }, { "name": "Owner" }, { "name": "Staff" }]),
+        );
+
+        // Changes to roles and to members' roles are audited, newest first.
+        let log = format!("{{ user {{ me {{ shops(id: \"{}\") {{ auditLog {{ entries {{ entity entityId action before after }} }} }} }} }} }}", seed::DINER);
+        let response = api.execute(&log, &owner);
+        let entries = response["data"]["user"]["me"]["shops"][0]["auditLog"]["entries"].as_array().unwrap().clone();
+        let actions: Vec<(&serde_json::Value, &serde_json::Value)> = entries.iter().map(|entry| (&entry["entity"], &entry["action"])).collect();
+        assert_eq!(
+            actions,
+            vec![
+                (&json!("ROLE"), &json!("DELETE")),
+                (&json!("MEMBER"), &json!("UPDATE")),
+                (&json!("ROLE"), &json!("UPDATE")),
+                (&json!("PRODUCT"), &json!("UPDATE")),
+                (&json!("MEMBER"), &json!("UPDATE")),
+                (&json!("ROLE"), &json!("CREATE")),
+                (&json!("ROLE"), &json!("CREATE")),
+            ],
+        );
+        let parse = |entry: &serde_json::Value, field: &str| -> serde_json::Value {
+            serde_json::from_str(entry[field].as_str().unwrap()).unwrap()
+        };
+        assert_eq!(entries[0]["entityId"], json!(stock_keeper));
+        assert_eq!(parse(&entries[0], "before")["name"], json!("Stock keeper"));
+        assert_eq!(entries[0]["after"], json!(null));
+        assert_eq!(entries[1]["entityId"], json!(seed::STAFF.to_string()));
+        assert_eq!(
+            (parse(&entries[1], "before")["role"].clone(), parse(&entries[1], "after")["role"].clone()),
+            (json!("Stock keeper"), json!("Staff")),
+        );
+        assert_eq!(
+            (parse(&entries[2], "before")["capabilities"].clone(), parse(&entries[2], "after")["capabilities"].clone()),
+            (json!(["view_members", "manage_stock"]), json!(["view_members"])),
+        );
+    }
+}
@@ -0,0 +1,358 @@
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+use crate::{
+    graphql::{
+        context::Context,
+        money::Money,
+        promotion::Line,
+    },
+    error::Error,
+};
+
+// Rates are in basis points, hundredths of a percent, so 500 is 5%.
+pub const MAX_RATE: i32 = 10000;
+
+// How a shop taxes its sales. Products take the rate of their series when
+// it has one and the shop's rate otherwise.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TaxSettings {
+    // Whether prices already include tax, so it is shown but not added.
+    pub inclusive: bool,
+    pub rate: i32,
+    pub series_rates: BTreeMap<Uuid, i32>,
+    // Charged on the discounted subtotal and taxed at the shop's rate.
+    pub service_charge_rate: i32,
+}
+
+impl TaxSettings {
+    pub fn rate_of(&self, series_id: Option<Uuid>) -> i32 {
+        series_id
+            .and_then(|id| self.series_rates.get(&id).cloned())
+            .unwrap_or(self.rate)
+    }
+
+    // Service charge and taxes of `lines` once `discount` is taken off them.
+    // `series` maps product keys to their series; products without one, or
+    // no longer in the catalog, are taxed at the shop's rate.
+    pub fn charges(&self, shop_id: Uuid, lines: &[Line], series: &HashMap<Uuid, Uuid>, discount: i32) -> Charges {
+        let mut taxable: BTreeMap<i32, i64> = BTreeMap::new();
+        for line in lines {
+            let rate = self.rate_of(series.get(&line.product_key).cloned());
+            *taxable.entry(rate).or_insert(0) += line.unit_price as i64 * line.count as i64;
+        }
+
+        // The discount is shared between rates in proportion to what each
+        // is charged on. Rounding the running total keeps every share within
+        // its amount and the shares adding up to the discount.
+        let subtotal: i64 = taxable.values().sum();
+        let discount = (discount as i64).max(0).min(subtotal);
+        let (mut before, mut shared) = (0, 0);
+        for amount in taxable.values_mut() {
+            before += *amount;
+            let upto = if subtotal == 0 { 0 } else { discount * before / subtotal };
+            *amount -= upto - shared;
+            shared = upto;
+        }
+
+        let service_charge = proportion(subtotal - discount, self.service_charge_rate as i64, MAX_RATE as i64);
+        if service_charge > 0 {
+            *taxable.entry(self.rate).or_insert(0) += service_charge;
+        }
+
+        let mut charges = Charges::new(shop_id, self.inclusive, self.service_charge_rate, service_charge as i32);
+        for (&rate, &amount) in taxable.iter().filter(|&(&rate, &amount)| rate > 0 && amount > 0) {
+            let tax = if self.inclusive {
+                proportion(amount, rate as i64, (MAX_RATE + rate) as i64)
+            } else {
+                proportion(amount, rate as i64, MAX_RATE as i64)
+            };
+            charges.add_tax(TaxLine::new(shop_id, rate, amount as i32, tax as i32));
+        }
+        charges
+    }
+}
+
+// Prices of shops that never set up taxes are taken as final.
+impl Default for TaxSettings {
+    fn default() -> Self {
+        TaxSettings {
+            inclusive: true,
+            rate: 0,
+            series_rates: BTreeMap::new(),
+            service_charge_rate: 0,
+        }
+    }
+}
+
+// `amount * numerator / denominator`, rounded half up.
+fn proportion(amount: i64, numerator: i64, denominator: i64) -> i64 {
+    (amount * numerator * 2 + denominator) / (denominator * 2)
+}
+
+#[juniper::graphql_object]
+impl TaxSettings {
+    fn inclusive(&self) -> bool {
+        self.inclusive
+    }
+
+    // In basis points, for products without a series rate.
+    fn rate(&self) -> i32 {
+        self.rate
+    }
+
+    fn series_rates(&self) -> Vec<SeriesTaxRate> {
+        self.series_rates.iter()
+            .map(|(&series_id, &rate)| SeriesTaxRate { series_id: series_id, rate: rate })
+            .collect()
+    }
+
+    // In basis points, 0 when the shop doesn't charge for service.
+    fn service_charge_rate(&self) -> i32 {
+        self.service_charge_rate
+    }
+}
+
+#[derive(juniper::GraphQLObject)]
+pub struct SeriesTaxRate {
+    pub series_id: Uuid,
+    pub rate: i32,
+}
+
+#[derive(juniper::GraphQLInputObject)]
+pub struct SeriesTaxRateInput {
+    pub series_id: Uuid,
+    pub rate: i32,
+}
+
+#[derive(juniper::GraphQLInputObject)]
+pub struct TaxSettingsInput {
+    pub inclusive: bool,
+    // Rates are in basis points, so 500 is 5%.
+    pub rate: i32,
+    pub series_rates: Option<Vec<SeriesTaxRateInput>>,
+    pub service_charge_rate: Option<i32>,
+}
+
+impl TaxSettingsInput {
+    pub fn checked(self) -> Result<TaxSettings, Error> {
+        let service_charge_rate = self.service_charge_rate.unwrap_or(0);
+        let mut series_rates = BTreeMap::new();
+        for series in self.series_rates.unwrap_or_default() {
+            checked_rate(series.rate)?;
+            if series_rates.insert(series.series_id, series.rate).is_some() {
+                return Err(Error::invalid_input(&format!("Series {} has more than one tax rate.", series.series_id)));
+            }
+        }
+
+        Ok(TaxSettings {
+            inclusive: self.inclusive,
+            rate: checked_rate(self.rate)?,
+            series_rates: series_rates,
+            service_charge_rate: checked_rate(service_charge_rate)?,
+        })
+    }
+}
+
+fn checked_rate(rate: i32) -> Result<i32, Error> {
+    if rate < 0 || rate > MAX_RATE {
+        Err(Error::invalid_input(&format!("Rates must be between 0 and {} basis points.", MAX_RATE)))
+    } else {
+        Ok(rate)
+    }
+}
+
+// Service charge and taxes of a cart, or of an order as they were when it
+// was placed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Charges {
+    shop_id: Uuid,
+    inclusive: bool,
+    service_charge_rate: i32,
+    service_charge: i32,
+    taxes: Vec<TaxLine>,
+}
+
+impl Charges {
+    pub fn new(shop_id: Uuid, inclusive: bool, service_charge_rate: i32, service_charge: i32) -> Self {
+        Charges {
+            shop_id: shop_id,
+            inclusive: inclusive,
+            service_charge_rate: service_charge_rate,
+            service_charge: service_charge,
+            taxes: Vec::new(),
+        }
+    }
+
+    // Nothing charged on top of the prices, as for orders placed before
+    // shops had taxes.
+    pub fn none(shop_id: Uuid) -> Self {
+        Charges::new(shop_id, true, 0, 0)
+    }
+
+    pub fn inclusive(&self) -> bool {
+        self.inclusive
+    }
+
+    pub fn service_charge_rate(&self) -> i32 {
+        self.service_charge_rate
+    }
+
+    pub fn service_charge(&self) -> i32 {
+        self.service_charge
+    }
+
+    // One line per rate, lowest first.
+    pub fn taxes(&self) -> &Vec<TaxLine> {
+        &self.taxes
+    }
+
+    pub fn add_tax(&mut self, tax: TaxLine) {
+        self.taxes.push(tax);
+    }
+
+    pub fn tax(&self) -> i32 {
+        self.taxes.iter().map(TaxLine::amount).sum()
+    }
+
+    // What the charges add to the discounted subtotal. Tax included in the
+    // prices adds nothing.
+    pub fn surcharge(&self) -> i32 {
+        if self.inclusive {
+            self.service_charge
+        } else {
+            self.service_charge + self.tax()
+        }
+    }
+}
+
+// Tax at one rate.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TaxLine {
+    shop_id: Uuid,
+    rate: i32,
+    taxable: i32,
+    amount: i32,
+}
+
+impl TaxLine {
+    pub fn new(shop_id: Uuid, rate: i32, taxable: i32, amount: i32) -> Self {
+        TaxLine {
+            shop_id: shop_id,
+            rate: rate,
+            taxable: taxable,
+            amount: amount,
+        }
+    }
+
+    pub fn rate(&self) -> i32 {
+        self.rate
+    }
+
+    pub fn taxable(&self) -> i32 {
+        self.taxable
+    }
+
+    pub fn amount(&self) -> i32 {
+        self.amount
+    }
+}
+
+#[juniper::graphql_object(Context = Context)]
+impl TaxLine {
+    // In basis points.
+    fn rate(&self) -> i32 {
+        self.rate
+    }
+
+    // What the tax is charged on, after discounts. It includes the tax when
+    // prices do.
+    fn taxable(&self) -> i32 {
+        self.taxable
+    }
+
+    fn taxable_money(&self, context: &Context) -> Result<Money, Error> {
+        Ok(Money::new(self.taxable, context.currency(self.shop_id)?))
+    }
+
+    fn amount(&self) -> i32 {
+        self.amount
+    }
+
+    fn amount_money(&self, context: &Context) -> Result<Money, Error> {
+        Ok(Money::new(self.amount, context.currency(self.shop_id)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use uuid::Uuid;
+    use crate::graphql::promotion::Line;
+    use super::{TaxSettings, TaxSettingsInput, SeriesTaxRateInput};
+
+    fn line(product_key: Uuid, unit_price: i32, count: i32) -> Line {
+        Line {
+            product_key: product_key,
+            unit_price: unit_price,
+            count: count,
+        }
+    }
+
+    fn rates(charges: &super::Charges) -> Vec<(i32, i32, i32)> {
+        charges.taxes().iter().map(|tax| (tax.rate(), tax.taxable(), tax.amount())).collect()
+    }
+
+    #[test]
+    fn test_charges() {
+        let (food, drink, drinks) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let series: HashMap<Uuid, Uuid> = vec![(drink, drinks)].into_iter().collect();
+        let lines = [line(food, 100, 2), line(drink, 50, 2)];
+
+        let mut settings = TaxSettings::default();
+        let charges = settings.charges(Uuid::nil(), &lines, &series, 0);
+        assert_eq!((charges.tax(), charges.surcharge()), (0, 0));
+        assert!(charges.taxes().is_empty());
+
+        // 5% included in the prices: 300 * 500 / 10500.
+        settings.rate = 500;
+        let charges = settings.charges(Uuid::nil(), &lines, &series, 0);
+        assert_eq!(rates(&charges), vec![(500, 300, 14)]);
+        assert_eq!(charges.surcharge(), 0);
+
+        settings.inclusive = false;
+        settings.series_rates.insert(drinks, 1000);
+        let charges = settings.charges(Uuid::nil(), &lines, &series, 0);
+        assert_eq!(rates(&charges), vec![(500, 200, 10), (1000, 100, 10)]);
+        assert_eq!(charges.surcharge(), 20);
+
+        // The discount is shared 2:1, and the service charge on what is left
+        // is taxed at the shop's rate.
+        settings.service_charge_rate = 1000;
+        let charges = settings.charges(Uuid::nil(), &lines, &series, 30);
+        assert_eq!(charges.service_charge(), 27);
+        assert_eq!(rates(&charges), vec![(500, 207, 10), (1000, 90, 9)]);
+        assert_eq!(charges.surcharge(), 46);
+
+        // Shares never exceed what they are taken from.
+        let charges = settings.charges(Uuid::nil(), &lines, &series, 1000);
+        assert_eq!((charges.service_charge(), charges.tax()), (0, 0));
+    }
+
+    #[test]
+    fn test_checked_input() {
+        let input = |rate: i32, series: Vec<(Uuid, i32)>| TaxSettingsInput {
+            inclusive: true,
+            rate: rate,
+            series_rates: Some(series.into_iter().map(|(id, rate)| SeriesTaxRateInput { series_id: id, rate: rate }).collect()),
+            service_charge_rate: None,
+        };
+        let series = Uuid::new_v4();
+
+        let settings = input(500, vec![(series, 0)]).checked().unwrap();
+        assert_eq!((settings.rate_of(None), settings.rate_of(Some(series))), (500, 0));
+        assert_eq!(settings.rate_of(Some(Uuid::new_v4())), 500);
+        assert!(input(-1, vec![]).checked().is_err());
+        assert!(input(500, vec![(series, 10001)]).checked().is_err());
+        assert!(input(500, vec![(series, 100), (series, 200)]).checked().is_err());
+    }
+}
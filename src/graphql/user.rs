@@ -1,18 +1,21 @@
+use juniper::ID;
 use uuid::Uuid;
+use chrono::{DateTime, Utc};
 use crate::{
-    sql::{
-        UuidNN,
-        Permission,
-        clause::Clause,
-    },
+    sql::Permission,
     graphql::{
         context::Context,
         shop::Shop,
-        order::{
-            Order,
-            query_orders,
-        },
+        order::Order,
+        analytics::{self, Granularity, SalesBucket},
+        promotion::Promotion,
+        audit::{self, AuditLogFilter, AuditLogPage},
+        api_token::{self, ApiToken, ApiTokenInput, CreatedApiToken},
+        role::{Capabilities, Capability, Role},
+        guard,
+        node::{GlobalId, Node},
     },
+    repository::NewApiToken,
     error::Error,
 };
 
@@ -21,46 +24,48 @@ pub struct QueryUser;
 #[juniper::graphql_object(Context = Context)]
 impl QueryUser {
     fn me(context: &Context) -> Result<CurrentUser, Error> {
-        let user_session_id = context.user_session_id()?;
-        let mut conn = context.state().db_connection()?;
-        let (id, username, nickname,) = query_one!(
-            conn,
-            "WITH ss_user AS (
-                SELECT user_id id FROM get_session_user($1)
-            )
-            SELECT id, username, nickname FROM users WHERE id = (SELECT id FROM ss_user);",
-            &[&UuidNN(user_session_id)],
-            (id: Uuid, username: String, nickname: Option<String>),
-        )?;
-        Ok(CurrentUser::new(id, username, nickname))
+        guard::check(context, "QueryUser.me", None)?;
+        let user = context.state().users().user(context.user_id()?)?;
+        Ok(CurrentUser::new(user.id, user.username, user.nickname))
     }
 
     fn search(context: &Context, id: Option<Uuid>, name: Option<String>) -> Result<Vec<User>, Error> {
-        let mut conn = context.state().db_connection()?;
+        context.throttle("user.search")?;
+        context.state().users().search(id, name)
+    }
+}
 
-        let mut clause = Clause::new();
-        if let Some(id) = id.as_ref() {
-            clause.and(Clause::equal("id", format!("'{}'", id)));
-        }
-        if let Some(name) = name.as_ref() {
-            clause.and(Clause::like("upper(name)", format!("upper('%{}%')", name)));
-        }
+pub struct MutationUser;
 
-        let rows = query!(
-            conn,
-            format!("SELECT id, username, nickname FROM users{}", clause).as_str(),
-            &[],
-        )?;
-        Ok(
-            rows.iter().map(|row| {
-                User::new(
-                    row.get("id"),
-                    row.get("username"),
-                    row.get("nickname"),
-                )
-            })
-            .collect()
-        )
+#[juniper::graphql_object(Context = Context)]
+impl MutationUser {
+    // The token's secret is in the result and can't be looked up again.
+    fn create_api_token(context: &Context, token: ApiTokenInput) -> Result<CreatedApiToken, Error> {
+        guard::check(context, "MutationUser.createApiToken", None)?;
+        let user_id = context.user_id()?;
+        let user_authority = token.shop_id.map(|shop_id| context.shop_capabilities(shop_id).map(|capabilities| capabilities.to_authority())).transpose()?;
+        let (name, authority) = token.checked(user_authority, Utc::now())?;
+
+        let secret = api_token::generate();
+        let id = context.state().api_tokens().create_api_token(NewApiToken {
+            user_id: user_id,
+            shop_id: token.shop_id,
+            name: name,
+            token_hash: api_token::hash(&secret),
+            authority: authority,
+            expires_at: token.expires_at,
+        })?;
+        let api_token = context.state().api_tokens().api_tokens(user_id)?
+            .into_iter()
+            .find(|api_token| api_token.id() == id)
+            .ok_or_else(|| Error::not_found("API token"))?;
+        Ok(CreatedApiToken::new(secret, api_token))
+    }
+
+    fn revoke_api_token(context: &Context, id: Uuid) -> Result<Uuid, Error> {
+        guard::check(context, "MutationUser.revokeApiToken", None)?;
+        context.state().api_tokens().revoke_api_token(context.user_id()?, id)?;
+        Ok(id)
     }
 }
 
@@ -94,53 +99,20 @@ impl CurrentUser {
         self.nickname.as_ref()
     }
 
+    // With an API token, only those it works for and as far as it allows.
     fn shops(&self, context: &Context, id: Option<Uuid>, name: Option<String>) -> Result<Vec<UserShop>, Error> {
-        let mut conn = context.state().db_connection()?;
-
-        let mut clause = Clause::new();
-        if let Some(id) = id.as_ref() {
-            clause.and(Clause::equal("id", format!("'{}'", id)));
-        }
-        if let Some(name) = name.as_ref() {
-            clause.and(Clause::like("upper(name)", format!("upper('%{}%')", name)));
+        let mut shops = Vec::new();
+        for (shop, role) in context.state().shops().user_shops(self.id, id, name)? {
+            if let Some(capabilities) = context.limit_capabilities(shop.id(), role.capabilities())? {
+                shops.push(UserShop::new(shop, role, capabilities));
+            }
         }
+        Ok(shops)
+    }
 
-        let rows = query!(
-            conn,
-            format!(
-                "SELECT
-                    shop.id,
-                    shop.name,
-                    shop.latest_update,
-                    shop_user.member_authority,
-                    shop_user.order_authority,
-                    shop_user.product_authority
-                FROM
-                    (SELECT * FROM shops{}) shop
-                INNER JOIN
-                    shop_user
-                ON
-                    shop.id = shop_user.shop_id
-                    AND shop_user.user_id = $1",
-                clause
-            ).as_str(),
-            &[&self.id],
-        )?;
-        Ok(
-            rows.iter().map(|row| {
-                UserShop::new(
-                    Shop::new(
-                        row.get("id"),
-                        row.get("name"),
-                        row.get("latest_update"),
-                    ),
-                    row.get("member_authority"),
-                    row.get("order_authority"),
-                    row.get("product_authority"),
-                )
-            })
-            .collect()
-        )
+    fn api_tokens(&self, context: &Context) -> Result<Vec<ApiToken>, Error> {
+        guard::check(context, "CurrentUser.apiTokens", None)?;
+        context.state().api_tokens().api_tokens(context.user_id()?)
     }
 }
 
@@ -158,11 +130,23 @@ impl User {
             nickname: nickname,
         }
     }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn global_id(&self) -> GlobalId {
+        GlobalId::User(self.id)
+    }
 }
 
-#[juniper::graphql_object]
+#[juniper::graphql_object(Context = Context, interfaces = [Node])]
 impl User {
-    fn id(&self) -> &Uuid {
+    fn id(&self) -> ID {
+        self.global_id().encode()
+    }
+
+    fn uuid(&self) -> &Uuid {
         &self.id
     }
 
@@ -175,20 +159,19 @@ impl User {
     }
 }
 
+// A shop of the user, with what their role lets the request do in it.
 struct UserShop {
     shop: Shop,
-    member_authority: Permission,
-    order_authority: Permission,
-    product_authority: Permission,
+    role: Role,
+    capabilities: Capabilities,
 }
 
 impl UserShop {
-    fn new(shop: Shop, member_authority: Permission, order_authority: Permission, product_authority: Permission) -> Self {
+    fn new(shop: Shop, role: Role, capabilities: Capabilities) -> Self {
         UserShop {
             shop: shop,
-            member_authority: member_authority,
-            order_authority: order_authority,
-            product_authority: product_authority,
+            role: role,
+            capabilities: capabilities,
         }
     }
 
@@ -203,100 +186,107 @@ impl UserShop {
         &self.shop
     }
 
-    fn member_authority(&self) -> &Permission {
-        &self.member_authority
+    fn role(&self) -> &Role {
+        &self.role
+    }
+
+    // Those of the role, no more than the API token allows with one.
+    fn capabilities(&self) -> Vec<Capability> {
+        self.capabilities.to_vec()
     }
 
-    fn order_authority(&self) -> &Permission {
-        &self.order_authority
+    // The permissions the capabilities cover in full.
+    fn member_authority(&self) -> Permission {
+        self.capabilities.to_authority().member()
     }
 
-    fn product_authority(&self) -> &Permission {
-        &self.product_authority
+    fn order_authority(&self) -> Permission {
+        self.capabilities.to_authority().order()
+    }
+
+    fn product_authority(&self) -> Permission {
+        self.capabilities.to_authority().product()
     }
 
     fn members(&self, context: &Context) -> Result<Option<Vec<Member>>, Error> {
-        if let Permission::None = self.member_authority {
-            return Err(Error::unauthorized())
-        }
+        guard::check(context, "UserShop.members", Some(self.id()))?;
 
-        let mut conn = context.state().db_connection()?;
-        let user_session_id = context.user_session_id()?;
-
-        let rows = query!(
-            conn,
-            "SELECT
-                users.id,
-                users.username,
-                users.nickname,
-                shop_user.member_authority,
-                shop_user.order_authority,
-                shop_user.product_authority
-            FROM
-                shop_user
-            INNER JOIN
-                users
-            ON
-                shop_user.user_id = users.id
-            WHERE
-                shop_user.shop_id = $1
-            ",
-            &[&self.id()],
-        )?;
+        let members = context.state().shops().members(self.id())?;
         Ok(Some(
-            rows.iter().map(|row| {
-                // Only person who have "All" member_authority can query authority of members.
-                let authority = if let Permission::All = self.member_authority {
-                    Some(Authority::new(
-                        row.get("member_authority"),
-                        row.get("order_authority"),
-                        row.get("product_authority"),
-                    ))
-                } else {
-                    None
-                };
-
+            members.into_iter().map(|(user, role)| {
                 Member::new(
-                    row.get("id"),
-                    row.get("username"),
-                    row.get("nickname"),
-                    authority,
+                    user.id,
+                    self.id(),
+                    user.username,
+                    user.nickname,
+                    role,
                 )
             })
             .collect()
         ))
     }
 
+    fn roles(&self, context: &Context) -> Result<Vec<Role>, Error> {
+        guard::check(context, "UserShop.roles", Some(self.id()))?;
+
+        context.state().roles().roles(self.id(), None)
+    }
+
     fn orders(&self, context: &Context) -> Result<Option<Vec<Order>>, Error> {
-        if let Permission::None = self.order_authority {
-            return Err(Error::unauthorized())
-        }
+        guard::check(context, "UserShop.orders", Some(self.id()))?;
+
+        Ok(Some(context.state().orders().orders(Some(self.id()), None)?))
+    }
 
-        let mut conn = context.state().db_connection()?;
+    // Every bucket of `[from, to)` in the shop's time zone, including those
+    // without orders.
+    fn sales_summary(&self, context: &Context, from: DateTime<Utc>, to: DateTime<Utc>, granularity: Granularity) -> Result<Vec<SalesBucket>, Error> {
+        guard::check(context, "UserShop.salesSummary", Some(self.id()))?;
 
-        query_orders(conn, Some(self.id()), None)
+        let timezone = context.state().shops().schedule(self.id())?.timezone;
+        let starts = granularity.starts(from, to, timezone)?;
+        let buckets = context.state().orders().sales_summary(self.id(), from, to, granularity, timezone)?;
+        Ok(analytics::fill(starts, buckets))
+    }
+
+    // Including their codes, so only for members who view promotions.
+    fn promotions(&self, context: &Context) -> Result<Vec<Promotion>, Error> {
+        guard::check(context, "UserShop.promotions", Some(self.id()))?;
+
+        context.state().promotions().promotions(self.id(), None)
+    }
+
+    // Changes members made to the shop.
+    fn audit_log(&self, context: &Context, filter: Option<AuditLogFilter>, offset: Option<i32>, limit: Option<i32>) -> Result<AuditLogPage, Error> {
+        guard::check(context, "UserShop.auditLog", Some(self.id()))?;
+        let (offset, limit) = audit::checked_page(offset, limit)?;
+
+        let (entries, total_count) = context.state().audit_log().audit_log(self.id(), &filter.unwrap_or_default(), offset, limit)?;
+        Ok(AuditLogPage::new(entries, total_count))
     }
 }
 
 struct Member {
     id: Uuid,
+    shop_id: Uuid,
     username: String,
     nickname: Option<String>,
-    authority: Option<Authority>,
+    role: Role,
 }
 
 impl Member {
-    fn new(id: Uuid, username: String, nickname: Option<String>, authority: Option<Authority>) -> Self {
+    fn new(id: Uuid, shop_id: Uuid, username: String, nickname: Option<String>, role: Role) -> Self {
         Member {
             id: id,
+            shop_id: shop_id,
             username: username,
             nickname: nickname,
-            authority: authority,
+            role: role,
         }
     }
 }
 
-#[juniper::graphql_object]
+#[juniper::graphql_object(Context = Context)]
 impl Member {
     fn id(&self) -> &Uuid {
         &self.id
@@ -310,28 +300,54 @@ impl Member {
         self.nickname.as_ref()
     }
 
-    fn authority(&self) -> Result<Option<&Authority>, Error> {
-        if self.authority.is_none() {
-            return Err(Error::unauthorized())
-        }
-        Ok(self.authority.as_ref())
+    fn role(&self, context: &Context) -> Result<Option<&Role>, Error> {
+        guard::check(context, "Member.role", Some(self.shop_id))?;
+        Ok(Some(&self.role))
+    }
+
+    // What the role covers in full.
+    fn authority(&self, context: &Context) -> Result<Option<Authority>, Error> {
+        guard::check(context, "Member.authority", Some(self.shop_id))?;
+        Ok(Some(self.role.capabilities().to_authority()))
     }
 }
 
-struct Authority {
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Authority {
     member: Permission,
     order: Permission,
     product: Permission,
 }
 
 impl Authority {
-    fn new(member: Permission, order: Permission, product: Permission) -> Self {
+    pub fn new(member: Permission, order: Permission, product: Permission) -> Self {
         Authority {
             member: member,
             order: order,
             product: product,
         }
     }
+
+    pub fn member(&self) -> Permission {
+        self.member
+    }
+
+    pub fn order(&self) -> Permission {
+        self.order
+    }
+
+    pub fn product(&self) -> Permission {
+        self.product
+    }
+
+    // The lesser of the two for each permission.
+    pub fn capped(&self, cap: &Authority) -> Self {
+        Authority {
+            member: self.member.min(cap.member),
+            order: self.order.min(cap.order),
+            product: self.product.min(cap.product),
+        }
+    }
 }
 
 #[juniper::graphql_object]
@@ -347,4 +363,319 @@ impl Authority {
     fn product(&self) -> &Permission {
         &self.product
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, Duration, Utc};
+    use serde_json::json;
+    use crate::{
+        graphql::{
+            api_token,
+            analytics::{Granularity, MAX_BUCKETS},
+        },
+        tests::{
+            harness::{Api, Memory, TestServer, error_type},
+            seed,
+        },
+    };
+
+    #[test]
+    fn test_me() {
+        me(&mut Memory::seeded());
+    }
+
+    #[test]
+    #[ignore]
+    fn test_me_postgres() {
+        me(&mut TestServer::start());
+    }
+
+    fn me(api: &mut dyn Api) {
+        let query = "{ user { me { id username nickname } } }";
+        let response = api.execute(query, &[("USSID", seed::OWNER_SESSION)]);
+        assert_eq!(
+            response["data"]["user"]["me"],
+            json!({ "id": seed::OWNER.to_string(), "username": "owner", "nickname": "Owner" }),
+        );
+
+        assert_eq!(error_type(&api.execute(query, &[])), Some("NoValidCookie"));
+        assert_eq!(error_type(&api.execute(query, &[("USSID", seed::EXPIRED_USER_SESSION)])), Some("SessionExpired"));
+    }
+
+    #[test]
+    fn test_search_by_username() {
+        search_by_username(&mut Memory::seeded());
+    }
+
+    #[test]
+    #[ignore]
+    fn test_search_by_username_postgres() {
+        search_by_username(&mut TestServer::start());
+    }
+
+    fn search_by_username(api: &mut dyn Api) {
+        let response = api.execute("{ user { search(name: \"STA\") { uuid username } } }", &[]);
+        assert!(response.get("errors").is_none());
+        assert_eq!(
+            response["data"]["user"]["search"],
+            json!([{ "uuid": seed::STAFF.to_string(), "username": "staff" }]),
+        );
+    }
+
+    #[test]
+    fn test_member_authority_requires_all() {
+        member_authority_requires_all(&mut Memory::seeded());
+    }
+
+    #[test]
+    #[ignore]
+    fn test_member_authority_requires_all_postgres() {
+        member_authority_requires_all(&mut TestServer::start());
+    }
+
+    fn member_authority_requires_all(api: &mut dyn Api) {
+        let query = format!(
+            "{{ user {{ me {{ shops(id: \"{}\") {{
+                memberAuthority orderAuthority members {{ id username authority {{ member order product }} }}
+            }} }} }} }}",
+            seed::DINER,
+        );
+
+        let response = api.execute(&query, &[("USSID", seed::OWNER_SESSION)]);
+        assert!(response.get("errors").is_none());
+        let shop = &response["data"]["user"]["me"]["shops"][0];
+        assert_eq!(shop["memberAuthority"], "ALL");
+        let members = shop["members"].as_array().unwrap();
+        assert_eq!(members.len(), 2);
+        assert!(members.contains(&json!({
+            "id": seed::STAFF.to_string(),
+            "username": "staff",
+            "authority": { "member": "READ_ONLY", "order": "NONE", "product": "READ_ONLY" },
+        })));
+
+        // Read-only members can list members but not their authority.
+        let response = api.execute(&query, &[("USSID", seed::STAFF_SESSION)]);
+        assert_eq!(error_type(&response), Some("Unauthorized"));
+        let shop = &response["data"]["user"]["me"]["shops"][0];
+        assert_eq!(shop["orderAuthority"], "NONE");
+        assert!(shop["members"].as_array().unwrap().iter().all(|member| member["authority"].is_null()));
+    }
+
+    #[test]
+    fn test_orders_require_order_authority() {
+        orders_require_order_authority(&mut Memory::seeded());
+    }
+
+    #[test]
+    #[ignore]
+    fn test_orders_require_order_authority_postgres() {
+        orders_require_order_authority(&mut TestServer::start());
+    }
+
+    fn orders_require_order_authority(api: &mut dyn Api) {
+        let query = format!(
+            "{{ user {{ me {{ shops(id: \"{}\") {{ orders {{
+                uuid orderNumber items {{ key name price count customizes {{ customizeKey selection selectionPrice }} }}
+            }} }} }} }} }}",
+            seed::DINER,
+        );
+
+        let response = api.execute(&query, &[("USSID", seed::OWNER_SESSION)]);
+        assert_eq!(
+            response["data"]["user"]["me"]["shops"][0]["orders"],
+            json!([{
+                "uuid": seed::ORDER.to_string(),
+                "orderNumber": 1,
+                "items": [{
+                    "key": seed::ORDER_ITEM.to_string(),
+                    "name": "Braised Pork Rice",
+                    "price": 80,
+                    "count": 1,
+                    "customizes": [{ "customizeKey": seed::SIZE.to_string(), "selection": "Large", "selectionPrice": 20 }],
+                }],
+            }]),
+        );
+
+        let response = api.execute(&query, &[("USSID", seed::STAFF_SESSION)]);
+        assert_eq!(error_type(&response), Some("Unauthorized"));
+        assert!(response["data"]["user"]["me"]["shops"][0]["orders"].is_null());
+    }
+
+    #[test]
+    fn test_sales_summary() {
+        sales_summary(&mut Memory::seeded());
+    }
+
+    #[test]
+    #[ignore]
+    fn test_sales_summary_postgres() {
+        sales_summary(&mut TestServer::start());
+    }
+
+    fn sales_summary(api: &mut dyn Api) {
+        let owner = [("USSID", seed::OWNER_SESSION)];
+        let from = Utc::now() - Duration::days(2);
+        let to = Utc::now() + Duration::days(1);
+        let query = |from: DateTime<Utc>, to: DateTime<Utc>| format!(
+            "{{
+                user {{
+                    me {{
+                        shops(id: \"{}\") {{
+                            salesSummary(from: \"{}\", to: \"{}\", granularity: DAY) {{
+                                start
+                                revenue
+                                orderCount
+                                itemCount
+                                averageTicket
+                                topProducts {{ productKey name count revenue }}
+                                topSelections {{ selectionKey customizeName selection count }}
+                            }}
+                        }}
+                    }}
+                }}
+            }}",
+            seed::DINER,
+            from.to_rfc3339(),
+            to.to_rfc3339(),
+        );
+        let summary = |api: &mut dyn Api| {
+            api.execute(&query(from, to), &owner)["data"]["user"]["me"]["shops"][0]["salesSummary"].as_array().unwrap().clone()
+        };
+
+        // Days are bucketed in the shop's time zone, and those without
+        // orders are zeroed rather than left out.
+        let hours = format!(
+            "mutation {{ shop {{ setOpeningHours(shopId: \"{}\", timezone: \"Asia/Taipei\", hours: []) {{ name }} }} }}",
+            seed::DINER,
+        );
+        assert!(api.execute(&hours, &owner).get("errors").is_none());
+        let buckets = summary(api);
+        let starts: Vec<DateTime<Utc>> = buckets.iter().map(|bucket| bucket["start"].as_str().unwrap().parse().unwrap()).collect();
+        assert_eq!(starts, Granularity::Day.starts(from, to, "Asia/Taipei".parse().unwrap()).unwrap());
+        let (mut sold, unsold): (Vec<_>, Vec<_>) = buckets.into_iter().partition(|bucket| bucket["orderCount"] != json!(0));
+        assert_eq!(sold.len(), 1);
+        sold[0].as_object_mut().unwrap().remove("start");
+        assert_eq!(sold[0], json!({
+            "revenue": 100,
+            "orderCount": 1,
+            "itemCount": 1,
+            "averageTicket": 100.0,
+            "topProducts": [{ "productKey": seed::PORK_RICE.to_string(), "name": "Braised Pork Rice", "count": 1, "revenue": 100 }],
+            "topSelections": [{ "selectionKey": seed::LARGE.to_string(), "customizeName": "Size", "selection": "Large", "count": 1 }],
+        }));
+        assert!(unsold.iter().all(|bucket| bucket["revenue"] == json!(0) && bucket["topProducts"] == json!([])));
+
+        let response = api.execute(&query(from, from + Duration::days(MAX_BUCKETS as i64 + 1)), &owner);
+        assert_eq!(error_type(&response), Some("InvalidInput"));
+
+        let response = api.execute(&query(from, to), &[("USSID", seed::STAFF_SESSION)]);
+        assert_eq!(error_type(&response), Some("Unauthorized"));
+
+        // Revenue is gross and comes to the subtotals orders report.
+        let checkout = format!("mutation {{ guest {{ checkout(shopId: \"{}\") {{ orderNumber }} }} }}", seed::DINER);
+        assert!(api.execute(&checkout, &[("GSSID", seed::GUEST_SESSION)]).get("errors").is_none());
+        let revenue: i64 = summary(api).iter().map(|bucket| bucket["revenue"].as_i64().unwrap()).sum();
+        let orders = format!("{{ user {{ me {{ shops(id: \"{}\") {{ orders {{ subtotal }} }} }} }} }}", seed::DINER);
+        let response = api.execute(&orders, &owner);
+        let orders = response["data"]["user"]["me"]["shops"][0]["orders"].as_array().unwrap();
+        assert_eq!(orders.len(), 2);
+        assert_eq!(revenue, orders.iter().map(|order| order["subtotal"].as_i64().unwrap()).sum::<i64>());
+    }
+
+    #[test]
+    fn test_api_tokens() {
+        let mut memory = Memory::seeded();
+        let token = api_tokens(&mut memory);
+        assert_eq!(memory.repository().read().api_tokens[0].token_hash, api_token::hash(&token));
+    }
+
+    #[test]
+    #[ignore]
+    fn test_api_tokens_postgres() {
+        api_tokens(&mut TestServer::start());
+    }
+
+    // Returns the one token left unrevoked.
+    fn api_tokens(api: &mut dyn Api) -> String {
+        let owner = [("USSID", seed::OWNER_SESSION)];
+        let create = format!(
+            "mutation {{ user {{ createApiToken(token: {{ name: \"Stock sync\", shopId: \"{}\", memberAuthority: ALL, productAuthority: READ_ONLY }}) {{
+                token
+                apiToken {{ id name shopId authority {{ member order product }} expiresAt }}
+            }} }} }}",
+            seed::DINER,
+        );
+        let response = api.execute(&create, &owner);
+        let created = &response["data"]["user"]["createApiToken"];
+        let id = created["apiToken"]["id"].as_str().unwrap().to_string();
+        let mut details = created["apiToken"].clone();
+        details.as_object_mut().unwrap().remove("id");
+        assert_eq!(
+            details,
+            json!({
+                "name": "Stock sync",
+                "shopId": seed::DINER.to_string(),
+                "authority": { "member": "ALL", "order": "NONE", "product": "READ_ONLY" },
+                "expiresAt": null,
+            }),
+        );
+        let token = created["token"].as_str().unwrap().to_string();
+        assert!(token.starts_with("pk_"));
+
+        // The token acts as the owner, in its shop and no further than it allows.
+        let query = "{ user { me { username shops { shop { name } memberAuthority productAuthority } } } }";
+        let response = api.graphql_with_bearer(query, json!({}), &token);
+        assert_eq!(
+            response["data"]["user"]["me"],
+            json!({
+                "username": "owner",
+                "shops": [{ "shop": { "name": "Pigskit Diner" }, "memberAuthority": "ALL", "productAuthority": "READ_ONLY" }],
+            }),
+        );
+        let set_address = |shop_id| format!("mutation {{ shop {{ setShopAddress(shopId: \"{}\", address: \"1 Main St.\") {{ id }} }} }}", shop_id);
+        assert!(api.graphql_with_bearer(&set_address(seed::DINER), json!({}), &token).get("errors").is_none());
+        assert_eq!(error_type(&api.graphql_with_bearer(&set_address(seed::BACON_BAR), json!({}), &token)), Some("Unauthorized"));
+        let set_inventory = format!(
+            "mutation {{ shop {{ setProductInventory(shopId: \"{}\", productKey: \"{}\", stock: 3, soldOut: false) {{ key }} }} }}",
+            seed::DINER,
+            seed::PORK_RICE,
+        );
+        assert_eq!(error_type(&api.graphql_with_bearer(&set_inventory, json!({}), &token)), Some("Unauthorized"));
+
+        // Tokens can't manage tokens.
+        assert_eq!(error_type(&api.graphql_with_bearer(&create, json!({}), &token)), Some("Unauthorized"));
+        assert_eq!(error_type(&api.graphql_with_bearer("{ user { me { apiTokens { id } } } }", json!({}), &token)), Some("Unauthorized"));
+
+        // Nor be issued with more than the user has.
+        let escalate = format!(
+            "mutation {{ user {{ createApiToken(token: {{ name: \"Orders\", shopId: \"{}\", orderAuthority: READ_ONLY }}) {{ token }} }} }}",
+            seed::DINER,
+        );
+        assert_eq!(error_type(&api.execute(&escalate, &[("USSID", seed::STAFF_SESSION)])), Some("Unauthorized"));
+
+        let revoke = format!("mutation {{ user {{ revokeApiToken(id: \"{}\") }} }}", id);
+        assert_eq!(error_type(&api.execute(&revoke, &[("USSID", seed::STAFF_SESSION)])), Some("NotFound"));
+        assert_eq!(api.execute(&revoke, &owner)["data"]["user"]["revokeApiToken"], json!(id));
+        assert_eq!(error_type(&api.graphql_with_bearer(query, json!({}), &token)), Some("InvalidToken"));
+        assert_eq!(error_type(&api.graphql_with_bearer(query, json!({}), "pk_made-up")), Some("InvalidToken"));
+
+        // A token without a shop works in every shop of the user.
+        let response = api.graphql(
+            "mutation ($token: ApiTokenInput!) { user { createApiToken(token: $token) { token apiToken { authority { member order product } } } } }",
+            json!({ "token": { "name": "Reports", "orderAuthority": "READ_ONLY", "expiresAt": "2100-01-01T00:00:00Z" } }),
+            &owner,
+        );
+        let created = &response["data"]["user"]["createApiToken"];
+        assert_eq!(created["apiToken"]["authority"], json!({ "member": "NONE", "order": "READ_ONLY", "product": "NONE" }));
+        let token = created["token"].as_str().unwrap().to_string();
+        let response = api.graphql_with_bearer("{ user { me { shops { orderAuthority } } } }", json!({}), &token);
+        assert_eq!(
+            response["data"]["user"]["me"]["shops"],
+            json!([{ "orderAuthority": "READ_ONLY" }, { "orderAuthority": "READ_ONLY" }]),
+        );
+        let pause = format!("mutation {{ shop {{ pauseOrdering(shopId: \"{}\", minutes: 10) {{ id }} }} }}", seed::DINER);
+        assert_eq!(error_type(&api.graphql_with_bearer(&pause, json!({}), &token)), Some("Unauthorized"));
+
+        token
+    }
+}
@@ -8,13 +8,37 @@ mod state;
 mod argument;
 mod error;
 mod utils;
+mod repository;
+mod shutdown;
+mod migration;
+mod picture;
+mod rate_limit;
+mod auth;
+#[cfg(test)] mod tests;
 
+use std::{
+    cmp,
+    fs,
+    process,
+    sync::Arc,
+    time::Duration,
+};
+use clap::ArgMatches;
+use futures::Future;
 use state::{State, db::init_pool};
+use repository::memory::MemoryRepository;
+use picture::storage::LocalStorage;
+use rate_limit::{RateLimiter, RateLimits, store::PostgresStore};
 
 const DEFAULT_PORT: u16 = 80;
 const DEFAULT_PORT_DEV: u16 = 8000;
 const PG_CONFIG: &'static str = "host=postgres-server user=postgres dbname=postgres";
 const PG_CONFIG_DEV: &'static str = "host=localhost user=postgres dbname=postgres";
+// Resolvers and shared rate limits draw on the same pool.
+const DEFAULT_DB_POOL_SIZE: u32 = 16;
+// CSV exports may hold at most a quarter of the pool.
+const EXPORT_SHARE: usize = 4;
+const DEFAULT_PICTURE_DIR: &'static str = "pictures";
 
 fn main() {
     ::std::env::set_var("RUST_LOG", "info");
@@ -22,22 +46,142 @@ fn main() {
 
     let mut port = DEFAULT_PORT;
     let mut pg_config = PG_CONFIG;
+    let mut shutdown_timeout = shutdown::DEFAULT_TIMEOUT;
 
     let args = argument::parse_arguments();
+    if let Some(schema_args) = args.subcommand_matches("schema") {
+        export_schema(schema_args);
+        return;
+    }
+
     let is_dev = args.is_present("dev");
     if is_dev {
         port = DEFAULT_PORT_DEV;
         pg_config = PG_CONFIG_DEV;
     }
+    if let Some(migrate_args) = args.subcommand_matches("migrate") {
+        migrate(migrate_args, pg_config);
+        return;
+    }
     if let Some(p) = argument::args_port(&args) {
         port = p;
     }
+    if let Some(t) = argument::args_shutdown_timeout(&args) {
+        shutdown_timeout = Duration::from_secs(t);
+    }
 
-    let db_pool = init_pool(pg_config, 1);
+    let state = if args.is_present("memory") {
+        info!("Serving from an empty in-memory store.");
+        State::init_memory(Arc::new(MemoryRepository::new()))
+    } else {
+        let db_pool_size = argument::args_db_pool_size(&args).unwrap_or(DEFAULT_DB_POOL_SIZE);
+        let db_pool = init_pool(pg_config, db_pool_size);
+        let schema_check = db_pool.get()
+            .map_err(|err| -> error::Error { err.into() })
+            .and_then(|mut conn| migration::check(&mut conn));
+        if let Err(err) = schema_check {
+            error!("Refusing to serve: {}", err);
+            process::exit(1);
+        }
+        let picture_dir = args.value_of("picture-dir").unwrap_or(DEFAULT_PICTURE_DIR);
+        let state = State::init(db_pool.clone(), Arc::new(LocalStorage::new(picture_dir)))
+            .with_export_limit(cmp::max(1, db_pool_size as usize / EXPORT_SHARE));
+        if args.is_present("shared-rate-limits") {
+            state.with_rate_limiter(RateLimiter::new(RateLimits::default(), Arc::new(PostgresStore::new(db_pool))))
+        } else {
+            state
+        }
+    };
 
-    if is_dev {
-        warp::serve(route::dev_routes(State::init(db_pool))).run(([0, 0, 0, 0], port));
+    let state = state
+        .with_password_params(argument::args_password_params(&args))
+        .with_trusted_proxies(argument::args_trusted_proxies(&args));
+
+    let signal = shutdown::signal(state.requests().clone()).shared();
+    let graceful = signal.clone().map(|_| ()).map_err(|_| ());
+
+    let (addr, server): (_, shutdown::Server) = if is_dev {
+        let (addr, server) = warp::serve(route::dev_routes(state.clone()))
+            .bind_with_graceful_shutdown(([0, 0, 0, 0], port), graceful);
+        (addr, Box::new(server))
     } else {
-        warp::serve(route::routes(State::init(db_pool))).run(([0, 0, 0, 0], port));
+        let (addr, server) = warp::serve(route::routes(state.clone()))
+            .bind_with_graceful_shutdown(([0, 0, 0, 0], port), graceful);
+        (addr, Box::new(server))
+    };
+    info!("Listening on http://{}", addr);
+
+    let deadline = shutdown::run(server, signal, shutdown_timeout, state.requests());
+    state.close(deadline);
+}
+
+// Introspection never touches the repositories, so an empty in-memory store will do.
+fn export_schema(args: &ArgMatches) {
+    let state = State::init_memory(Arc::new(MemoryRepository::new()));
+    let context = graphql::Context::new(state, None, None);
+
+    let schema = match args.value_of("format") {
+        Some("json") => graphql::export::introspection_json(&context),
+        _ => graphql::export::sdl(&context),
+    };
+    let schema = match schema {
+        Ok(schema) => schema,
+        Err(err) => {
+            error!("Failed to export schema: {}", err);
+            process::exit(1);
+        }
+    };
+
+    if let Some(path) = args.value_of("output") {
+        if let Err(err) = fs::write(path, schema) {
+            error!("Failed to write schema to {}: {}", path, err);
+            process::exit(1);
+        }
+    } else {
+        print!("{}", schema);
+    }
+}
+
+fn migrate(args: &ArgMatches, pg_config: &str) {
+    let mut conn = match postgres::Client::connect(pg_config, postgres::NoTls) {
+        Ok(conn) => conn,
+        Err(err) => {
+            error!("Failed to connect to database: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let result = match args.subcommand() {
+        ("up", Some(up_args)) => {
+            migration::up(&mut conn, argument::args_migrate_target(up_args)).map(|migrated| {
+                if migrated.is_empty() {
+                    info!("Database schema is up to date.");
+                }
+            })
+        }
+        ("baseline", Some(baseline_args)) => {
+            match argument::args_migrate_target(baseline_args) {
+                Some(target) => migration::baseline(&mut conn, target).map(|_| ()),
+                None => Err(error::Error::invalid_input("Baseline needs a version.")),
+            }
+        }
+        ("down", Some(down_args)) => {
+            migration::down(&mut conn, argument::args_migrate_steps(down_args)).map(|_| ())
+        }
+        _ => {
+            migration::status(&mut conn).map(|migrations| {
+                for (migration, applied_at) in migrations {
+                    let applied_at = applied_at
+                        .map(|applied_at| applied_at.to_rfc3339())
+                        .unwrap_or_else(|| "pending".to_string());
+                    println!("{:>4}  {:<32}  {}", migration.version, migration.name, applied_at);
+                }
+            })
+        }
+    };
+
+    if let Err(err) = result {
+        error!("Migration failed: {:?}", err);
+        process::exit(1);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,199 @@
+// Databases created before migrations were embedded have the schema of
+// 0001_initial but no `schema_migrations` table, and the server refuses to
+// serve them. To upgrade one, record that migration as applied without
+// running it, then apply the rest:
+//
+//     pigskit-server migrate baseline --to 1
+//     pigskit-server migrate up
+
+use chrono::{DateTime, Utc};
+use postgres::Client;
+use crate::error::Error;
+
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    up: &'static str,
+    down: &'static str,
+}
+
+macro_rules! migration {
+    ($version:expr, $name:literal) => {
+        Migration {
+            version: $version,
+            name: $name,
+            up: include_str!(concat!("../../migrations/", $name, ".up.sql")),
+            down: include_str!(concat!("../../migrations/", $name, ".down.sql")),
+        }
+    }
+}
+
+// Migrations embedded in the binary, ordered by version.
+pub const MIGRATIONS: &[Migration] = &[
+    migration!(1, "0001_initial"),
+    migration!(2, "0002_series"),
+    migration!(3, "0003_inventory"),
+    migration!(4, "0004_search"),
+    migration!(5, "0005_location"),
+    migration!(6, "0006_opening_hours"),
+    migration!(7, "0007_translations"),
+    migration!(8, "0008_currency"),
+    migration!(9, "0009_promotions"),
+    migration!(10, "0010_taxes"),
+    migration!(11, "0011_audit_log"),
+    migration!(12, "0012_rate_limits"),
+    migration!(13, "0013_login_failures"),
+    migration!(14, "0014_api_tokens"),
+    migration!(15, "0015_roles"),
+    migration!(16, "0016_audit_roles"),
+];
+
+pub fn latest_version() -> i32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+fn ensure_table(conn: &mut Client) -> Result<(), Error> {
+    conn.batch_execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+        );"
+    )?;
+    Ok(())
+}
+
+fn table_exists(conn: &mut Client, table: &str) -> Result<bool, Error> {
+    let (exists,) = query_one!(
+        conn,
+        "SELECT to_regclass($1) IS NOT NULL exists",
+        &[&table],
+        (exists: bool),
+    )?;
+    Ok(exists)
+}
+
+// Whether the database has the schema but no record of migrations, as
+// before they were embedded.
+fn is_unversioned(conn: &mut Client) -> Result<bool, Error> {
+    Ok(!table_exists(conn, "schema_migrations")? && table_exists(conn, "shops")?)
+}
+
+// Read-only, so checking a database at startup changes nothing.
+fn applied(conn: &mut Client) -> Result<Vec<(i32, DateTime<Utc>)>, Error> {
+    if !table_exists(conn, "schema_migrations")? {
+        return Ok(Vec::new());
+    }
+    let rows = query!(
+        conn,
+        "SELECT version, applied_at FROM schema_migrations ORDER BY version",
+        &[],
+    )?;
+    Ok(rows.iter().map(|row| (row.get("version"), row.get("applied_at"))).collect())
+}
+
+pub fn current_version(conn: &mut Client) -> Result<i32, Error> {
+    Ok(applied(conn)?.last().map(|&(version, _)| version).unwrap_or(0))
+}
+
+// Apply every pending migration up to `target`, or all of them.
+pub fn up(conn: &mut Client, target: Option<i32>) -> Result<Vec<&'static Migration>, Error> {
+    if is_unversioned(conn)? {
+        return Err(Error::unversioned_schema());
+    }
+    ensure_table(conn)?;
+    let current = current_version(conn)?;
+    let target = target.unwrap_or_else(latest_version);
+
+    let mut migrated = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current && m.version <= target) {
+        let mut tx = conn.transaction()?;
+        tx.batch_execute(migration.up)?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version, name) VALUES ($1, $2)",
+            &[&migration.version, &migration.name],
+        )?;
+        tx.commit()?;
+        info!("Applied migration {} {}.", migration.version, migration.name);
+        migrated.push(migration);
+    }
+    Ok(migrated)
+}
+
+// Revert the latest `steps` applied migrations.
+pub fn down(conn: &mut Client, steps: usize) -> Result<Vec<&'static Migration>, Error> {
+    let applied = applied(conn)?;
+
+    let mut reverted = Vec::new();
+    for &(version, _) in applied.iter().rev().take(steps) {
+        let migration = MIGRATIONS.iter()
+            .find(|m| m.version == version)
+            .ok_or_else(|| Error::unknown_migration(version))?;
+
+        let mut tx = conn.transaction()?;
+        tx.batch_execute(migration.down)?;
+        tx.execute(
+            "DELETE FROM schema_migrations WHERE version = $1",
+            &[&migration.version],
+        )?;
+        tx.commit()?;
+        info!("Reverted migration {} {}.", migration.version, migration.name);
+        reverted.push(migration);
+    }
+    Ok(reverted)
+}
+
+// Record migrations up to `target` as applied without running them, for a
+// database that already has their schema and no record of migrations.
+pub fn baseline(conn: &mut Client, target: i32) -> Result<Vec<&'static Migration>, Error> {
+    if !MIGRATIONS.iter().any(|m| m.version == target) {
+        return Err(Error::unknown_migration(target));
+    }
+    if !applied(conn)?.is_empty() {
+        return Err(Error::invalid_input("Database already records applied migrations."));
+    }
+    ensure_table(conn)?;
+
+    let baselined: Vec<_> = MIGRATIONS.iter().filter(|m| m.version <= target).collect();
+    let mut tx = conn.transaction()?;
+    for migration in &baselined {
+        tx.execute(
+            "INSERT INTO schema_migrations (version, name) VALUES ($1, $2)",
+            &[&migration.version, &migration.name],
+        )?;
+    }
+    tx.commit()?;
+    for migration in &baselined {
+        info!("Marked migration {} {} as applied.", migration.version, migration.name);
+    }
+    Ok(baselined)
+}
+
+pub fn status(conn: &mut Client) -> Result<Vec<(&'static Migration, Option<DateTime<Utc>>)>, Error> {
+    let applied = applied(conn)?;
+    Ok(
+        MIGRATIONS.iter().map(|migration| {
+            let applied_at = applied.iter()
+                .find(|&&(version, _)| version == migration.version)
+                .map(|&(_, applied_at)| applied_at);
+            (migration, applied_at)
+        })
+        .collect()
+    )
+}
+
+// Refuse to run against a database that is missing embedded migrations.
+pub fn check(conn: &mut Client) -> Result<(), Error> {
+    if is_unversioned(conn)? {
+        return Err(Error::unversioned_schema());
+    }
+    let current = current_version(conn)?;
+    let latest = latest_version();
+    if current < latest {
+        return Err(Error::outdated_schema(current, latest))
+    }
+    if current > latest {
+        warn!("Database schema version {} is newer than this server ({}).", current, latest);
+    }
+    Ok(())
+}
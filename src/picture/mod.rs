@@ -0,0 +1,185 @@
+use std::io::Cursor;
+use image::{
+    DynamicImage,
+    ImageFormat,
+    ImageOutputFormat,
+    imageops::FilterType,
+    io::Reader,
+};
+use crate::error::Error;
+
+pub mod storage;
+
+// Uploads larger than this are rejected before decoding.
+pub const MAX_PICTURE_SIZE: usize = 5 * 1024 * 1024;
+// Guards against small files that decode into huge bitmaps.
+pub const MAX_PICTURE_DIMENSION: u32 = 6000;
+
+const JPEG_QUALITY: u8 = 85;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Variant {
+    Thumbnail,
+    Medium,
+    Original,
+}
+
+impl Variant {
+    pub const ALL: [Variant; 3] = [Variant::Thumbnail, Variant::Medium, Variant::Original];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Variant::Thumbnail => "thumbnail",
+            Variant::Medium => "medium",
+            Variant::Original => "original",
+        }
+    }
+
+    // Longest side in pixels, `None` keeps the upload as it is.
+    fn max_side(&self) -> Option<u32> {
+        match self {
+            Variant::Thumbnail => Some(160),
+            Variant::Medium => Some(640),
+            Variant::Original => None,
+        }
+    }
+}
+
+impl Default for Variant {
+    fn default() -> Self {
+        Variant::Original
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Picture {
+    pub content_type: &'static str,
+    pub bytes: Vec<u8>,
+}
+
+impl Picture {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Picture {
+            content_type: content_type(&bytes).unwrap_or("application/octet-stream"),
+            bytes: bytes,
+        }
+    }
+}
+
+fn accepted_format(bytes: &[u8]) -> Option<ImageFormat> {
+    match image::guess_format(bytes) {
+        Ok(format @ ImageFormat::Jpeg) | Ok(format @ ImageFormat::Png) | Ok(format @ ImageFormat::WebP) => Some(format),
+        _ => None,
+    }
+}
+
+// Sniffed from the content, the declared multipart type is not trusted.
+pub fn content_type(bytes: &[u8]) -> Option<&'static str> {
+    match accepted_format(bytes)? {
+        ImageFormat::Jpeg => Some("image/jpeg"),
+        ImageFormat::Png => Some("image/png"),
+        _ => Some("image/webp"),
+    }
+}
+
+// Validate an uploaded picture and render every variant of it.
+pub fn process(bytes: &[u8]) -> Result<Vec<(Variant, Picture)>, Error> {
+    if bytes.len() > MAX_PICTURE_SIZE {
+        return Err(Error::picture_too_large(MAX_PICTURE_SIZE));
+    }
+    let format = accepted_format(bytes)
+        .ok_or_else(|| Error::invalid_picture("Only JPEG, PNG and WebP pictures are accepted."))?;
+
+    let (width, height) = Reader::with_format(Cursor::new(bytes), format)
+        .into_dimensions()
+        .map_err(|_| Error::invalid_picture("Picture could not be decoded."))?;
+    if width > MAX_PICTURE_DIMENSION || height > MAX_PICTURE_DIMENSION {
+        return Err(Error::invalid_picture(&format!(
+            "Picture may be at most {0}x{0} pixels.",
+            MAX_PICTURE_DIMENSION,
+        )));
+    }
+    let image = image::load_from_memory_with_format(bytes, format)
+        .map_err(|_| Error::invalid_picture("Picture could not be decoded."))?;
+
+    let mut pictures = Vec::new();
+    for variant in Variant::ALL.iter() {
+        let picture = match variant.max_side() {
+            Some(side) if width > side || height > side => resize(&image, side)?,
+            _ => Picture::new(bytes.to_vec()),
+        };
+        pictures.push((*variant, picture));
+    }
+    Ok(pictures)
+}
+
+// Keeps the aspect ratio, and keeps transparency by falling back to PNG.
+fn resize(image: &DynamicImage, side: u32) -> Result<Picture, Error> {
+    let resized = image.resize(side, side, FilterType::Lanczos3);
+    let format = if resized.color().has_alpha() {
+        ImageOutputFormat::Png
+    } else {
+        ImageOutputFormat::Jpeg(JPEG_QUALITY)
+    };
+
+    let mut bytes = Vec::new();
+    resized.write_to(&mut bytes, format)?;
+    Ok(Picture::new(bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use image::{DynamicImage, GenericImageView, ImageOutputFormat};
+    use super::{
+        process,
+        Variant,
+        MAX_PICTURE_SIZE,
+    };
+
+    fn encode(image: DynamicImage, format: ImageOutputFormat) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image.write_to(&mut bytes, format).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_process_resizes_variants() {
+        let bytes = encode(DynamicImage::new_rgb8(1200, 600), ImageOutputFormat::Jpeg(90));
+        let pictures = process(&bytes).unwrap();
+
+        let dimensions: Vec<(Variant, (u32, u32))> = pictures.iter()
+            .map(|(variant, picture)| (*variant, image::load_from_memory(&picture.bytes).unwrap().dimensions()))
+            .collect();
+        assert_eq!(dimensions, vec![
+            (Variant::Thumbnail, (160, 80)),
+            (Variant::Medium, (640, 320)),
+            (Variant::Original, (1200, 600)),
+        ]);
+        assert!(pictures.iter().all(|(_, picture)| picture.content_type == "image/jpeg"));
+        assert_eq!(pictures[2].1.bytes, bytes);
+    }
+
+    #[test]
+    fn test_process_keeps_transparency() {
+        let bytes = encode(DynamicImage::new_rgba8(800, 800), ImageOutputFormat::Png);
+        let pictures = process(&bytes).unwrap();
+        assert!(pictures.iter().all(|(_, picture)| picture.content_type == "image/png"));
+    }
+
+    #[test]
+    fn test_process_small_picture_is_not_upscaled() {
+        let bytes = encode(DynamicImage::new_rgb8(100, 50), ImageOutputFormat::Png);
+        let pictures = process(&bytes).unwrap();
+        assert!(pictures.iter().all(|(_, picture)| picture.bytes == bytes));
+    }
+
+    #[test]
+    fn test_process_rejects_invalid_pictures() {
+        let error = |bytes: &[u8]| process(bytes).unwrap_err().to_string();
+
+        assert!(error(b"GIF89a not accepted").contains("InvalidPicture"));
+        assert!(error(b"\x89PNG\r\n\x1a\n truncated").contains("InvalidPicture"));
+        assert!(error(&vec![0; MAX_PICTURE_SIZE + 1]).contains("PictureTooLarge"));
+    }
+}
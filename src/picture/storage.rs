@@ -0,0 +1,127 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::ErrorKind,
+    path::PathBuf,
+    sync::RwLock,
+};
+use uuid::Uuid;
+use crate::{
+    picture::{Picture, Variant},
+    error::Error,
+};
+
+pub trait PictureStorage: Send + Sync {
+    // Replace every variant of a product's picture.
+    fn put(&self, shop_id: Uuid, product_key: Uuid, pictures: &[(Variant, Picture)]) -> Result<(), Error>;
+
+    fn get(&self, shop_id: Uuid, product_key: Uuid, variant: Variant) -> Result<Option<Picture>, Error>;
+
+    fn delete(&self, shop_id: Uuid, product_key: Uuid) -> Result<(), Error>;
+}
+
+// Stores variants as `{root}/{shop_id}/{product_key}/{variant}`.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        LocalStorage {
+            root: root.into(),
+        }
+    }
+
+    fn directory(&self, shop_id: Uuid, product_key: Uuid) -> PathBuf {
+        self.root.join(shop_id.to_string()).join(product_key.to_string())
+    }
+}
+
+impl PictureStorage for LocalStorage {
+    fn put(&self, shop_id: Uuid, product_key: Uuid, pictures: &[(Variant, Picture)]) -> Result<(), Error> {
+        let directory = self.directory(shop_id, product_key);
+        fs::create_dir_all(&directory)?;
+
+        // Write aside and rename, so readers never see a partially written file.
+        for (variant, picture) in pictures {
+            let path = directory.join(variant.name());
+            let temp = directory.join(format!("{}.tmp", variant.name()));
+            fs::write(&temp, &picture.bytes)?;
+            fs::rename(&temp, &path)?;
+        }
+        Ok(())
+    }
+
+    fn get(&self, shop_id: Uuid, product_key: Uuid, variant: Variant) -> Result<Option<Picture>, Error> {
+        match fs::read(self.directory(shop_id, product_key).join(variant.name())) {
+            Ok(bytes) => Ok(Some(Picture::new(bytes))),
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn delete(&self, shop_id: Uuid, product_key: Uuid) -> Result<(), Error> {
+        match fs::remove_dir_all(self.directory(shop_id, product_key)) {
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            result => Ok(result?),
+        }
+    }
+}
+
+pub struct MemoryStorage {
+    pictures: RwLock<HashMap<(Uuid, Uuid, Variant), Picture>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage {
+            pictures: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+impl PictureStorage for MemoryStorage {
+    fn put(&self, shop_id: Uuid, product_key: Uuid, pictures: &[(Variant, Picture)]) -> Result<(), Error> {
+        let mut stored = self.pictures.write().unwrap();
+        for (variant, picture) in pictures {
+            stored.insert((shop_id, product_key, *variant), picture.clone());
+        }
+        Ok(())
+    }
+
+    fn get(&self, shop_id: Uuid, product_key: Uuid, variant: Variant) -> Result<Option<Picture>, Error> {
+        Ok(self.pictures.read().unwrap().get(&(shop_id, product_key, variant)).cloned())
+    }
+
+    fn delete(&self, shop_id: Uuid, product_key: Uuid) -> Result<(), Error> {
+        self.pictures.write().unwrap().retain(|&(shop, product, _), _| shop != shop_id || product != product_key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use uuid::Uuid;
+    use super::{PictureStorage, LocalStorage};
+    use crate::picture::{Picture, Variant};
+
+    #[test]
+    fn test_local_storage() {
+        let root = env::temp_dir().join(format!("pigskit_pictures_{}", Uuid::new_v4().to_simple()));
+        let storage = LocalStorage::new(&root);
+        let (shop_id, product_key) = (Uuid::new_v4(), Uuid::new_v4());
+
+        assert!(storage.get(shop_id, product_key, Variant::Original).unwrap().is_none());
+        storage.put(shop_id, product_key, &[(Variant::Original, Picture::new(b"\x89PNG\r\n\x1a\n".to_vec()))]).unwrap();
+        let picture = storage.get(shop_id, product_key, Variant::Original).unwrap().unwrap();
+        assert_eq!(picture.content_type, "image/png");
+        assert!(storage.get(shop_id, product_key, Variant::Thumbnail).unwrap().is_none());
+
+        storage.delete(shop_id, product_key).unwrap();
+        assert!(storage.get(shop_id, product_key, Variant::Original).unwrap().is_none());
+        storage.delete(shop_id, product_key).unwrap();
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,294 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Arc,
+};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::graphql::api_token;
+
+pub mod store;
+
+use store::RateLimitStore;
+
+// How many requests a client may make at once, and how fast that allowance
+// comes back.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Budget {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+impl Budget {
+    pub fn new(burst: u32, per_minute: u32) -> Self {
+        Budget {
+            burst: burst,
+            per_minute: per_minute.max(1),
+        }
+    }
+
+    fn per_second(&self) -> f64 {
+        self.per_minute as f64 / 60.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Budgets {
+    pub anonymous: Budget,
+    pub authenticated: Budget,
+}
+
+impl Budgets {
+    fn of(&self, client: &Client) -> Budget {
+        if client.is_authenticated() {
+            self.authenticated
+        } else {
+            self.anonymous
+        }
+    }
+}
+
+// Every request draws on the client's general budget. Operations with an
+// override, such as searches, draw on a budget of their own as well.
+#[derive(Clone, Debug)]
+pub struct RateLimits {
+    pub general: Budgets,
+    pub operations: HashMap<&'static str, Budgets>,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        let searches = Budgets {
+            anonymous: Budget::new(30, 30),
+            authenticated: Budget::new(120, 120),
+        };
+        let mut operations = HashMap::new();
+        operations.insert("user.search", Budgets {
+            anonymous: Budget::new(10, 10),
+            authenticated: Budget::new(60, 60),
+        });
+        operations.insert("shop.search", searches);
+        operations.insert("shop.find", searches);
+        operations.insert("shop.nearby", searches);
+
+        RateLimits {
+            // A menu page loads a picture per product, so anonymous budgets
+            // leave room for those.
+            general: Budgets {
+                anonymous: Budget::new(120, 120),
+                authenticated: Budget::new(600, 600),
+            },
+            operations: operations,
+        }
+    }
+}
+
+// Who a request is charged to. Before the routes, that's whatever the
+// request presents, taken at its word so nothing has to be looked up: the API
+// token, then the user session, then the guest session, then the address.
+// Resolvers charge operations to the client once its credentials check out,
+// see `Context::client`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Client {
+    // The hash of the token's secret, as keys may end up in the database.
+    Token(String),
+    User(Uuid),
+    Guest(Uuid),
+    Address(IpAddr),
+    Unknown,
+}
+
+impl Client {
+    pub fn claimed(
+        user_session_id: Option<Uuid>,
+        guest_session_id: Option<Uuid>,
+        bearer_token: Option<&str>,
+        addr: Option<IpAddr>,
+    ) -> Self {
+        if let Some(secret) = bearer_token {
+            Client::Token(api_token::hash(secret))
+        } else if let Some(id) = user_session_id {
+            Client::User(id)
+        } else if let Some(id) = guest_session_id {
+            Client::Guest(id)
+        } else {
+            addr.map_or(Client::Unknown, Client::Address)
+        }
+    }
+
+    fn is_authenticated(&self) -> bool {
+        match self {
+            Client::Token(_) | Client::User(_) => true,
+            _ => false,
+        }
+    }
+
+    fn key(&self) -> String {
+        match self {
+            Client::Token(id) => format!("token:{}", id),
+            Client::User(id) => format!("ussid:{}", id),
+            Client::Guest(id) => format!("gssid:{}", id),
+            Client::Address(ip) => format!("ip:{}", ip),
+            Client::Unknown => "unknown".to_string(),
+        }
+    }
+
+    // Credentials are keyed along with the address they are sent from, so
+    // one that is made up or leaked only spends its own budget there.
+    fn key_at(&self, addr: Option<IpAddr>) -> String {
+        match (self, addr) {
+            (Client::Address(_), _) | (_, None) => self.key(),
+            (_, Some(ip)) => format!("{}@{}", self.key(), ip),
+        }
+    }
+}
+
+// A token bucket. It holds up to `burst` tokens, refills at `per_minute`
+// and every request takes one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bucket {
+    pub tokens: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Bucket {
+    pub fn full(budget: Budget, now: DateTime<Utc>) -> Self {
+        Bucket {
+            tokens: budget.burst as f64,
+            updated_at: now,
+        }
+    }
+
+    // Refills the bucket for the time since it was last used and takes a
+    // token. When it is empty, returns the seconds until it has one again.
+    pub fn take(&mut self, budget: Budget, now: DateTime<Utc>) -> Option<u64> {
+        let elapsed = (now - self.updated_at).num_milliseconds().max(0) as f64 / 1000.0;
+        self.tokens = (self.tokens + elapsed * budget.per_second()).min(budget.burst as f64);
+        self.updated_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            None
+        } else {
+            Some(((1.0 - self.tokens) / budget.per_second()).ceil().max(1.0) as u64)
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimiter {
+    limits: Arc<RateLimits>,
+    store: Arc<dyn RateLimitStore>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits, store: Arc<dyn RateLimitStore>) -> Self {
+        RateLimiter {
+            limits: Arc::new(limits),
+            store: store,
+        }
+    }
+
+    // Charges a request from `addr` to the client's general budget. Returns
+    // the seconds to wait when it is spent.
+    pub fn check(&self, client: &Client, addr: Option<IpAddr>) -> Option<u64> {
+        self.take(&client.key_at(addr), self.limits.general.of(client))
+    }
+
+    // Charges `operation` to its own budget, when it has one.
+    pub fn check_operation(&self, client: &Client, operation: &str) -> Option<u64> {
+        let budgets = self.limits.operations.get(operation)?;
+        self.take(&format!("{}:{}", operation, client.key()), budgets.of(client))
+    }
+
+    // A store that fails lets requests through rather than turning everyone
+    // away.
+    fn take(&self, key: &str, budget: Budget) -> Option<u64> {
+        match self.store.take(key, budget, Utc::now()) {
+            Ok(retry_after) => retry_after,
+            Err(err) => {
+                error!("Failed to check rate limit: {}", err);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::IpAddr,
+        sync::Arc,
+    };
+    use chrono::{Duration, Utc};
+    use crate::tests::seed;
+    use super::{Budget, Budgets, Bucket, Client, RateLimiter, RateLimits, api_token, store::MemoryStore};
+
+    #[test]
+    fn test_bucket() {
+        let budget = Budget::new(2, 60);
+        let now = Utc::now();
+        let mut bucket = Bucket::full(budget, now);
+
+        assert_eq!(bucket.take(budget, now), None);
+        assert_eq!(bucket.take(budget, now), None);
+        assert_eq!(bucket.take(budget, now), Some(1));
+
+        // A token a second comes back, never more than the burst.
+        assert_eq!(bucket.take(budget, now + Duration::milliseconds(1500)), None);
+        assert_eq!(bucket.take(budget, now + Duration::milliseconds(1500)), Some(1));
+        assert_eq!(bucket.take(budget, now + Duration::seconds(60)), None);
+        assert_eq!(bucket.take(budget, now + Duration::seconds(60)), None);
+        assert_eq!(bucket.take(budget, now + Duration::seconds(60)), Some(1));
+
+        let slow = Budget::new(1, 6);
+        let mut bucket = Bucket::full(slow, now);
+        assert_eq!(bucket.take(slow, now), None);
+        assert_eq!(bucket.take(slow, now), Some(10));
+    }
+
+    #[test]
+    fn test_limiter() {
+        let addr: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        let budgets = |anonymous: u32, authenticated: u32| Budgets {
+            anonymous: Budget::new(anonymous, 1),
+            authenticated: Budget::new(authenticated, 1),
+        };
+        let mut limits = RateLimits {
+            general: budgets(1, 2),
+            operations: Default::default(),
+        };
+        limits.operations.insert("user.search", budgets(1, 1));
+        let limiter = RateLimiter::new(limits, Arc::new(MemoryStore::new()));
+
+        let anonymous = Client::claimed(None, None, None, Some(addr));
+        assert_eq!(anonymous, Client::Address(addr));
+        assert_eq!(limiter.check(&anonymous, Some(addr)), None);
+        assert!(limiter.check(&anonymous, Some(addr)).is_some());
+
+        let guest = Client::claimed(None, Some(seed::GUEST_SESSION), None, Some(addr));
+        assert_eq!(guest, Client::Guest(seed::GUEST_SESSION));
+        assert_eq!(limiter.check(&guest, Some(addr)), None);
+
+        // Tokens come first, whatever cookies are sent along, and are keyed
+        // by their hash.
+        let token = Client::claimed(Some(seed::OWNER_SESSION), None, Some("pk_secret"), Some(addr));
+        assert_eq!(token.key(), format!("token:{}", api_token::hash("pk_secret")));
+
+        let owner = Client::claimed(Some(seed::OWNER_SESSION), Some(seed::GUEST_SESSION), None, Some(addr));
+        assert_eq!(owner, Client::User(seed::OWNER_SESSION));
+        assert_eq!(limiter.check(&owner, Some(addr)), None);
+        assert_eq!(limiter.check(&owner, Some(addr)), None);
+        assert!(limiter.check(&owner, Some(addr)).is_some());
+
+        // The same session from another address has a budget of its own.
+        assert_eq!(limiter.check(&owner, Some(other)), None);
+
+        // Overrides have buckets of their own.
+        assert_eq!(limiter.check_operation(&owner, "user.search"), None);
+        assert!(limiter.check_operation(&owner, "user.search").is_some());
+        assert_eq!(limiter.check_operation(&guest, "user.search"), None);
+        assert_eq!(limiter.check_operation(&owner, "shop.search"), None);
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+use chrono::{DateTime, Duration, Utc};
+use crate::{
+    rate_limit::{Budget, Bucket},
+    state::db::{Pool, Connection},
+    error::Error,
+};
+
+// Buckets idle this long have refilled under any budget worth having, so
+// forgetting them changes nothing.
+const MAX_IDLE_MINUTES: i64 = 60;
+
+// Memory stores sweep idle buckets once they hold this many.
+const SWEEP_ABOVE: usize = 10_000;
+
+// Postgres stores sweep idle buckets every this many requests.
+const SWEEP_EVERY: usize = 1_000;
+
+pub trait RateLimitStore: Send + Sync {
+    // Take a token from the bucket `key`, full when first used. Returns the
+    // seconds until it has one when it is empty.
+    fn take(&self, key: &str, budget: Budget, now: DateTime<Utc>) -> Result<Option<u64>, Error>;
+}
+
+// Buckets of this server alone.
+pub struct MemoryStore {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore {
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl RateLimitStore for MemoryStore {
+    fn take(&self, key: &str, budget: Budget, now: DateTime<Utc>) -> Result<Option<u64>, Error> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > SWEEP_ABOVE {
+            let idle_since = now - Duration::minutes(MAX_IDLE_MINUTES);
+            buckets.retain(|_, bucket| bucket.updated_at >= idle_since);
+        }
+
+        Ok(buckets.entry(key.to_string())
+            .or_insert_with(|| Bucket::full(budget, now))
+            .take(budget, now))
+    }
+}
+
+// Buckets in the database, shared by every server using it.
+pub struct PostgresStore {
+    pool: Pool,
+    requests: AtomicUsize,
+}
+
+impl PostgresStore {
+    pub fn new(pool: Pool) -> Self {
+        PostgresStore {
+            pool: pool,
+            requests: AtomicUsize::new(0),
+        }
+    }
+
+    fn connection(&self) -> Result<Connection, Error> {
+        self.pool.get().map_err(|err| -> Error {
+            err.into()
+        })
+    }
+}
+
+impl RateLimitStore for PostgresStore {
+    fn take(&self, key: &str, budget: Budget, now: DateTime<Utc>) -> Result<Option<u64>, Error> {
+        let mut conn = self.connection()?;
+        if self.requests.fetch_add(1, Ordering::Relaxed) % SWEEP_EVERY == 0 {
+            conn.execute(
+                "DELETE FROM rate_limit_buckets WHERE updated_at < $1",
+                &[&(now - Duration::minutes(MAX_IDLE_MINUTES))],
+            )?;
+        }
+
+        let mut tx = conn.transaction()?;
+        let full = Bucket::full(budget, now);
+        tx.execute(
+            "INSERT INTO rate_limit_buckets (key, tokens, updated_at) VALUES ($1, $2, $3) ON CONFLICT (key) DO NOTHING",
+            &[&key, &full.tokens, &full.updated_at],
+        )?;
+        let (tokens, updated_at) = query_one!(
+            tx,
+            "SELECT tokens, updated_at FROM rate_limit_buckets WHERE key = $1 FOR UPDATE",
+            &[&key],
+            (tokens: f64, updated_at: DateTime<Utc>),
+        )?;
+
+        let mut bucket = Bucket {
+            tokens: tokens,
+            updated_at: updated_at,
+        };
+        let retry_after = bucket.take(budget, now);
+        tx.execute(
+            "UPDATE rate_limit_buckets SET tokens = $2, updated_at = $3 WHERE key = $1",
+            &[&key, &bucket.tokens, &bucket.updated_at],
+        )?;
+        tx.commit()?;
+        Ok(retry_after)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+    use crate::{
+        rate_limit::Budget,
+        tests::harness::TestDatabase,
+    };
+    use super::{RateLimitStore, MemoryStore, PostgresStore};
+
+    fn check_store(store: &dyn RateLimitStore) {
+        let budget = Budget::new(2, 60);
+        let now = Utc::now();
+
+        assert_eq!(store.take("a", budget, now).unwrap(), None);
+        assert_eq!(store.take("a", budget, now).unwrap(), None);
+        assert_eq!(store.take("a", budget, now).unwrap(), Some(1));
+        assert_eq!(store.take("b", budget, now).unwrap(), None);
+        assert_eq!(store.take("a", budget, now + Duration::seconds(1)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_memory_store() {
+        check_store(&MemoryStore::new());
+    }
+
+    #[test]
+    #[ignore]
+    fn test_postgres_store() {
+        let db = TestDatabase::create();
+        check_store(&PostgresStore::new(db.pool()));
+    }
+}
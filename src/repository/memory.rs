@@ -0,0 +1,1240 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+    sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+use uuid::Uuid;
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
+use crate::{
+    auth::lockout::{Lockout, LoginFailures},
+    graphql::{
+        analytics::{
+            Granularity,
+            SalesBucket,
+            ProductSales,
+            SelectionSales,
+            TOP_SALES,
+        },
+        hours::{Schedule, WeeklyHours, OpeningException},
+        user::User,
+        shop::{Shop, Coordinates, Product, Series, Inventory, Customize, Selection},
+        order::{self, Order, Cart, ProductItem, CustomizeItem},
+        search::{self, SearchHit},
+        locale::{Locale, TranslatedField},
+        money::Currency,
+        promotion::{self, Promotion, PromotionTerms, AppliedPromotion, Line},
+        tax::{TaxSettings, Charges},
+        audit::{AuditEntry, AuditLogFilter},
+        api_token::ApiToken,
+        role::{Role, Capabilities},
+    },
+    repository::{
+        NewItem,
+        NewAuditEntry,
+        NewApiToken,
+        SessionRepository,
+        CredentialRepository,
+        UserRepository,
+        ShopRepository,
+        CatalogRepository,
+        OrderRepository,
+        CartRepository,
+        PromotionRepository,
+        AuditLogRepository,
+        ApiTokenRepository,
+        RoleRepository,
+        Transactional,
+    },
+    error::Error,
+};
+
+#[derive(Clone)]
+pub struct UserRow {
+    pub id: Uuid,
+    pub username: String,
+    pub password: String,
+    pub nickname: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct UserSessionRow {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub expire_time: DateTime<Utc>,
+}
+
+#[derive(Clone)]
+pub struct GuestSessionRow {
+    pub id: Uuid,
+    pub expire_time: DateTime<Utc>,
+}
+
+#[derive(Clone)]
+pub struct SelectionRow {
+    pub key: Uuid,
+    pub name: String,
+    pub price: i32,
+}
+
+#[derive(Clone)]
+pub struct CustomizeRow {
+    pub key: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub latest_update: DateTime<Utc>,
+    pub selections: Vec<SelectionRow>,
+}
+
+#[derive(Clone)]
+pub struct ProductRow {
+    pub key: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub price: i32,
+    pub series_id: Option<Uuid>,
+    pub has_picture: bool,
+    pub latest_update: DateTime<Utc>,
+    pub customizes: Vec<CustomizeRow>,
+}
+
+#[derive(Clone)]
+pub struct SeriesRow {
+    pub id: Uuid,
+    pub name: String,
+    pub ordering: i32,
+    pub latest_update: DateTime<Utc>,
+}
+
+#[derive(Clone)]
+pub struct ShopRow {
+    pub id: Uuid,
+    pub name: String,
+    pub address: Option<String>,
+    pub coordinates: Option<Coordinates>,
+    pub schedule: Schedule,
+    pub currency: Currency,
+    pub taxes: TaxSettings,
+    pub latest_update: DateTime<Utc>,
+    pub series: Vec<SeriesRow>,
+    pub products: Vec<ProductRow>,
+}
+
+#[derive(Clone)]
+pub struct ShopUserRow {
+    pub shop_id: Uuid,
+    pub user_id: Uuid,
+    pub role_id: Uuid,
+}
+
+#[derive(Clone)]
+pub struct InventoryRow {
+    pub shop_id: Uuid,
+    pub key: Uuid,
+    pub stock: Option<i32>,
+    pub sold_out: bool,
+}
+
+#[derive(Clone)]
+pub struct TranslationRow {
+    pub shop_id: Uuid,
+    pub key: Uuid,
+    pub field: TranslatedField,
+    pub locale: Locale,
+    pub text: String,
+}
+
+#[derive(Clone)]
+pub struct CustomizeItemRow {
+    pub customize_key: Uuid,
+    pub name: String,
+    pub selection: Option<String>,
+    pub selection_key: Option<Uuid>,
+    pub selection_price: Option<i32>,
+    pub order_at: DateTime<Utc>,
+}
+
+#[derive(Clone)]
+pub struct ProductItemRow {
+    pub key: Uuid,
+    pub product_key: Uuid,
+    pub name: String,
+    pub price: i32,
+    pub count: i32,
+    pub remark: Option<String>,
+    pub order_at: DateTime<Utc>,
+    pub customizes: Vec<CustomizeItemRow>,
+}
+
+#[derive(Clone)]
+pub struct CartRow {
+    pub id: Uuid,
+    pub shop_id: Uuid,
+    pub guest_session_id: Uuid,
+    pub coupon_code: Option<String>,
+    pub items: Vec<ProductItemRow>,
+}
+
+#[derive(Clone)]
+pub struct OrderRow {
+    pub id: Uuid,
+    pub shop_id: Uuid,
+    pub guest_session_id: Uuid,
+    pub order_number: i32,
+    pub order_at: DateTime<Utc>,
+    pub items: Vec<ProductItemRow>,
+    pub promotions: Vec<AppliedPromotion>,
+    pub charges: Charges,
+}
+
+#[derive(Clone)]
+pub struct ApiTokenRow {
+    pub token: ApiToken,
+    pub token_hash: String,
+}
+
+// Tables mirroring the Postgres schema.
+#[derive(Clone, Default)]
+pub struct Data {
+    pub users: Vec<UserRow>,
+    pub user_sessions: Vec<UserSessionRow>,
+    pub guest_sessions: Vec<GuestSessionRow>,
+    pub shops: Vec<ShopRow>,
+    pub roles: Vec<Role>,
+    pub shop_users: Vec<ShopUserRow>,
+    pub inventory: Vec<InventoryRow>,
+    pub translations: Vec<TranslationRow>,
+    pub promotions: Vec<Promotion>,
+    pub carts: Vec<CartRow>,
+    pub orders: Vec<OrderRow>,
+    pub audit_log: Vec<AuditEntry>,
+    pub login_failures: HashMap<String, LoginFailures>,
+    pub api_tokens: Vec<ApiTokenRow>,
+}
+
+impl Data {
+    fn member_role(&self, member: &ShopUserRow) -> Option<Role> {
+        self.roles.iter().find(|role| role.id() == member.role_id).cloned()
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryRepository {
+    data: Arc<RwLock<Data>>,
+    // For repositories from `begin`, the data as it was before, put back
+    // unless committed. Changes others made in the meantime are lost with
+    // it, which is fine for tests and development.
+    undo: Mutex<Option<Data>>,
+}
+
+impl MemoryRepository {
+    pub fn new() -> Self {
+        MemoryRepository::default()
+    }
+
+    pub fn with_data(data: Data) -> Self {
+        MemoryRepository {
+            data: Arc::new(RwLock::new(data)),
+            undo: Mutex::new(None),
+        }
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<Data> {
+        self.data.read().unwrap()
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<Data> {
+        self.data.write().unwrap()
+    }
+}
+
+impl Transactional for MemoryRepository {
+    fn begin(&self) -> Result<Self, Error> {
+        Ok(MemoryRepository {
+            data: self.data.clone(),
+            undo: Mutex::new(Some(self.read().clone())),
+        })
+    }
+
+    fn commit(&self) -> Result<(), Error> {
+        self.undo.lock().unwrap().take();
+        Ok(())
+    }
+}
+
+impl Drop for MemoryRepository {
+    fn drop(&mut self) {
+        if let Some(data) = self.undo.lock().unwrap().take() {
+            *self.write() = data;
+        }
+    }
+}
+
+fn contains_ignore_case(value: &str, pattern: &str) -> bool {
+    value.to_uppercase().contains(&pattern.to_uppercase())
+}
+
+impl UserRow {
+    fn to_user(&self) -> User {
+        User::new(self.id, self.username.clone(), self.nickname.clone())
+    }
+}
+
+impl ShopRow {
+    fn to_shop(&self) -> Shop {
+        Shop::new(self.id, self.name.clone(), self.address.clone(), self.coordinates, self.latest_update)
+    }
+}
+
+impl SeriesRow {
+    fn to_series(&self) -> Series {
+        Series::new(self.id, self.name.clone(), self.ordering, self.latest_update)
+    }
+}
+
+impl Data {
+    // Untracked keys have no row and are always available.
+    fn inventory_of(&self, shop_id: Uuid, key: Uuid) -> Inventory {
+        self.inventory.iter()
+            .find(|row| row.shop_id == shop_id && row.key == key)
+            .map(|row| Inventory::new(row.stock, row.sold_out))
+            .unwrap_or_default()
+    }
+}
+
+impl ProductRow {
+    fn to_product(&self, shop_id: Uuid, data: &Data) -> Product {
+        let mut product = Product::new(
+            shop_id,
+            self.key,
+            self.name.clone(),
+            self.description.clone(),
+            self.price,
+            self.series_id,
+            self.has_picture,
+            data.inventory_of(shop_id, self.key),
+            self.latest_update,
+        );
+        for cus in self.customizes.iter() {
+            let customize = product.insert_customize_uncheck(
+                cus.key,
+                Customize::new(cus.key, cus.name.clone(), cus.description.clone(), cus.latest_update),
+            );
+            for sel in cus.selections.iter() {
+                customize.insert_selection_uncheck(
+                    sel.key,
+                    Selection::new(shop_id, sel.key, sel.name.clone(), sel.price, data.inventory_of(shop_id, sel.key)),
+                );
+            }
+        }
+        for row in data.translations.iter().filter(|row| row.shop_id == shop_id) {
+            product.translate(row.key, row.field, row.locale.clone(), row.text.clone());
+        }
+        product
+    }
+}
+
+impl ProductItemRow {
+    fn to_item(&self, shop_id: Uuid) -> ProductItem {
+        let mut item = ProductItem::new(
+            shop_id,
+            self.key,
+            self.product_key,
+            self.name.clone(),
+            self.price,
+            self.count,
+            self.remark.clone(),
+            self.order_at,
+        );
+        for cus in self.customizes.iter() {
+            item.insert_customize_uncheck(
+                cus.customize_key,
+                CustomizeItem::new(
+                    shop_id,
+                    cus.customize_key,
+                    cus.name.clone(),
+                    cus.selection.clone(),
+                    cus.selection_key,
+                    cus.selection_price,
+                    cus.order_at,
+                ),
+            );
+        }
+        item
+    }
+
+    fn amount(&self) -> Result<i32, Error> {
+        let unit_price = order::unit_price(self.price, self.customizes.iter().map(|cus| cus.selection_price))?;
+        order::subtotal(unit_price, self.count)
+    }
+}
+
+impl SessionRepository for MemoryRepository {
+    fn session_user(&self, user_session_id: Uuid) -> Result<Uuid, Error> {
+        self.read().user_sessions.iter()
+            .find(|session| session.id == user_session_id && session.expire_time > Utc::now())
+            .map(|session| session.user_id)
+            .ok_or_else(|| Error::session_expired("USSID"))
+    }
+
+    fn is_guest_session_valid(&self, guest_session_id: Uuid) -> Result<bool, Error> {
+        Ok(
+            self.read().guest_sessions.iter()
+                .any(|session| session.id == guest_session_id && session.expire_time > Utc::now())
+        )
+    }
+
+    fn create_user_session(&self, user_id: Uuid, expire_time: DateTime<Utc>) -> Result<Uuid, Error> {
+        let id = Uuid::new_v4();
+        self.write().user_sessions.push(UserSessionRow { id: id, user_id: user_id, expire_time: expire_time });
+        Ok(id)
+    }
+}
+
+impl CredentialRepository for MemoryRepository {
+    fn credentials(&self, username: &str) -> Result<Option<(Uuid, String)>, Error> {
+        Ok(
+            self.read().users.iter()
+                .find(|user| user.username == username)
+                .map(|user| (user.id, user.password.clone()))
+        )
+    }
+
+    fn set_password_hash(&self, user_id: Uuid, hash: String) -> Result<(), Error> {
+        let mut data = self.write();
+        let user = data.users.iter_mut()
+            .find(|user| user.id == user_id)
+            .ok_or_else(|| Error::not_found("User"))?;
+        user.password = hash;
+        Ok(())
+    }
+
+    fn login_failures(&self, key: &str) -> Result<Option<LoginFailures>, Error> {
+        Ok(self.read().login_failures.get(key).cloned())
+    }
+
+    fn take_login_attempt(&self, key: &str, lockout: &Lockout, now: DateTime<Utc>, forget_before: DateTime<Utc>) -> Result<bool, Error> {
+        let mut data = self.write();
+        let failures = data.login_failures.entry(key.to_string())
+            .or_insert(LoginFailures { count: 0, last_failed_at: now });
+        if lockout.locked_until(failures).map_or(false, |until| until > now) {
+            return Ok(false);
+        }
+        if failures.last_failed_at < forget_before {
+            failures.count = 0;
+        }
+        failures.count += 1;
+        failures.last_failed_at = now;
+        Ok(true)
+    }
+
+    fn release_login_attempt(&self, key: &str) -> Result<(), Error> {
+        let mut data = self.write();
+        if let Some(failures) = data.login_failures.get_mut(key) {
+            failures.count -= 1;
+            if failures.count <= 0 {
+                data.login_failures.remove(key);
+            }
+        }
+        Ok(())
+    }
+
+    fn clear_login_failures(&self, key: &str) -> Result<(), Error> {
+        self.write().login_failures.remove(key);
+        Ok(())
+    }
+}
+
+impl UserRepository for MemoryRepository {
+    fn user(&self, id: Uuid) -> Result<User, Error> {
+        self.read().users.iter()
+            .find(|user| user.id == id)
+            .map(UserRow::to_user)
+            .ok_or_else(|| Error::not_found("User"))
+    }
+
+    fn search(&self, id: Option<Uuid>, name: Option<String>) -> Result<Vec<User>, Error> {
+        Ok(
+            self.read().users.iter()
+                .filter(|user| id.map_or(true, |id| user.id == id))
+                .filter(|user| name.as_ref().map_or(true, |name| contains_ignore_case(&user.username, name)))
+                .map(UserRow::to_user)
+                .collect()
+        )
+    }
+}
+
+impl ShopRepository for MemoryRepository {
+    fn search(&self, id: Option<Uuid>, name: Option<String>) -> Result<Vec<Shop>, Error> {
+        Ok(
+            self.read().shops.iter()
+                .filter(|shop| id.map_or(true, |id| shop.id == id))
+                .filter(|shop| name.as_ref().map_or(true, |name| contains_ignore_case(&shop.name, name)))
+                .map(ShopRow::to_shop)
+                .collect()
+        )
+    }
+
+    fn user_shops(&self, user_id: Uuid, id: Option<Uuid>, name: Option<String>) -> Result<Vec<(Shop, Role)>, Error> {
+        let data = self.read();
+        Ok(
+            data.shops.iter()
+                .filter(|shop| id.map_or(true, |id| shop.id == id))
+                .filter(|shop| name.as_ref().map_or(true, |name| contains_ignore_case(&shop.name, name)))
+                .filter_map(|shop| {
+                    data.shop_users.iter()
+                        .find(|member| member.shop_id == shop.id && member.user_id == user_id)
+                        .and_then(|member| data.member_role(member))
+                        .map(|role| (shop.to_shop(), role))
+                })
+                .collect()
+        )
+    }
+
+    fn members(&self, shop_id: Uuid) -> Result<Vec<(User, Role)>, Error> {
+        let data = self.read();
+        Ok(
+            data.shop_users.iter()
+                .filter(|member| member.shop_id == shop_id)
+                .filter_map(|member| {
+                    let user = data.users.iter().find(|user| user.id == member.user_id)?;
+                    Some((user.to_user(), data.member_role(member)?))
+                })
+                .collect()
+        )
+    }
+
+    fn set_address(&self, shop_id: Uuid, address: Option<String>) -> Result<(), Error> {
+        let mut data = self.write();
+        let shop = data.shops.iter_mut()
+            .find(|shop| shop.id == shop_id)
+            .ok_or_else(|| Error::not_found("Shop"))?;
+
+        shop.address = address;
+        shop.latest_update = Utc::now();
+        Ok(())
+    }
+
+    fn set_coordinates(&self, shop_id: Uuid, coordinates: Option<Coordinates>) -> Result<(), Error> {
+        let mut data = self.write();
+        let shop = data.shops.iter_mut()
+            .find(|shop| shop.id == shop_id)
+            .ok_or_else(|| Error::not_found("Shop"))?;
+
+        shop.coordinates = coordinates;
+        shop.latest_update = Utc::now();
+        Ok(())
+    }
+
+    fn nearby(&self, center: Coordinates, radius: f64, limit: i32) -> Result<Vec<(Shop, f64)>, Error> {
+        let mut shops: Vec<(Shop, f64)> = self.read().shops.iter()
+            .filter_map(|shop| shop.coordinates.map(|coordinates| (shop, center.distance(&coordinates))))
+            .filter(|&(_, distance)| distance <= radius)
+            .map(|(shop, distance)| (shop.to_shop(), distance))
+            .collect();
+
+        shops.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap().then_with(|| a.0.name().cmp(b.0.name())));
+        shops.truncate(limit as usize);
+        Ok(shops)
+    }
+
+    fn schedule(&self, shop_id: Uuid) -> Result<Schedule, Error> {
+        self.read().shops.iter()
+            .find(|shop| shop.id == shop_id)
+            .map(|shop| shop.schedule.clone())
+            .ok_or_else(|| Error::not_found("Shop"))
+    }
+
+    fn set_opening_hours(&self, shop_id: Uuid, timezone: Tz, hours: Vec<WeeklyHours>) -> Result<(), Error> {
+        let mut data = self.write();
+        let shop = data.shops.iter_mut()
+            .find(|shop| shop.id == shop_id)
+            .ok_or_else(|| Error::not_found("Shop"))?;
+
+        shop.schedule.timezone = timezone;
+        shop.schedule.weekly = hours;
+        shop.latest_update = Utc::now();
+        Ok(())
+    }
+
+    fn set_opening_exception(&self, shop_id: Uuid, exception: OpeningException) -> Result<(), Error> {
+        let mut data = self.write();
+        let shop = data.shops.iter_mut()
+            .find(|shop| shop.id == shop_id)
+            .ok_or_else(|| Error::not_found("Shop"))?;
+
+        let exceptions = &mut shop.schedule.exceptions;
+        exceptions.retain(|existing| existing.date != exception.date);
+        exceptions.push(exception);
+        exceptions.sort_by_key(|exception| exception.date);
+        Ok(())
+    }
+
+    fn delete_opening_exception(&self, shop_id: Uuid, date: NaiveDate) -> Result<(), Error> {
+        let mut data = self.write();
+        let shop = data.shops.iter_mut()
+            .find(|shop| shop.id == shop_id)
+            .ok_or_else(|| Error::not_found("Shop"))?;
+
+        let exceptions = &mut shop.schedule.exceptions;
+        let count = exceptions.len();
+        exceptions.retain(|exception| exception.date != date);
+        if exceptions.len() == count {
+            Err(Error::not_found("Opening exception"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn set_paused_until(&self, shop_id: Uuid, paused_until: Option<DateTime<Utc>>) -> Result<(), Error> {
+        let mut data = self.write();
+        let shop = data.shops.iter_mut()
+            .find(|shop| shop.id == shop_id)
+            .ok_or_else(|| Error::not_found("Shop"))?;
+
+        shop.schedule.paused_until = paused_until;
+        Ok(())
+    }
+
+    fn currency(&self, shop_id: Uuid) -> Result<Currency, Error> {
+        self.read().shops.iter()
+            .find(|shop| shop.id == shop_id)
+            .map(|shop| shop.currency.clone())
+            .ok_or_else(|| Error::not_found("Shop"))
+    }
+
+    fn set_currency(&self, shop_id: Uuid, currency: Currency) -> Result<(), Error> {
+        let mut data = self.write();
+        let shop = data.shops.iter_mut()
+            .find(|shop| shop.id == shop_id)
+            .ok_or_else(|| Error::not_found("Shop"))?;
+
+        shop.currency = currency;
+        shop.latest_update = Utc::now();
+        Ok(())
+    }
+
+    fn tax_settings(&self, shop_id: Uuid) -> Result<TaxSettings, Error> {
+        self.read().shops.iter()
+            .find(|shop| shop.id == shop_id)
+            .map(|shop| shop.taxes.clone())
+            .ok_or_else(|| Error::not_found("Shop"))
+    }
+
+    fn set_tax_settings(&self, shop_id: Uuid, settings: TaxSettings) -> Result<(), Error> {
+        let mut data = self.write();
+        let shop = data.shops.iter_mut()
+            .find(|shop| shop.id == shop_id)
+            .ok_or_else(|| Error::not_found("Shop"))?;
+
+        shop.taxes = settings;
+        shop.latest_update = Utc::now();
+        Ok(())
+    }
+}
+
+impl CatalogRepository for MemoryRepository {
+    fn products(&self, shop_id: Uuid, key: Option<Uuid>, name: Option<String>, series_id: Option<Uuid>) -> Result<Vec<Product>, Error> {
+        let data = self.read();
+        Ok(
+            data.shops.iter()
+                .filter(|shop| shop.id == shop_id)
+                .flat_map(|shop| shop.products.iter())
+                .filter(|product| key.map_or(true, |key| product.key == key))
+                .filter(|product| name.as_ref().map_or(true, |name| contains_ignore_case(&product.name, name)))
+                .filter(|product| series_id.map_or(true, |series_id| product.series_id == Some(series_id)))
+                .map(|product| product.to_product(shop_id, &data))
+                .collect()
+        )
+    }
+
+    fn product_series(&self, shop_id: Uuid, product_keys: &[Uuid]) -> Result<HashMap<Uuid, Uuid>, Error> {
+        Ok(
+            self.read().shops.iter()
+                .filter(|shop| shop.id == shop_id)
+                .flat_map(|shop| shop.products.iter())
+                .filter(|product| product_keys.contains(&product.key))
+                .filter_map(|product| product.series_id.map(|series_id| (product.key, series_id)))
+                .collect()
+        )
+    }
+
+    fn set_has_picture(&self, shop_id: Uuid, product_key: Uuid, has_picture: bool) -> Result<(), Error> {
+        let mut data = self.write();
+        let shop = data.shops.iter_mut()
+            .find(|shop| shop.id == shop_id)
+            .ok_or_else(|| Error::not_found("Product"))?;
+        let product = shop.products.iter_mut()
+            .find(|product| product.key == product_key)
+            .ok_or_else(|| Error::not_found("Product"))?;
+
+        product.has_picture = has_picture;
+        product.latest_update = Utc::now();
+        shop.latest_update = product.latest_update;
+        Ok(())
+    }
+
+    fn series(&self, shop_id: Uuid, id: Option<Uuid>) -> Result<Vec<Series>, Error> {
+        let data = self.read();
+        let mut series: Vec<&SeriesRow> = data.shops.iter()
+            .filter(|shop| shop.id == shop_id)
+            .flat_map(|shop| shop.series.iter())
+            .filter(|series| id.map_or(true, |id| series.id == id))
+            .collect();
+        series.sort_by(|a, b| (a.ordering, &a.name).cmp(&(b.ordering, &b.name)));
+        Ok(series.into_iter().map(SeriesRow::to_series).collect())
+    }
+
+    fn create_series(&self, shop_id: Uuid, name: String, ordering: Option<i32>) -> Result<Uuid, Error> {
+        let mut data = self.write();
+        let shop = data.shops.iter_mut()
+            .find(|shop| shop.id == shop_id)
+            .ok_or_else(|| Error::not_found("Shop"))?;
+
+        let id = Uuid::new_v4();
+        let ordering = ordering.unwrap_or_else(|| {
+            shop.series.iter().map(|series| series.ordering + 1).max().unwrap_or(0)
+        });
+        shop.latest_update = Utc::now();
+        shop.series.push(SeriesRow {
+            id: id,
+            name: name,
+            ordering: ordering,
+            latest_update: shop.latest_update,
+        });
+        Ok(id)
+    }
+
+    fn update_series(&self, shop_id: Uuid, id: Uuid, name: Option<String>, ordering: Option<i32>) -> Result<(), Error> {
+        let mut data = self.write();
+        let shop = data.shops.iter_mut()
+            .find(|shop| shop.id == shop_id)
+            .ok_or_else(|| Error::not_found("Series"))?;
+        let series = shop.series.iter_mut()
+            .find(|series| series.id == id)
+            .ok_or_else(|| Error::not_found("Series"))?;
+
+        if let Some(name) = name {
+            series.name = name;
+        }
+        if let Some(ordering) = ordering {
+            series.ordering = ordering;
+        }
+        series.latest_update = Utc::now();
+        shop.latest_update = series.latest_update;
+        Ok(())
+    }
+
+    fn delete_series(&self, shop_id: Uuid, id: Uuid) -> Result<(), Error> {
+        let mut data = self.write();
+        let shop = data.shops.iter_mut()
+            .find(|shop| shop.id == shop_id && shop.series.iter().any(|series| series.id == id))
+            .ok_or_else(|| Error::not_found("Series"))?;
+
+        let now = Utc::now();
+        shop.series.retain(|series| series.id != id);
+        shop.taxes.series_rates.remove(&id);
+        for product in shop.products.iter_mut().filter(|product| product.series_id == Some(id)) {
+            product.series_id = None;
+            product.latest_update = now;
+        }
+        shop.latest_update = now;
+        Ok(())
+    }
+
+    fn set_product_series(&self, shop_id: Uuid, product_key: Uuid, series_id: Option<Uuid>) -> Result<(), Error> {
+        let mut data = self.write();
+        let shop = data.shops.iter_mut()
+            .find(|shop| shop.id == shop_id)
+            .ok_or_else(|| Error::not_found("Product or series"))?;
+        if let Some(series_id) = series_id {
+            if !shop.series.iter().any(|series| series.id == series_id) {
+                return Err(Error::not_found("Product or series"));
+            }
+        }
+        let product = shop.products.iter_mut()
+            .find(|product| product.key == product_key)
+            .ok_or_else(|| Error::not_found("Product or series"))?;
+
+        product.series_id = series_id;
+        product.latest_update = Utc::now();
+        shop.latest_update = product.latest_update;
+        Ok(())
+    }
+
+    fn set_inventory(&self, shop_id: Uuid, key: Uuid, inventory: Inventory) -> Result<(), Error> {
+        let mut data = self.write();
+        data.inventory.retain(|row| row.shop_id != shop_id || row.key != key);
+        if inventory != Inventory::default() {
+            data.inventory.push(InventoryRow {
+                shop_id: shop_id,
+                key: key,
+                stock: inventory.stock,
+                sold_out: inventory.sold_out,
+            });
+        }
+        Ok(())
+    }
+
+    fn set_translation(&self, shop_id: Uuid, key: Uuid, field: TranslatedField, locale: Locale, text: String) -> Result<(), Error> {
+        let mut data = self.write();
+        data.translations.retain(|row| (row.shop_id, row.key, row.field, &row.locale) != (shop_id, key, field, &locale));
+        data.translations.push(TranslationRow {
+            shop_id: shop_id,
+            key: key,
+            field: field,
+            locale: locale,
+            text: text,
+        });
+        Ok(())
+    }
+
+    fn delete_translation(&self, shop_id: Uuid, key: Uuid, field: TranslatedField, locale: Locale) -> Result<(), Error> {
+        let mut data = self.write();
+        let count = data.translations.len();
+        data.translations.retain(|row| (row.shop_id, row.key, row.field, &row.locale) != (shop_id, key, field, &locale));
+        if data.translations.len() == count {
+            return Err(Error::not_found("Translation"));
+        }
+        Ok(())
+    }
+
+    fn search(&self, query: String, shop_id: Option<Uuid>, limit: i32) -> Result<Vec<SearchHit>, Error> {
+        let data = self.read();
+        let mut hits: Vec<SearchHit> = Vec::new();
+        for shop in data.shops.iter().filter(|shop| shop_id.map_or(true, |id| shop.id == id)) {
+            if let Some(score) = search::score(&query, &shop.name, None) {
+                hits.push(SearchHit::new(shop.id, None, shop.name.clone(), None, score));
+            }
+            for product in shop.products.iter() {
+                if let Some(score) = search::score(&query, &product.name, product.description.as_ref().map(String::as_str)) {
+                    hits.push(SearchHit::new(shop.id, Some(product.key), product.name.clone(), product.description.clone(), score));
+                }
+            }
+        }
+
+        hits.sort_by(|a, b| b.score().partial_cmp(&a.score()).unwrap().then_with(|| a.name().cmp(b.name())));
+        hits.truncate(limit as usize);
+        Ok(hits)
+    }
+}
+
+impl OrderRepository for MemoryRepository {
+    fn orders(&self, shop_id: Option<Uuid>, guest_session_id: Option<Uuid>) -> Result<Vec<Order>, Error> {
+        Ok(
+            self.read().orders.iter()
+                .filter(|order| shop_id.map_or(true, |id| order.shop_id == id))
+                .filter(|order| guest_session_id.map_or(true, |id| order.guest_session_id == id))
+                .map(|row| {
+                    let mut order = Order::new(row.id, row.guest_session_id, row.shop_id, row.order_number, row.order_at);
+                    for item in row.items.iter() {
+                        order.insert_item_uncheck(item.key, item.to_item(row.shop_id));
+                    }
+                    for promotion in row.promotions.iter() {
+                        order.add_promotion(promotion.clone());
+                    }
+                    order.set_charges(row.charges.clone());
+                    order
+                })
+                .collect()
+        )
+    }
+
+    fn sales_summary(&self, shop_id: Uuid, from: DateTime<Utc>, to: DateTime<Utc>, granularity: Granularity, timezone: Tz) -> Result<Vec<SalesBucket>, Error> {
+        #[derive(Default)]
+        struct Tally {
+            revenue: i32,
+            order_count: i32,
+            item_count: i32,
+            products: BTreeMap<Uuid, (String, i32, i32)>,
+            selections: BTreeMap<(Uuid, Uuid), (String, Option<String>, i32)>,
+        }
+
+        let data = self.read();
+        let mut tallies: BTreeMap<DateTime<Utc>, Tally> = BTreeMap::new();
+        for order in data.orders.iter() {
+            if order.shop_id != shop_id || order.order_at < from || order.order_at >= to {
+                continue;
+            }
+
+            let tally = tallies.entry(granularity.truncate(order.order_at, timezone)).or_default();
+            tally.order_count += 1;
+            for item in order.items.iter() {
+                let amount = item.amount()?;
+                tally.revenue += amount;
+                tally.item_count += item.count;
+
+                let product = tally.products.entry(item.product_key).or_insert((item.name.clone(), 0, 0));
+                product.1 += item.count;
+                product.2 += amount;
+
+                for cus in item.customizes.iter() {
+                    if let Some(selection_key) = cus.selection_key {
+                        let selection = tally.selections
+                            .entry((cus.customize_key, selection_key))
+                            .or_insert((cus.name.clone(), cus.selection.clone(), 0));
+                        selection.2 += item.count;
+                    }
+                }
+            }
+        }
+
+        Ok(
+            tallies.into_iter().map(|(start, tally)| {
+                let mut bucket = SalesBucket::new(start, tally.revenue, tally.order_count, tally.item_count);
+
+                let mut products: Vec<_> = tally.products.into_iter().collect();
+                products.sort_by(|a, b| (b.1).1.cmp(&(a.1).1).then((b.1).2.cmp(&(a.1).2)));
+                for (product_key, (name, count, revenue)) in products.into_iter().take(TOP_SALES) {
+                    bucket.push_product(ProductSales::new(product_key, name, count, revenue));
+                }
+
+                let mut selections: Vec<_> = tally.selections.into_iter().collect();
+                selections.sort_by(|a, b| (b.1).2.cmp(&(a.1).2));
+                for ((customize_key, selection_key), (name, selection, count)) in selections.into_iter().take(TOP_SALES) {
+                    bucket.push_selection(SelectionSales::new(customize_key, selection_key, name, selection, count));
+                }
+
+                bucket
+            })
+            .collect()
+        )
+    }
+
+    fn export_orders(&self, shop_id: Uuid, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>, each: &mut dyn FnMut(Order) -> Result<(), Error>) -> Result<(), Error> {
+        let mut orders: Vec<Order> = self.orders(Some(shop_id), None)?.into_iter()
+            .filter(|order| from.map_or(true, |from| order.order_at() >= from))
+            .filter(|order| to.map_or(true, |to| order.order_at() < to))
+            .collect();
+        orders.sort_by_key(|order| (order.order_at(), order.order_number()));
+        for order in orders {
+            each(order)?;
+        }
+        Ok(())
+    }
+
+    fn place_order(&self, shop_id: Uuid, guest_session_id: Uuid) -> Result<Uuid, Error> {
+        let mut data = self.write();
+        let cart = data.carts.iter()
+            .position(|cart| cart.shop_id == shop_id && cart.guest_session_id == guest_session_id && !cart.items.is_empty())
+            .ok_or_else(|| Error::not_found("Cart"))?;
+
+        let mut demand: BTreeMap<Uuid, (String, i32)> = BTreeMap::new();
+        for item in data.carts[cart].items.iter() {
+            demand.entry(item.product_key).or_insert_with(|| (item.name.clone(), 0)).1 += item.count;
+            for cus in item.customizes.iter() {
+                if let (Some(key), Some(selection)) = (cus.selection_key, cus.selection.as_ref()) {
+                    demand.entry(key).or_insert_with(|| (selection.clone(), 0)).1 += item.count;
+                }
+            }
+        }
+        for (&key, (name, count)) in demand.iter() {
+            if !data.inventory_of(shop_id, key).allows(*count) {
+                return Err(Error::unavailable(name));
+            }
+        }
+
+        let lines: Vec<Line> = data.carts[cart].items.iter()
+            .map(|item| Line::try_from(&item.to_item(shop_id)))
+            .collect::<Result<Vec<Line>, Error>>()?;
+        let promotions: Vec<Promotion> = data.promotions.iter()
+            .filter(|promotion| promotion.shop_id() == shop_id)
+            .cloned()
+            .collect();
+        let code = data.carts[cart].coupon_code.as_ref().map(String::as_str);
+        let applied = promotion::redeem(&promotions, code, &lines, Utc::now())?;
+        let shop = data.shops.iter()
+            .find(|shop| shop.id == shop_id)
+            .ok_or_else(|| Error::not_found("Shop"))?;
+        let series: HashMap<Uuid, Uuid> = shop.products.iter()
+            .filter_map(|product| product.series_id.map(|series_id| (product.key, series_id)))
+            .collect();
+        let discount = applied.iter().map(AppliedPromotion::discount).sum();
+        let charges = shop.taxes.charges(shop_id, &lines, &series, discount);
+
+        for row in data.inventory.iter_mut().filter(|row| row.shop_id == shop_id) {
+            if let (Some(stock), Some((_, count))) = (row.stock.as_mut(), demand.get(&row.key)) {
+                *stock -= count;
+            }
+        }
+        for promotion in data.promotions.iter_mut() {
+            if applied.iter().any(|applied| applied.promotion_id() == promotion.id()) {
+                promotion.count_use();
+            }
+        }
+
+        let cart = data.carts.remove(cart);
+        let order_number = data.orders.iter()
+            .filter(|order| order.shop_id == shop_id)
+            .map(|order| order.order_number)
+            .max()
+            .unwrap_or(0) + 1;
+        let id = Uuid::new_v4();
+        data.orders.push(OrderRow {
+            id: id,
+            shop_id: shop_id,
+            guest_session_id: guest_session_id,
+            order_number: order_number,
+            order_at: Utc::now(),
+            items: cart.items,
+            promotions: applied,
+            charges: charges,
+        });
+        Ok(id)
+    }
+}
+
+impl CartRepository for MemoryRepository {
+    fn carts(&self, shop_id: Option<Uuid>, guest_session_id: Option<Uuid>) -> Result<Vec<Cart>, Error> {
+        Ok(
+            self.read().carts.iter()
+                .filter(|cart| shop_id.map_or(true, |id| cart.shop_id == id))
+                .filter(|cart| guest_session_id.map_or(true, |id| cart.guest_session_id == id))
+                .map(|row| {
+                    let mut cart = Cart::new(row.id, row.shop_id, row.guest_session_id, row.coupon_code.clone());
+                    for item in row.items.iter() {
+                        cart.insert_item_uncheck(item.key, item.to_item(row.shop_id));
+                    }
+                    cart
+                })
+                .collect()
+        )
+    }
+
+    fn add_item(&self, shop_id: Uuid, guest_session_id: Uuid, item: NewItem) -> Result<Uuid, Error> {
+        let mut data = self.write();
+        let now = Utc::now();
+        let key = Uuid::new_v4();
+        let row = ProductItemRow {
+            key: key,
+            product_key: item.product_key,
+            name: item.name,
+            price: item.price,
+            count: item.count,
+            remark: item.remark,
+            order_at: now,
+            customizes: item.customizes.into_iter()
+                .map(|cus| CustomizeItemRow {
+                    customize_key: cus.customize_key,
+                    name: cus.name,
+                    selection: cus.selection,
+                    selection_key: cus.selection_key,
+                    selection_price: cus.selection_price,
+                    order_at: now,
+                })
+                .collect(),
+        };
+
+        if let Some(cart) = data.carts.iter_mut().find(|cart| cart.shop_id == shop_id && cart.guest_session_id == guest_session_id) {
+            cart.items.push(row);
+        } else {
+            data.carts.push(CartRow {
+                id: Uuid::new_v4(),
+                shop_id: shop_id,
+                guest_session_id: guest_session_id,
+                coupon_code: None,
+                items: vec![row],
+            });
+        }
+        Ok(key)
+    }
+
+    fn set_coupon_code(&self, shop_id: Uuid, guest_session_id: Uuid, coupon_code: Option<String>) -> Result<(), Error> {
+        let mut data = self.write();
+        if let Some(cart) = data.carts.iter_mut().find(|cart| cart.shop_id == shop_id && cart.guest_session_id == guest_session_id) {
+            cart.coupon_code = coupon_code;
+        } else if coupon_code.is_some() {
+            data.carts.push(CartRow {
+                id: Uuid::new_v4(),
+                shop_id: shop_id,
+                guest_session_id: guest_session_id,
+                coupon_code: coupon_code,
+                items: vec![],
+            });
+        }
+        Ok(())
+    }
+}
+
+impl PromotionRepository for MemoryRepository {
+    fn promotions(&self, shop_id: Uuid, id: Option<Uuid>) -> Result<Vec<Promotion>, Error> {
+        Ok(
+            self.read().promotions.iter()
+                .filter(|promotion| promotion.shop_id() == shop_id)
+                .filter(|promotion| id.map_or(true, |id| promotion.id() == id))
+                .cloned()
+                .collect()
+        )
+    }
+
+    fn create_promotion(&self, shop_id: Uuid, terms: PromotionTerms) -> Result<Uuid, Error> {
+        let mut data = self.write();
+        if !data.shops.iter().any(|shop| shop.id == shop_id) {
+            return Err(Error::not_found("Shop"));
+        }
+        let taken = terms.code.is_some() && data.promotions.iter()
+            .any(|promotion| promotion.shop_id() == shop_id && promotion.code() == terms.code.as_ref().map(String::as_str));
+        if taken {
+            return Err(Error::invalid_input("The shop already has a promotion with this code."));
+        }
+
+        let id = Uuid::new_v4();
+        data.promotions.push(Promotion::new(id, shop_id, terms, 0));
+        Ok(id)
+    }
+
+    fn delete_promotion(&self, shop_id: Uuid, id: Uuid) -> Result<(), Error> {
+        let mut data = self.write();
+        let count = data.promotions.len();
+        data.promotions.retain(|promotion| (promotion.shop_id(), promotion.id()) != (shop_id, id));
+        if data.promotions.len() == count {
+            return Err(Error::not_found("Promotion"));
+        }
+        Ok(())
+    }
+}
+
+impl AuditLogRepository for MemoryRepository {
+    fn record(&self, shop_id: Uuid, entry: NewAuditEntry) -> Result<(), Error> {
+        let mut data = self.write();
+        if !data.shops.iter().any(|shop| shop.id == shop_id) {
+            return Err(Error::not_found("Shop"));
+        }
+
+        data.audit_log.push(AuditEntry::new(Uuid::new_v4(), shop_id, entry, Utc::now()));
+        Ok(())
+    }
+
+    fn audit_log(&self, shop_id: Uuid, filter: &AuditLogFilter, offset: i32, limit: i32) -> Result<(Vec<AuditEntry>, i32), Error> {
+        let data = self.read();
+        let matching: Vec<&AuditEntry> = data.audit_log.iter()
+            .rev()
+            .filter(|entry| entry.shop_id() == shop_id && entry.matches(filter))
+            .collect();
+
+        Ok((
+            matching.iter()
+                .skip(offset as usize)
+                .take(limit as usize)
+                .map(|&entry| entry.clone())
+                .collect(),
+            matching.len() as i32,
+        ))
+    }
+}
+
+impl ApiTokenRepository for MemoryRepository {
+    fn api_tokens(&self, user_id: Uuid) -> Result<Vec<ApiToken>, Error> {
+        Ok(
+            self.read().api_tokens.iter()
+                .filter(|row| row.token.user_id() == user_id)
+                .map(|row| row.token.clone())
+                .collect()
+        )
+    }
+
+    fn find_api_token(&self, token_hash: &str) -> Result<Option<ApiToken>, Error> {
+        Ok(
+            self.read().api_tokens.iter()
+                .find(|row| row.token_hash == token_hash)
+                .map(|row| row.token.clone())
+        )
+    }
+
+    fn create_api_token(&self, token: NewApiToken) -> Result<Uuid, Error> {
+        let mut data = self.write();
+        if let Some(shop_id) = token.shop_id {
+            if !data.shops.iter().any(|shop| shop.id == shop_id) {
+                return Err(Error::not_found("Shop"));
+            }
+        }
+
+        let id = Uuid::new_v4();
+        data.api_tokens.push(ApiTokenRow {
+            token: ApiToken::new(id, token.user_id, token.shop_id, token.name, token.authority, Utc::now(), token.expires_at),
+            token_hash: token.token_hash,
+        });
+        Ok(id)
+    }
+
+    fn revoke_api_token(&self, user_id: Uuid, id: Uuid) -> Result<(), Error> {
+        let mut data = self.write();
+        let count = data.api_tokens.len();
+        data.api_tokens.retain(|row| (row.token.user_id(), row.token.id()) != (user_id, id));
+        if data.api_tokens.len() == count {
+            return Err(Error::not_found("API token"));
+        }
+        Ok(())
+    }
+}
+
+impl RoleRepository for MemoryRepository {
+    fn roles(&self, shop_id: Uuid, id: Option<Uuid>) -> Result<Vec<Role>, Error> {
+        let mut roles: Vec<Role> = self.read().roles.iter()
+            .filter(|role| role.shop_id() == shop_id && id.map_or(true, |id| role.id() == id))
+            .cloned()
+            .collect();
+        roles.sort_by(|a, b| a.name().cmp(b.name()));
+        Ok(roles)
+    }
+
+    fn create_role(&self, shop_id: Uuid, name: String, capabilities: &Capabilities) -> Result<Uuid, Error> {
+        let mut data = self.write();
+        if !data.shops.iter().any(|shop| shop.id == shop_id) {
+            return Err(Error::not_found("Shop"));
+        }
+        if data.roles.iter().any(|role| role.shop_id() == shop_id && role.name() == name) {
+            return Err(Error::invalid_input("The shop already has a role with this name."));
+        }
+
+        let id = Uuid::new_v4();
+        data.roles.push(Role::new(id, shop_id, name, capabilities.clone()));
+        Ok(id)
+    }
+
+    fn update_role(&self, shop_id: Uuid, id: Uuid, name: Option<String>, capabilities: Option<&Capabilities>) -> Result<(), Error> {
+        let mut data = self.write();
+        let taken = name.as_ref().map_or(false, |name| {
+            data.roles.iter().any(|role| role.shop_id() == shop_id && role.id() != id && role.name() == name)
+        });
+        let role = data.roles.iter_mut()
+            .find(|role| role.shop_id() == shop_id && role.id() == id)
+            .ok_or_else(|| Error::not_found("Role"))?;
+        if taken {
+            return Err(Error::invalid_input("The shop already has a role with this name."));
+        }
+
+        *role = Role::new(
+            id,
+            shop_id,
+            name.unwrap_or_else(|| role.name().to_string()),
+            capabilities.cloned().unwrap_or_else(|| role.capabilities().clone()),
+        );
+        Ok(())
+    }
+
+    fn delete_role(&self, shop_id: Uuid, id: Uuid) -> Result<(), Error> {
+        let mut data = self.write();
+        if !data.roles.iter().any(|role| role.shop_id() == shop_id && role.id() == id) {
+            return Err(Error::not_found("Role"));
+        }
+        if data.shop_users.iter().any(|member| member.shop_id == shop_id && member.role_id == id) {
+            return Err(Error::invalid_input("Members still have this role."));
+        }
+
+        data.roles.retain(|role| role.id() != id);
+        Ok(())
+    }
+
+    fn set_member_role(&self, shop_id: Uuid, user_id: Uuid, role_id: Uuid) -> Result<(), Error> {
+        let mut data = self.write();
+        if !data.roles.iter().any(|role| role.shop_id() == shop_id && role.id() == role_id) {
+            return Err(Error::not_found("Role"));
+        }
+        let member = data.shop_users.iter_mut()
+            .find(|member| member.shop_id == shop_id && member.user_id == user_id)
+            .ok_or_else(|| Error::not_found("Member"))?;
+        member.role_id = role_id;
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use uuid::Uuid;
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
+use serde_json::Value;
+use crate::{
+    auth::lockout::{Lockout, LoginFailures},
+    graphql::{
+        analytics::{Granularity, SalesBucket},
+        hours::{Schedule, WeeklyHours, OpeningException},
+        search::SearchHit,
+        locale::{Locale, TranslatedField},
+        money::Currency,
+        promotion::{Promotion, PromotionTerms},
+        tax::TaxSettings,
+        audit::{AuditEntity, AuditAction, AuditEntry, AuditLogFilter},
+        api_token::ApiToken,
+        role::{Role, Capabilities},
+        user::{User, Authority},
+        shop::{Shop, Coordinates, Product, Series, Inventory},
+        order::{Order, Cart},
+    },
+    error::Error,
+};
+
+pub mod postgres;
+pub mod memory;
+
+// Stores that can make several changes as one.
+pub trait Transactional: Send + Sync {
+    // Repositories over the same store whose changes are only kept once
+    // committed, and undone if they are dropped before. Transactions begun
+    // from them are nested in theirs.
+    fn begin(&self) -> Result<Self, Error> where Self: Sized;
+
+    fn commit(&self) -> Result<(), Error>;
+}
+
+// A store implementing every repository, such as those behind `State`.
+pub trait Repository: SessionRepository + CredentialRepository + UserRepository + ShopRepository + CatalogRepository + OrderRepository + CartRepository + PromotionRepository + AuditLogRepository + ApiTokenRepository + RoleRepository + Transactional + 'static {}
+
+impl<R> Repository for R
+where
+    R: SessionRepository + CredentialRepository + UserRepository + ShopRepository + CatalogRepository + OrderRepository + CartRepository + PromotionRepository + AuditLogRepository + ApiTokenRepository + RoleRepository + Transactional + 'static,
+{}
+
+// A product put into a cart, priced as it was when added.
+pub struct NewItem {
+    pub product_key: Uuid,
+    pub name: String,
+    pub price: i32,
+    pub count: i32,
+    pub remark: Option<String>,
+    pub customizes: Vec<NewCustomizeItem>,
+}
+
+pub struct NewCustomizeItem {
+    pub customize_key: Uuid,
+    pub name: String,
+    pub selection: Option<String>,
+    pub selection_key: Option<Uuid>,
+    pub selection_price: Option<i32>,
+}
+
+// A change to record in a shop's audit log, with the entity as it was before
+// and after as JSON.
+pub struct NewAuditEntry {
+    pub actor_id: Uuid,
+    pub entity: AuditEntity,
+    pub entity_id: Uuid,
+    pub action: AuditAction,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+// An API token to store, with the hash of its secret.
+pub struct NewApiToken {
+    pub user_id: Uuid,
+    pub shop_id: Option<Uuid>,
+    pub name: String,
+    pub token_hash: String,
+    pub authority: Authority,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+pub trait SessionRepository: Send + Sync {
+    // Resolve a user session into its user, failing with `SessionExpired`.
+    fn session_user(&self, user_session_id: Uuid) -> Result<Uuid, Error>;
+
+    fn is_guest_session_valid(&self, guest_session_id: Uuid) -> Result<bool, Error>;
+
+    fn create_user_session(&self, user_id: Uuid, expire_time: DateTime<Utc>) -> Result<Uuid, Error>;
+}
+
+pub trait CredentialRepository: Send + Sync {
+    // The user with `username` and their stored password.
+    fn credentials(&self, username: &str) -> Result<Option<(Uuid, String)>, Error>;
+
+    fn set_password_hash(&self, user_id: Uuid, hash: String) -> Result<(), Error>;
+
+    fn login_failures(&self, key: &str) -> Result<Option<LoginFailures>, Error>;
+
+    // Counts an attempt against `key` as a failure, starting over when the
+    // last one was before `forget_before`, unless `lockout` holds `key` at
+    // `now`. Checked and counted at once, so attempts at the same time can't
+    // all get in under the limit. False when locked out.
+    fn take_login_attempt(&self, key: &str, lockout: &Lockout, now: DateTime<Utc>, forget_before: DateTime<Utc>) -> Result<bool, Error>;
+
+    // Takes back an attempt that turned out to succeed.
+    fn release_login_attempt(&self, key: &str) -> Result<(), Error>;
+
+    fn clear_login_failures(&self, key: &str) -> Result<(), Error>;
+}
+
+pub trait UserRepository: Send + Sync {
+    fn user(&self, id: Uuid) -> Result<User, Error>;
+
+    fn search(&self, id: Option<Uuid>, name: Option<String>) -> Result<Vec<User>, Error>;
+}
+
+pub trait ShopRepository: Send + Sync {
+    fn search(&self, id: Option<Uuid>, name: Option<String>) -> Result<Vec<Shop>, Error>;
+
+    // Shops the user is a member of, along with the user's role in each.
+    fn user_shops(&self, user_id: Uuid, id: Option<Uuid>, name: Option<String>) -> Result<Vec<(Shop, Role)>, Error>;
+
+    fn members(&self, shop_id: Uuid) -> Result<Vec<(User, Role)>, Error>;
+
+    // Fails with `NotFound` when there is no such shop.
+    fn set_address(&self, shop_id: Uuid, address: Option<String>) -> Result<(), Error>;
+
+    // Fails with `NotFound` when there is no such shop.
+    fn set_coordinates(&self, shop_id: Uuid, coordinates: Option<Coordinates>) -> Result<(), Error>;
+
+    // Shops within `radius` meters of `center` with their distance in
+    // meters, nearest first.
+    fn nearby(&self, center: Coordinates, radius: f64, limit: i32) -> Result<Vec<(Shop, f64)>, Error>;
+
+    // Fails with `NotFound` when there is no such shop.
+    fn schedule(&self, shop_id: Uuid) -> Result<Schedule, Error>;
+
+    // Replaces the shop's time zone and all of its weekly hours.
+    fn set_opening_hours(&self, shop_id: Uuid, timezone: Tz, hours: Vec<WeeklyHours>) -> Result<(), Error>;
+
+    // Replaces any exception on the same date.
+    fn set_opening_exception(&self, shop_id: Uuid, exception: OpeningException) -> Result<(), Error>;
+
+    // Fails with `NotFound` when the date has no exception.
+    fn delete_opening_exception(&self, shop_id: Uuid, date: NaiveDate) -> Result<(), Error>;
+
+    fn set_paused_until(&self, shop_id: Uuid, paused_until: Option<DateTime<Utc>>) -> Result<(), Error>;
+
+    // Fails with `NotFound` when there is no such shop.
+    fn currency(&self, shop_id: Uuid) -> Result<Currency, Error>;
+
+    // Prices are kept as they are, so they read in the new currency's minor units.
+    fn set_currency(&self, shop_id: Uuid, currency: Currency) -> Result<(), Error>;
+
+    // Fails with `NotFound` when there is no such shop.
+    fn tax_settings(&self, shop_id: Uuid) -> Result<TaxSettings, Error>;
+
+    fn set_tax_settings(&self, shop_id: Uuid, settings: TaxSettings) -> Result<(), Error>;
+}
+
+pub trait CatalogRepository: Send + Sync {
+    fn products(&self, shop_id: Uuid, key: Option<Uuid>, name: Option<String>, series_id: Option<Uuid>) -> Result<Vec<Product>, Error>;
+
+    // The series of each of `product_keys` that has one.
+    fn product_series(&self, shop_id: Uuid, product_keys: &[Uuid]) -> Result<HashMap<Uuid, Uuid>, Error>;
+
+    // Fails with `NotFound` when the shop has no such product.
+    fn set_has_picture(&self, shop_id: Uuid, product_key: Uuid, has_picture: bool) -> Result<(), Error>;
+
+    // Ordered by `ordering`, then by name.
+    fn series(&self, shop_id: Uuid, id: Option<Uuid>) -> Result<Vec<Series>, Error>;
+
+    // Without an `ordering` the series goes after the last one.
+    fn create_series(&self, shop_id: Uuid, name: String, ordering: Option<i32>) -> Result<Uuid, Error>;
+
+    fn update_series(&self, shop_id: Uuid, id: Uuid, name: Option<String>, ordering: Option<i32>) -> Result<(), Error>;
+
+    // Products of the series are left without one, and the shop's tax rate
+    // for it is dropped.
+    fn delete_series(&self, shop_id: Uuid, id: Uuid) -> Result<(), Error>;
+
+    // Fails with `NotFound` when the shop has no such product or series.
+    fn set_product_series(&self, shop_id: Uuid, product_key: Uuid, series_id: Option<Uuid>) -> Result<(), Error>;
+
+    // `key` is a product or selection key.
+    fn set_inventory(&self, shop_id: Uuid, key: Uuid, inventory: Inventory) -> Result<(), Error>;
+
+    // `key` is a product, customize or selection key. Replaces any
+    // translation of the field in the same locale.
+    fn set_translation(&self, shop_id: Uuid, key: Uuid, field: TranslatedField, locale: Locale, text: String) -> Result<(), Error>;
+
+    // Fails with `NotFound` when there is no such translation.
+    fn delete_translation(&self, shop_id: Uuid, key: Uuid, field: TranslatedField, locale: Locale) -> Result<(), Error>;
+
+    // Shops and products matching `query`, most relevant first.
+    fn search(&self, query: String, shop_id: Option<Uuid>, limit: i32) -> Result<Vec<SearchHit>, Error>;
+}
+
+pub trait OrderRepository: Send + Sync {
+    fn orders(&self, shop_id: Option<Uuid>, guest_session_id: Option<Uuid>) -> Result<Vec<Order>, Error>;
+
+    // Gross sales of orders placed in `[from, to)`, bucketed by `granularity`
+    // in `timezone`. Buckets without orders are left out.
+    fn sales_summary(&self, shop_id: Uuid, from: DateTime<Utc>, to: DateTime<Utc>, granularity: Granularity, timezone: Tz) -> Result<Vec<SalesBucket>, Error>;
+
+    // Orders of the shop placed in `[from, to)`, oldest first, handed to
+    // `each` one at a time so exports need not hold them all.
+    fn export_orders(&self, shop_id: Uuid, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>, each: &mut dyn FnMut(Order) -> Result<(), Error>) -> Result<(), Error>;
+
+    // Turn the guest's cart into an order and take its items out of stock,
+    // all or nothing. Fails with `ItemUnavailable` when stock runs short and
+    // with `InvalidCoupon` when the cart's coupon can't be used. Promotions
+    // the order gets are snapshotted onto it and count towards their limits,
+    // and its service charge and taxes are worked out and kept with it.
+    fn place_order(&self, shop_id: Uuid, guest_session_id: Uuid) -> Result<Uuid, Error>;
+}
+
+pub trait CartRepository: Send + Sync {
+    fn carts(&self, shop_id: Option<Uuid>, guest_session_id: Option<Uuid>) -> Result<Vec<Cart>, Error>;
+
+    // Creates the cart on first use and returns the new item's key.
+    fn add_item(&self, shop_id: Uuid, guest_session_id: Uuid, item: NewItem) -> Result<Uuid, Error>;
+
+    // Creates the cart on first use when setting a code.
+    fn set_coupon_code(&self, shop_id: Uuid, guest_session_id: Uuid, coupon_code: Option<String>) -> Result<(), Error>;
+}
+
+pub trait PromotionRepository: Send + Sync {
+    // Oldest first, the order their discounts apply in.
+    fn promotions(&self, shop_id: Uuid, id: Option<Uuid>) -> Result<Vec<Promotion>, Error>;
+
+    // Fails with `InvalidInput` when the shop has a promotion with the same code.
+    fn create_promotion(&self, shop_id: Uuid, terms: PromotionTerms) -> Result<Uuid, Error>;
+
+    // Orders keep their snapshots of the promotion.
+    fn delete_promotion(&self, shop_id: Uuid, id: Uuid) -> Result<(), Error>;
+}
+
+pub trait AuditLogRepository: Send + Sync {
+    fn record(&self, shop_id: Uuid, entry: NewAuditEntry) -> Result<(), Error>;
+
+    // Entries matching `filter`, newest first, skipping `offset` and taking
+    // at most `limit`, along with how many match in all.
+    fn audit_log(&self, shop_id: Uuid, filter: &AuditLogFilter, offset: i32, limit: i32) -> Result<(Vec<AuditEntry>, i32), Error>;
+}
+
+pub trait ApiTokenRepository: Send + Sync {
+    // The user's tokens, oldest first.
+    fn api_tokens(&self, user_id: Uuid) -> Result<Vec<ApiToken>, Error>;
+
+    // The token with the hash, expired or not.
+    fn find_api_token(&self, token_hash: &str) -> Result<Option<ApiToken>, Error>;
+
+    fn create_api_token(&self, token: NewApiToken) -> Result<Uuid, Error>;
+
+    // Fails with `NotFound` unless the token is the user's.
+    fn revoke_api_token(&self, user_id: Uuid, id: Uuid) -> Result<(), Error>;
+}
+
+pub trait RoleRepository: Send + Sync {
+    // Ordered by name.
+    fn roles(&self, shop_id: Uuid, id: Option<Uuid>) -> Result<Vec<Role>, Error>;
+
+    // Fails with `InvalidInput` when the shop has a role with the same name.
+    fn create_role(&self, shop_id: Uuid, name: String, capabilities: &Capabilities) -> Result<Uuid, Error>;
+
+    // Fails with `InvalidInput` when the shop has another role with the name.
+    fn update_role(&self, shop_id: Uuid, id: Uuid, name: Option<String>, capabilities: Option<&Capabilities>) -> Result<(), Error>;
+
+    // Fails with `InvalidInput` while members have the role.
+    fn delete_role(&self, shop_id: Uuid, id: Uuid) -> Result<(), Error>;
+
+    // Fails with `NotFound` unless the user is a member of the shop.
+    fn set_member_role(&self, shop_id: Uuid, user_id: Uuid, role_id: Uuid) -> Result<(), Error>;
+}
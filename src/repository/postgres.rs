@@ -0,0 +1,2257 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    ops::{Deref, DerefMut},
+    sync::{
+        Arc,
+        Mutex,
+        MutexGuard,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+use uuid::Uuid;
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use chrono_tz::Tz;
+use postgres::{Client, Row, types::ToSql};
+use crate::{
+    sql::{
+        UuidNN,
+        clause::{Clause, like_pattern},
+    },
+    auth::lockout::{Lockout, LoginFailures},
+    graphql::{
+        analytics::{
+            Granularity,
+            SalesBucket,
+            ProductSales,
+            SelectionSales,
+            TOP_SALES,
+        },
+        hours::{Day, Interval, Schedule, WeeklyHours, OpeningException},
+        user::{User, Authority},
+        shop::{Shop, Coordinates, Product, Series, Inventory, Customize, Selection},
+        order::{self, Order, Cart, ProductItem, CustomizeItem},
+        search::SearchHit,
+        locale::{Locale, TranslatedField},
+        money::Currency,
+        promotion::{self, Promotion, PromotionTerms, Reward, AppliedPromotion, Line},
+        tax::{TaxSettings, Charges, TaxLine},
+        audit::{AuditEntity, AuditAction, AuditEntry, AuditLogFilter},
+        api_token::ApiToken,
+        role::{Role, Capabilities},
+    },
+    repository::{
+        NewItem,
+        NewAuditEntry,
+        NewApiToken,
+        SessionRepository,
+        CredentialRepository,
+        UserRepository,
+        ShopRepository,
+        CatalogRepository,
+        OrderRepository,
+        CartRepository,
+        PromotionRepository,
+        AuditLogRepository,
+        ApiTokenRepository,
+        RoleRepository,
+        Transactional,
+    },
+    state::db::{Pool, Connection},
+    error::Error,
+    utils::dict::Dict,
+};
+
+// Orders of a shop placed in `[$2, $3)` with their items, tagged with the
+// `date_trunc($4, ...)` bucket they fall in.
+const SALES_ITEMS: &str = "
+    query_orders AS (
+        SELECT id, date_trunc($4, order_at AT TIME ZONE $5) AT TIME ZONE $5 bucket, items
+        FROM orders
+        WHERE shop_id = $1 AND order_at >= $2 AND order_at < $3
+    ),
+    query_items AS (
+        SELECT bucket, key item_key, value::PRODUCT_ITEM item FROM query_orders, each(items)
+    ),
+    priced_items AS (
+        SELECT
+            bucket,
+            item,
+            ((item).price + COALESCE(selection_price, 0)) * (item).count amount
+        FROM
+            query_items
+        LEFT JOIN LATERAL
+            (SELECT sum((customize).price) selection_price FROM query_product_item_customize_items(item)) customizes
+        ON TRUE
+    )";
+
+pub struct PostgresRepository {
+    pool: Pool,
+    // For repositories from `begin`, the connection every query goes through
+    // and how deeply the transaction is nested in others on it.
+    transaction: Option<(Arc<Mutex<Connection>>, usize)>,
+    committed: AtomicBool,
+}
+
+impl PostgresRepository {
+    pub fn new(pool: Pool) -> Self {
+        PostgresRepository {
+            pool: pool,
+            transaction: None,
+            committed: AtomicBool::new(false),
+        }
+    }
+
+    fn connection(&self) -> Result<Conn, Error> {
+        match self.transaction.as_ref() {
+            Some((conn, _)) => Ok(Conn::Held(conn.lock().unwrap())),
+            None => self.pooled().map(Conn::Pooled),
+        }
+    }
+
+    // A connection of its own, outside any transaction.
+    fn pooled(&self) -> Result<Connection, Error> {
+        self.pool.get().map_err(|err| -> Error {
+            err.into()
+        })
+    }
+}
+
+// Transactions nested in others are savepoints.
+fn transaction_statement(depth: usize, outer: &str, nested: &str) -> String {
+    if depth == 0 {
+        outer.to_string()
+    } else {
+        format!("{} sp{}", nested, depth)
+    }
+}
+
+impl Transactional for PostgresRepository {
+    fn begin(&self) -> Result<Self, Error> {
+        let (conn, depth) = match self.transaction.as_ref() {
+            Some((conn, depth)) => (conn.clone(), depth + 1),
+            None => (Arc::new(Mutex::new(self.pooled()?)), 0),
+        };
+        conn.lock().unwrap().batch_execute(&transaction_statement(depth, "BEGIN", "SAVEPOINT"))?;
+        Ok(PostgresRepository {
+            pool: self.pool.clone(),
+            transaction: Some((conn, depth)),
+            committed: AtomicBool::new(false),
+        })
+    }
+
+    fn commit(&self) -> Result<(), Error> {
+        if let Some((conn, depth)) = self.transaction.as_ref() {
+            conn.lock().unwrap().batch_execute(&transaction_statement(*depth, "COMMIT", "RELEASE SAVEPOINT"))?;
+            self.committed.store(true, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PostgresRepository {
+    fn drop(&mut self) {
+        if let Some((conn, depth)) = self.transaction.as_ref() {
+            if !self.committed.load(Ordering::SeqCst) {
+                let rollback = transaction_statement(*depth, "ROLLBACK", "ROLLBACK TO SAVEPOINT");
+                if let Err(err) = conn.lock().unwrap().batch_execute(&rollback) {
+                    error!("Failed to roll back transaction: {}", err);
+                }
+            }
+        }
+    }
+}
+
+// A connection from the pool, or that of the transaction the repository is in.
+enum Conn<'a> {
+    Pooled(Connection),
+    Held(MutexGuard<'a, Connection>),
+}
+
+impl<'a> Conn<'a> {
+    // Within a transaction already, changes join it and are kept once it is
+    // committed.
+    fn transaction(&mut self) -> Result<Tx, Error> {
+        match self {
+            Conn::Pooled(conn) => Ok(Tx::Own(conn.transaction()?)),
+            Conn::Held(conn) => Ok(Tx::Joined(&mut **conn)),
+        }
+    }
+}
+
+impl<'a> Deref for Conn<'a> {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        match self {
+            Conn::Pooled(conn) => conn,
+            Conn::Held(conn) => conn,
+        }
+    }
+}
+
+impl<'a> DerefMut for Conn<'a> {
+    fn deref_mut(&mut self) -> &mut Client {
+        match self {
+            Conn::Pooled(conn) => conn,
+            Conn::Held(conn) => conn,
+        }
+    }
+}
+
+enum Tx<'a> {
+    Own(postgres::Transaction<'a>),
+    Joined(&'a mut Client),
+}
+
+impl<'a> Tx<'a> {
+    fn execute(&mut self, statement: &str, params: &[&(dyn ToSql + Sync)]) -> Result<u64, postgres::Error> {
+        match self {
+            Tx::Own(tx) => tx.execute(statement, params),
+            Tx::Joined(conn) => conn.execute(statement, params),
+        }
+    }
+
+    fn query(&mut self, statement: &str, params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, postgres::Error> {
+        match self {
+            Tx::Own(tx) => tx.query(statement, params),
+            Tx::Joined(conn) => conn.query(statement, params),
+        }
+    }
+
+    fn query_one(&mut self, statement: &str, params: &[&(dyn ToSql + Sync)]) -> Result<Row, postgres::Error> {
+        match self {
+            Tx::Own(tx) => tx.query_one(statement, params),
+            Tx::Joined(conn) => conn.query_one(statement, params),
+        }
+    }
+
+    fn query_opt(&mut self, statement: &str, params: &[&(dyn ToSql + Sync)]) -> Result<Option<Row>, postgres::Error> {
+        match self {
+            Tx::Own(tx) => tx.query_opt(statement, params),
+            Tx::Joined(conn) => conn.query_opt(statement, params),
+        }
+    }
+
+    fn commit(self) -> Result<(), postgres::Error> {
+        match self {
+            Tx::Own(tx) => tx.commit(),
+            Tx::Joined(_) => Ok(()),
+        }
+    }
+}
+
+// Rows fetched at a time when exporting orders.
+const EXPORT_BATCH_ROWS: i32 = 500;
+
+// Columns every shop query selects.
+const SHOP_COLUMNS: &str = "id, name, address, latitude, longitude, latest_update";
+
+fn shop(row: &Row) -> Shop {
+    let coordinates = match (row.get("latitude"), row.get("longitude")) {
+        (Some(latitude), Some(longitude)) => Some(Coordinates::new(latitude, longitude)),
+        _ => None,
+    };
+    Shop::new(
+        row.get("id"),
+        row.get("name"),
+        row.get("address"),
+        coordinates,
+        row.get("latest_update"),
+    )
+}
+
+// Columns every promotion query selects.
+const PROMOTION_COLUMNS: &str = "id, shop_id, name, kind, percent, amount, product_key, buy_count, get_count, \
+    min_spend, starts_at, ends_at, code, usage_limit, usage_count";
+
+// `None` for rows whose reward doesn't fit their kind, which the table's
+// checks rule out.
+fn promotion(row: &Row) -> Option<Promotion> {
+    let reward = Reward::parse(
+        row.get("kind"),
+        row.get("percent"),
+        row.get("amount"),
+        row.get("product_key"),
+        row.get("buy_count"),
+        row.get("get_count"),
+    )?;
+    Some(Promotion::new(
+        row.get("id"),
+        row.get("shop_id"),
+        PromotionTerms {
+            name: row.get("name"),
+            reward: reward,
+            min_spend: row.get("min_spend"),
+            starts_at: row.get("starts_at"),
+            ends_at: row.get("ends_at"),
+            code: row.get("code"),
+            usage_limit: row.get("usage_limit"),
+        },
+        row.get("usage_count"),
+    ))
+}
+
+const API_TOKEN_COLUMNS: &str = "id, user_id, shop_id, name, member_authority, order_authority, product_authority, \
+    created_at, expires_at";
+
+fn api_token(row: &Row) -> ApiToken {
+    ApiToken::new(
+        row.get("id"),
+        row.get("user_id"),
+        row.get("shop_id"),
+        row.get("name"),
+        Authority::new(row.get("member_authority"), row.get("order_authority"), row.get("product_authority")),
+        row.get("created_at"),
+        row.get("expires_at"),
+    )
+}
+
+// Aliased, as they are selected along with shops and users.
+const ROLE_COLUMNS: &str = "roles.id role_id, roles.shop_id role_shop_id, roles.name role_name, roles.capabilities";
+
+fn role(row: &Row) -> Role {
+    Role::new(
+        row.get("role_id"),
+        row.get("role_shop_id"),
+        row.get("role_name"),
+        Capabilities::parse(&row.get::<&str, Vec<String>>("capabilities")),
+    )
+}
+
+// Selects a shop's tax settings, with its series rates as parallel arrays.
+const TAX_SETTINGS_QUERY: &str = "
+    SELECT
+        tax_inclusive,
+        tax_rate,
+        ARRAY(SELECT key::UUID FROM each(series_tax_rates) ORDER BY key) series_ids,
+        ARRAY(SELECT value::INTEGER FROM each(series_tax_rates) ORDER BY key) series_rates,
+        service_charge_rate
+    FROM shops
+    WHERE id = $1";
+
+fn tax_settings(row: &Row) -> TaxSettings {
+    let series_ids: Vec<Uuid> = row.get("series_ids");
+    let series_rates: Vec<i32> = row.get("series_rates");
+    TaxSettings {
+        inclusive: row.get("tax_inclusive"),
+        rate: row.get("tax_rate"),
+        series_rates: series_ids.into_iter().zip(series_rates).collect(),
+        service_charge_rate: row.get("service_charge_rate"),
+    }
+}
+
+// Audit entries of shop `$1` matching the filters `$2` to `$7` of
+// `AuditLogFilter`, each ignored when NULL.
+const AUDIT_LOG_FILTER: &str = "
+    WHERE
+        shop_id = $1
+        AND ($2::TEXT IS NULL OR entity = $2)
+        AND ($3::UUID IS NULL OR entity_id = $3)
+        AND ($4::UUID IS NULL OR actor_id = $4)
+        AND ($5::TEXT IS NULL OR action = $5)
+        AND ($6::TIMESTAMPTZ IS NULL OR created_at >= $6)
+        AND ($7::TIMESTAMPTZ IS NULL OR created_at < $7)";
+
+// Orders matching `filter`, one row per customize of each item, or per item
+// without customizes. Orders without items get a single row of NULL items.
+fn order_items_query<T: Display>(filter: T) -> String {
+    format!(
+        "WITH
+            query_orders AS (
+                SELECT * FROM orders {}
+            ),
+            query_items AS (
+                SELECT id order_id, (each(items)).* FROM query_orders
+            ),
+            query_customizes AS (
+                SELECT key item_key, (query_product_item_customize_items(value::PRODUCT_ITEM)).* FROM query_items
+            )
+        SELECT
+            id order_id,
+            guest_session_id,
+            shop_id,
+            order_number,
+            order_at,
+            tax_inclusive,
+            service_charge_rate,
+            service_charge,
+            item_key,
+            (item).product_key,
+            (item).name,
+            (item).price,
+            (item).count,
+            (item).remark,
+            (item).order_at item_order_at,
+            customize_key,
+            (customize).name customize_name,
+            (customize).selection,
+            (customize).selection_key,
+            (customize).price selection_price,
+            (customize).order_at customize_order_at
+        FROM
+            query_orders
+        LEFT JOIN
+            (
+                SELECT
+                    order_id,
+                    query_items.key::UUID item_key,
+                    query_items.value::PRODUCT_ITEM item,
+                    query_customizes.key::UUID customize_key,
+                    customize
+                FROM
+                    query_items
+                LEFT JOIN
+                    query_customizes
+                ON
+                    query_items.key = query_customizes.item_key
+            ) item_join_cus
+        ON
+            query_orders.id = item_join_cus.order_id
+        ",
+        filter,
+    )
+}
+
+fn order(row: &Row) -> Order {
+    let mut order = Order::new(
+        row.get("order_id"),
+        row.get("guest_session_id"),
+        row.get("shop_id"),
+        row.get("order_number"),
+        row.get("order_at"),
+    );
+    order.set_charges(Charges::new(
+        row.get("shop_id"),
+        row.get("tax_inclusive"),
+        row.get("service_charge_rate"),
+        row.get("service_charge"),
+    ));
+    order
+}
+
+// Add the item and customize a row of `order_items_query` carries, if any.
+fn add_order_item(order: &mut Order, row: &Row) {
+    if let Ok(item_key) = row.try_get::<&str, Uuid>("item_key") {
+        let item = if let Some(item) = order.ref_mut_item(item_key) {
+            item
+        } else {
+            let item = ProductItem::new(
+                row.get("shop_id"),
+                item_key,
+                row.get("product_key"),
+                row.get("name"),
+                row.get("price"),
+                row.get("count"),
+                row.get("remark"),
+                row.get("item_order_at"),
+            );
+            order.insert_item_uncheck(item_key, item)
+        };
+
+        if let Ok(customize_key) = row.try_get::<&str, Uuid>("customize_key") {
+            if let None = item.ref_mut_customize(customize_key) {
+                let customize = CustomizeItem::new(
+                    row.get("shop_id"),
+                    customize_key,
+                    row.get("customize_name"),
+                    row.get("selection"),
+                    row.get("selection_key"),
+                    row.get("selection_price"),
+                    row.get("customize_order_at"),
+                );
+                item.insert_customize_uncheck(customize_key, customize);
+            }
+        }
+    }
+}
+
+impl SessionRepository for PostgresRepository {
+    fn session_user(&self, user_session_id: Uuid) -> Result<Uuid, Error> {
+        let mut conn = self.connection()?;
+        let (user_id,) = query_one!(
+            conn,
+            "SELECT user_id FROM get_session_user($1);",
+            &[&UuidNN(user_session_id)],
+            (user_id: Uuid),
+        )?;
+        Ok(user_id)
+    }
+
+    fn is_guest_session_valid(&self, guest_session_id: Uuid) -> Result<bool, Error> {
+        let mut conn = self.connection()?;
+        let (ok,) = query_one!(
+            conn,
+            "SELECT is_guest_session_valid($1) AS ok;",
+            &[&UuidNN(guest_session_id)],
+            (ok: bool),
+        )?;
+        Ok(ok)
+    }
+
+    fn create_user_session(&self, user_id: Uuid, expire_time: DateTime<Utc>) -> Result<Uuid, Error> {
+        let mut conn = self.connection()?;
+        let (id,) = query_one!(
+            conn,
+            "INSERT INTO user_session (user_id, expire_time) VALUES ($1, $2) RETURNING id",
+            &[&user_id, &expire_time],
+            (id: Uuid),
+        )?;
+        Ok(id)
+    }
+}
+
+impl CredentialRepository for PostgresRepository {
+    fn credentials(&self, username: &str) -> Result<Option<(Uuid, String)>, Error> {
+        let mut conn = self.connection()?;
+        let row = query_opt!(
+            conn,
+            "SELECT id, password FROM users WHERE username = $1",
+            &[&username],
+        )?;
+        Ok(row.map(|row| (row.get("id"), row.get("password"))))
+    }
+
+    fn set_password_hash(&self, user_id: Uuid, hash: String) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+        let updated = conn.execute("UPDATE users SET password = $2 WHERE id = $1", &[&user_id, &hash])?;
+        if updated == 0 {
+            return Err(Error::not_found("User"));
+        }
+        Ok(())
+    }
+
+    fn login_failures(&self, key: &str) -> Result<Option<LoginFailures>, Error> {
+        let mut conn = self.connection()?;
+        let row = query_opt!(
+            conn,
+            "SELECT failures, last_failed_at FROM login_failures WHERE key = $1",
+            &[&key],
+        )?;
+        Ok(row.map(|row| LoginFailures {
+            count: row.get("failures"),
+            last_failed_at: row.get("last_failed_at"),
+        }))
+    }
+
+    // The condition mirrors `Lockout::locked_until`.
+    fn take_login_attempt(&self, key: &str, lockout: &Lockout, now: DateTime<Utc>, forget_before: DateTime<Utc>) -> Result<bool, Error> {
+        let mut conn = self.connection()?;
+        let taken = conn.execute(
+            "INSERT INTO login_failures AS f (key, failures, last_failed_at) VALUES ($1, 1, $2)
+            ON CONFLICT (key) DO UPDATE SET
+                failures = CASE WHEN f.last_failed_at < $3 THEN 1 ELSE f.failures + 1 END,
+                last_failed_at = $2
+            WHERE f.failures < $4
+                OR f.last_failed_at + make_interval(secs => LEAST($5::bigint * 2 ^ LEAST(f.failures - $4, 32), $6::bigint)) <= $2",
+            &[&key, &now, &forget_before, &lockout.free_attempts, &lockout.base_seconds, &lockout.max_seconds],
+        )?;
+        Ok(taken > 0)
+    }
+
+    fn release_login_attempt(&self, key: &str) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+        conn.execute(
+            "WITH released AS (DELETE FROM login_failures WHERE key = $1 AND failures = 1)
+            UPDATE login_failures SET failures = failures - 1 WHERE key = $1 AND failures > 1",
+            &[&key],
+        )?;
+        Ok(())
+    }
+
+    fn clear_login_failures(&self, key: &str) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+        conn.execute("DELETE FROM login_failures WHERE key = $1", &[&key])?;
+        Ok(())
+    }
+}
+
+impl UserRepository for PostgresRepository {
+    fn user(&self, id: Uuid) -> Result<User, Error> {
+        let mut conn = self.connection()?;
+        let (id, username, nickname,) = query_one!(
+            conn,
+            "SELECT id, username, nickname FROM users WHERE id = $1;",
+            &[&id],
+            (id: Uuid, username: String, nickname: Option<String>),
+        )?;
+        Ok(User::new(id, username, nickname))
+    }
+
+    fn search(&self, id: Option<Uuid>, name: Option<String>) -> Result<Vec<User>, Error> {
+        let mut conn = self.connection()?;
+
+        let mut clause = Clause::new();
+        if let Some(id) = id.as_ref() {
+            clause.and(Clause::equal("id", format!("'{}'", id)));
+        }
+        clause.and(Clause::contains("upper(username)", 1));
+
+        let rows = query!(
+            conn,
+            format!("SELECT id, username, nickname FROM users{}", clause).as_str(),
+            &[&name.as_ref().map(|name| like_pattern(name))],
+        )?;
+        Ok(
+            rows.iter().map(|row| {
+                User::new(
+                    row.get("id"),
+                    row.get("username"),
+                    row.get("nickname"),
+                )
+            })
+            .collect()
+        )
+    }
+}
+
+impl ShopRepository for PostgresRepository {
+    fn search(&self, id: Option<Uuid>, name: Option<String>) -> Result<Vec<Shop>, Error> {
+        let mut clause = Clause::new();
+        if let Some(id) = id.as_ref() {
+            clause.and(Clause::equal("id", format!("'{}'", id)));
+        }
+        clause.and(Clause::contains("name_upper", 1));
+
+        let mut conn = self.connection()?;
+        let rows = query!(
+            conn,
+            format!("SELECT {} FROM shops{}", SHOP_COLUMNS, clause).as_str(),
+            &[&name.as_ref().map(|name| like_pattern(name))],
+        )?;
+        Ok(rows.iter().map(shop).collect())
+    }
+
+    fn user_shops(&self, user_id: Uuid, id: Option<Uuid>, name: Option<String>) -> Result<Vec<(Shop, Role)>, Error> {
+        let mut conn = self.connection()?;
+
+        let mut clause = Clause::new();
+        if let Some(id) = id.as_ref() {
+            clause.and(Clause::equal("id", format!("'{}'", id)));
+        }
+        clause.and(Clause::contains("upper(name)", 2));
+
+        let rows = query!(
+            conn,
+            format!(
+                "SELECT
+                    shop.id,
+                    shop.name,
+                    shop.address,
+                    shop.latitude,
+                    shop.longitude,
+                    shop.latest_update,
+                    {}
+                FROM
+                    (SELECT * FROM shops{}) shop
+                INNER JOIN
+                    shop_user
+                ON
+                    shop.id = shop_user.shop_id
+                    AND shop_user.user_id = $1
+                INNER JOIN
+                    roles
+                ON
+                    roles.id = shop_user.role_id",
+                ROLE_COLUMNS,
+                clause
+            ).as_str(),
+            &[&user_id, &name.as_ref().map(|name| like_pattern(name))],
+        )?;
+        Ok(rows.iter().map(|row| (shop(row), role(row))).collect())
+    }
+
+    fn members(&self, shop_id: Uuid) -> Result<Vec<(User, Role)>, Error> {
+        let mut conn = self.connection()?;
+
+        let rows = query!(
+            conn,
+            format!(
+                "SELECT
+                    users.id,
+                    users.username,
+                    users.nickname,
+                    {}
+                FROM
+                    shop_user
+                INNER JOIN
+                    users
+                ON
+                    shop_user.user_id = users.id
+                INNER JOIN
+                    roles
+                ON
+                    roles.id = shop_user.role_id
+                WHERE
+                    shop_user.shop_id = $1
+                ",
+                ROLE_COLUMNS,
+            ).as_str(),
+            &[&shop_id],
+        )?;
+        Ok(
+            rows.iter().map(|row| {
+                (
+                    User::new(
+                        row.get("id"),
+                        row.get("username"),
+                        row.get("nickname"),
+                    ),
+                    role(row),
+                )
+            })
+            .collect()
+        )
+    }
+
+    fn set_address(&self, shop_id: Uuid, address: Option<String>) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+
+        let updated = conn.execute(
+            "UPDATE shops SET address = $2, latest_update = now() WHERE id = $1",
+            &[&shop_id, &address],
+        )?;
+
+        if updated == 0 {
+            Err(Error::not_found("Shop"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn set_coordinates(&self, shop_id: Uuid, coordinates: Option<Coordinates>) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+
+        let updated = conn.execute(
+            "UPDATE shops SET latitude = $2, longitude = $3, latest_update = now() WHERE id = $1",
+            &[&shop_id, &coordinates.map(|c| c.latitude), &coordinates.map(|c| c.longitude)],
+        )?;
+
+        if updated == 0 {
+            Err(Error::not_found("Shop"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn nearby(&self, center: Coordinates, radius: f64, limit: i32) -> Result<Vec<(Shop, f64)>, Error> {
+        let mut conn = self.connection()?;
+
+        // `earth_box` can use the `shops_location` index; it is a bounding
+        // cube, so the exact distance is checked afterwards.
+        let rows = query!(
+            conn,
+            format!(
+                "SELECT {}, distance
+                FROM
+                    shops,
+                    LATERAL (
+                        SELECT earth_distance(ll_to_earth($1, $2), ll_to_earth(latitude, longitude)) distance
+                    ) measured
+                WHERE
+                    latitude IS NOT NULL
+                    AND earth_box(ll_to_earth($1, $2), $3) @> ll_to_earth(latitude, longitude)
+                    AND distance <= $3
+                ORDER BY distance, name
+                LIMIT $4",
+                SHOP_COLUMNS,
+            ).as_str(),
+            &[&center.latitude, &center.longitude, &radius, &(limit as i64)],
+        )?;
+        Ok(rows.iter().map(|row| (shop(row), row.get("distance"))).collect())
+    }
+
+    fn schedule(&self, shop_id: Uuid) -> Result<Schedule, Error> {
+        let mut conn = self.connection()?;
+
+        let (timezone, paused_until) = query_opt!(
+            conn,
+            "SELECT timezone, ordering_paused_until FROM shops WHERE id = $1",
+            &[&shop_id],
+        )?
+        .map(|row| (row.get::<&str, String>("timezone"), row.get("ordering_paused_until")))
+        .ok_or_else(|| Error::not_found("Shop"))?;
+
+        let weekly = query!(
+            conn,
+            "SELECT weekday, opens, closes FROM opening_hours WHERE shop_id = $1 ORDER BY weekday, opens",
+            &[&shop_id],
+        )?;
+        let exceptions = query!(
+            conn,
+            "SELECT date, opens, closes, note FROM opening_exceptions WHERE shop_id = $1 ORDER BY date",
+            &[&shop_id],
+        )?;
+
+        Ok(Schedule {
+            timezone: timezone.parse().unwrap_or(Tz::UTC),
+            weekly: weekly.iter()
+                .filter_map(|row| {
+                    Day::from_index(row.get("weekday"))
+                        .map(|day| WeeklyHours::new(day, Interval::new(row.get("opens"), row.get("closes"))))
+                })
+                .collect(),
+            exceptions: exceptions.iter()
+                .map(|row| {
+                    let opens: Vec<NaiveTime> = row.get("opens");
+                    let closes: Vec<NaiveTime> = row.get("closes");
+                    OpeningException::new(
+                        row.get("date"),
+                        opens.into_iter().zip(closes).map(|(opens, closes)| Interval::new(opens, closes)).collect(),
+                        row.get("note"),
+                    )
+                })
+                .collect(),
+            paused_until: paused_until,
+        })
+    }
+
+    fn set_opening_hours(&self, shop_id: Uuid, timezone: Tz, hours: Vec<WeeklyHours>) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+        let mut tx = conn.transaction()?;
+
+        let updated = tx.execute(
+            "UPDATE shops SET timezone = $2, latest_update = now() WHERE id = $1",
+            &[&shop_id, &timezone.name()],
+        )?;
+        if updated == 0 {
+            return Err(Error::not_found("Shop"));
+        }
+
+        let days: Vec<i16> = hours.iter().map(|hours| hours.day.index()).collect();
+        let opens: Vec<NaiveTime> = hours.iter().map(|hours| hours.interval.opens).collect();
+        let closes: Vec<NaiveTime> = hours.iter().map(|hours| hours.interval.closes).collect();
+        tx.execute("DELETE FROM opening_hours WHERE shop_id = $1", &[&shop_id])?;
+        tx.execute(
+            "INSERT INTO opening_hours (shop_id, weekday, opens, closes)
+            SELECT $1, * FROM unnest($2::SMALLINT[], $3::TIME[], $4::TIME[])",
+            &[&shop_id, &days, &opens, &closes],
+        )?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn set_opening_exception(&self, shop_id: Uuid, exception: OpeningException) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+
+        let opens: Vec<NaiveTime> = exception.intervals.iter().map(|interval| interval.opens).collect();
+        let closes: Vec<NaiveTime> = exception.intervals.iter().map(|interval| interval.closes).collect();
+        conn.execute(
+            "INSERT INTO opening_exceptions (shop_id, date, opens, closes, note)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (shop_id, date) DO UPDATE
+            SET opens = EXCLUDED.opens, closes = EXCLUDED.closes, note = EXCLUDED.note",
+            &[&shop_id, &exception.date, &opens, &closes, &exception.note],
+        )?;
+        Ok(())
+    }
+
+    fn delete_opening_exception(&self, shop_id: Uuid, date: NaiveDate) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+
+        let deleted = conn.execute(
+            "DELETE FROM opening_exceptions WHERE shop_id = $1 AND date = $2",
+            &[&shop_id, &date],
+        )?;
+
+        if deleted == 0 {
+            Err(Error::not_found("Opening exception"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn set_paused_until(&self, shop_id: Uuid, paused_until: Option<DateTime<Utc>>) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+
+        let updated = conn.execute(
+            "UPDATE shops SET ordering_paused_until = $2 WHERE id = $1",
+            &[&shop_id, &paused_until],
+        )?;
+
+        if updated == 0 {
+            Err(Error::not_found("Shop"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn currency(&self, shop_id: Uuid) -> Result<Currency, Error> {
+        let mut conn = self.connection()?;
+
+        query_opt!(
+            conn,
+            "SELECT currency, minor_units FROM shops WHERE id = $1",
+            &[&shop_id],
+        )?
+        .map(|row| Currency::new(row.get("currency"), row.get("minor_units")))
+        .ok_or_else(|| Error::not_found("Shop"))
+    }
+
+    fn set_currency(&self, shop_id: Uuid, currency: Currency) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+
+        let updated = conn.execute(
+            "UPDATE shops SET currency = $2, minor_units = $3, latest_update = now() WHERE id = $1",
+            &[&shop_id, &currency.code(), &currency.minor_units()],
+        )?;
+
+        if updated == 0 {
+            Err(Error::not_found("Shop"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn tax_settings(&self, shop_id: Uuid) -> Result<TaxSettings, Error> {
+        let mut conn = self.connection()?;
+
+        query_opt!(conn, TAX_SETTINGS_QUERY, &[&shop_id],)?
+            .map(|row| tax_settings(&row))
+            .ok_or_else(|| Error::not_found("Shop"))
+    }
+
+    fn set_tax_settings(&self, shop_id: Uuid, settings: TaxSettings) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+
+        let series_ids: Vec<Uuid> = settings.series_rates.keys().cloned().collect();
+        let series_rates: Vec<i32> = settings.series_rates.values().cloned().collect();
+        let updated = conn.execute(
+            "UPDATE shops
+            SET
+                tax_inclusive = $2,
+                tax_rate = $3,
+                series_tax_rates = hstore($4::UUID[]::TEXT[], $5::INTEGER[]::TEXT[]),
+                service_charge_rate = $6,
+                latest_update = now()
+            WHERE id = $1",
+            &[&shop_id, &settings.inclusive, &settings.rate, &series_ids, &series_rates, &settings.service_charge_rate],
+        )?;
+
+        if updated == 0 {
+            Err(Error::not_found("Shop"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl CatalogRepository for PostgresRepository {
+    fn products(&self, shop_id: Uuid, key: Option<Uuid>, name: Option<String>, series_id: Option<Uuid>) -> Result<Vec<Product>, Error> {
+        let mut conn = self.connection()?;
+
+        let mut clause = Clause::new();
+        if let Some(key) = key.as_ref() {
+            clause.and(Clause::equal("key", format!("'{}'", key)));
+        }
+        clause.and(Clause::contains("upper((product).name)", 2));
+        if let Some(series_id) = series_id.as_ref() {
+            clause.and(Clause::equal("(product).series_id", format!("'{}'", series_id)));
+        }
+
+        let rows = query!(
+            conn,
+            format!(
+                "WITH
+                    products AS (
+                        SELECT
+                            key,
+                            product
+                        FROM
+                            query_shop_products($1){}
+                    ),
+                    customizes AS (
+                        SELECT
+                            key prod_key,
+                            (query_product_customizes(product)).*
+                        FROM
+                            products
+                    ),
+                    selections AS (
+                        SELECT
+                            key cus_key,
+                            (query_customize_selections(customize)).*
+                        FROM
+                            customizes
+                    )
+                SELECT
+                    products.key prod_key,
+                    (product).name prod_name,
+                    (product).description prod_description,
+                    (product).price prod_price,
+                    (product).series_id prod_series_id,
+                    (product).has_picture prod_has_picture,
+                    prod_inventory.stock prod_stock,
+                    COALESCE(prod_inventory.sold_out, FALSE) prod_sold_out,
+                    (product).latest_update prod_latest_update,
+                    cus_join_sel.cus_key,
+                    (cus_join_sel.customize).name cus_name,
+                    (cus_join_sel.customize).description cus_description,
+                    (cus_join_sel.customize).latest_update cus_latest_update,
+                    cus_join_sel.sel_key,
+                    (cus_join_sel.selection).name sel_name,
+                    (cus_join_sel.selection).price sel_price,
+                    sel_inventory.stock sel_stock,
+                    COALESCE(sel_inventory.sold_out, FALSE) sel_sold_out
+                FROM
+                    products
+                LEFT JOIN
+                    (
+                        SELECT
+                            prod_key,
+                            customizes.key cus_key,
+                            customize,
+                            selections.key sel_key,
+                            selection
+                        FROM
+                            customizes
+                        LEFT JOIN
+                            selections
+                        ON
+                            customizes.key = selections.cus_key
+                    ) cus_join_sel
+                ON
+                    products.key = cus_join_sel.prod_key
+                LEFT JOIN
+                    inventory prod_inventory
+                ON
+                    prod_inventory.shop_id = $1 AND prod_inventory.key = products.key
+                LEFT JOIN
+                    inventory sel_inventory
+                ON
+                    sel_inventory.shop_id = $1 AND sel_inventory.key = cus_join_sel.sel_key",
+                clause,
+            ).as_str(),
+            &[&UuidNN(shop_id), &name.as_ref().map(|name| like_pattern(name))],
+        )?;
+
+        let mut products = Dict::new();
+        let mut keys: Vec<Uuid> = Vec::new();
+        for row in rows.iter() {
+            let prod_key = row.get::<&str, Uuid>("prod_key");
+            keys.push(prod_key);
+            keys.extend(row.get::<&str, Option<Uuid>>("cus_key"));
+            keys.extend(row.get::<&str, Option<Uuid>>("sel_key"));
+            let product = if let Some(product) = products.ref_mut_value(prod_key) {
+                product
+            } else {
+                let product = Product::new(
+                    shop_id,
+                    prod_key,
+                    row.get("prod_name"),
+                    row.get("prod_description"),
+                    row.get("prod_price"),
+                    row.get("prod_series_id"),
+                    row.get("prod_has_picture"),
+                    Inventory::new(row.get("prod_stock"), row.get("prod_sold_out")),
+                    row.get("prod_latest_update"),
+                );
+                products.insert_uncheck(prod_key, product)
+            };
+
+            if let Ok(cus_key) = row.try_get::<&str, Uuid>("cus_key") {
+                let customize = if let Some(customize) = product.ref_mut_customize(cus_key) {
+                    customize
+                } else {
+                    let customize = Customize::new(
+                        cus_key,
+                        row.get("cus_name"),
+                        row.get("cus_description"),
+                        row.get("cus_latest_update"),
+                    );
+                    product.insert_customize_uncheck(cus_key, customize)
+                };
+
+                if let Ok(sel_key) = row.try_get::<&str, Uuid>("sel_key") {
+                    if let None = customize.ref_mut_selection(sel_key) {
+                        let selection = Selection::new(
+                            shop_id,
+                            sel_key,
+                            row.get("sel_name"),
+                            row.get("sel_price"),
+                            Inventory::new(row.get("sel_stock"), row.get("sel_sold_out")),
+                        );
+                        customize.insert_selection_uncheck(sel_key, selection);
+                    }
+                }
+            }
+        }
+
+        let mut products = products.values();
+        let translations = query!(
+            conn,
+            "SELECT key, field, locale, text FROM translations WHERE shop_id = $1 AND key = ANY($2)",
+            &[&shop_id, &keys],
+        )?;
+        for row in translations.iter() {
+            if let Some(field) = TranslatedField::parse(row.get("field")) {
+                let locale = Locale::parse(row.get("locale"))?;
+                for product in products.iter_mut() {
+                    product.translate(row.get("key"), field, locale.clone(), row.get("text"));
+                }
+            }
+        }
+
+        Ok(products)
+    }
+
+    fn product_series(&self, shop_id: Uuid, product_keys: &[Uuid]) -> Result<HashMap<Uuid, Uuid>, Error> {
+        let mut conn = self.connection()?;
+        Ok(query!(
+            conn,
+            "SELECT key, (product).series_id FROM query_shop_products($1) WHERE key = ANY($2) AND (product).series_id IS NOT NULL",
+            &[&shop_id, &product_keys],
+        )?
+        .iter()
+        .map(|row| (row.get("key"), row.get("series_id")))
+        .collect())
+    }
+
+    fn set_has_picture(&self, shop_id: Uuid, product_key: Uuid, has_picture: bool) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+
+        let updated = conn.execute(
+            "WITH
+                product AS (
+                    SELECT ((products -> $2::UUID::TEXT)::PRODUCT).*
+                    FROM shops
+                    WHERE id = $1 AND products ? $2::UUID::TEXT
+                )
+            UPDATE shops
+            SET
+                products = products || hstore(
+                    $2::UUID::TEXT,
+                    ROW(product.name, product.description, product.price, product.series_id, $3, product.customizes, now())::PRODUCT::TEXT
+                ),
+                latest_update = now()
+            FROM product
+            WHERE shops.id = $1",
+            &[&shop_id, &product_key, &has_picture],
+        )?;
+
+        if updated == 0 {
+            Err(Error::not_found("Product"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn series(&self, shop_id: Uuid, id: Option<Uuid>) -> Result<Vec<Series>, Error> {
+        let mut conn = self.connection()?;
+
+        let mut clause = Clause::new();
+        if let Some(id) = id.as_ref() {
+            clause.and(Clause::equal("key", format!("'{}'", id)));
+        }
+
+        let rows = query!(
+            conn,
+            format!(
+                "SELECT
+                    key,
+                    (series).name,
+                    (series).ordering,
+                    (series).latest_update
+                FROM
+                    query_shop_series($1){}
+                ORDER BY
+                    (series).ordering,
+                    (series).name",
+                clause,
+            ).as_str(),
+            &[&UuidNN(shop_id)],
+        )?;
+
+        Ok(
+            rows.iter()
+                .map(|row| Series::new(
+                    row.get("key"),
+                    row.get("name"),
+                    row.get("ordering"),
+                    row.get("latest_update"),
+                ))
+                .collect()
+        )
+    }
+
+    fn create_series(&self, shop_id: Uuid, name: String, ordering: Option<i32>) -> Result<Uuid, Error> {
+        let mut conn = self.connection()?;
+
+        let id = Uuid::new_v4();
+        let updated = conn.execute(
+            "UPDATE shops
+            SET
+                series = series || hstore(
+                    $2::UUID::TEXT,
+                    ROW(
+                        $3,
+                        COALESCE($4, (SELECT COALESCE(max((entry.value::SERIES).ordering) + 1, 0) FROM each(shops.series) entry)),
+                        now()
+                    )::SERIES::TEXT
+                ),
+                latest_update = now()
+            WHERE id = $1",
+            &[&shop_id, &id, &name, &ordering],
+        )?;
+
+        if updated == 0 {
+            Err(Error::not_found("Shop"))
+        } else {
+            Ok(id)
+        }
+    }
+
+    fn update_series(&self, shop_id: Uuid, id: Uuid, name: Option<String>, ordering: Option<i32>) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+
+        let updated = conn.execute(
+            "WITH
+                current AS (
+                    SELECT ((series -> $2::UUID::TEXT)::SERIES).*
+                    FROM shops
+                    WHERE id = $1 AND series ? $2::UUID::TEXT
+                )
+            UPDATE shops
+            SET
+                series = series || hstore(
+                    $2::UUID::TEXT,
+                    ROW(COALESCE($3, current.name), COALESCE($4, current.ordering), now())::SERIES::TEXT
+                ),
+                latest_update = now()
+            FROM current
+            WHERE shops.id = $1",
+            &[&shop_id, &id, &name, &ordering],
+        )?;
+
+        if updated == 0 {
+            Err(Error::not_found("Series"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn delete_series(&self, shop_id: Uuid, id: Uuid) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+
+        let updated = conn.execute(
+            "UPDATE shops
+            SET
+                series = delete(series, $2::UUID::TEXT),
+                series_tax_rates = delete(series_tax_rates, $2::UUID::TEXT),
+                products = COALESCE(
+                    (
+                        SELECT hstore(
+                            array_agg(entry.key),
+                            array_agg(
+                                CASE WHEN product.series_id = $2
+                                THEN ROW(product.name, product.description, product.price, NULL, product.has_picture, product.customizes, now())::PRODUCT::TEXT
+                                ELSE entry.value
+                                END
+                            )
+                        )
+                        FROM each(shops.products) entry, LATERAL (SELECT (entry.value::PRODUCT).*) product
+                    ),
+                    ''
+                ),
+                latest_update = now()
+            WHERE id = $1 AND series ? $2::UUID::TEXT",
+            &[&shop_id, &id],
+        )?;
+
+        if updated == 0 {
+            Err(Error::not_found("Series"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn set_product_series(&self, shop_id: Uuid, product_key: Uuid, series_id: Option<Uuid>) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+
+        let updated = conn.execute(
+            "WITH
+                product AS (
+                    SELECT ((products -> $2::UUID::TEXT)::PRODUCT).*
+                    FROM shops
+                    WHERE id = $1 AND products ? $2::UUID::TEXT AND ($3::UUID IS NULL OR series ? $3::UUID::TEXT)
+                )
+            UPDATE shops
+            SET
+                products = products || hstore(
+                    $2::UUID::TEXT,
+                    ROW(product.name, product.description, product.price, $3, product.has_picture, product.customizes, now())::PRODUCT::TEXT
+                ),
+                latest_update = now()
+            FROM product
+            WHERE shops.id = $1",
+            &[&shop_id, &product_key, &series_id],
+        )?;
+
+        if updated == 0 {
+            Err(Error::not_found("Product or series"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn set_inventory(&self, shop_id: Uuid, key: Uuid, inventory: Inventory) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+
+        if inventory == Inventory::default() {
+            conn.execute(
+                "DELETE FROM inventory WHERE shop_id = $1 AND key = $2",
+                &[&shop_id, &key],
+            )?;
+        } else {
+            conn.execute(
+                "INSERT INTO inventory (shop_id, key, stock, sold_out)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (shop_id, key) DO UPDATE SET stock = EXCLUDED.stock, sold_out = EXCLUDED.sold_out",
+                &[&shop_id, &key, &inventory.stock, &inventory.sold_out],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn set_translation(&self, shop_id: Uuid, key: Uuid, field: TranslatedField, locale: Locale, text: String) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+        conn.execute(
+            "INSERT INTO translations (shop_id, key, field, locale, text) VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (shop_id, key, field, locale) DO UPDATE SET text = EXCLUDED.text",
+            &[&shop_id, &key, &field.as_str(), &locale.as_str(), &text],
+        )?;
+        Ok(())
+    }
+
+    fn delete_translation(&self, shop_id: Uuid, key: Uuid, field: TranslatedField, locale: Locale) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+        let deleted = conn.execute(
+            "DELETE FROM translations WHERE shop_id = $1 AND key = $2 AND field = $3 AND locale = $4",
+            &[&shop_id, &key, &field.as_str(), &locale.as_str()],
+        )?;
+        if deleted == 0 {
+            return Err(Error::not_found("Translation"));
+        }
+        Ok(())
+    }
+
+    fn search(&self, query: String, shop_id: Option<Uuid>, limit: i32) -> Result<Vec<SearchHit>, Error> {
+        let mut conn = self.connection()?;
+
+        // Full-text rank of the name and description plus trigram similarity
+        // of the name, which also lets typos match through `<%`.
+        let rows = query!(
+            conn,
+            "WITH
+                query AS (
+                    SELECT search_query($1) tsquery, lower($1) text
+                )
+            SELECT
+                shop_id,
+                product_key,
+                name,
+                description,
+                (ts_rank_cd(document, tsquery, 1) + word_similarity(text, lower(name)))::FLOAT8 score
+            FROM search_documents, query
+            WHERE (document @@ tsquery OR text <% lower(name))
+                AND ($2::UUID IS NULL OR shop_id = $2)
+            ORDER BY score DESC, name
+            LIMIT $3",
+            &[&query, &shop_id, &(limit as i64)],
+        )?;
+
+        Ok(
+            rows.iter()
+                .map(|row| SearchHit::new(
+                    row.get("shop_id"),
+                    row.get("product_key"),
+                    row.get("name"),
+                    row.get("description"),
+                    row.get("score"),
+                ))
+                .collect()
+        )
+    }
+}
+
+impl OrderRepository for PostgresRepository {
+    fn orders(&self, shop_id: Option<Uuid>, guest_session_id: Option<Uuid>) -> Result<Vec<Order>, Error> {
+        let mut conn = self.connection()?;
+
+        let mut clause = Clause::new();
+        if let Some(shop_id) = shop_id.as_ref() {
+            clause.and(Clause::equal("shop_id", format!("'{}'", shop_id)));
+        }
+        if let Some(guest_session_id) = guest_session_id.as_ref() {
+            clause.and(Clause::equal("guest_session_id", format!("'{}'", guest_session_id)));
+        }
+
+        let rows = query!(
+            conn,
+            order_items_query(clause).as_str(),
+            &[],
+        )?;
+
+        let mut orders = Dict::new();
+        for row in rows.iter() {
+            let order_id = row.get::<&str, Uuid>("order_id");
+            let order = if let Some(order) = orders.ref_mut_value(order_id) {
+                order
+            } else {
+                orders.insert_uncheck(order_id, order(row))
+            };
+            add_order_item(order, row);
+        }
+
+        let ids: Vec<Uuid> = orders.ref_values().iter().map(Order::id).collect();
+        let promotions = query!(
+            conn,
+            "SELECT order_id, promotion_id, name, code, discount FROM order_promotions
+            WHERE order_id = ANY($1)
+            ORDER BY order_id, position",
+            &[&ids],
+        )?;
+        for row in promotions.iter() {
+            if let Some(order) = orders.ref_mut_value(row.get("order_id")) {
+                let promotion = AppliedPromotion::new(
+                    order.shop_id(),
+                    row.get("promotion_id"),
+                    row.get("name"),
+                    row.get("code"),
+                    row.get("discount"),
+                );
+                order.add_promotion(promotion);
+            }
+        }
+
+        let taxes = query!(
+            conn,
+            "SELECT order_id, rate, taxable, amount FROM order_taxes
+            WHERE order_id = ANY($1)
+            ORDER BY order_id, rate",
+            &[&ids],
+        )?;
+        for row in taxes.iter() {
+            if let Some(order) = orders.ref_mut_value(row.get("order_id")) {
+                let tax = TaxLine::new(order.shop_id(), row.get("rate"), row.get("taxable"), row.get("amount"));
+                order.add_tax(tax);
+            }
+        }
+
+        Ok(orders.values())
+    }
+
+    fn sales_summary(&self, shop_id: Uuid, from: DateTime<Utc>, to: DateTime<Utc>, granularity: Granularity, timezone: Tz) -> Result<Vec<SalesBucket>, Error> {
+        let mut conn = self.connection()?;
+        let top = TOP_SALES as i64;
+
+        let rows = query!(
+            conn,
+            format!(
+                "WITH{},
+                    order_buckets AS (
+                        SELECT bucket, count(*) order_count FROM query_orders GROUP BY bucket
+                    ),
+                    item_buckets AS (
+                        SELECT bucket, sum(amount) revenue, sum((item).count) item_count
+                        FROM priced_items
+                        GROUP BY bucket
+                    )
+                SELECT
+                    order_buckets.bucket,
+                    COALESCE(revenue, 0)::INT revenue,
+                    order_count::INT order_count,
+                    COALESCE(item_count, 0)::INT item_count
+                FROM
+                    order_buckets
+                LEFT JOIN
+                    item_buckets
+                ON
+                    order_buckets.bucket = item_buckets.bucket
+                ORDER BY
+                    order_buckets.bucket",
+                SALES_ITEMS,
+            ).as_str(),
+            &[&shop_id, &from, &to, &granularity.as_sql(), &timezone.name()],
+        )?;
+
+        let mut buckets = Dict::new();
+        for row in rows.iter() {
+            let start = row.get::<&str, DateTime<Utc>>("bucket");
+            buckets.insert_uncheck(
+                start,
+                SalesBucket::new(
+                    start,
+                    row.get("revenue"),
+                    row.get("order_count"),
+                    row.get("item_count"),
+                ),
+            );
+        }
+
+        let rows = query!(
+            conn,
+            format!(
+                "WITH{},
+                    product_sales AS (
+                        SELECT
+                            bucket,
+                            (item).product_key,
+                            max((item).name) product_name,
+                            sum((item).count) count,
+                            sum(amount) revenue
+                        FROM priced_items
+                        GROUP BY bucket, (item).product_key
+                    ),
+                    ranked AS (
+                        SELECT *, row_number() OVER (PARTITION BY bucket ORDER BY count DESC, revenue DESC) rank
+                        FROM product_sales
+                    )
+                SELECT bucket, product_key, product_name, count::INT count, revenue::INT revenue
+                FROM ranked
+                WHERE rank <= $6
+                ORDER BY bucket, rank",
+                SALES_ITEMS,
+            ).as_str(),
+            &[&shop_id, &from, &to, &granularity.as_sql(), &timezone.name(), &top],
+        )?;
+        for row in rows.iter() {
+            if let Some(bucket) = buckets.ref_mut_value(row.get("bucket")) {
+                bucket.push_product(ProductSales::new(
+                    row.get("product_key"),
+                    row.get("product_name"),
+                    row.get("count"),
+                    row.get("revenue"),
+                ));
+            }
+        }
+
+        let rows = query!(
+            conn,
+            format!(
+                "WITH{},
+                    selection_sales AS (
+                        SELECT
+                            bucket,
+                            customizes.key customize_key,
+                            (customize).selection_key,
+                            max((customize).name) customize_name,
+                            max((customize).selection) selection,
+                            sum((item).count) count
+                        FROM
+                            priced_items,
+                            query_product_item_customize_items(item) customizes
+                        WHERE
+                            (customize).selection_key IS NOT NULL
+                        GROUP BY bucket, customizes.key, (customize).selection_key
+                    ),
+                    ranked AS (
+                        SELECT *, row_number() OVER (PARTITION BY bucket ORDER BY count DESC) rank
+                        FROM selection_sales
+                    )
+                SELECT bucket, customize_key, selection_key, customize_name, selection, count::INT count
+                FROM ranked
+                WHERE rank <= $6
+                ORDER BY bucket, rank",
+                SALES_ITEMS,
+            ).as_str(),
+            &[&shop_id, &from, &to, &granularity.as_sql(), &timezone.name(), &top],
+        )?;
+        for row in rows.iter() {
+            if let Some(bucket) = buckets.ref_mut_value(row.get("bucket")) {
+                bucket.push_selection(SelectionSales::new(
+                    row.get("customize_key"),
+                    row.get("selection_key"),
+                    row.get("customize_name"),
+                    row.get("selection"),
+                    row.get("count"),
+                ));
+            }
+        }
+
+        Ok(buckets.values())
+    }
+
+    fn export_orders(&self, shop_id: Uuid, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>, each: &mut dyn FnMut(Order) -> Result<(), Error>) -> Result<(), Error> {
+        let mut conn = self.pooled()?;
+        // Portals only live within a transaction.
+        let mut tx = conn.transaction()?;
+
+        let query = format!(
+            "{} ORDER BY order_at, order_number, order_id",
+            order_items_query(
+                "WHERE shop_id = $1
+                AND ($2::TIMESTAMPTZ IS NULL OR order_at >= $2)
+                AND ($3::TIMESTAMPTZ IS NULL OR order_at < $3)"
+            ),
+        );
+        let portal = tx.bind(query.as_str(), &[&shop_id, &from, &to])?;
+
+        let mut current: Option<Order> = None;
+        loop {
+            let rows = tx.query_portal(&portal, EXPORT_BATCH_ROWS)?;
+            for row in rows.iter() {
+                let order_id = row.get::<&str, Uuid>("order_id");
+                if current.as_ref().map_or(true, |order| order.id() != order_id) {
+                    if let Some(order) = current.replace(order(row)) {
+                        each(order)?;
+                    }
+                }
+                add_order_item(current.as_mut().unwrap(), row);
+            }
+            if rows.len() < EXPORT_BATCH_ROWS as usize {
+                break;
+            }
+        }
+        if let Some(order) = current {
+            each(order)?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn place_order(&self, shop_id: Uuid, guest_session_id: Uuid) -> Result<Uuid, Error> {
+        let mut conn = self.connection()?;
+        let mut tx = conn.transaction()?;
+
+        let (items, coupon_code): (String, Option<String>) = query_opt!(
+            tx,
+            "DELETE FROM cart WHERE shop_id = $1 AND guest_session_id = $2 AND items <> ''
+            RETURNING items::TEXT, coupon_code",
+            &[&shop_id, &guest_session_id],
+        )?
+        .map(|row| (row.get(0), row.get(1)))
+        .ok_or_else(|| Error::not_found("Cart"))?;
+
+        // Units wanted of every product and selection in the cart.
+        let rows = query!(
+            tx,
+            "WITH
+                items AS (
+                    SELECT value::PRODUCT_ITEM item FROM each($1::TEXT::HSTORE)
+                )
+            SELECT (item).product_key key, (item).name, (item).count FROM items
+            UNION ALL
+            SELECT (customize).selection_key, (customize).selection, (item).count
+            FROM items, query_product_item_customize_items(item)
+            WHERE (customize).selection_key IS NOT NULL",
+            &[&items],
+        )?;
+        let mut demand: BTreeMap<Uuid, (String, i32)> = BTreeMap::new();
+        for row in rows.iter() {
+            let wanted = demand.entry(row.get("key")).or_insert_with(|| (row.get("name"), 0));
+            wanted.1 += row.get::<&str, i32>("count");
+        }
+
+        let keys: Vec<Uuid> = demand.keys().cloned().collect();
+        // Locked in key order, so checkouts sharing products can't deadlock.
+        let stocked = query!(
+            tx,
+            "SELECT key, stock, sold_out FROM inventory WHERE shop_id = $1 AND key = ANY($2) ORDER BY key FOR UPDATE",
+            &[&shop_id, &keys],
+        )?;
+        for row in stocked.iter() {
+            let (name, count) = &demand[&row.get::<&str, Uuid>("key")];
+            if !Inventory::new(row.get("stock"), row.get("sold_out")).allows(*count) {
+                return Err(Error::unavailable(name));
+            }
+        }
+        for row in stocked.iter().filter(|row| row.get::<&str, Option<i32>>("stock").is_some()) {
+            let key: Uuid = row.get("key");
+            tx.execute(
+                "UPDATE inventory SET stock = stock - $3 WHERE shop_id = $1 AND key = $2",
+                &[&shop_id, &key, &demand[&key].1],
+            )?;
+        }
+
+        // Locked so usage limits hold against concurrent checkouts.
+        let promotions: Vec<Promotion> = query!(
+            tx,
+            format!(
+                "SELECT {} FROM promotions WHERE shop_id = $1 ORDER BY created_at, id FOR UPDATE",
+                PROMOTION_COLUMNS,
+            ).as_str(),
+            &[&shop_id],
+        )?
+        .iter()
+        .filter_map(promotion)
+        .collect();
+        let lines: Vec<Line> = query!(
+            tx,
+            "WITH
+                items AS (
+                    SELECT value::PRODUCT_ITEM item FROM each($1::TEXT::HSTORE)
+                )
+            SELECT
+                (item).product_key,
+                (item).price,
+                (item).count,
+                ARRAY(SELECT (customize).price FROM query_product_item_customize_items(item)) selection_prices
+            FROM
+                items",
+            &[&items],
+        )?
+        .iter()
+        .map(|row| -> Result<Line, Error> {
+            Ok(Line {
+                product_key: row.get("product_key"),
+                unit_price: order::unit_price(row.get("price"), row.get::<&str, Vec<Option<i32>>>("selection_prices"))?,
+                count: row.get("count"),
+            })
+        })
+        .collect::<Result<Vec<Line>, Error>>()?;
+        let applied = promotion::redeem(&promotions, coupon_code.as_ref().map(String::as_str), &lines, Utc::now())?;
+        for promotion in applied.iter() {
+            tx.execute(
+                "UPDATE promotions SET usage_count = usage_count + 1 WHERE id = $1",
+                &[&promotion.promotion_id()],
+            )?;
+        }
+
+        // Numbers are per shop, so serialize checkouts of the same shop. The
+        // lock also keeps the rates from changing under the order.
+        tx.execute("SELECT 1 FROM shops WHERE id = $1 FOR UPDATE", &[&shop_id])?;
+        let settings = query_opt!(tx, TAX_SETTINGS_QUERY, &[&shop_id],)?
+            .map(|row| tax_settings(&row))
+            .ok_or_else(|| Error::not_found("Shop"))?;
+        let product_keys: Vec<Uuid> = lines.iter().map(|line| line.product_key).collect();
+        let series: HashMap<Uuid, Uuid> = query!(
+            tx,
+            "SELECT key, (product).series_id FROM query_shop_products($1) WHERE key = ANY($2) AND (product).series_id IS NOT NULL",
+            &[&shop_id, &product_keys],
+        )?
+        .iter()
+        .map(|row| (row.get("key"), row.get("series_id")))
+        .collect();
+        let discount = applied.iter().map(AppliedPromotion::discount).sum();
+        let charges = settings.charges(shop_id, &lines, &series, discount);
+
+        let (id,) = query_one!(
+            tx,
+            "INSERT INTO orders (shop_id, guest_session_id, order_number, items, tax_inclusive, service_charge_rate, service_charge)
+            SELECT $1, $2, COALESCE(max(order_number), 0) + 1, $3::TEXT::HSTORE, $4, $5, $6
+            FROM orders
+            WHERE shop_id = $1
+            RETURNING id",
+            &[&shop_id, &guest_session_id, &items, &charges.inclusive(), &charges.service_charge_rate(), &charges.service_charge()],
+            (id: Uuid),
+        )?;
+        for (position, promotion) in applied.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO order_promotions (order_id, position, promotion_id, name, code, discount)
+                VALUES ($1, $2, $3, $4, $5, $6)",
+                &[&id, &(position as i32), &promotion.promotion_id(), &promotion.name(), &promotion.code(), &promotion.discount()],
+            )?;
+        }
+        for tax in charges.taxes().iter() {
+            tx.execute(
+                "INSERT INTO order_taxes (order_id, rate, taxable, amount) VALUES ($1, $2, $3, $4)",
+                &[&id, &tax.rate(), &tax.taxable(), &tax.amount()],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(id)
+    }
+}
+
+impl CartRepository for PostgresRepository {
+    fn carts(&self, shop_id: Option<Uuid>, guest_session_id: Option<Uuid>) -> Result<Vec<Cart>, Error> {
+        let mut conn = self.connection()?;
+
+        let mut clause = Clause::new();
+        if let Some(shop_id) = shop_id.as_ref() {
+            clause.and(Clause::equal("shop_id", format!("'{}'", shop_id)));
+        }
+        if let Some(guest_session_id) = guest_session_id.as_ref() {
+            clause.and(Clause::equal("guest_session_id", format!("'{}'", guest_session_id)));
+        }
+
+        let rows = query!(
+            conn,
+            format!(
+                "WITH
+                    query_carts AS (
+                        SELECT * FROM cart {}
+                    ),
+                    query_items AS (
+                        SELECT id cart_id, (each(items)).* FROM query_carts
+                    ),
+                    query_customizes AS (
+                        SELECT key item_key, (query_product_item_customize_items(value::PRODUCT_ITEM)).* FROM query_items
+                    )
+                SELECT
+                    id cart_id,
+                    guest_session_id,
+                    shop_id,
+                    coupon_code,
+                    item_key,
+                    (item).product_key,
+                    (item).name,
+                    (item).price,
+                    (item).count,
+                    (item).remark,
+                    (item).order_at item_order_at,
+                    customize_key,
+                    (customize).name customize_name,
+                    (customize).selection,
+                    (customize).selection_key,
+                    (customize).price selection_price,
+                    (customize).order_at customize_order_at
+                FROM
+                    query_carts
+                LEFT JOIN
+                    (
+                        SELECT
+                            cart_id,
+                            query_items.key::UUID item_key,
+                            query_items.value::PRODUCT_ITEM item,
+                            query_customizes.key::UUID customize_key,
+                            customize
+                        FROM
+                            query_items
+                        LEFT JOIN
+                            query_customizes
+                        ON
+                            query_items.key = query_customizes.item_key
+                    ) item_join_cus
+                ON
+                    query_carts.id = item_join_cus.cart_id
+                ",
+                clause,
+            ).as_str(),
+            &[],
+        )?;
+
+        let mut carts = Dict::new();
+        for row in rows.iter() {
+            let cart_id = row.get::<&str, Uuid>("cart_id");
+            let cart = if let Some(cart) = carts.ref_mut_value(cart_id) {
+                cart
+            } else {
+                let cart = Cart::new(
+                    cart_id,
+                    row.get("shop_id"),
+                    row.get("guest_session_id"),
+                    row.get("coupon_code"),
+                );
+                carts.insert_uncheck(cart_id, cart)
+            };
+
+            if let Ok(item_key) = row.try_get::<&str, Uuid>("item_key") {
+                let item = if let Some(item) = cart.ref_mut_item(item_key) {
+                    item
+                } else {
+                    let item = ProductItem::new(
+                        row.get("shop_id"),
+                        item_key,
+                        row.get("product_key"),
+                        row.get("name"),
+                        row.get("price"),
+                        row.get("count"),
+                        row.get("remark"),
+                        row.get("item_order_at"),
+                    );
+                    cart.insert_item_uncheck(item_key, item)
+                };
+
+                if let Ok(customize_key) = row.try_get::<&str, Uuid>("customize_key") {
+                    if let None = item.ref_mut_customize(customize_key) {
+                        let customize = CustomizeItem::new(
+                            row.get("shop_id"),
+                            customize_key,
+                            row.get("customize_name"),
+                            row.get("selection"),
+                            row.get("selection_key"),
+                            row.get("selection_price"),
+                            row.get("customize_order_at"),
+                        );
+                        item.insert_customize_uncheck(customize_key, customize);
+                    }
+                }
+            }
+        }
+
+        Ok(carts.values())
+    }
+
+    fn add_item(&self, shop_id: Uuid, guest_session_id: Uuid, item: NewItem) -> Result<Uuid, Error> {
+        let mut conn = self.connection()?;
+
+        let key = Uuid::new_v4();
+        let customize_keys: Vec<Uuid> = item.customizes.iter().map(|cus| cus.customize_key).collect();
+        let names: Vec<&String> = item.customizes.iter().map(|cus| &cus.name).collect();
+        let selections: Vec<&Option<String>> = item.customizes.iter().map(|cus| &cus.selection).collect();
+        let selection_keys: Vec<Option<Uuid>> = item.customizes.iter().map(|cus| cus.selection_key).collect();
+        let prices: Vec<Option<i32>> = item.customizes.iter().map(|cus| cus.selection_price).collect();
+
+        conn.execute(
+            "INSERT INTO cart (shop_id, guest_session_id, items)
+            VALUES (
+                $1,
+                $2,
+                hstore(
+                    $3::UUID::TEXT,
+                    ROW(
+                        $4::UUID,
+                        $5::TEXT,
+                        $6::INTEGER,
+                        $7::INTEGER,
+                        $8::TEXT,
+                        (
+                            SELECT COALESCE(
+                                hstore(
+                                    array_agg(key::TEXT),
+                                    array_agg(ROW(name, selection, selection_key, price, now())::CUSTOMIZE_ITEM::TEXT)
+                                ),
+                                ''
+                            )
+                            FROM unnest($9::UUID[], $10::TEXT[], $11::TEXT[], $12::UUID[], $13::INTEGER[])
+                                AS customize (key, name, selection, selection_key, price)
+                        ),
+                        now()
+                    )::PRODUCT_ITEM::TEXT
+                )
+            )
+            ON CONFLICT (shop_id, guest_session_id) DO UPDATE SET items = cart.items || EXCLUDED.items",
+            &[
+                &shop_id,
+                &guest_session_id,
+                &key,
+                &item.product_key,
+                &item.name,
+                &item.price,
+                &item.count,
+                &item.remark,
+                &customize_keys,
+                &names,
+                &selections,
+                &selection_keys,
+                &prices,
+            ],
+        )?;
+
+        Ok(key)
+    }
+
+    fn set_coupon_code(&self, shop_id: Uuid, guest_session_id: Uuid, coupon_code: Option<String>) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+
+        conn.execute(
+            "INSERT INTO cart (shop_id, guest_session_id, coupon_code)
+            SELECT $1, $2, $3
+            WHERE $3 IS NOT NULL
+            ON CONFLICT (shop_id, guest_session_id) DO UPDATE SET coupon_code = EXCLUDED.coupon_code",
+            &[&shop_id, &guest_session_id, &coupon_code],
+        )?;
+        if coupon_code.is_none() {
+            conn.execute(
+                "UPDATE cart SET coupon_code = NULL WHERE shop_id = $1 AND guest_session_id = $2",
+                &[&shop_id, &guest_session_id],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl PromotionRepository for PostgresRepository {
+    fn promotions(&self, shop_id: Uuid, id: Option<Uuid>) -> Result<Vec<Promotion>, Error> {
+        let mut conn = self.connection()?;
+
+        let rows = query!(
+            conn,
+            format!(
+                "SELECT {} FROM promotions
+                WHERE shop_id = $1 AND ($2::UUID IS NULL OR id = $2)
+                ORDER BY created_at, id",
+                PROMOTION_COLUMNS,
+            ).as_str(),
+            &[&shop_id, &id],
+        )?;
+        Ok(rows.iter().filter_map(promotion).collect())
+    }
+
+    fn create_promotion(&self, shop_id: Uuid, terms: PromotionTerms) -> Result<Uuid, Error> {
+        let mut conn = self.connection()?;
+
+        let (percent, amount, product_key, buy_count, get_count) = match terms.reward {
+            Reward::Percentage(percent) => (Some(percent), None, None, None, None),
+            Reward::Fixed(amount) => (None, Some(amount), None, None, None),
+            Reward::BuyGet { product_key, buy, get } => (None, None, Some(product_key), Some(buy), Some(get)),
+        };
+        query_opt!(
+            conn,
+            "INSERT INTO promotions (
+                shop_id, name, kind, percent, amount, product_key, buy_count, get_count,
+                min_spend, starts_at, ends_at, code, usage_limit
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+            ON CONFLICT DO NOTHING
+            RETURNING id",
+            &[
+                &shop_id,
+                &terms.name,
+                &terms.reward.kind().as_str(),
+                &percent,
+                &amount,
+                &product_key,
+                &buy_count,
+                &get_count,
+                &terms.min_spend,
+                &terms.starts_at,
+                &terms.ends_at,
+                &terms.code,
+                &terms.usage_limit,
+            ],
+        )?
+        .map(|row| row.get("id"))
+        .ok_or_else(|| Error::invalid_input("The shop already has a promotion with this code."))
+    }
+
+    fn delete_promotion(&self, shop_id: Uuid, id: Uuid) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+
+        let deleted = conn.execute(
+            "DELETE FROM promotions WHERE shop_id = $1 AND id = $2",
+            &[&shop_id, &id],
+        )?;
+
+        if deleted == 0 {
+            Err(Error::not_found("Promotion"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl AuditLogRepository for PostgresRepository {
+    fn record(&self, shop_id: Uuid, entry: NewAuditEntry) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+
+        let before = entry.before.as_ref().map(serde_json::to_string).transpose()?;
+        let after = entry.after.as_ref().map(serde_json::to_string).transpose()?;
+        conn.execute(
+            "INSERT INTO audit_log (shop_id, actor_id, entity, entity_id, action, before, after)
+            VALUES ($1, $2, $3, $4, $5, $6::TEXT::JSONB, $7::TEXT::JSONB)",
+            &[&shop_id, &entry.actor_id, &entry.entity.as_str(), &entry.entity_id, &entry.action.as_str(), &before, &after],
+        )?;
+        Ok(())
+    }
+
+    fn audit_log(&self, shop_id: Uuid, filter: &AuditLogFilter, offset: i32, limit: i32) -> Result<(Vec<AuditEntry>, i32), Error> {
+        let mut conn = self.connection()?;
+
+        let entity = filter.entity.map(|entity| entity.as_str());
+        let action = filter.action.map(|action| action.as_str());
+        let (total,) = query_one!(
+            conn,
+            format!("SELECT count(*)::INTEGER total FROM audit_log {}", AUDIT_LOG_FILTER).as_str(),
+            &[&shop_id, &entity, &filter.entity_id, &filter.actor_id, &action, &filter.from, &filter.to],
+            (total: i32),
+        )?;
+
+        let rows = query!(
+            conn,
+            format!(
+                "SELECT id, actor_id, entity, entity_id, action, before::TEXT, after::TEXT, created_at
+                FROM audit_log {}
+                ORDER BY created_at DESC, id
+                OFFSET $8
+                LIMIT $9",
+                AUDIT_LOG_FILTER,
+            ).as_str(),
+            &[
+                &shop_id,
+                &entity,
+                &filter.entity_id,
+                &filter.actor_id,
+                &action,
+                &filter.from,
+                &filter.to,
+                &(offset as i64),
+                &(limit as i64),
+            ],
+        )?;
+
+        let mut entries = Vec::new();
+        for row in rows.iter() {
+            let json = |column: &str| -> Result<Option<serde_json::Value>, Error> {
+                Ok(row.get::<&str, Option<String>>(column).map(|text| serde_json::from_str(&text)).transpose()?)
+            };
+            // Rows of entities or actions the table's checks rule out are skipped.
+            let kind = (AuditEntity::parse(row.get("entity")), AuditAction::parse(row.get("action")));
+            if let (Some(entity), Some(action)) = kind {
+                let entry = NewAuditEntry {
+                    actor_id: row.get("actor_id"),
+                    entity: entity,
+                    entity_id: row.get("entity_id"),
+                    action: action,
+                    before: json("before")?,
+                    after: json("after")?,
+                };
+                entries.push(AuditEntry::new(row.get("id"), shop_id, entry, row.get("created_at")));
+            }
+        }
+        Ok((entries, total))
+    }
+}
+
+impl ApiTokenRepository for PostgresRepository {
+    fn api_tokens(&self, user_id: Uuid) -> Result<Vec<ApiToken>, Error> {
+        let mut conn = self.connection()?;
+        let rows = query!(
+            conn,
+            format!("SELECT {} FROM api_tokens WHERE user_id = $1 ORDER BY created_at, id", API_TOKEN_COLUMNS).as_str(),
+            &[&user_id],
+        )?;
+        Ok(rows.iter().map(api_token).collect())
+    }
+
+    fn find_api_token(&self, token_hash: &str) -> Result<Option<ApiToken>, Error> {
+        let mut conn = self.connection()?;
+        let row = query_opt!(
+            conn,
+            format!("SELECT {} FROM api_tokens WHERE token_hash = $1", API_TOKEN_COLUMNS).as_str(),
+            &[&token_hash],
+        )?;
+        Ok(row.as_ref().map(api_token))
+    }
+
+    fn create_api_token(&self, token: NewApiToken) -> Result<Uuid, Error> {
+        let mut conn = self.connection()?;
+        let (id,) = query_one!(
+            conn,
+            "INSERT INTO api_tokens (
+                user_id, shop_id, name, token_hash, member_authority, order_authority, product_authority, expires_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING id",
+            &[
+                &token.user_id,
+                &token.shop_id,
+                &token.name,
+                &token.token_hash,
+                &token.authority.member(),
+                &token.authority.order(),
+                &token.authority.product(),
+                &token.expires_at,
+            ],
+            (id: Uuid),
+        )?;
+        Ok(id)
+    }
+
+    fn revoke_api_token(&self, user_id: Uuid, id: Uuid) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+
+        let deleted = conn.execute(
+            "DELETE FROM api_tokens WHERE user_id = $1 AND id = $2",
+            &[&user_id, &id],
+        )?;
+
+        if deleted == 0 {
+            Err(Error::not_found("API token"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl RoleRepository for PostgresRepository {
+    fn roles(&self, shop_id: Uuid, id: Option<Uuid>) -> Result<Vec<Role>, Error> {
+        let mut conn = self.connection()?;
+        let rows = query!(
+            conn,
+            format!(
+                "SELECT {} FROM roles
+                WHERE shop_id = $1 AND ($2::UUID IS NULL OR id = $2)
+                ORDER BY name",
+                ROLE_COLUMNS,
+            ).as_str(),
+            &[&shop_id, &id],
+        )?;
+        Ok(rows.iter().map(role).collect())
+    }
+
+    fn create_role(&self, shop_id: Uuid, name: String, capabilities: &Capabilities) -> Result<Uuid, Error> {
+        let mut conn = self.connection()?;
+        let row = query_opt!(
+            conn,
+            "INSERT INTO roles (shop_id, name, capabilities)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            RETURNING id",
+            &[&shop_id, &name, &capabilities.to_strings()],
+        )?;
+        row.map(|row| row.get("id"))
+            .ok_or_else(|| Error::invalid_input("The shop already has a role with this name."))
+    }
+
+    fn update_role(&self, shop_id: Uuid, id: Uuid, name: Option<String>, capabilities: Option<&Capabilities>) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+        let mut tx = conn.transaction()?;
+
+        let (exists, taken) = query_one!(
+            tx,
+            "SELECT
+                EXISTS (SELECT 1 FROM roles WHERE shop_id = $1 AND id = $2) exists,
+                EXISTS (SELECT 1 FROM roles WHERE shop_id = $1 AND id <> $2 AND name = $3) taken",
+            &[&shop_id, &id, &name],
+            (exists: bool, taken: bool),
+        )?;
+        if !exists {
+            return Err(Error::not_found("Role"));
+        }
+        if taken {
+            return Err(Error::invalid_input("The shop already has a role with this name."));
+        }
+
+        tx.execute(
+            "UPDATE roles SET
+                name = COALESCE($3, name),
+                capabilities = COALESCE($4, capabilities)
+            WHERE shop_id = $1 AND id = $2",
+            &[&shop_id, &id, &name, &capabilities.map(Capabilities::to_strings)],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn delete_role(&self, shop_id: Uuid, id: Uuid) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+        let mut tx = conn.transaction()?;
+
+        let (exists, in_use) = query_one!(
+            tx,
+            "SELECT
+                EXISTS (SELECT 1 FROM roles WHERE shop_id = $1 AND id = $2 FOR UPDATE) exists,
+                EXISTS (SELECT 1 FROM shop_user WHERE shop_id = $1 AND role_id = $2) in_use",
+            &[&shop_id, &id],
+            (exists: bool, in_use: bool),
+        )?;
+        if !exists {
+            return Err(Error::not_found("Role"));
+        }
+        if in_use {
+            return Err(Error::invalid_input("Members still have this role."));
+        }
+
+        tx.execute("DELETE FROM roles WHERE shop_id = $1 AND id = $2", &[&shop_id, &id])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn set_member_role(&self, shop_id: Uuid, user_id: Uuid, role_id: Uuid) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+        let updated = conn.execute(
+            "UPDATE shop_user SET role_id = $3 WHERE shop_id = $1 AND user_id = $2",
+            &[&shop_id, &user_id, &role_id],
+        )?;
+        if updated == 0 {
+            return Err(Error::not_found("Member"));
+        }
+        Ok(())
+    }
+}
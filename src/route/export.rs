@@ -0,0 +1,276 @@
+use std::{io, thread};
+use chrono::{DateTime, Utc};
+use futures::{Future, Sink, Stream, sync::mpsc};
+use hyper::Body;
+use uuid::Uuid;
+use warp::{
+    Filter,
+    filters::BoxedFilter,
+    http::{
+        Response,
+        StatusCode,
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE, RETRY_AFTER},
+    },
+    path,
+    reply::Reply,
+};
+use crate::{
+    graphql::{
+        Context,
+        order::Order,
+        role::Capability,
+    },
+    route::{blocking, context_filter},
+    state::{
+        State,
+        slots::Slot,
+    },
+    error::Error,
+};
+
+// Chunks of rows buffered ahead of a slow client, one per order.
+const BUFFERED_ORDERS: usize = 16;
+
+// Seconds clients are asked to wait while every export slot is taken.
+const BUSY_RETRY_AFTER: u64 = 10;
+
+const COLUMNS: [&str; 15] = [
+    "order_number",
+    "order_id",
+    "order_at",
+    "item_key",
+    "product_key",
+    "product",
+    "item_order_at",
+    "price",
+    "count",
+    "unit_price",
+    "subtotal",
+    "remark",
+    "customize",
+    "selection",
+    "selection_price",
+];
+
+#[derive(Deserialize, Default)]
+struct ExportQuery {
+    from: Option<String>,
+    to: Option<String>,
+}
+
+fn parse_bound(name: &str, value: Option<String>) -> Result<Option<DateTime<Utc>>, Error> {
+    value
+        .map(|value| {
+            DateTime::parse_from_rfc3339(&value)
+                .map(|at| at.with_timezone(&Utc))
+                .map_err(|_| Error::invalid_input(&format!(r#"Invalid "{}", expected an RFC 3339 date time."#, name)))
+        })
+        .transpose()
+}
+
+// `GET /shops/{shop_id}/orders.csv?from=&to=`, orders placed in `[from, to)`
+// with either bound optional. Needs a USSID with order authority in the shop.
+// Answers 503 while as many exports as the state allows are running.
+pub fn orders_csv_filter(state: State) -> BoxedFilter<(impl Reply,)> {
+    warp::get2()
+    .and(warp::path!("shops" / Uuid / "orders.csv"))
+    .and(path::end())
+    .and(
+        warp::query::<ExportQuery>()
+        .or(warp::any().map(ExportQuery::default))
+        .unify()
+    )
+    .and(context_filter(state))
+    .and_then(move |shop_id: Uuid, query: ExportQuery, context: Context| {
+        blocking(move || {
+            let bounds = context.require_capability(shop_id, Capability::ViewOrders)
+                .and_then(|_| Ok((parse_bound("from", query.from)?, parse_bound("to", query.to)?)));
+            (context, bounds)
+        })
+        .map(move |(context, bounds)| match bounds {
+            Ok((from, to)) => match context.state().exports().try_take() {
+                Some(slot) => csv_response(shop_id, stream_orders(context, slot, shop_id, from, to)),
+                None => busy_response(),
+            },
+            Err(err) => error_response(err),
+        })
+    })
+    .boxed()
+}
+
+fn csv_response(shop_id: Uuid, body: Body) -> Response<Body> {
+    Response::builder()
+        .header(CONTENT_TYPE, "text/csv; charset=utf-8")
+        .header(CONTENT_DISPOSITION, format!(r#"attachment; filename="orders-{}.csv""#, shop_id).as_str())
+        .body(body)
+        .unwrap()
+}
+
+fn busy_response() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header(CONTENT_TYPE, "application/json")
+        .header(RETRY_AFTER, BUSY_RETRY_AFTER.to_string().as_str())
+        .body(Body::from(Error::unavailable("Too many exports are running, try again later.").to_string()))
+        .unwrap()
+}
+
+fn error_response(err: Error) -> Response<Body> {
+    let status = match err.error_type() {
+        "NoValidCookie" | "SessionExpired" => StatusCode::UNAUTHORIZED,
+        "Unauthorized" => StatusCode::FORBIDDEN,
+        "InvalidInput" => StatusCode::BAD_REQUEST,
+        _ => {
+            error!("Failed to export orders: {:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    };
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(err.to_string()))
+        .unwrap()
+}
+
+// The repository hands orders over one at a time from its own thread, which
+// waits whenever the client falls `BUFFERED_ORDERS` behind. A failure after
+// the header is sent aborts the body, so clients see a truncated download
+// rather than a short but well-formed file. The thread owns the context and
+// export slot, so both are held until the export is done.
+fn stream_orders(context: Context, slot: Slot, shop_id: Uuid, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Body {
+    let (sender, receiver) = mpsc::channel::<Result<String, io::Error>>(BUFFERED_ORDERS);
+
+    thread::spawn(move || {
+        let _slot = slot;
+        let mut sender = sender.wait();
+        let mut header = String::new();
+        write_row(&mut header, COLUMNS.iter().map(|column| column.to_string()));
+        if sender.send(Ok(header)).is_err() {
+            return;
+        }
+
+        let result = context.state().orders().export_orders(shop_id, from, to, &mut |order| {
+            sender.send(Ok(order_rows(&order)?))
+                .map_err(|_| Error::new("ExportCancelled", "Client went away."))
+        });
+        match result {
+            Ok(()) => (),
+            Err(ref err) if err.error_type() == "ExportCancelled" => (),
+            Err(err) => {
+                error!("Failed to export orders: {:?}", err);
+                let _ = sender.send(Err(io::Error::new(io::ErrorKind::Other, err.to_string())));
+            }
+        }
+        let _ = sender.flush();
+    });
+
+    Body::wrap_stream(
+        receiver
+        .map_err(|()| io::Error::new(io::ErrorKind::Other, "Export channel failed."))
+        .and_then(|chunk| chunk)
+    )
+}
+
+// Spreadsheets run cells starting with these as formulas.
+fn is_formula(value: &str) -> bool {
+    value.starts_with(|c| c == '=' || c == '+' || c == '-' || c == '@' || c == '\t' || c == '\r')
+}
+
+// A text cell, neutralizing formulas and quoting as RFC 4180 requires.
+fn text(value: &str) -> String {
+    let value = if is_formula(value) { format!("'{}", value) } else { value.to_string() };
+    if value.contains(|c| c == ',' || c == '"' || c == '\r' || c == '\n') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+fn write_row<I: IntoIterator<Item = String>>(out: &mut String, cells: I) {
+    let cells: Vec<String> = cells.into_iter().collect();
+    out.push_str(&cells.join(","));
+    out.push_str("\r\n");
+}
+
+// One row per customize of each item, or one per item without customizes.
+// Item columns repeat across the rows of an item.
+fn order_rows(order: &Order) -> Result<String, Error> {
+    let mut out = String::new();
+    for item in order.items() {
+        let item_cells = vec![
+            order.order_number().to_string(),
+            order.id().to_string(),
+            order.order_at().to_rfc3339(),
+            item.key().to_string(),
+            item.product_key().to_string(),
+            text(item.name()),
+            item.order_at().to_rfc3339(),
+            item.price().to_string(),
+            item.count().to_string(),
+            item.unit_price()?.to_string(),
+            item.subtotal()?.to_string(),
+            item.remark().as_ref().map_or(String::new(), |remark| text(remark)),
+        ];
+
+        if item.customizes().is_empty() {
+            write_row(&mut out, item_cells.into_iter().chain(vec![String::new(); 3]));
+        }
+        for customize in item.customizes() {
+            write_row(&mut out, item_cells.iter().cloned().chain(vec![
+                text(customize.name()),
+                customize.selection().as_ref().map_or(String::new(), |selection| text(selection)),
+                customize.selection_price().map_or(String::new(), |price| price.to_string()),
+            ]));
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use uuid::Uuid;
+    use chrono::{TimeZone, Utc};
+    use crate::graphql::order::{Order, ProductItem, CustomizeItem};
+    use super::{text, order_rows, parse_bound, COLUMNS};
+
+    #[test]
+    fn test_text() {
+        assert_eq!(text("Black Tea"), "Black Tea");
+        assert_eq!(text("Less ice, no sugar"), "\"Less ice, no sugar\"");
+        assert_eq!(text("The \"big\" one"), "\"The \"\"big\"\" one\"");
+        assert_eq!(text("two\nlines"), "\"two\nlines\"");
+        assert_eq!(text("=HYPERLINK(\"x\")"), "\"'=HYPERLINK(\"\"x\"\")\"");
+        assert_eq!(text("-1"), "'-1");
+    }
+
+    #[test]
+    fn test_order_rows() {
+        let at = Utc.with_ymd_and_hms(2024, 1, 1, 4, 0, 0).unwrap();
+        let mut order = Order::new(Uuid::nil(), Uuid::nil(), Uuid::nil(), 7, at);
+
+        let mut rice = ProductItem::new(Uuid::nil(), Uuid::nil(), Uuid::nil(), "Pork Rice".to_string(), 80, 2, Some("Extra, sauce".to_string()), at);
+        rice.insert_customize_uncheck(Uuid::new_v4(), CustomizeItem::new(Uuid::nil(), Uuid::nil(), "Size".to_string(), Some("Large".to_string()), None, Some(20), at));
+        rice.insert_customize_uncheck(Uuid::new_v4(), CustomizeItem::new(Uuid::nil(), Uuid::nil(), "Egg".to_string(), None, None, None, at));
+        order.insert_item_uncheck(Uuid::new_v4(), rice);
+        order.insert_item_uncheck(Uuid::new_v4(), ProductItem::new(Uuid::nil(), Uuid::nil(), Uuid::nil(), "Black Tea".to_string(), 30, 1, None, at));
+
+        let rows = order_rows(&order).unwrap();
+        let lines: Vec<&str> = rows.split("\r\n").filter(|line| !line.is_empty()).collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("7,"));
+        assert!(lines[0].ends_with(",80,2,100,200,\"Extra, sauce\",Size,Large,20"));
+        assert!(lines[1].ends_with(",80,2,100,200,\"Extra, sauce\",Egg,,"));
+        assert!(lines[2].ends_with(",Black Tea,2024-01-01T04:00:00+00:00,30,1,30,30,,,,"));
+        assert_eq!(lines[2].split(',').count(), COLUMNS.len());
+    }
+
+    #[test]
+    fn test_parse_bound() {
+        assert_eq!(parse_bound("from", None).unwrap(), None);
+        assert_eq!(
+            parse_bound("from", Some("2024-01-01T12:00:00+08:00".to_string())).unwrap(),
+            Some(Utc.with_ymd_and_hms(2024, 1, 1, 4, 0, 0).unwrap()),
+        );
+        assert!(parse_bound("from", Some("2024-01-01".to_string())).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use std::sync::Arc;
+use futures::{Future, future::{self, Either}};
+use hyper::Body;
+use juniper::{
+    InputValue,
+    http::{GraphQLRequest, GraphQLResponse},
+};
+use serde_json::json;
+use warp::{
+    Filter,
+    filters::BoxedFilter,
+    http::{
+        Response,
+        StatusCode,
+        header::{CONTENT_TYPE, RETRY_AFTER},
+    },
+    reply::Reply,
+};
+use crate::{
+    graphql::{
+        Context,
+        Schema,
+    },
+    route::blocking,
+};
+
+#[derive(Deserialize)]
+struct GetRequest {
+    query: String,
+    #[serde(rename = "operationName", alias = "operation_name")]
+    operation_name: Option<String>,
+    // JSON, as the query string can't hold it otherwise.
+    variables: Option<String>,
+}
+
+impl GetRequest {
+    fn into_request(self) -> Result<GraphQLRequest, String> {
+        let variables = self.variables
+            .map(|variables| serde_json::from_str::<InputValue>(&variables))
+            .transpose()
+            .map_err(|_| r#"Parameter "variables" is not valid JSON."#.to_string())?;
+        Ok(GraphQLRequest::new(self.query, self.operation_name, variables))
+    }
+}
+
+// `POST /graphql` with a JSON request, or `GET /graphql?query=&operationName=&variables=`.
+pub fn graphql_filter(schema: Arc<Schema>, context: BoxedFilter<(Context,)>) -> BoxedFilter<(impl Reply,)> {
+    let post = warp::post2()
+        .and(warp::body::json())
+        .map(|request: GraphQLRequest| Ok::<_, String>(request));
+    let get = warp::get2()
+        .and(warp::query::<GetRequest>())
+        .map(GetRequest::into_request);
+
+    post.or(get)
+    .unify()
+    .and(context)
+    .and_then(move |request: Result<GraphQLRequest, String>, context: Context| {
+        let schema = schema.clone();
+        match request {
+            Ok(request) => Either::A(blocking(move || graphql_response(&request.execute(&schema, &context), &context))),
+            Err(message) => Either::B(future::ok(bad_request(&message))),
+        }
+    })
+    .boxed()
+}
+
+// 400 when the request failed as a whole, and 429 with `Retry-After` when a
+// field was rate limited.
+pub fn graphql_response(response: &GraphQLResponse, context: &Context) -> Response<Body> {
+    let mut builder = Response::builder();
+    builder.header(CONTENT_TYPE, "application/json");
+    if let Some(retry_after) = context.retry_after() {
+        builder
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .header(RETRY_AFTER, retry_after.to_string().as_str());
+    } else if !response.is_ok() {
+        builder.status(StatusCode::BAD_REQUEST);
+    }
+    builder.body(Body::from(serde_json::to_vec(response).unwrap())).unwrap()
+}
+
+pub fn bad_request(message: &str) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "errors": [{ "message": message }] }).to_string()))
+        .unwrap()
+}
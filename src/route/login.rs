@@ -0,0 +1,75 @@
+use std::net::IpAddr;
+use chrono::Utc;
+use futures::Future;
+use hyper::Body;
+use serde_json::json;
+use warp::{
+    Filter,
+    filters::BoxedFilter,
+    http::{
+        Response,
+        StatusCode,
+        header::{CONTENT_TYPE, SET_COOKIE},
+    },
+    path,
+    reply::Reply,
+};
+use crate::{
+    auth::{self, Session},
+    route::{blocking, client_addr},
+    state::State,
+    error::Error,
+};
+
+#[derive(Deserialize)]
+struct LoginRequest {
+    username: String,
+    password: String,
+}
+
+// `POST /login` with `{ "username": "", "password": "" }`. Sets the USSID
+// cookie to a new user session.
+pub fn login_filter(state: State) -> BoxedFilter<(impl Reply,)> {
+    warp::post2()
+    .and(path("login"))
+    .and(path::end())
+    .and(warp::body::json())
+    .and(client_addr(state.clone()))
+    .and_then(move |request: LoginRequest, addr: Option<IpAddr>| {
+        let state = state.clone();
+        blocking(move || auth::login(&state, &request.username, &request.password, addr, Utc::now()))
+        .map(|session| match session {
+            Ok(session) => session_response(session),
+            Err(err) => error_response(err),
+        })
+    })
+    .boxed()
+}
+
+fn session_response(session: Session) -> Response<Body> {
+    let max_age = (session.expire_time - Utc::now()).num_seconds().max(0);
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .header(
+            SET_COOKIE,
+            format!("USSID={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax", session.id, max_age).as_str(),
+        )
+        .body(Body::from(json!({ "expireTime": session.expire_time.to_rfc3339() }).to_string()))
+        .unwrap()
+}
+
+fn error_response(err: Error) -> Response<Body> {
+    let status = match err.error_type() {
+        "InvalidCredentials" => StatusCode::UNAUTHORIZED,
+        "LoginLocked" => StatusCode::TOO_MANY_REQUESTS,
+        _ => {
+            error!("Failed to log in: {:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    };
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(err.to_string()))
+        .unwrap()
+}
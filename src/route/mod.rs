@@ -1,28 +1,89 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+use futures::{Future, future::poll_fn};
 use warp::{
     Filter,
+    Rejection,
     reply::Reply,
     filters::BoxedFilter,
     cookie,
+    header,
     path,
     options,
 };
-use juniper_warp::{
-    make_graphql_filter,
-    graphiql_filter,
-};
+use juniper_warp::graphiql_filter;
 use uuid::Uuid;
 use crate::{
     graphql::{
         Context,
         schema,
+        locale,
     },
     state::State,
 };
 
+mod graphql;
+mod upload;
+mod picture;
+mod export;
+mod rate_limit;
+mod login;
+
+// Run blocking work, such as repository or storage calls, off the reactor.
+fn blocking<F, T>(f: F) -> impl Future<Item = T, Error = Rejection>
+where
+    F: FnOnce() -> T,
+{
+    let mut f = Some(f);
+    poll_fn(move || tokio_threadpool::blocking(|| (f.take().unwrap())()))
+    .map_err(warp::reject::custom)
+}
+
+// The secret of an `Authorization: Bearer` header.
+fn bearer_token(authorization: Option<String>) -> Option<String> {
+    authorization
+        .filter(|value| value.starts_with("Bearer "))
+        .map(|value| value["Bearer ".len()..].trim().to_string())
+        .filter(|token| !token.is_empty())
+}
+
+// The address a request came from. Behind proxies given as trusted, that's
+// the last address in `X-Forwarded-For` they didn't add themselves; anywhere
+// else the header is ignored, as clients can send anything in it.
+fn client_addr(state: State) -> BoxedFilter<(Option<IpAddr>,)> {
+    warp::addr::remote()
+    .and(header::optional::<String>("x-forwarded-for"))
+    .map(move |remote_addr: Option<SocketAddr>, forwarded_for: Option<String>| {
+        forwarded_addr(remote_addr.map(|addr| addr.ip()), forwarded_for.as_ref().map(String::as_str), state.trusted_proxies())
+    })
+    .boxed()
+}
+
+fn forwarded_addr(remote_addr: Option<IpAddr>, forwarded_for: Option<&str>, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+    let mut addr = remote_addr?;
+    if let Some(forwarded_for) = forwarded_for {
+        for hop in forwarded_for.rsplit(',') {
+            if !trusted_proxies.contains(&addr) {
+                break;
+            }
+            match hop.trim().parse::<IpAddr>() {
+                Ok(hop) => addr = hop,
+                Err(_) => break,
+            }
+        }
+    }
+    Some(addr)
+}
+
 fn context_filter(state: State) -> BoxedFilter<(Context,)> {
     cookie::optional("USSID")
     .and(cookie::optional("GSSID"))
-    .map(move |user_session_cookie: Option<String>, guest_session_cookie: Option<String>| {
+    .and(header::optional::<String>("authorization"))
+    .and(header::optional::<String>("accept-language"))
+    .and(client_addr(state.clone()))
+    .map(move |user_session_cookie: Option<String>, guest_session_cookie: Option<String>, authorization: Option<String>, accept_language: Option<String>, remote_addr: Option<IpAddr>| {
         let user_session_id = if let Some(cookie) = user_session_cookie {
             if let Ok(id) = Uuid::parse_str(cookie.as_str()) {
                 Some(id)
@@ -48,14 +109,24 @@ fn context_filter(state: State) -> BoxedFilter<(Context,)> {
             user_session_id,
             guest_session_id,
         )
+        .with_bearer_token(bearer_token(authorization))
+        .with_locales(accept_language.map_or(Vec::new(), |value| locale::accept_language(&value)))
+        .with_remote_addr(remote_addr)
     })
     .boxed()
 }
 
 pub fn routes(state: State) -> BoxedFilter<(impl Reply,)> {
-    path("graphql").and(
-        make_graphql_filter(schema(), context_filter(state.clone()))
+    rate_limit::rate_limit_filter(state.clone())
+    .or(
+        path("graphql").and(
+            upload::graphql_upload_filter(Arc::new(schema()), context_filter(state.clone()))
+            .or(graphql::graphql_filter(Arc::new(schema()), context_filter(state.clone())))
+        )
     )
+    .or(picture::pictures_filter(state.clone()))
+    .or(export::orders_csv_filter(state.clone()))
+    .or(login::login_filter(state.clone()))
     .or(
         path("graphiql").and(
             graphiql_filter("/graphql")
@@ -70,9 +141,30 @@ pub fn dev_routes(state: State) -> BoxedFilter<(impl Reply,)> {
         // filter for preflight requests.
         options().map(warp::reply)
     )
-    .map(|reply| warp::reply::with_header(reply, "Access-Control-Allow-Headers", "Content-Type"))
+    .map(|reply| warp::reply::with_header(reply, "Access-Control-Allow-Headers", "Content-Type, Authorization"))
     .map(|reply| warp::reply::with_header(reply, "Access-Control-Allow-Credentials", "true"))
     .map(|reply| warp::reply::with_header(reply, "Access-Control-Allow-Origin", "http://localhost:3000"))
     .map(|reply| warp::reply::with_header(reply, "Access-Control-Allow-Methods", "GET, POST, OPTIONS, PUT, PATCH, DELETE"))
     .boxed()
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+    use super::forwarded_addr;
+
+    #[test]
+    fn test_forwarded_addr() {
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+        let proxies = [ip("10.0.0.1"), ip("10.0.0.2")];
+
+        // Only trusted proxies are believed, and only about the hops before
+        // them.
+        assert_eq!(forwarded_addr(Some(ip("192.0.2.1")), Some("198.51.100.1"), &proxies), Some(ip("192.0.2.1")));
+        assert_eq!(forwarded_addr(Some(ip("10.0.0.1")), Some("198.51.100.1"), &proxies), Some(ip("198.51.100.1")));
+        assert_eq!(forwarded_addr(Some(ip("10.0.0.1")), Some("203.0.113.9, 198.51.100.1, 10.0.0.2"), &proxies), Some(ip("198.51.100.1")));
+        assert_eq!(forwarded_addr(Some(ip("10.0.0.1")), Some("made-up"), &proxies), Some(ip("10.0.0.1")));
+        assert_eq!(forwarded_addr(Some(ip("10.0.0.1")), None, &proxies), Some(ip("10.0.0.1")));
+        assert_eq!(forwarded_addr(None, Some("198.51.100.1"), &proxies), None);
+    }
+}
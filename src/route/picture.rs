@@ -0,0 +1,110 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+use futures::Future;
+use uuid::Uuid;
+use warp::{
+    Filter,
+    filters::BoxedFilter,
+    http::{
+        Response,
+        StatusCode,
+        header::{CACHE_CONTROL, CONTENT_TYPE, ETAG},
+    },
+    path,
+    reply::Reply,
+};
+use crate::{
+    picture::{Picture, Variant},
+    route::blocking,
+    state::State,
+};
+
+// Pictures are replaced in place, so caches revalidate against the ETag.
+const CACHE_CONTROL_VALUE: &str = "public, max-age=300, must-revalidate";
+
+#[derive(Deserialize, Default)]
+struct PictureQuery {
+    #[serde(default)]
+    size: Variant,
+}
+
+// `GET /pictures/{shop_id}/{product_key}?size=thumbnail|medium|original`
+pub fn pictures_filter(state: State) -> BoxedFilter<(impl Reply,)> {
+    warp::get2()
+    .and(warp::path!("pictures" / Uuid / Uuid))
+    .and(path::end())
+    .and(
+        warp::query::<PictureQuery>()
+        .or(warp::any().map(PictureQuery::default))
+        .unify()
+    )
+    .and(warp::header::optional::<String>("if-none-match"))
+    .and_then(move |shop_id: Uuid, product_key: Uuid, query: PictureQuery, if_none_match: Option<String>| {
+        let state = state.clone();
+        blocking(move || state.pictures().get(shop_id, product_key, query.size))
+        .and_then(|picture| match picture {
+            Ok(Some(picture)) => Ok(picture),
+            Ok(None) => Err(warp::reject::not_found()),
+            Err(err) => {
+                error!("Failed to read picture: {}", err);
+                Err(warp::reject::custom(err.to_string()))
+            }
+        })
+        .map(move |picture| picture_response(picture, if_none_match))
+    })
+    .boxed()
+}
+
+fn etag(bytes: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+fn picture_response(picture: Picture, if_none_match: Option<String>) -> Response<Vec<u8>> {
+    let etag = etag(&picture.bytes);
+    let not_modified = if_none_match.map_or(false, |tags| {
+        tags.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*")
+    });
+
+    let mut builder = Response::builder();
+    builder
+        .header(CACHE_CONTROL, CACHE_CONTROL_VALUE)
+        .header(ETAG, etag.as_str());
+    if not_modified {
+        builder.status(StatusCode::NOT_MODIFIED).body(Vec::new())
+    } else {
+        builder.header(CONTENT_TYPE, picture.content_type).body(picture.bytes)
+    }
+    .unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use warp::http::{
+        StatusCode,
+        header::{CACHE_CONTROL, ETAG},
+    };
+    use crate::picture::Picture;
+    use super::picture_response;
+
+    #[test]
+    fn test_picture_response() {
+        let picture = || Picture::new(b"\x89PNG\r\n\x1a\n".to_vec());
+
+        let response = picture_response(picture(), None);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "image/png");
+        assert!(response.headers().contains_key(CACHE_CONTROL));
+        let etag = response.headers()[ETAG].to_str().unwrap().to_string();
+
+        let response = picture_response(picture(), Some(format!("\"other\", {}", etag)));
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert!(response.body().is_empty());
+
+        let response = picture_response(picture(), Some("\"other\"".to_string()));
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,54 @@
+use std::net::IpAddr;
+use futures::Future;
+use hyper::Body;
+use uuid::Uuid;
+use warp::{
+    Filter,
+    filters::BoxedFilter,
+    http::{
+        Response,
+        StatusCode,
+        header::{CONTENT_TYPE, RETRY_AFTER},
+    },
+    cookie,
+    header,
+};
+use crate::{
+    rate_limit::Client,
+    route::{blocking, bearer_token, client_addr},
+    state::State,
+    error::Error,
+};
+
+// Turns away clients that have spent their budget with a 429, before any
+// route runs. Requests that may go ahead are rejected, so the routes after
+// this one get them. Nothing is looked up here: credentials are checked by
+// the resolvers that need them.
+pub fn rate_limit_filter(state: State) -> BoxedFilter<(Response<Body>,)> {
+    cookie::optional("USSID")
+    .and(cookie::optional("GSSID"))
+    .and(header::optional::<String>("authorization"))
+    .and(client_addr(state.clone()))
+    .and_then(move |user_session_cookie: Option<String>, guest_session_cookie: Option<String>, authorization: Option<String>, addr: Option<IpAddr>| {
+        let state = state.clone();
+        let session = |cookie: Option<String>| cookie.and_then(|cookie| Uuid::parse_str(&cookie).ok());
+        let bearer_token = bearer_token(authorization);
+        let client = Client::claimed(session(user_session_cookie), session(guest_session_cookie), bearer_token.as_ref().map(String::as_str), addr);
+        // Shared rate limits are kept in the database.
+        blocking(move || state.rate_limiter().check(&client, addr))
+        .and_then(|retry_after| match retry_after {
+            Some(retry_after) => Ok(too_many_requests(retry_after)),
+            None => Err(warp::reject::not_found()),
+        })
+    })
+    .boxed()
+}
+
+fn too_many_requests(retry_after: u64) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header(CONTENT_TYPE, "application/json")
+        .header(RETRY_AFTER, retry_after.to_string().as_str())
+        .body(Body::from(Error::rate_limited(retry_after).to_string()))
+        .unwrap()
+}
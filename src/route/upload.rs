@@ -0,0 +1,161 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+};
+use futures::{
+    Future,
+    Stream,
+    future::{self, Either},
+};
+use juniper::http::GraphQLRequest;
+use serde_json::Value;
+use warp::{
+    Buf,
+    Filter,
+    Rejection,
+    filters::BoxedFilter,
+    multipart::{self, FormData, Part},
+    reply::Reply,
+};
+use crate::{
+    graphql::{
+        Context,
+        Schema,
+        Upload,
+    },
+    picture::MAX_PICTURE_SIZE,
+    route::{
+        blocking,
+        graphql::{graphql_response, bad_request},
+    },
+};
+
+// Room for `operations` and `map` next to the file itself.
+const MAX_FORM_SIZE: u64 = MAX_PICTURE_SIZE as u64 + 64 * 1024;
+
+// Serves the GraphQL multipart request spec: an `operations` field holding the
+// request, a `map` field pointing each file at variables, then the files.
+// Mapped variables are set to the name of their file, which resolvers read
+// back through `Context::upload`.
+pub fn graphql_upload_filter(schema: Arc<Schema>, context: BoxedFilter<(Context,)>) -> BoxedFilter<(impl Reply,)> {
+    warp::post2()
+    .and(multipart::form().max_length(MAX_FORM_SIZE))
+    .and(context)
+    .and_then(move |form: FormData, context: Context| {
+        let schema = schema.clone();
+        read_form(form).and_then(move |form| match form {
+            Ok((request, uploads)) => {
+                let context = context.with_uploads(uploads);
+                Either::A(blocking(move || graphql_response(&request.execute(&schema, &context), &context)))
+            }
+            Err(message) => {
+                Either::B(future::ok(bad_request(&message)))
+            }
+        })
+    })
+    .boxed()
+}
+
+fn read_form(form: FormData) -> impl Future<Item = Result<(GraphQLRequest, HashMap<String, Upload>), String>, Error = Rejection> {
+    form.and_then(read_part)
+    .collect()
+    .map(parse_form)
+    .map_err(warp::reject::custom)
+}
+
+fn read_part(part: Part) -> impl Future<Item = (String, Vec<u8>), Error = warp::Error> {
+    let name = part.name().to_string();
+    part.fold(Vec::new(), |mut bytes, chunk| {
+        bytes.extend_from_slice(chunk.bytes());
+        Ok::<_, warp::Error>(bytes)
+    })
+    .map(move |bytes| (name, bytes))
+}
+
+fn parse_form(parts: Vec<(String, Vec<u8>)>) -> Result<(GraphQLRequest, HashMap<String, Upload>), String> {
+    let mut operations = None;
+    let mut map = HashMap::new();
+    let mut uploads = HashMap::new();
+    for (name, bytes) in parts {
+        match name.as_str() {
+            "operations" => {
+                operations = Some(
+                    serde_json::from_slice::<Value>(&bytes)
+                    .map_err(|_| r#"Field "operations" is not valid JSON."#.to_string())?
+                );
+            }
+            "map" => {
+                map = serde_json::from_slice::<HashMap<String, Vec<String>>>(&bytes)
+                    .map_err(|_| r#"Field "map" must map file names to lists of paths."#.to_string())?;
+            }
+            _ => {
+                uploads.insert(name, Upload { bytes: bytes });
+            }
+        }
+    }
+
+    let mut operations = operations.ok_or_else(|| r#"Missing field "operations"."#.to_string())?;
+    for (file, paths) in map {
+        if !uploads.contains_key(&file) {
+            return Err(format!(r#"Missing file "{}"."#, file));
+        }
+        for path in paths {
+            let target = path.split('.').try_fold(&mut operations, |value, key| match value {
+                Value::Object(object) => object.get_mut(key),
+                Value::Array(list) => key.parse::<usize>().ok().and_then(move |index| list.get_mut(index)),
+                _ => None,
+            })
+            .ok_or_else(|| format!(r#"Cannot map file "{}" to "{}"."#, file, path))?;
+            *target = Value::String(file.clone());
+        }
+    }
+
+    let request = serde_json::from_value(operations)
+        .map_err(|_| r#"Field "operations" must hold a single GraphQL request."#.to_string())?;
+    Ok((request, uploads))
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use super::parse_form;
+
+    fn part(name: &str, bytes: &[u8]) -> (String, Vec<u8>) {
+        (name.to_string(), bytes.to_vec())
+    }
+
+    #[test]
+    fn test_parse_form() {
+        let operations = json!({
+            "query": "mutation ($file: String!) { shop { uploadProductPicture(shopId: \"\", productKey: \"\", file: $file) { key } } }",
+            "variables": { "file": null },
+        });
+        let (request, uploads) = parse_form(vec![
+            part("operations", operations.to_string().as_bytes()),
+            part("map", br#"{ "0": ["variables.file"] }"#),
+            part("0", b"picture"),
+        ]).unwrap();
+
+        assert_eq!(uploads["0"].bytes, b"picture");
+        assert_eq!(
+            serde_json::to_value(&request).unwrap()["variables"],
+            json!({ "file": "0" }),
+        );
+    }
+
+    #[test]
+    fn test_parse_form_errors() {
+        let operations = json!({ "query": "{ __typename }", "variables": {} }).to_string();
+
+        assert!(parse_form(vec![part("0", b"picture")]).is_err());
+        assert!(parse_form(vec![
+            part("operations", operations.as_bytes()),
+            part("map", br#"{ "0": ["variables.file"] }"#),
+        ]).is_err());
+        assert!(parse_form(vec![
+            part("operations", operations.as_bytes()),
+            part("map", br#"{ "0": ["variables.file"] }"#),
+            part("0", b"picture"),
+        ]).is_err());
+    }
+}
@@ -0,0 +1,213 @@
+use std::{
+    cmp,
+    io,
+    thread,
+    time::{Duration, Instant},
+};
+use futures::{
+    Future,
+    Stream,
+    future::Shared,
+};
+use tokio::{
+    runtime::Runtime,
+    timer::Delay,
+};
+use crate::state::{
+    db,
+    requests::RequestTracker,
+};
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// How often the pool is looked at again while waiting for it to drain.
+const DRAIN_INTERVAL: Duration = Duration::from_millis(50);
+
+pub type Server = Box<dyn Future<Item = (), Error = ()> + Send>;
+
+// Request counters captured at the moment a shutdown signal arrives.
+#[derive(Clone, Copy)]
+pub struct Snapshot {
+    at: Instant,
+    in_flight: usize,
+    completed: usize,
+}
+
+fn first<S: Stream<Error = io::Error>>(stream: S) -> impl Future<Item = (), Error = io::Error> {
+    stream.into_future()
+    .map(|_| ())
+    .map_err(|(err, _)| err)
+}
+
+#[cfg(unix)]
+fn termination() -> Box<dyn Future<Item = &'static str, Error = io::Error> + Send> {
+    use tokio_signal::unix::{Signal, SIGTERM};
+
+    let interrupt = tokio_signal::ctrl_c()
+        .and_then(|stream| first(stream))
+        .map(|_| "SIGINT");
+    let terminate = Signal::new(SIGTERM)
+        .and_then(|stream| first(stream))
+        .map(|_| "SIGTERM");
+
+    Box::new(
+        interrupt.select(terminate)
+        .map(|(name, _)| name)
+        .map_err(|(err, _)| err)
+    )
+}
+
+#[cfg(not(unix))]
+fn termination() -> Box<dyn Future<Item = &'static str, Error = io::Error> + Send> {
+    Box::new(
+        tokio_signal::ctrl_c()
+        .and_then(|stream| first(stream))
+        .map(|_| "SIGINT")
+    )
+}
+
+// Resolves once SIGINT or SIGTERM is received.
+pub fn signal(requests: RequestTracker) -> impl Future<Item = Snapshot, Error = ()> + Send {
+    termination()
+    .map(move |name| {
+        let snapshot = Snapshot {
+            at: Instant::now(),
+            in_flight: requests.in_flight(),
+            completed: requests.completed(),
+        };
+        info!(
+            "Received {}, stop accepting connections with {} requests in flight.",
+            name,
+            snapshot.in_flight,
+        );
+        snapshot
+    })
+    .map_err(|err| {
+        error!("Failed to listen for shutdown signals: {}", err);
+    })
+}
+
+// Run the server until it has shut down gracefully, or until `timeout` has
+// passed since the shutdown signal, whichever comes first. Returns that
+// deadline, which is now when the server stopped without a signal.
+pub fn run<S>(server: Server, signal: Shared<S>, timeout: Duration, requests: &RequestTracker) -> Instant
+where
+    S: Future<Item = Snapshot, Error = ()> + Send + 'static,
+{
+    let deadline = signal.clone()
+        .map_err(|_| ())
+        .and_then(move |_| {
+            Delay::new(Instant::now() + timeout)
+            .map_err(|err| {
+                error!("Shutdown deadline timer failed: {}", err);
+            })
+        });
+
+    let mut runtime = Runtime::new().expect("Init runtime.");
+    let drained = runtime.block_on(
+        server.map(|_| true)
+        .select(deadline.map(|_| false))
+        .map(|(drained, _)| drained)
+        .map_err(|_| ())
+    )
+    .unwrap_or(false);
+    let completed = requests.completed();
+    let abandoned = if drained { 0 } else { requests.in_flight() };
+
+    // Logged before shutting the runtime down, which past the deadline may
+    // wait on requests still blocked in the database.
+    let deadline = match signal.peek() {
+        Some(Ok(snapshot)) => {
+            info!(
+                "Drained {} requests in {:.1}s, {} were in flight at shutdown and {} were abandoned at the deadline.",
+                completed - snapshot.completed,
+                snapshot.at.elapsed().as_secs_f64(),
+                snapshot.in_flight,
+                abandoned,
+            );
+            snapshot.at + timeout
+        }
+        _ => {
+            info!("Server stopped without a shutdown signal, abandoning {} requests in flight.", abandoned);
+            Instant::now()
+        }
+    };
+
+    // Drop the connections that are still open past the deadline.
+    let _ = runtime.shutdown_now().wait();
+    deadline
+}
+
+// Wait for `in_use` to come down to nothing, or for `deadline` to pass.
+// Returns what was still in use at the end.
+fn drain<F: Fn() -> u32>(in_use: F, deadline: Instant) -> u32 {
+    loop {
+        let count = in_use();
+        let now = Instant::now();
+        if count == 0 || now >= deadline {
+            return count;
+        }
+        thread::sleep(cmp::min(DRAIN_INTERVAL, deadline - now));
+    }
+}
+
+// Close the pool once every connection is back in it, or at the deadline.
+// Connections that requests abandoned at the deadline still hold close with
+// the process instead.
+pub fn close_pool(pool: db::Pool, deadline: Instant) {
+    let in_use = drain(
+        || {
+            let state = pool.state();
+            state.connections - state.idle_connections
+        },
+        deadline,
+    );
+    if in_use == 0 {
+        info!("Closing database pool with {} idle connections.", pool.state().idle_connections);
+    } else {
+        warn!("Closing database pool with {} connections still in use at the deadline.", in_use);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicU32, Ordering},
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+    use super::drain;
+
+    #[test]
+    fn test_drain() {
+        let in_use = Arc::new(AtomicU32::new(2));
+        let returning = in_use.clone();
+        let release = thread::spawn(move || {
+            for _ in 0..2 {
+                thread::sleep(Duration::from_millis(20));
+                returning.fetch_sub(1, Ordering::SeqCst);
+            }
+        });
+
+        let started = Instant::now();
+        assert_eq!(drain(|| in_use.load(Ordering::SeqCst), started + Duration::from_secs(10)), 0);
+        assert!(started.elapsed() < Duration::from_secs(10));
+        release.join().unwrap();
+    }
+
+    #[test]
+    fn test_drain_deadline() {
+        let started = Instant::now();
+        let deadline = started + Duration::from_millis(100);
+        assert_eq!(drain(|| 1, deadline), 1);
+        assert!(Instant::now() >= deadline);
+
+        // Past the deadline, nothing is waited for.
+        let started = Instant::now();
+        assert_eq!(drain(|| 3, started), 3);
+        assert!(started.elapsed() < Duration::from_millis(50));
+    }
+}
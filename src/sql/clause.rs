@@ -14,8 +14,10 @@ impl Clause {
         Clause::Entries(format!("{} = {}", left, right))
     }
 
-    pub fn like<L: std::fmt::Display, R: std::fmt::Display>(left: L, right: R) -> Self {
-        Clause::Entries(format!("{} LIKE {}", left, right))
+    // Whether upper case `left` contains the text of parameter `$index`,
+    // passed through `like_pattern`. A null parameter matches everything.
+    pub fn contains<L: std::fmt::Display>(left: L, index: usize) -> Self {
+        Clause::Entries(format!("(${index}::TEXT IS NULL OR {left} LIKE upper(${index}))", index = index, left = left))
     }
 
     pub fn inner(&self) -> &String {
@@ -70,4 +72,9 @@ impl std::fmt::Display for Clause {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+// A `LIKE` pattern matching any text containing `text` as it is.
+pub fn like_pattern(text: &str) -> String {
+    format!("%{}%", text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"))
+}
@@ -9,7 +9,8 @@ use uuid::Uuid;
 
 pub mod clause;
 
-#[derive(Serialize, Deserialize, Debug, ToSql, FromSql, juniper::GraphQLEnum)]
+// Ordered from the least to the most a member may do.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ToSql, FromSql, juniper::GraphQLEnum)]
 #[postgres(name = "permission")]
 pub enum Permission {
     #[postgres(name = "none")]
@@ -130,23 +131,26 @@ macro_rules! query_opt {
 mod test {
     use uuid::Uuid;
     use crate::{
-        state::db::init_pool,
         error::Error,
+        tests::{
+            harness::TestDatabase,
+            seed,
+        },
     };
-    use crate::PG_CONFIG;
 
     #[test]
+    #[ignore]
     fn test_query_one() {
-        let pool = init_pool(PG_CONFIG, 1);
-        let mut conn = pool.get().unwrap();
-        let username = "david0608";
-        let password = "123123";
-        let (id, nick_name) = query_one!(
+        let db = TestDatabase::create();
+        let mut conn = db.pool().get().unwrap();
+        let username = "owner";
+        let (id, nickname) = query_one!(
             conn,
-            "SELECT id, nick_name FROM account WEHRE username = $1 AND password = $2",
-            &[&username, &password],
-            (id: Uuid, nick_name: String),
+            "SELECT id, nickname FROM users WHERE username = $1",
+            &[&username],
+            (id: Uuid, nickname: Option<String>),
         ).unwrap();
-        println!("id:{}, nickname:{}", id, nick_name)
+        assert_eq!(id, seed::OWNER);
+        assert_eq!(nickname, Some("Owner".to_string()));
     }
-}
\ No newline at end of file
+}
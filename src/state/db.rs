@@ -14,3 +14,4 @@ pub fn init_pool(config: &str, size: u32) -> Pool {
         .build(manager)
         .expect("Init sync pool.")
 }
+
@@ -1,22 +1,238 @@
+use std::{
+    net::IpAddr,
+    sync::Arc,
+    time::Instant,
+};
+use crate::repository::{
+    SessionRepository,
+    CredentialRepository,
+    UserRepository,
+    ShopRepository,
+    CatalogRepository,
+    OrderRepository,
+    CartRepository,
+    PromotionRepository,
+    AuditLogRepository,
+    ApiTokenRepository,
+    RoleRepository,
+    Repository,
+    Transactional,
+    postgres::PostgresRepository,
+    memory::MemoryRepository,
+};
+use crate::picture::storage::{PictureStorage, MemoryStorage};
+use crate::rate_limit::{RateLimiter, RateLimits, store::MemoryStore};
+use crate::auth::password::HashParams;
 use crate::error::Error;
+use crate::shutdown;
 
 pub mod db;
+pub mod requests;
+pub mod slots;
+
+// CSV exports running at once, unless set otherwise.
+const DEFAULT_EXPORTS: usize = 4;
+
+// Begins a transaction, giving the state over repositories in it.
+type Begin = dyn Fn(&State) -> Result<(State, Arc<dyn Transactional>), Error> + Send + Sync;
 
 #[derive(Clone)]
 pub struct State {
-    db_pool: db::Pool,
+    sessions: Arc<dyn SessionRepository>,
+    credentials: Arc<dyn CredentialRepository>,
+    users: Arc<dyn UserRepository>,
+    shops: Arc<dyn ShopRepository>,
+    catalog: Arc<dyn CatalogRepository>,
+    orders: Arc<dyn OrderRepository>,
+    carts: Arc<dyn CartRepository>,
+    promotions: Arc<dyn PromotionRepository>,
+    audit_log: Arc<dyn AuditLogRepository>,
+    api_tokens: Arc<dyn ApiTokenRepository>,
+    roles: Arc<dyn RoleRepository>,
+    begin: Arc<Begin>,
+    // Kept to close the pool on shutdown, None over the memory repository.
+    db_pool: Option<db::Pool>,
+    pictures: Arc<dyn PictureStorage>,
+    requests: requests::RequestTracker,
+    exports: slots::Slots,
+    rate_limiter: RateLimiter,
+    password_params: HashParams,
+    trusted_proxies: Arc<Vec<IpAddr>>,
 }
 
 impl State {
-    pub fn init(db_pool: db::Pool) -> Self {
+    pub fn init(db_pool: db::Pool, pictures: Arc<dyn PictureStorage>) -> Self {
+        State {
+            db_pool: Some(db_pool.clone()),
+            ..State::from_repository(Arc::new(PostgresRepository::new(db_pool)), pictures)
+        }
+    }
+
+    pub fn init_memory(repository: Arc<MemoryRepository>) -> Self {
+        State::from_repository(repository, Arc::new(MemoryStorage::new()))
+    }
+
+    fn from_repository<R: Repository>(repository: Arc<R>, pictures: Arc<dyn PictureStorage>) -> Self {
         State {
-            db_pool: db_pool,
+            sessions: repository.clone(),
+            credentials: repository.clone(),
+            users: repository.clone(),
+            shops: repository.clone(),
+            catalog: repository.clone(),
+            orders: repository.clone(),
+            carts: repository.clone(),
+            promotions: repository.clone(),
+            audit_log: repository.clone(),
+            api_tokens: repository.clone(),
+            roles: repository.clone(),
+            begin: begin(repository),
+            db_pool: None,
+            pictures: pictures,
+            requests: requests::RequestTracker::new(),
+            exports: slots::Slots::new(DEFAULT_EXPORTS),
+            rate_limiter: RateLimiter::new(RateLimits::default(), Arc::new(MemoryStore::new())),
+            password_params: HashParams::default(),
+            trusted_proxies: Arc::new(Vec::new()),
         }
     }
 
-    pub fn db_connection(&self) -> Result<db::Connection, Error> {
-        self.db_pool.get().map_err(|err| -> Error {
-            err.into()
-        })
+    // The same state over other repositories.
+    fn with_repository<R: Repository>(&self, repository: Arc<R>) -> Self {
+        State {
+            sessions: repository.clone(),
+            credentials: repository.clone(),
+            users: repository.clone(),
+            shops: repository.clone(),
+            catalog: repository.clone(),
+            orders: repository.clone(),
+            carts: repository.clone(),
+            promotions: repository.clone(),
+            audit_log: repository.clone(),
+            api_tokens: repository.clone(),
+            roles: repository.clone(),
+            begin: begin(repository),
+            ..self.clone()
+        }
+    }
+
+    // Runs `work` against the repositories in one transaction, keeping its
+    // changes only when it succeeds.
+    pub fn transaction<T, F>(&self, work: F) -> Result<T, Error>
+    where
+        F: FnOnce(&State) -> Result<T, Error>,
+    {
+        let (state, transaction) = (self.begin)(self)?;
+        let result = work(&state)?;
+        transaction.commit()?;
+        Ok(result)
+    }
+
+    pub fn with_password_params(mut self, password_params: HashParams) -> Self {
+        self.password_params = password_params;
+        self
+    }
+
+    // Proxies whose `X-Forwarded-For` is believed. None unless given here.
+    pub fn with_trusted_proxies(mut self, trusted_proxies: Vec<IpAddr>) -> Self {
+        self.trusted_proxies = Arc::new(trusted_proxies);
+        self
+    }
+
+    // Each export holds a database connection until it's done, so this
+    // should leave most of the pool to other requests.
+    pub fn with_export_limit(mut self, limit: usize) -> Self {
+        self.exports = slots::Slots::new(limit);
+        self
+    }
+
+    // Rate limits are kept in memory unless replaced here.
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+
+    pub fn sessions(&self) -> &dyn SessionRepository {
+        self.sessions.as_ref()
+    }
+
+    pub fn credentials(&self) -> &dyn CredentialRepository {
+        self.credentials.as_ref()
+    }
+
+    pub fn users(&self) -> &dyn UserRepository {
+        self.users.as_ref()
+    }
+
+    pub fn shops(&self) -> &dyn ShopRepository {
+        self.shops.as_ref()
+    }
+
+    pub fn catalog(&self) -> &dyn CatalogRepository {
+        self.catalog.as_ref()
     }
-}
\ No newline at end of file
+
+    pub fn orders(&self) -> &dyn OrderRepository {
+        self.orders.as_ref()
+    }
+
+    pub fn carts(&self) -> &dyn CartRepository {
+        self.carts.as_ref()
+    }
+
+    pub fn promotions(&self) -> &dyn PromotionRepository {
+        self.promotions.as_ref()
+    }
+
+    pub fn audit_log(&self) -> &dyn AuditLogRepository {
+        self.audit_log.as_ref()
+    }
+
+    pub fn api_tokens(&self) -> &dyn ApiTokenRepository {
+        self.api_tokens.as_ref()
+    }
+
+    pub fn roles(&self) -> &dyn RoleRepository {
+        self.roles.as_ref()
+    }
+
+    pub fn pictures(&self) -> &dyn PictureStorage {
+        self.pictures.as_ref()
+    }
+
+    pub fn requests(&self) -> &requests::RequestTracker {
+        &self.requests
+    }
+
+    pub fn exports(&self) -> &slots::Slots {
+        &self.exports
+    }
+
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.rate_limiter
+    }
+
+    pub fn password_params(&self) -> &HashParams {
+        &self.password_params
+    }
+
+    pub fn trusted_proxies(&self) -> &[IpAddr] {
+        &self.trusted_proxies
+    }
+
+    // Gives up this handle to the repositories, then closes the database pool
+    // once its connections are back, or at `deadline`.
+    pub fn close(mut self, deadline: Instant) {
+        let db_pool = self.db_pool.take();
+        drop(self);
+        if let Some(db_pool) = db_pool {
+            shutdown::close_pool(db_pool, deadline);
+        }
+    }
+}
+
+fn begin<R: Repository>(repository: Arc<R>) -> Arc<Begin> {
+    Arc::new(move |state: &State| -> Result<(State, Arc<dyn Transactional>), Error> {
+        let transaction = Arc::new(repository.begin()?);
+        Ok((state.with_repository(transaction.clone()), transaction as Arc<dyn Transactional>))
+    })
+}
@@ -0,0 +1,48 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+#[derive(Default)]
+struct Counters {
+    in_flight: AtomicUsize,
+    completed: AtomicUsize,
+}
+
+#[derive(Clone, Default)]
+pub struct RequestTracker {
+    counters: Arc<Counters>,
+}
+
+impl RequestTracker {
+    pub fn new() -> Self {
+        RequestTracker::default()
+    }
+
+    pub fn start(&self) -> RequestGuard {
+        self.counters.in_flight.fetch_add(1, Ordering::SeqCst);
+        RequestGuard {
+            counters: self.counters.clone(),
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.counters.in_flight.load(Ordering::SeqCst)
+    }
+
+    pub fn completed(&self) -> usize {
+        self.counters.completed.load(Ordering::SeqCst)
+    }
+}
+
+// Counts a request as in flight until dropped.
+pub struct RequestGuard {
+    counters: Arc<Counters>,
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        self.counters.in_flight.fetch_sub(1, Ordering::SeqCst);
+        self.counters.completed.fetch_add(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,59 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+// A fixed number of slots for work that must not pile up, taken without
+// waiting.
+#[derive(Clone)]
+pub struct Slots {
+    taken: Arc<AtomicUsize>,
+    limit: usize,
+}
+
+impl Slots {
+    pub fn new(limit: usize) -> Self {
+        Slots {
+            taken: Arc::new(AtomicUsize::new(0)),
+            limit: limit,
+        }
+    }
+
+    // A slot, or None while every one is taken.
+    pub fn try_take(&self) -> Option<Slot> {
+        let limit = self.limit;
+        self.taken
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |taken| if taken < limit { Some(taken + 1) } else { None })
+            .ok()
+            .map(|_| Slot {
+                taken: self.taken.clone(),
+            })
+    }
+}
+
+// Holds its slot until dropped.
+pub struct Slot {
+    taken: Arc<AtomicUsize>,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.taken.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Slots;
+
+    #[test]
+    fn test_slots() {
+        let slots = Slots::new(2);
+        let first = slots.try_take().unwrap();
+        let _second = slots.try_take().unwrap();
+        assert!(slots.try_take().is_none());
+
+        drop(first);
+        assert!(slots.try_take().is_some());
+    }
+}
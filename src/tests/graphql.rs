@@ -0,0 +1,319 @@
+use std::{collections::HashMap, sync::Arc};
+use serde_json::json;
+use crate::{
+    rate_limit::{Budget, Budgets, RateLimiter, RateLimits, store::PostgresStore},
+    tests::{
+        harness::{Api, TestServer, error_type},
+        seed,
+    },
+};
+
+#[test]
+#[ignore]
+fn test_shop_search_and_products() {
+    let mut server = TestServer::start();
+
+    let response = server.graphql(
+        "query ShopQuery($key: Uuid) {
+            shop {
+                search(name: \"diner\") {
+                    uuid
+                    name
+                    products(key: $key) {
+                        key
+                        name
+                        price
+                        customizes {
+                            key
+                            name
+                            selections { key name price }
+                        }
+                    }
+                }
+            }
+        }",
+        json!({ "key": seed::PORK_RICE.to_string() }),
+        &[],
+    );
+
+    let shops = &response["data"]["shop"]["search"];
+    assert_eq!(shops.as_array().unwrap().len(), 1);
+    assert_eq!(shops[0]["uuid"], seed::DINER.to_string());
+    assert_eq!(shops[0]["name"], "Pigskit Diner");
+
+    let products = &shops[0]["products"];
+    assert_eq!(products.as_array().unwrap().len(), 1);
+    assert_eq!(products[0]["name"], "Braised Pork Rice");
+    assert_eq!(products[0]["price"], 80);
+    assert_eq!(products[0]["customizes"][0]["key"], seed::SIZE.to_string());
+
+    let selections = products[0]["customizes"][0]["selections"].as_array().unwrap();
+    assert_eq!(selections.len(), 2);
+    assert!(selections.contains(&json!({ "key": seed::LARGE.to_string(), "name": "Large", "price": 20 })));
+    assert!(selections.contains(&json!({ "key": seed::SMALL.to_string(), "name": "Small", "price": 0 })));
+}
+
+#[test]
+#[ignore]
+fn test_user_shops() {
+    let mut server = TestServer::start();
+    let query = "{ user { me { shops { shop { uuid } } } } }";
+
+    let response = server.graphql(query, json!({}), &[("USSID", seed::OWNER_SESSION)]);
+    let shops = response["data"]["user"]["me"]["shops"].as_array().unwrap();
+    assert_eq!(shops.len(), 2);
+    assert!(shops.contains(&json!({ "shop": { "uuid": seed::DINER.to_string() } })));
+    assert!(shops.contains(&json!({ "shop": { "uuid": seed::BACON_BAR.to_string() } })));
+
+    let response = server.graphql(query, json!({}), &[("USSID", seed::STAFF_SESSION)]);
+    assert_eq!(
+        response["data"]["user"]["me"]["shops"],
+        json!([{ "shop": { "uuid": seed::DINER.to_string() } }]),
+    );
+}
+
+#[test]
+#[ignore]
+fn test_guest_carts_and_orders() {
+    let mut server = TestServer::start();
+    let query = "
+        query GuestQuery($shopId: Uuid!) {
+            guest {
+                carts(shopId: $shopId) {
+                    uuid
+                    items { key productKey name count remark customizes { name } }
+                }
+                orders(shopId: $shopId) { uuid orderNumber }
+            }
+        }
+    ";
+    let variables = json!({ "shopId": seed::DINER.to_string() });
+
+    let response = server.graphql(query, variables.clone(), &[("GSSID", seed::GUEST_SESSION)]);
+    assert_eq!(
+        response["data"]["guest"],
+        json!({
+            "carts": [{
+                "uuid": seed::CART.to_string(),
+                "items": [{
+                    "key": seed::CART_ITEM.to_string(),
+                    "productKey": seed::BLACK_TEA.to_string(),
+                    "name": "Black Tea",
+                    "count": 2,
+                    "remark": "Less ice",
+                    "customizes": [],
+                }],
+            }],
+            "orders": [{ "uuid": seed::ORDER.to_string(), "orderNumber": 1 }],
+        }),
+    );
+
+    let response = server.graphql(query, variables, &[("GSSID", seed::EXPIRED_GUEST_SESSION)]);
+    assert_eq!(error_type(&response), Some("SessionExpired"));
+}
+
+#[test]
+#[ignore]
+fn test_search_text() {
+    let mut server = TestServer::start();
+    let mut shops = |name: &str| {
+        let response = server.graphql(
+            "query Search($name: String) { shop { search(name: $name) { name products(name: $name) { name } } } }",
+            json!({ "name": name }),
+            &[],
+        );
+        response["data"]["shop"]["search"].clone()
+    };
+
+    // Names are matched as they are, quotes and wildcards included.
+    assert_eq!(shops("diner")[0]["name"], json!("Pigskit Diner"));
+    assert_eq!(shops("%"), json!([]));
+    assert_eq!(shops("_"), json!([]));
+    assert_eq!(shops("' OR ''='"), json!([]));
+    let response = server.graphql(
+        "query Search($name: String) { user { search(name: $name) { username } } }",
+        json!({ "name": "%" }),
+        &[("USSID", seed::OWNER_SESSION)],
+    );
+    assert_eq!(response["data"]["user"]["search"], json!([]));
+}
+
+#[test]
+#[ignore]
+fn test_product_picture() {
+    let mut server = TestServer::start();
+    let mut png = Vec::new();
+    image::DynamicImage::new_rgb8(800, 600).write_to(&mut png, image::ImageOutputFormat::Png).unwrap();
+    let path = format!("/pictures/{}/{}", seed::DINER, seed::PORK_RICE);
+
+    assert_eq!(server.get(&path, &[]).status(), 404);
+
+    let response = server.graphql_upload(
+        "mutation Upload($shopId: Uuid!, $productKey: Uuid!, $file: String!) {
+            shop {
+                uploadProductPicture(shopId: $shopId, productKey: $productKey, file: $file) { hasPicture }
+            }
+        }",
+        json!({ "shopId": seed::DINER.to_string(), "productKey": seed::PORK_RICE.to_string() }),
+        "file",
+        &png,
+        &[("USSID", seed::OWNER_SESSION)],
+    );
+    assert_eq!(response["data"]["shop"]["uploadProductPicture"], json!({ "hasPicture": true }));
+
+    let original = server.get(&path, &[]);
+    assert_eq!(original.status(), 200);
+    assert_eq!(original.headers()["content-type"], "image/png");
+    assert_eq!(original.body(), &png);
+    let etag = original.headers()["etag"].to_str().unwrap().to_string();
+    assert_eq!(server.get(&path, &[("If-None-Match", &etag)]).status(), 304);
+
+    let thumbnail = server.get(&format!("{}?size=thumbnail", path), &[]);
+    assert_eq!(thumbnail.status(), 200);
+    assert_eq!(thumbnail.headers()["content-type"], "image/jpeg");
+
+    let response = server.graphql(
+        "{ shop { search(name: \"diner\") { products { name hasPicture } } } }",
+        json!({}),
+        &[],
+    );
+    let products = response["data"]["shop"]["search"][0]["products"].as_array().unwrap();
+    assert!(products.contains(&json!({ "name": "Braised Pork Rice", "hasPicture": true })));
+}
+
+#[test]
+#[ignore]
+fn test_orders_csv() {
+    let mut server = TestServer::start();
+    let path = format!("/shops/{}/orders.csv", seed::DINER);
+    let owner = format!("USSID={}", seed::OWNER_SESSION);
+    let staff = format!("USSID={}", seed::STAFF_SESSION);
+
+    assert_eq!(server.get(&path, &[]).status(), 401);
+    assert_eq!(server.get(&path, &[("Cookie", &staff)]).status(), 403);
+    assert_eq!(server.get(&format!("{}?from=yesterday", path), &[("Cookie", &owner)]).status(), 400);
+
+    let response = server.get(&path, &[("Cookie", &owner)]);
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "text/csv; charset=utf-8");
+    let csv = String::from_utf8(response.body().clone()).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("order_number,order_id,order_at,"));
+    assert!(lines[1].starts_with(&format!("1,{},", seed::ORDER)));
+    assert!(lines[1].contains(&format!(",{},{},Braised Pork Rice,", seed::ORDER_ITEM, seed::PORK_RICE)));
+    assert!(lines[1].ends_with(",80,1,100,100,,Size,Large,20"));
+
+    let response = server.get(&format!("{}?from=2999-01-01T00:00:00Z", path), &[("Cookie", &owner)]);
+    assert_eq!(String::from_utf8(response.body().clone()).unwrap().lines().count(), 1);
+}
+
+#[test]
+#[ignore]
+fn test_orders_csv_busy() {
+    // With no slot free, exports are turned away before touching the pool.
+    let mut server = TestServer::start_with(|state| state.with_export_limit(0));
+    let response = server.get(
+        &format!("/shops/{}/orders.csv", seed::DINER),
+        &[("Cookie", &format!("USSID={}", seed::OWNER_SESSION))],
+    );
+    assert_eq!(response.status(), 503);
+    assert!(response.headers()["retry-after"].to_str().unwrap().parse::<u64>().unwrap() > 0);
+}
+
+#[test]
+#[ignore]
+fn test_accept_language() {
+    let mut server = TestServer::start();
+    let query = "query Product($key: Uuid) {
+        shop { search(name: \"diner\") { products(key: $key) { name description customizes { name selections { name } } } } }
+    }";
+    let key = json!({ "key": seed::PORK_RICE.to_string() });
+
+    let response = server.graphql_with_headers(query, key.clone(), &[], &[("Accept-Language", "ja, zh-TW;q=0.8, en;q=0.5")]);
+    assert_eq!(
+        response["data"]["shop"]["search"][0]["products"][0],
+        json!({
+            "name": "滷肉飯",
+            "description": "慢燉五花肉配白飯。",
+            "customizes": [{ "name": "份量", "selections": [{ "name": "大" }, { "name": "Small" }] }],
+        }),
+    );
+
+    let response = server.graphql(query, key, &[]);
+    assert_eq!(response["data"]["shop"]["search"][0]["products"][0]["name"], "Braised Pork Rice");
+}
+
+#[test]
+#[ignore]
+fn test_rate_limits() {
+    let budgets = |anonymous: u32, authenticated: u32| Budgets {
+        anonymous: Budget::new(anonymous, 1),
+        authenticated: Budget::new(authenticated, 1),
+    };
+    let mut operations = HashMap::new();
+    operations.insert("user.search", budgets(1, 1));
+    let limits = RateLimits {
+        general: budgets(3, 5),
+        operations: operations,
+    };
+    let mut server = TestServer::start_with_rate_limiter(|pool| RateLimiter::new(limits, Arc::new(PostgresStore::new(pool))));
+    let search = "{ user { search(name: \"owner\") { username } } }";
+
+    // Searches have a budget of their own, and going over it turns the
+    // whole response into a 429.
+    let response = server.graphql_response(search, json!({}), &[], &[]);
+    assert_eq!(response.status(), 200);
+    let response = server.graphql_response(search, json!({}), &[], &[]);
+    assert_eq!(response.status(), 429);
+    assert!(response.headers()["retry-after"].to_str().unwrap().parse::<u64>().unwrap() > 0);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(error_type(&body), Some("RateLimited"));
+
+    // Anonymous requests share the address's budget, whatever the route,
+    // and are turned away before they run.
+    assert_eq!(server.get("/pictures/00000000-0000-0000-0000-000000000000/00000000-0000-0000-0000-000000000000", &[]).status(), 404);
+    let response = server.graphql_response("{ user { me { id } } }", json!({}), &[], &[]);
+    assert_eq!(response.status(), 429);
+    assert!(response.headers().contains_key("retry-after"));
+
+    // Signed in users have a budget of their own.
+    for _ in 0..5 {
+        let response = server.graphql_response("{ user { me { id } } }", json!({}), &[("USSID", seed::OWNER_SESSION)], &[]);
+        assert_eq!(response.status(), 200);
+    }
+    let response = server.graphql_response("{ user { me { id } } }", json!({}), &[("USSID", seed::OWNER_SESSION)], &[]);
+    assert_eq!(response.status(), 429);
+}
+
+#[test]
+#[ignore]
+fn test_login() {
+    let mut server = TestServer::start();
+
+    let response = server.post_json("/login", json!({ "username": "owner", "password": "wrong" }));
+    assert_eq!(response.status(), 401);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["type"], json!("InvalidCredentials"));
+
+    let response = server.post_json("/login", json!({ "username": "owner", "password": "owner-password" }));
+    assert_eq!(response.status(), 200);
+    let cookie = response.headers()["set-cookie"].to_str().unwrap().to_string();
+    assert!(cookie.contains("HttpOnly"));
+    let session = uuid::Uuid::parse_str(cookie.trim_start_matches("USSID=").split(';').next().unwrap()).unwrap();
+    let response = server.graphql("{ user { me { username } } }", json!({}), &[("USSID", session)]);
+    assert_eq!(response["data"]["user"]["me"]["username"], json!("owner"));
+
+    // The password was rehashed, and still logs in.
+    let response = server.post_json("/login", json!({ "username": "owner", "password": "owner-password" }));
+    assert_eq!(response.status(), 200);
+
+    for _ in 0..5 {
+        let response = server.post_json("/login", json!({ "username": "staff", "password": "wrong" }));
+        assert_eq!(response.status(), 401);
+    }
+    let response = server.post_json("/login", json!({ "username": "staff", "password": "staff-password" }));
+    assert_eq!(response.status(), 429);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["type"], json!("LoginLocked"));
+}
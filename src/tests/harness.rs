@@ -0,0 +1,312 @@
+use std::{
+    collections::HashMap,
+    env,
+    net::SocketAddr,
+    sync::Arc,
+};
+use futures::{Future, Stream};
+use hyper::{
+    Body,
+    Client as HttpClient,
+    Request,
+    Response,
+    header::{CONTENT_TYPE, COOKIE},
+};
+use postgres::{Client, NoTls};
+use serde_json::{json, Value};
+use tokio::runtime::Runtime;
+use uuid::Uuid;
+use juniper::Variables;
+use crate::{
+    route,
+    migration,
+    graphql::{
+        Context,
+        Upload,
+        schema,
+    },
+    repository::memory::MemoryRepository,
+    picture::storage::MemoryStorage,
+    rate_limit::RateLimiter,
+    state::{
+        State,
+        db::{Pool, init_pool},
+    },
+    tests::seed,
+    PG_CONFIG_DEV,
+};
+
+const MULTIPART_BOUNDARY: &str = "pigskit-test-boundary";
+
+fn admin_config() -> String {
+    env::var("PIGSKIT_TEST_PG").unwrap_or_else(|_| PG_CONFIG_DEV.to_string())
+}
+
+// A database created for a single test, migrated, seeded and dropped again
+// once the test is done with it.
+pub struct TestDatabase {
+    name: String,
+    pool: Option<Pool>,
+}
+
+impl TestDatabase {
+    pub fn create() -> Self {
+        let name = format!("pigskit_test_{}", Uuid::new_v4().to_simple());
+        let mut admin = Client::connect(&admin_config(), NoTls).expect("Connect to test postgres.");
+        admin.batch_execute(&format!("CREATE DATABASE {}", name)).expect("Create test database.");
+
+        let pool = init_pool(&format!("{} dbname={}", admin_config(), name), 4);
+        {
+            let mut conn = pool.get().expect("Connect to test database.");
+            migration::up(&mut conn, None).expect("Migrate test database.");
+            conn.batch_execute(seed::SQL).expect("Seed test database.");
+        }
+
+        TestDatabase {
+            name: name,
+            pool: Some(pool),
+        }
+    }
+
+    pub fn pool(&self) -> Pool {
+        self.pool.clone().unwrap()
+    }
+}
+
+impl Drop for TestDatabase {
+    fn drop(&mut self) {
+        self.pool.take();
+        if let Ok(mut admin) = Client::connect(&admin_config(), NoTls) {
+            let _ = admin.batch_execute(&format!("DROP DATABASE IF EXISTS {} WITH (FORCE)", self.name));
+        }
+    }
+}
+
+// Serves `route::routes` over loopback on a seeded `TestDatabase`.
+pub struct TestServer {
+    runtime: Option<Runtime>,
+    addr: SocketAddr,
+    _db: TestDatabase,
+}
+
+impl TestServer {
+    pub fn start() -> Self {
+        let db = TestDatabase::create();
+        let state = State::init(db.pool(), Arc::new(MemoryStorage::new()));
+        TestServer::serve(db, state)
+    }
+
+    // Like `start`, rate limiting with what `rate_limiter` makes of the
+    // test database.
+    pub fn start_with_rate_limiter<F: FnOnce(Pool) -> RateLimiter>(rate_limiter: F) -> Self {
+        let db = TestDatabase::create();
+        let state = State::init(db.pool(), Arc::new(MemoryStorage::new())).with_rate_limiter(rate_limiter(db.pool()));
+        TestServer::serve(db, state)
+    }
+
+    // Like `start`, over the state as `configure` leaves it.
+    pub fn start_with<F: FnOnce(State) -> State>(configure: F) -> Self {
+        let db = TestDatabase::create();
+        let state = configure(State::init(db.pool(), Arc::new(MemoryStorage::new())));
+        TestServer::serve(db, state)
+    }
+
+    fn serve(db: TestDatabase, state: State) -> Self {
+        let mut runtime = Runtime::new().expect("Init test runtime.");
+        let (addr, server) = warp::serve(route::routes(state))
+            .bind_ephemeral(([127, 0, 0, 1], 0));
+        runtime.spawn(server);
+
+        TestServer {
+            runtime: Some(runtime),
+            addr: addr,
+            _db: db,
+        }
+    }
+
+    pub fn graphql_with_headers(&mut self, query: &str, variables: Value, cookies: &[(&str, Uuid)], headers: &[(&str, &str)]) -> Value {
+        serde_json::from_slice(self.graphql_response(query, variables, cookies, headers).body()).expect("Parse GraphQL response.")
+    }
+
+    // Like `graphql_with_headers`, keeping the status and headers.
+    pub fn graphql_response(&mut self, query: &str, variables: Value, cookies: &[(&str, Uuid)], headers: &[(&str, &str)]) -> Response<Vec<u8>> {
+        let mut request = Request::post(format!("http://{}/graphql", self.addr));
+        request
+            .header(CONTENT_TYPE, "application/json")
+            .header(COOKIE, cookie_header(cookies));
+        for (name, value) in headers {
+            request.header(*name, *value);
+        }
+        let request = request
+            .body(Body::from(json!({ "query": query, "variables": variables }).to_string()))
+            .unwrap();
+
+        self.send(request)
+    }
+
+    // Run a GraphQL operation as a multipart request, with `file` uploaded
+    // for the variable named `variable`.
+    pub fn graphql_upload(&mut self, query: &str, mut variables: Value, variable: &str, file: &[u8], cookies: &[(&str, Uuid)]) -> Value {
+        variables[variable] = Value::Null;
+        let operations = json!({ "query": query, "variables": variables }).to_string();
+        let map = json!({ "0": [format!("variables.{}", variable)] }).to_string();
+
+        let mut body = Vec::new();
+        for (name, content) in &[("operations", operations.as_bytes()), ("map", map.as_bytes()), ("0", file)] {
+            body.extend_from_slice(format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n\r\n",
+                MULTIPART_BOUNDARY,
+                name,
+                name,
+            ).as_bytes());
+            body.extend_from_slice(content);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", MULTIPART_BOUNDARY).as_bytes());
+
+        let request = Request::post(format!("http://{}/graphql", self.addr))
+            .header(CONTENT_TYPE, format!("multipart/form-data; boundary={}", MULTIPART_BOUNDARY))
+            .header(COOKIE, cookie_header(cookies))
+            .body(Body::from(body))
+            .unwrap();
+
+        serde_json::from_slice(self.send(request).body()).expect("Parse GraphQL response.")
+    }
+
+    pub fn post_json(&mut self, path: &str, body: Value) -> Response<Vec<u8>> {
+        let request = Request::post(format!("http://{}{}", self.addr, path))
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        self.send(request)
+    }
+
+    pub fn get(&mut self, path: &str, headers: &[(&str, &str)]) -> Response<Vec<u8>> {
+        let mut request = Request::get(format!("http://{}{}", self.addr, path));
+        for (name, value) in headers {
+            request.header(*name, *value);
+        }
+        self.send(request.body(Body::empty()).unwrap())
+    }
+
+    fn send(&mut self, request: Request<Body>) -> Response<Vec<u8>> {
+        let (parts, body) = self.runtime.as_mut().unwrap().block_on(
+            HttpClient::new()
+            .request(request)
+            .and_then(|response| {
+                let (parts, body) = response.into_parts();
+                body.concat2().map(move |body| (parts, body))
+            })
+        )
+        .expect("Send request.");
+
+        Response::from_parts(parts, body.to_vec())
+    }
+}
+
+fn cookie_header(cookies: &[(&str, Uuid)]) -> String {
+    let cookies: Vec<String> = cookies.iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    cookies.join("; ")
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.runtime.take() {
+            let _ = runtime.shutdown_now().wait();
+        }
+    }
+}
+
+// What a test runs its GraphQL operations through, so that one scenario
+// covers both the memory repository and Postgres end to end.
+pub trait Api {
+    // Run a GraphQL operation with the given cookies and return the JSON response.
+    fn graphql(&mut self, query: &str, variables: Value, cookies: &[(&str, Uuid)]) -> Value;
+
+    // Like `graphql`, as if sent with `Authorization: Bearer <token>`.
+    fn graphql_with_bearer(&mut self, query: &str, variables: Value, token: &str) -> Value;
+
+    // Like `graphql`, for operations without variables.
+    fn execute(&mut self, query: &str, cookies: &[(&str, Uuid)]) -> Value {
+        self.graphql(query, json!({}), cookies)
+    }
+}
+
+impl Api for TestServer {
+    fn graphql(&mut self, query: &str, variables: Value, cookies: &[(&str, Uuid)]) -> Value {
+        self.graphql_with_headers(query, variables, cookies, &[])
+    }
+
+    fn graphql_with_bearer(&mut self, query: &str, variables: Value, token: &str) -> Value {
+        let authorization = format!("Bearer {}", token);
+        self.graphql_with_headers(query, variables, &[], &[("Authorization", &authorization)])
+    }
+}
+
+// The seeded in-memory store, run against without a server.
+pub struct Memory {
+    repository: Arc<MemoryRepository>,
+}
+
+impl Memory {
+    pub fn seeded() -> Self {
+        Memory {
+            repository: Arc::new(seed::memory()),
+        }
+    }
+
+    pub fn repository(&self) -> Arc<MemoryRepository> {
+        self.repository.clone()
+    }
+}
+
+impl Api for Memory {
+    fn graphql(&mut self, query: &str, variables: Value, cookies: &[(&str, Uuid)]) -> Value {
+        execute_in(&cookie_context(self.repository(), cookies), query, variables)
+    }
+
+    fn graphql_with_bearer(&mut self, query: &str, variables: Value, token: &str) -> Value {
+        let context = Context::new(State::init_memory(self.repository()), None, None)
+            .with_bearer_token(Some(token.to_string()));
+        execute_in(&context, query, variables)
+    }
+}
+
+// Run a GraphQL operation against an in-memory store, without a server.
+pub fn execute(repository: Arc<MemoryRepository>, query: &str, cookies: &[(&str, Uuid)]) -> Value {
+    execute_with_uploads(repository, query, cookies, HashMap::new())
+}
+
+// Like `execute`, as if `uploads` were sent along a multipart request.
+pub fn execute_with_uploads(repository: Arc<MemoryRepository>, query: &str, cookies: &[(&str, Uuid)], uploads: HashMap<String, Upload>) -> Value {
+    let context = cookie_context(repository, cookies).with_uploads(uploads);
+    execute_in(&context, query, json!({}))
+}
+
+// Like `execute`, as if sent with `Authorization: Bearer <token>`.
+pub fn execute_with_bearer(repository: Arc<MemoryRepository>, query: &str, token: &str) -> Value {
+    Memory { repository: repository }.graphql_with_bearer(query, json!({}), token)
+}
+
+fn cookie_context(repository: Arc<MemoryRepository>, cookies: &[(&str, Uuid)]) -> Context {
+    let cookie = |name: &str| cookies.iter().find(|(n, _)| *n == name).map(|&(_, id)| id);
+    Context::new(State::init_memory(repository), cookie("USSID"), cookie("GSSID"))
+}
+
+fn execute_in(context: &Context, query: &str, variables: Value) -> Value {
+    let variables: Variables = serde_json::from_value(variables).expect("Parse GraphQL variables.");
+    let (data, errors) = juniper::execute(query, None, &schema(), &variables, context)
+        .expect("Execute GraphQL operation.");
+    let mut response = json!({ "data": serde_json::to_value(&data).unwrap() });
+    if !errors.is_empty() {
+        response["errors"] = serde_json::to_value(&errors).unwrap();
+    }
+    response
+}
+
+// The `type` extension of the first error in a GraphQL response.
+pub fn error_type(response: &Value) -> Option<&str> {
+    response["errors"][0]["extensions"]["type"].as_str()
+}
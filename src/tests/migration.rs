@@ -0,0 +1,27 @@
+use crate::{
+    migration,
+    tests::harness::TestDatabase,
+};
+
+#[test]
+#[ignore]
+fn test_baseline() {
+    let db = TestDatabase::create();
+    let mut conn = db.pool().get().unwrap();
+    assert!(migration::check(&mut conn).is_ok());
+
+    // As databases were before migrations were embedded.
+    conn.batch_execute("DROP TABLE schema_migrations").unwrap();
+    assert_eq!(migration::check(&mut conn).unwrap_err().error_type(), "UnversionedSchema");
+    assert_eq!(migration::up(&mut conn, None).map(|_| ()).unwrap_err().error_type(), "UnversionedSchema");
+
+    // Checking leaves the database as it was.
+    let exists: bool = conn.query_one("SELECT to_regclass('schema_migrations') IS NOT NULL", &[]).unwrap().get(0);
+    assert!(!exists);
+
+    let baselined = migration::baseline(&mut conn, migration::latest_version()).unwrap();
+    assert_eq!(baselined.len(), migration::MIGRATIONS.len());
+    assert!(migration::check(&mut conn).is_ok());
+    assert!(migration::up(&mut conn, None).unwrap().is_empty());
+    assert_eq!(migration::baseline(&mut conn, 1).map(|_| ()).unwrap_err().error_type(), "InvalidInput");
+}
@@ -0,0 +1,16 @@
+// End-to-end tests run against a throwaway database on a local Postgres, 13
+// or later as they drop it `WITH (FORCE)`. Like every test needing Postgres
+// they are ignored by default; run them with `cargo test -- --ignored`, and
+// set PIGSKIT_TEST_PG to point them at a server other than `PG_CONFIG_DEV`.
+// CI runs them against Postgres 13 in .github/workflows/test.yml.
+//
+// Behaviour not tied to HTTP is tested once per feature, next to the code,
+// as a scenario over `harness::Api`: it runs on `harness::Memory` and again,
+// ignored, on a `TestServer`. The tests in `graphql` cover what only the
+// server does.
+
+pub mod harness;
+pub mod seed;
+
+mod graphql;
+mod migration;
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use uuid::Uuid;
+use chrono::{Duration, Utc};
+use crate::{
+    graphql::{
+        hours::Schedule,
+        shop::Coordinates,
+        locale::{Locale, TranslatedField},
+        money::Currency,
+        tax::{TaxSettings, Charges},
+        role::{Role, RoleTemplate},
+    },
+    repository::memory::{
+        MemoryRepository,
+        Data,
+        UserRow,
+        UserSessionRow,
+        GuestSessionRow,
+        ShopRow,
+        SeriesRow,
+        ProductRow,
+        CustomizeRow,
+        SelectionRow,
+        ShopUserRow,
+        InventoryRow,
+        TranslationRow,
+        CartRow,
+        OrderRow,
+        ProductItemRow,
+        CustomizeItemRow,
+    },
+};
+
+// Fixed ids of the rows inserted by `seed.sql`.
+
+pub const SQL: &str = include_str!("seed.sql");
+
+pub const OWNER: Uuid = id(0x0000, 1);
+pub const STAFF: Uuid = id(0x0000, 2);
+
+pub const OWNER_SESSION: Uuid = id(0x0001, 1);
+pub const STAFF_SESSION: Uuid = id(0x0001, 2);
+pub const EXPIRED_USER_SESSION: Uuid = id(0x0001, 3);
+
+pub const GUEST_SESSION: Uuid = id(0x0002, 1);
+pub const EXPIRED_GUEST_SESSION: Uuid = id(0x0002, 2);
+
+pub const DINER: Uuid = id(0x0003, 1);
+pub const BACON_BAR: Uuid = id(0x0003, 2);
+
+pub const PORK_RICE: Uuid = id(0x0004, 1);
+pub const BLACK_TEA: Uuid = id(0x0004, 2);
+
+pub const SIZE: Uuid = id(0x0005, 1);
+
+pub const LARGE: Uuid = id(0x0006, 1);
+pub const SMALL: Uuid = id(0x0006, 2);
+
+pub const CART: Uuid = id(0x0007, 1);
+
+pub const CART_ITEM: Uuid = id(0x0008, 1);
+pub const ORDER_ITEM: Uuid = id(0x0008, 2);
+
+pub const ORDER: Uuid = id(0x0009, 1);
+
+pub const MAINS: Uuid = id(0x000a, 1);
+pub const DRINKS: Uuid = id(0x000a, 2);
+
+pub const DINER_OWNER: Uuid = id(0x000b, 1);
+pub const DINER_STAFF: Uuid = id(0x000b, 2);
+pub const BACON_BAR_OWNER: Uuid = id(0x000b, 3);
+
+// 00000000-0000-0000-{group}-{index}
+const fn id(group: u16, index: u64) -> Uuid {
+    let group = group.to_be_bytes();
+    let index = index.to_be_bytes();
+    Uuid::from_bytes([
+        0, 0, 0, 0, 0, 0, 0, 0,
+        group[0], group[1], index[2], index[3], index[4], index[5], index[6], index[7],
+    ])
+}
+
+// The rows of `seed.sql` as an in-memory store.
+pub fn memory() -> MemoryRepository {
+    let now = Utc::now();
+    let day = Duration::days(1);
+
+    let user = |id, username: &str, nickname: Option<&str>| UserRow {
+        id: id,
+        username: username.to_string(),
+        password: format!("{}-password", username),
+        nickname: nickname.map(|nickname| nickname.to_string()),
+    };
+    let role = |id, shop_id, template: RoleTemplate| Role::new(id, shop_id, template.name().to_string(), template.capabilities());
+    let member = |shop_id, user_id, role_id| ShopUserRow {
+        shop_id: shop_id,
+        user_id: user_id,
+        role_id: role_id,
+    };
+    let translation = |key, field, text: &str| TranslationRow {
+        shop_id: DINER,
+        key: key,
+        field: field,
+        locale: Locale::parse("zh-TW").unwrap(),
+        text: text.to_string(),
+    };
+
+    MemoryRepository::with_data(Data {
+        users: vec![
+            user(OWNER, "owner", Some("Owner")),
+            user(STAFF, "staff", None),
+            user(id(0x0000, 3), "outsider", None),
+        ],
+        user_sessions: vec![
+            UserSessionRow { id: OWNER_SESSION, user_id: OWNER, expire_time: now + day },
+            UserSessionRow { id: STAFF_SESSION, user_id: STAFF, expire_time: now + day },
+            UserSessionRow { id: EXPIRED_USER_SESSION, user_id: id(0x0000, 3), expire_time: now - day },
+        ],
+        guest_sessions: vec![
+            GuestSessionRow { id: GUEST_SESSION, expire_time: now + day },
+            GuestSessionRow { id: EXPIRED_GUEST_SESSION, expire_time: now - day },
+        ],
+        shops: vec![
+            ShopRow {
+                id: DINER,
+                name: "Pigskit Diner".to_string(),
+                address: Some("3 Beiping W. Rd., Taipei".to_string()),
+                coordinates: Some(Coordinates::new(25.0478, 121.5170)),
+                schedule: Schedule::default(),
+                currency: Currency::default(),
+                taxes: TaxSettings::default(),
+                latest_update: now,
+                series: vec![
+                    SeriesRow { id: MAINS, name: "Mains".to_string(), ordering: 0, latest_update: now },
+                    SeriesRow { id: DRINKS, name: "Drinks".to_string(), ordering: 1, latest_update: now },
+                ],
+                products: vec![
+                    ProductRow {
+                        key: PORK_RICE,
+                        name: "Braised Pork Rice".to_string(),
+                        description: Some("Slow cooked pork belly on rice.".to_string()),
+                        price: 80,
+                        series_id: Some(MAINS),
+                        has_picture: false,
+                        latest_update: now,
+                        customizes: vec![
+                            CustomizeRow {
+                                key: SIZE,
+                                name: "Size".to_string(),
+                                description: None,
+                                latest_update: now,
+                                selections: vec![
+                                    SelectionRow { key: LARGE, name: "Large".to_string(), price: 20 },
+                                    SelectionRow { key: SMALL, name: "Small".to_string(), price: 0 },
+                                ],
+                            },
+                        ],
+                    },
+                    ProductRow {
+                        key: BLACK_TEA,
+                        name: "Black Tea".to_string(),
+                        description: None,
+                        price: 30,
+                        series_id: Some(DRINKS),
+                        has_picture: false,
+                        latest_update: now,
+                        customizes: vec![],
+                    },
+                ],
+            },
+            ShopRow {
+                id: BACON_BAR,
+                name: "Bacon Bar".to_string(),
+                address: None,
+                coordinates: Some(Coordinates::new(25.0330, 121.5654)),
+                schedule: Schedule::default(),
+                currency: Currency::default(),
+                taxes: TaxSettings::default(),
+                latest_update: now,
+                series: vec![],
+                products: vec![],
+            },
+        ],
+        roles: vec![
+            role(DINER_OWNER, DINER, RoleTemplate::Owner),
+            role(DINER_STAFF, DINER, RoleTemplate::Staff),
+            role(BACON_BAR_OWNER, BACON_BAR, RoleTemplate::Owner),
+        ],
+        shop_users: vec![
+            member(DINER, OWNER, DINER_OWNER),
+            member(DINER, STAFF, DINER_STAFF),
+            member(BACON_BAR, OWNER, BACON_BAR_OWNER),
+        ],
+        inventory: vec![
+            InventoryRow { shop_id: DINER, key: BLACK_TEA, stock: Some(3), sold_out: false },
+            InventoryRow { shop_id: DINER, key: SMALL, stock: None, sold_out: true },
+        ],
+        translations: vec![
+            translation(PORK_RICE, TranslatedField::Name, "滷肉飯"),
+            translation(PORK_RICE, TranslatedField::Description, "慢燉五花肉配白飯。"),
+            translation(SIZE, TranslatedField::Name, "份量"),
+            translation(LARGE, TranslatedField::Name, "大"),
+        ],
+        promotions: vec![],
+        carts: vec![
+            CartRow {
+                id: CART,
+                shop_id: DINER,
+                guest_session_id: GUEST_SESSION,
+                coupon_code: None,
+                items: vec![
+                    ProductItemRow {
+                        key: CART_ITEM,
+                        product_key: BLACK_TEA,
+                        name: "Black Tea".to_string(),
+                        price: 30,
+                        count: 2,
+                        remark: Some("Less ice".to_string()),
+                        order_at: now,
+                        customizes: vec![],
+                    },
+                ],
+            },
+        ],
+        orders: vec![
+            OrderRow {
+                id: ORDER,
+                shop_id: DINER,
+                guest_session_id: GUEST_SESSION,
+                order_number: 1,
+                order_at: now,
+                items: vec![
+                    ProductItemRow {
+                        key: ORDER_ITEM,
+                        product_key: PORK_RICE,
+                        name: "Braised Pork Rice".to_string(),
+                        price: 80,
+                        count: 1,
+                        remark: None,
+                        order_at: now,
+                        customizes: vec![
+                            CustomizeItemRow {
+                                customize_key: SIZE,
+                                name: "Size".to_string(),
+                                selection: Some("Large".to_string()),
+                                selection_key: Some(LARGE),
+                                selection_price: Some(20),
+                                order_at: now,
+                            },
+                        ],
+                    },
+                ],
+                promotions: vec![],
+                charges: Charges::none(DINER),
+            },
+        ],
+        audit_log: vec![],
+        login_failures: HashMap::new(),
+        api_tokens: vec![],
+    })
+}
@@ -0,0 +1,131 @@
+INSERT INTO users (id, username, password, nickname) VALUES
+    ('00000000-0000-0000-0000-000000000001', 'owner', 'owner-password', 'Owner'),
+    ('00000000-0000-0000-0000-000000000002', 'staff', 'staff-password', NULL),
+    ('00000000-0000-0000-0000-000000000003', 'outsider', 'outsider-password', NULL);
+
+INSERT INTO user_session (id, user_id, expire_time) VALUES
+    ('00000000-0000-0000-0001-000000000001', '00000000-0000-0000-0000-000000000001', now() + INTERVAL '1 day'),
+    ('00000000-0000-0000-0001-000000000002', '00000000-0000-0000-0000-000000000002', now() + INTERVAL '1 day'),
+    ('00000000-0000-0000-0001-000000000003', '00000000-0000-0000-0000-000000000003', now() - INTERVAL '1 day');
+
+INSERT INTO guest_session (id, expire_time) VALUES
+    ('00000000-0000-0000-0002-000000000001', now() + INTERVAL '1 day'),
+    ('00000000-0000-0000-0002-000000000002', now() - INTERVAL '1 day');
+
+INSERT INTO shops (id, name, address, latitude, longitude, series, products) VALUES
+    (
+        '00000000-0000-0000-0003-000000000001',
+        'Pigskit Diner',
+        '3 Beiping W. Rd., Taipei',
+        25.0478,
+        121.5170,
+        hstore(
+            ARRAY[
+                '00000000-0000-0000-000a-000000000001',
+                '00000000-0000-0000-000a-000000000002'
+            ],
+            ARRAY[
+                ROW('Mains', 0, now())::SERIES::TEXT,
+                ROW('Drinks', 1, now())::SERIES::TEXT
+            ]
+        ),
+        hstore(
+            ARRAY[
+                '00000000-0000-0000-0004-000000000001',
+                '00000000-0000-0000-0004-000000000002'
+            ],
+            ARRAY[
+                ROW(
+                    'Braised Pork Rice',
+                    'Slow cooked pork belly on rice.',
+                    80,
+                    '00000000-0000-0000-000a-000000000001',
+                    FALSE,
+                    hstore(
+                        '00000000-0000-0000-0005-000000000001',
+                        ROW(
+                            'Size',
+                            NULL,
+                            hstore(
+                                ARRAY[
+                                    '00000000-0000-0000-0006-000000000001',
+                                    '00000000-0000-0000-0006-000000000002'
+                                ],
+                                ARRAY[
+                                    ROW('Large', 20)::SELECTION::TEXT,
+                                    ROW('Small', 0)::SELECTION::TEXT
+                                ]
+                            ),
+                            now()
+                        )::CUSTOMIZE::TEXT
+                    ),
+                    now()
+                )::PRODUCT::TEXT,
+                ROW('Black Tea', NULL, 30, '00000000-0000-0000-000a-000000000002', FALSE, '', now())::PRODUCT::TEXT
+            ]
+        )
+    ),
+    ('00000000-0000-0000-0003-000000000002', 'Bacon Bar', NULL, 25.0330, 121.5654, '', '');
+
+INSERT INTO roles (id, shop_id, name, capabilities) VALUES
+    ('00000000-0000-0000-000b-000000000001', '00000000-0000-0000-0003-000000000001', 'Owner', ARRAY['view_members', 'manage_members', 'manage_shop', 'view_audit_log', 'view_orders', 'view_analytics', 'manage_ordering', 'refund_orders', 'view_promotions', 'edit_catalog', 'edit_prices', 'manage_stock']),
+    ('00000000-0000-0000-000b-000000000002', '00000000-0000-0000-0003-000000000001', 'Staff', ARRAY['view_members', 'view_promotions']),
+    ('00000000-0000-0000-000b-000000000003', '00000000-0000-0000-0003-000000000002', 'Owner', ARRAY['view_members', 'manage_members', 'manage_shop', 'view_audit_log', 'view_orders', 'view_analytics', 'manage_ordering', 'refund_orders', 'view_promotions', 'edit_catalog', 'edit_prices', 'manage_stock']);
+
+INSERT INTO shop_user (shop_id, user_id, role_id) VALUES
+    ('00000000-0000-0000-0003-000000000001', '00000000-0000-0000-0000-000000000001', '00000000-0000-0000-000b-000000000001'),
+    ('00000000-0000-0000-0003-000000000001', '00000000-0000-0000-0000-000000000002', '00000000-0000-0000-000b-000000000002'),
+    ('00000000-0000-0000-0003-000000000002', '00000000-0000-0000-0000-000000000001', '00000000-0000-0000-000b-000000000003');
+
+INSERT INTO inventory (shop_id, key, stock, sold_out) VALUES
+    ('00000000-0000-0000-0003-000000000001', '00000000-0000-0000-0004-000000000002', 3, FALSE),
+    ('00000000-0000-0000-0003-000000000001', '00000000-0000-0000-0006-000000000002', NULL, TRUE);
+
+INSERT INTO translations (shop_id, key, field, locale, text) VALUES
+    ('00000000-0000-0000-0003-000000000001', '00000000-0000-0000-0004-000000000001', 'name', 'zh-TW', '滷肉飯'),
+    ('00000000-0000-0000-0003-000000000001', '00000000-0000-0000-0004-000000000001', 'description', 'zh-TW', '慢燉五花肉配白飯。'),
+    ('00000000-0000-0000-0003-000000000001', '00000000-0000-0000-0005-000000000001', 'name', 'zh-TW', '份量'),
+    ('00000000-0000-0000-0003-000000000001', '00000000-0000-0000-0006-000000000001', 'name', 'zh-TW', '大');
+
+INSERT INTO cart (id, shop_id, guest_session_id, items) VALUES
+    (
+        '00000000-0000-0000-0007-000000000001',
+        '00000000-0000-0000-0003-000000000001',
+        '00000000-0000-0000-0002-000000000001',
+        hstore(
+            '00000000-0000-0000-0008-000000000001',
+            ROW(
+                '00000000-0000-0000-0004-000000000002',
+                'Black Tea',
+                30,
+                2,
+                'Less ice',
+                '',
+                now()
+            )::PRODUCT_ITEM::TEXT
+        )
+    );
+
+INSERT INTO orders (id, shop_id, guest_session_id, order_number, items, order_at) VALUES
+    (
+        '00000000-0000-0000-0009-000000000001',
+        '00000000-0000-0000-0003-000000000001',
+        '00000000-0000-0000-0002-000000000001',
+        1,
+        hstore(
+            '00000000-0000-0000-0008-000000000002',
+            ROW(
+                '00000000-0000-0000-0004-000000000001',
+                'Braised Pork Rice',
+                80,
+                1,
+                NULL,
+                hstore(
+                    '00000000-0000-0000-0005-000000000001',
+                    ROW('Size', 'Large', '00000000-0000-0000-0006-000000000001', 20, now())::CUSTOMIZE_ITEM::TEXT
+                ),
+                now()
+            )::PRODUCT_ITEM::TEXT
+        ),
+        now()
+    );
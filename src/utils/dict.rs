@@ -25,6 +25,10 @@ impl<K: Hash + Eq, V> Dict<K, V> {
         &self.values
     }
 
+    pub fn ref_mut_values(&mut self) -> &mut [V] {
+        &mut self.values
+    }
+
     pub fn ref_mut_value(&mut self, key: K) -> Option<&mut V> {
         if let Some(&idx) = self.map.get(&key) {
             Some(&mut self.values[idx])